@@ -0,0 +1,501 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use log::warn;
+use thiserror::Error;
+
+use super::options::BuiltInAction;
+use super::{extract_set_of_flags, option_table, BazelOption, CommandLineParsingError};
+
+const SYSTEM_RC: &str = "/etc/bazel.bazelrc";
+const WORKSPACE_MARKERS: [&str; 4] = ["MODULE.bazel", "REPO.bazel", "WORKSPACE.bazel", "WORKSPACE"];
+
+#[derive(Error, Debug)]
+pub enum BazelRcError {
+    #[error("Unable to read bazelrc {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+
+    #[error("Unterminated quote in bazelrc {0:?}")]
+    UnterminatedQuote(PathBuf),
+
+    #[error("Config value '{0}' is not defined in any .rc file")]
+    UnknownConfig(String),
+
+    #[error("Config value '{0}' expands to itself")]
+    RecursiveConfig(String),
+
+    #[error(transparent)]
+    CommandLineParsingError(#[from] CommandLineParsingError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RcEntry {
+    command: String,
+    config: Option<String>,
+    args: Vec<String>,
+}
+
+/// The option lines of every `.bazelrc` bazel would read, in the order it reads them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BazelRc {
+    entries: Vec<RcEntry>,
+}
+
+/// The nearest directory at or above `start` that bazel would treat as the workspace root.
+pub fn find_workspace_root(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .find(|dir| WORKSPACE_MARKERS.iter().any(|m| dir.join(m).is_file()))
+        .map(|dir| dir.to_path_buf())
+}
+
+fn startup_flag(startup_options: &[BazelOption], name: &str, default: bool) -> bool {
+    startup_options
+        .iter()
+        .rev()
+        .find_map(|e| match e {
+            BazelOption::BooleanOption(n, v) if n == name => Some(*v),
+            _ => None,
+        })
+        .unwrap_or(default)
+}
+
+/// Splits a logical rc line into words, shell style, dropping any trailing comment.
+fn tokenize(line: &str, source: &Path) -> Result<Vec<String>, BazelRcError> {
+    let mut tokens = Vec::default();
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => {
+                if let Some(escaped) = chars.next() {
+                    current.get_or_insert_with(String::default).push(escaped);
+                }
+            }
+            (Some(_), c) => current.get_or_insert_with(String::default).push(c),
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                current.get_or_insert_with(String::default);
+            }
+            (None, '\\') => {
+                if let Some(escaped) = chars.next() {
+                    current.get_or_insert_with(String::default).push(escaped);
+                }
+            }
+            (None, '#') if current.is_none() => break,
+            (None, c) if c.is_whitespace() => tokens.extend(current.take()),
+            (None, c) => current.get_or_insert_with(String::default).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(BazelRcError::UnterminatedQuote(source.to_path_buf()));
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+/// Rc files are read on every expansion, so each unknown flag is only warned about once.
+fn warn_unknown_rc_flag(arg: String) {
+    lazy_static! {
+        static ref WARNED: Mutex<HashSet<String>> = Mutex::new(HashSet::default());
+    }
+    if WARNED.lock().unwrap().insert(arg.clone()) {
+        warn!("Ignoring an option in the bazelrc we don't know: {}", arg);
+    }
+}
+
+/// Parses the args of one rc line, `--flag value` is allowed as well as `--flag=value`.
+///
+/// Flags the option table doesn't know are returned separately rather than failing the whole rc.
+fn parse_rc_args(
+    args: &[String],
+    flags: &[BazelOption],
+) -> Result<(Vec<BazelOption>, Vec<String>), CommandLineParsingError> {
+    let mut result = Vec::default();
+    let mut unknown = Vec::default();
+    let mut idx = 0;
+    while idx < args.len() {
+        let parsed = match extract_set_of_flags(&mut args[idx..idx + 1].iter().peekable(), flags) {
+            Err(CommandLineParsingError::MissingArgToOption(_)) if idx + 1 < args.len() => {
+                idx += 1;
+                extract_set_of_flags(&mut args[idx - 1..idx + 1].iter().peekable(), flags)
+            }
+            other => other,
+        };
+        idx += 1;
+        match parsed {
+            Ok(options) => result.extend(options),
+            Err(CommandLineParsingError::UnknownArgument(arg)) => unknown.push(arg),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((result, unknown))
+}
+
+/// Rc sections that apply to an action, least specific first.
+fn sections_for(action: BuiltInAction) -> Vec<String> {
+    let inherited: &[BuiltInAction] = match action {
+        BuiltInAction::Test
+        | BuiltInAction::Run
+        | BuiltInAction::Aquery
+        | BuiltInAction::Clean
+        | BuiltInAction::Info
+        | BuiltInAction::MobileInstall
+        | BuiltInAction::PrintAction => &[BuiltInAction::Build],
+        BuiltInAction::Coverage | BuiltInAction::Cquery => {
+            &[BuiltInAction::Build, BuiltInAction::Test]
+        }
+        _ => &[],
+    };
+    let mut sections = vec![String::from("common"), String::from("always")];
+    sections.extend(inherited.iter().map(|e| e.to_string()));
+    sections.push(action.to_string());
+    sections
+}
+
+impl BazelRc {
+    /// Reads the rc files bazel would for these startup options: system, workspace, home, then each `--bazelrc`.
+    ///
+    /// Like bazel, a relative `--bazelrc` is resolved against the directory bazel was run from.
+    pub fn load(
+        startup_options: &[BazelOption],
+        working_directory: &Path,
+        workspace_root: &Path,
+        home_dir: Option<&Path>,
+    ) -> Result<Self, BazelRcError> {
+        let mut bazelrc = Self::default();
+        if startup_flag(startup_options, "ignore_all_rc_files", false) {
+            return Ok(bazelrc);
+        }
+
+        let mut rc_files = Vec::default();
+        if startup_flag(startup_options, "system_rc", true) {
+            rc_files.push(PathBuf::from(SYSTEM_RC));
+        }
+        if startup_flag(startup_options, "workspace_rc", true) {
+            rc_files.push(workspace_root.join(".bazelrc"));
+        }
+        if startup_flag(startup_options, "home_rc", true) {
+            rc_files.extend(home_dir.map(|home| home.join(".bazelrc")));
+        }
+
+        let mut loaded: Vec<PathBuf> = Vec::default();
+        for rc_file in rc_files.into_iter().filter(|e| e.is_file()) {
+            bazelrc.read_file(&rc_file, workspace_root, &mut loaded)?;
+        }
+
+        for option in startup_options.iter() {
+            if let BazelOption::OptionWithArg(name, path) = option {
+                if name == "bazelrc" {
+                    // Bazel stops reading --bazelrc flags at the first /dev/null.
+                    if path == "/dev/null" {
+                        break;
+                    }
+                    bazelrc.read_file(
+                        &working_directory.join(path),
+                        workspace_root,
+                        &mut loaded,
+                    )?;
+                }
+            }
+        }
+        Ok(bazelrc)
+    }
+
+    /// Parses rc file contents, following any imports from disk.
+    pub fn parse(
+        contents: &str,
+        source: &Path,
+        workspace_root: &Path,
+    ) -> Result<Self, BazelRcError> {
+        let mut bazelrc = Self::default();
+        let mut loaded = vec![source.to_path_buf()];
+        bazelrc.parse_into(contents, source, workspace_root, &mut loaded)?;
+        Ok(bazelrc)
+    }
+
+    fn read_file(
+        &mut self,
+        path: &Path,
+        workspace_root: &Path,
+        loaded: &mut Vec<PathBuf>,
+    ) -> Result<(), BazelRcError> {
+        let path = workspace_root.join(path);
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if loaded.contains(&canonical) {
+            // Reading the same file twice only ever repeats its options, bazel skips it too.
+            return Ok(());
+        }
+        let contents =
+            std::fs::read_to_string(&path).map_err(|e| BazelRcError::Io(path.clone(), e))?;
+        loaded.push(canonical);
+        self.parse_into(&contents, &path, workspace_root, loaded)
+    }
+
+    fn parse_into(
+        &mut self,
+        contents: &str,
+        source: &Path,
+        workspace_root: &Path,
+        loaded: &mut Vec<PathBuf>,
+    ) -> Result<(), BazelRcError> {
+        let mut logical_line = String::default();
+        for line in contents.lines() {
+            let line = line.trim_end_matches('\r');
+            if let Some(continued) = line.strip_suffix('\\') {
+                logical_line.push_str(continued);
+                continue;
+            }
+            logical_line.push_str(line);
+            let tokens = tokenize(&logical_line, source)?;
+            logical_line.clear();
+
+            let mut tokens = tokens.into_iter();
+            let command = match tokens.next() {
+                Some(command) => command,
+                None => continue,
+            };
+            let args: Vec<String> = tokens.collect();
+
+            if command == "import" || command == "try-import" {
+                let import = match args.first() {
+                    Some(import) => PathBuf::from(
+                        import.replace("%workspace%", &workspace_root.to_string_lossy()),
+                    ),
+                    None => continue,
+                };
+                // Files already read are skipped, which also breaks import cycles.
+                let import_path = workspace_root.join(&import);
+                if command == "try-import" && !import_path.is_file() {
+                    continue;
+                }
+                self.read_file(&import_path, workspace_root, loaded)?;
+                continue;
+            }
+
+            let (command, config) = match command.split_once(':') {
+                Some((command, config)) => (command.to_string(), Some(config.to_string())),
+                None => (command, None),
+            };
+            self.entries.push(RcEntry {
+                command,
+                config,
+                args,
+            });
+        }
+        Ok(())
+    }
+
+    fn section_options(
+        &self,
+        sections: &[String],
+        config: Option<&str>,
+        flags: &[BazelOption],
+    ) -> Result<Vec<BazelOption>, BazelRcError> {
+        let mut result = Vec::default();
+        for section in sections.iter() {
+            for entry in self
+                .entries
+                .iter()
+                .filter(|e| &e.command == section && e.config.as_deref() == config)
+            {
+                let (options, unknown) = parse_rc_args(&entry.args, flags)?;
+                result.extend(options);
+                // Like bazel, options under common that don't apply to this action are ignored.
+                if section != "common" {
+                    unknown.into_iter().for_each(warn_unknown_rc_flag);
+                }
+            }
+        }
+        Ok(result)
+    }
+
+    fn expand_configs(
+        &self,
+        options: Vec<BazelOption>,
+        sections: &[String],
+        flags: &[BazelOption],
+        expanding: &mut Vec<String>,
+    ) -> Result<Vec<BazelOption>, BazelRcError> {
+        let mut result = Vec::default();
+        for option in options.into_iter() {
+            match option {
+                BazelOption::OptionWithArg(name, config) if name == "config" => {
+                    if expanding.contains(&config) {
+                        return Err(BazelRcError::RecursiveConfig(config));
+                    }
+                    if !self
+                        .entries
+                        .iter()
+                        .any(|e| e.config.as_deref() == Some(config.as_str()))
+                    {
+                        return Err(BazelRcError::UnknownConfig(config));
+                    }
+                    let config_options = self.section_options(sections, Some(&config), flags)?;
+                    expanding.push(config);
+                    result.extend(self.expand_configs(
+                        config_options,
+                        sections,
+                        flags,
+                        expanding,
+                    )?);
+                    expanding.pop();
+                }
+                other => result.push(other),
+            }
+        }
+        Ok(result)
+    }
+
+    /// Flags for the action, outside of `common`, that the active option table doesn't know.
+    ///
+    /// These come from a newer bazel than the table, and are skipped when expanding.
+    pub fn unknown_options(&self, action: BuiltInAction) -> Vec<String> {
+        let option_table = option_table::active();
+        let flags = option_table.options_for_action(&action);
+        let sections = sections_for(action);
+        self.entries
+            .iter()
+            .filter(|e| e.command != "common" && sections.contains(&e.command))
+            .filter_map(|e| parse_rc_args(&e.args, flags).ok())
+            .flat_map(|(_, unknown)| unknown)
+            .collect()
+    }
+
+    /// The options bazel would run the action with, rc options first and `--config`s expanded in place.
+    pub fn expand(
+        &self,
+        action: BuiltInAction,
+        command_line_options: &[BazelOption],
+    ) -> Result<Vec<BazelOption>, BazelRcError> {
+        let option_table = option_table::active();
+        let flags = option_table.options_for_action(&action);
+        let sections = sections_for(action);
+        let mut expanding = Vec::default();
+
+        let rc_options = self.section_options(&sections, None, flags)?;
+        let mut result = self.expand_configs(rc_options, &sections, flags, &mut expanding)?;
+        result.extend(self.expand_configs(
+            command_line_options.to_vec(),
+            &sections,
+            flags,
+            &mut expanding,
+        )?);
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_arg(name: &str, value: &str) -> BazelOption {
+        BazelOption::OptionWithArg(String::from(name), String::from(value))
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let source = Path::new(".bazelrc");
+        assert_eq!(
+            tokenize(
+                r#"build --copt="-DFOO=a b" --define 'x=y z' # trailing"#,
+                source
+            )
+            .unwrap(),
+            vec!["build", "--copt=-DFOO=a b", "--define", "x=y z"]
+        );
+        assert!(tokenize("# just a comment", source).unwrap().is_empty());
+        assert!(tokenize("build --copt=\"open", source).is_err());
+    }
+
+    #[test]
+    fn test_expand_sections_and_configs() {
+        let bazelrc = BazelRc::parse(
+            "common --color=no --not_a_real_flag\n\
+             build --keep_going --flag_from_a_newer_bazel\n\
+             build:ci --bes_backend=grpcs://bes.example.com \\\n  --config=remote\n\
+             build:remote --remote_header x-token=abc\n\
+             test --test_output=errors\n\
+             query --output=label\n",
+            Path::new(".bazelrc"),
+            Path::new("/workspace"),
+        )
+        .unwrap();
+
+        let effective = bazelrc
+            .expand(
+                BuiltInAction::Test,
+                &[with_arg("config", "ci"), with_arg("color", "yes")],
+            )
+            .unwrap();
+        assert_eq!(
+            effective,
+            vec![
+                with_arg("color", "no"),
+                BazelOption::BooleanOption(String::from("keep_going"), true),
+                with_arg("test_output", "errors"),
+                with_arg("bes_backend", "grpcs://bes.example.com"),
+                with_arg("remote_header", "x-token=abc"),
+                with_arg("color", "yes"),
+            ]
+        );
+
+        assert_eq!(
+            bazelrc.unknown_options(BuiltInAction::Test),
+            vec![String::from("--flag_from_a_newer_bazel")]
+        );
+        assert!(bazelrc.unknown_options(BuiltInAction::Shutdown).is_empty());
+
+        assert!(matches!(
+            bazelrc.expand(BuiltInAction::Build, &[with_arg("config", "missing")]),
+            Err(BazelRcError::UnknownConfig(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_rc_files() {
+        let workspace = tempfile::tempdir().unwrap();
+        let root = workspace.path();
+        std::fs::write(root.join("WORKSPACE"), "").unwrap();
+        std::fs::create_dir_all(root.join("tools")).unwrap();
+        std::fs::write(
+            root.join(".bazelrc"),
+            "import %workspace%/tools/shared.bazelrc\ntry-import %workspace%/user.bazelrc\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("tools/shared.bazelrc"), "build --keep_going\n").unwrap();
+
+        let nested = root.join("src/main");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_workspace_root(&nested), Some(root.to_path_buf()));
+        // A relative --bazelrc is found from where bazel was run, not the workspace root.
+        std::fs::write(nested.join("extra.bazelrc"), "build --color=no\n").unwrap();
+
+        let startup_options = vec![
+            BazelOption::BooleanOption(String::from("system_rc"), false),
+            with_arg("bazelrc", "extra.bazelrc"),
+        ];
+        let bazelrc = BazelRc::load(&startup_options, &nested, root, None).unwrap();
+        assert_eq!(
+            bazelrc.expand(BuiltInAction::Build, &[]).unwrap(),
+            vec![
+                BazelOption::BooleanOption(String::from("keep_going"), true),
+                with_arg("color", "no"),
+            ]
+        );
+
+        let no_workspace_rc = vec![
+            BazelOption::BooleanOption(String::from("system_rc"), false),
+            BazelOption::BooleanOption(String::from("workspace_rc"), false),
+        ];
+        let bazelrc = BazelRc::load(&no_workspace_rc, root, root, None).unwrap();
+        assert!(bazelrc
+            .expand(BuiltInAction::Build, &[])
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -1,6 +1,14 @@
+pub mod bazelrc;
+pub mod option_table;
 mod options;
-use std::{collections::HashMap, iter::Peekable, path::PathBuf};
-
+use std::{
+    collections::HashMap,
+    iter::Peekable,
+    path::{Path, PathBuf},
+};
+
+pub use bazelrc::{BazelRc, BazelRcError};
+pub use option_table::OptionTable;
 pub use options::BuiltInAction;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
@@ -118,6 +126,8 @@ pub struct ParsedCommandLine {
     pub action: Option<Action>,
     pub action_options: Vec<BazelOption>,
     pub remaining_args: Vec<String>,
+    /// The rc files bazel will read for this invocation, empty until loaded.
+    pub bazelrc: BazelRc,
 }
 impl ParsedCommandLine {
     pub fn all_args_normalized(&self) -> Result<Vec<String>, ArgNormalizationError> {
@@ -147,12 +157,12 @@ impl ParsedCommandLine {
         Ok(result)
     }
 
+    /// Adds the option unless bazel will already see it, from the command line or the loaded rc files.
     pub fn add_action_option_if_unset(&mut self, option: BazelOption) -> bool {
-        if self
-            .action_options
-            .iter()
-            .any(|e| e.name() == option.name())
-        {
+        let effective_options = self
+            .effective_action_options()
+            .unwrap_or_else(|_| self.action_options.clone());
+        if effective_options.iter().any(|e| e.name() == option.name()) {
             false
         } else {
             self.action_options.push(option);
@@ -160,10 +170,55 @@ impl ParsedCommandLine {
         }
     }
 
+    /// Replaces any occurrence of the option on the command line, which takes precedence over the rc files.
+    pub fn set_action_option(&mut self, option: BazelOption) {
+        self.remove_action_option(option.name());
+        self.action_options.push(option);
+    }
+
     pub fn is_action_option_set(&self, opt: &str) -> bool {
         self.action_options.iter().any(|e| e.name() == opt)
     }
 
+    /// Removes every occurrence of the option, returning them in command line order.
+    pub fn remove_action_option(&mut self, opt: &str) -> Vec<BazelOption> {
+        let (removed, kept) = self.action_options.drain(..).partition(|e| e.name() == opt);
+        self.action_options = kept;
+        removed
+    }
+
+    /// Loads the rc files bazel will read, resolving the workspace from `working_directory` as bazel does.
+    pub fn load_bazelrc(&mut self, working_directory: &Path) -> Result<(), BazelRcError> {
+        let workspace_root = bazelrc::find_workspace_root(working_directory)
+            .unwrap_or_else(|| working_directory.to_path_buf());
+        let home_dir = std::env::var_os("HOME").map(PathBuf::from);
+        self.bazelrc = BazelRc::load(
+            &self.startup_options,
+            working_directory,
+            &workspace_root,
+            home_dir.as_deref(),
+        )?;
+        Ok(())
+    }
+
+    /// Options in the loaded rc files for this action that the active option table doesn't know.
+    pub fn unknown_rc_options(&self) -> Vec<String> {
+        match &self.action {
+            Some(Action::BuiltIn(action)) => self.bazelrc.unknown_options(*action),
+            _ => Vec::default(),
+        }
+    }
+
+    /// The action options bazel will actually see once the loaded rc files and any `--config`s are applied.
+    ///
+    /// Custom actions aren't known to the rc files, so for those this is just the command line.
+    pub fn effective_action_options(&self) -> Result<Vec<BazelOption>, BazelRcError> {
+        match &self.action {
+            Some(Action::BuiltIn(action)) => self.bazelrc.expand(*action, &self.action_options),
+            _ => Ok(self.action_options.clone()),
+        }
+    }
+
     pub fn set_action(&mut self, action: Option<Action>) -> Option<Action> {
         let prev = self.action.take();
         if action.is_none() {
@@ -290,6 +345,19 @@ fn extract_set_of_flags<'a, I: Iterator<Item = &'a String>>(
 pub fn parse_bazel_command_line(
     command_line: &[String],
     custom_action_to_built_in: HashMap<String, BuiltInAction>,
+) -> Result<ParsedCommandLine, CommandLineParsingError> {
+    parse_bazel_command_line_with_options(
+        command_line,
+        custom_action_to_built_in,
+        &option_table::active(),
+    )
+}
+
+/// Parses the command line against a specific option table, rather than the active one.
+pub fn parse_bazel_command_line_with_options(
+    command_line: &[String],
+    custom_action_to_built_in: HashMap<String, BuiltInAction>,
+    option_table: &OptionTable,
 ) -> Result<ParsedCommandLine, CommandLineParsingError> {
     let mut command_line_iter = command_line.iter().peekable();
     let bazel_path = if let Some(p) = command_line_iter.next() {
@@ -298,18 +366,15 @@ pub fn parse_bazel_command_line(
         return Err(CommandLineParsingError::MissingBazelPath);
     };
 
-    let startup_options = extract_set_of_flags(&mut command_line_iter, &options::STARTUP_OPTIONS)?;
+    let startup_options =
+        extract_set_of_flags(&mut command_line_iter, option_table.startup_options())?;
 
     let action: Option<Action> = command_line_iter.peek().and_then(|cmd| cmd.parse().ok());
 
     if let Some(action) = action.as_ref() {
         command_line_iter.next();
-        let options: Vec<BazelOption> = options::ACTION_TO_OPTIONS
-            .get(&action.action_for_options(&custom_action_to_built_in)?)
-            .expect("Should be impossible not to find options")
-            .iter()
-            .map(|&o| options::ALL_ACTION_OPTIONS[o].clone())
-            .collect();
+        let options = option_table
+            .options_for_action(&action.action_for_options(&custom_action_to_built_in)?);
         let mut action_options = Vec::default();
         let mut action_args = Vec::default();
         'outer: loop {
@@ -324,7 +389,7 @@ pub fn parse_bazel_command_line(
                 action_args.push(opt.clone());
                 command_line_iter.next();
             }
-            let cur_options = extract_set_of_flags(&mut command_line_iter, &options)?;
+            let cur_options = extract_set_of_flags(&mut command_line_iter, options)?;
 
             if cur_options.is_empty() {
                 break 'outer;
@@ -339,6 +404,7 @@ pub fn parse_bazel_command_line(
             action: Some(action.clone()),
             action_options,
             remaining_args: action_args,
+            bazelrc: BazelRc::default(),
         })
     } else {
         Ok(ParsedCommandLine {
@@ -347,6 +413,7 @@ pub fn parse_bazel_command_line(
             action: None,
             action_options: Vec::default(),
             remaining_args: command_line_iter.cloned().collect(),
+            bazelrc: BazelRc::default(),
         })
     }
 }
@@ -698,4 +765,39 @@ mod tests {
             },
         }
     }
+
+    #[tokio::test]
+    async fn add_action_option_if_unset_sees_the_bazelrc() {
+        let mut command_line = parse_bazel_command_line(
+            &["bazel", "build", "//..."]
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>(),
+            Default::default(),
+        )
+        .unwrap();
+        command_line.bazelrc = BazelRc::parse(
+            "build --color=no\n",
+            Path::new(".bazelrc"),
+            Path::new("/workspace"),
+        )
+        .unwrap();
+
+        assert!(
+            !command_line.add_action_option_if_unset(BazelOption::OptionWithArg(
+                String::from("color"),
+                String::from("yes")
+            ))
+        );
+        assert!(
+            command_line.add_action_option_if_unset(BazelOption::BooleanOption(
+                String::from("keep_going"),
+                true
+            ))
+        );
+        assert_eq!(
+            command_line.action_options,
+            vec![BazelOption::BooleanOption(String::from("keep_going"), true)]
+        );
+    }
 }
@@ -0,0 +1,470 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use base64::Engine;
+use bazelfe_protos::bazel_flags::FlagCollection;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use prost::Message;
+use thiserror::Error;
+use tokio::process::Command;
+
+use super::options::{self, BuiltInAction};
+use super::BazelOption;
+
+#[derive(Error, Debug)]
+pub enum OptionTableError {
+    #[error("Unable to run bazel: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("`bazel {0}` failed: {1}")]
+    CommandFailed(String, String),
+
+    #[error("Unable to decode the flags bazel reported: {0}")]
+    Decode(String),
+
+    #[error("This bazel doesn't report which flags are boolean")]
+    MissingValueInfo,
+
+    #[error("Already failed with this bazel version: {0}")]
+    PreviouslyFailed(String),
+}
+
+/// The startup options and per action options the command line parser accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OptionTable {
+    startup_options: Vec<BazelOption>,
+    action_options: HashMap<BuiltInAction, Vec<BazelOption>>,
+}
+
+lazy_static! {
+    static ref ACTIVE: RwLock<Arc<OptionTable>> = RwLock::new(Arc::new(OptionTable::compiled_in()));
+}
+
+/// The table the command line parser is using, the compiled-in one unless another was installed.
+pub fn active() -> Arc<OptionTable> {
+    Arc::clone(&ACTIVE.read().unwrap())
+}
+
+async fn run_bazel(
+    bazel_binary: &Path,
+    startup_args: &[String],
+    args: &[&str],
+) -> Result<Vec<u8>, OptionTableError> {
+    let output = Command::new(bazel_binary)
+        .args(startup_args)
+        .args(args)
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(OptionTableError::CommandFailed(
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Where the flags of a released bazel version are cached, development builds aren't cached.
+fn cache_path(info: &str) -> Option<PathBuf> {
+    let info_value = |key: &str| {
+        info.lines()
+            .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+            .map(|e| e.trim())
+    };
+    let version = info_value("release")?.strip_prefix("release ")?;
+    let output_base = info_value("output_base")?;
+
+    let version: String = version
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Some(
+        Path::new(output_base)
+            .join("bazelfe")
+            .join(format!("flags-as-proto-{}", version)),
+    )
+}
+
+/// Remembers that a bazel version can't report its flags, so we don't retry on every invocation.
+fn failure_marker_path(cache_path: &Path) -> PathBuf {
+    let mut file_name = cache_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".failed");
+    cache_path.with_file_name(file_name)
+}
+
+fn write_cache(path: &Path, flags_as_proto: &[u8]) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temp_path = path.to_path_buf();
+    temp_path.set_extension("tmp");
+    std::fs::write(&temp_path, flags_as_proto)?;
+    std::fs::rename(temp_path, path)
+}
+
+impl OptionTable {
+    /// The table generated into `options.rs`, from whichever bazel it was last regenerated against.
+    pub fn compiled_in() -> Self {
+        let action_options = options::ACTION_TO_OPTIONS
+            .iter()
+            .map(|(action, indices)| {
+                let options = indices
+                    .iter()
+                    .map(|&o| options::ALL_ACTION_OPTIONS[o].clone())
+                    .collect();
+                (*action, options)
+            })
+            .collect();
+        Self {
+            startup_options: options::STARTUP_OPTIONS.clone(),
+            action_options,
+        }
+    }
+
+    pub fn from_flag_collection(flags: FlagCollection) -> Result<Self, OptionTableError> {
+        // Without this every flag would take a value, and swallow the argument after it.
+        if !flags
+            .flag_infos
+            .iter()
+            .any(|e| e.has_negative_flag() || e.requires_value.is_some())
+        {
+            return Err(OptionTableError::MissingValueInfo);
+        }
+
+        let mut table = Self {
+            startup_options: Vec::default(),
+            action_options: HashMap::default(),
+        };
+        for flag in flags.flag_infos.into_iter() {
+            // Older bazels don't report `requires_value`, then like the generator the `--[no]` flags
+            // are the boolean ones.
+            let requires_value = flag
+                .requires_value
+                .unwrap_or_else(|| !flag.has_negative_flag());
+            let option = if requires_value {
+                BazelOption::OptionWithArg(flag.name, String::default())
+            } else {
+                BazelOption::BooleanOption(flag.name, false)
+            };
+            for command in flag.commands.iter() {
+                if command == "startup" {
+                    table.startup_options.push(option.clone());
+                } else if let Ok(action) = command.parse::<BuiltInAction>() {
+                    table
+                        .action_options
+                        .entry(action)
+                        .or_default()
+                        .push(option.clone());
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    /// Decodes the base64 encoded `FlagCollection` printed by `bazel help flags-as-proto`.
+    pub fn from_flags_as_proto(output: &[u8]) -> Result<Self, OptionTableError> {
+        let encoded: Vec<u8> = output
+            .iter()
+            .copied()
+            .filter(|c| !c.is_ascii_whitespace())
+            .collect();
+        let decoded = base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| OptionTableError::Decode(e.to_string()))?;
+        let flags = FlagCollection::decode(decoded.as_slice())
+            .map_err(|e| OptionTableError::Decode(e.to_string()))?;
+        Self::from_flag_collection(flags)
+    }
+
+    /// Asks the bazel binary which options it supports, using the copy cached in its output base when there is one.
+    pub async fn from_bazel(
+        bazel_binary: &Path,
+        startup_args: &[String],
+    ) -> Result<Self, OptionTableError> {
+        let info = run_bazel(
+            bazel_binary,
+            startup_args,
+            &["info", "release", "output_base"],
+        )
+        .await?;
+        let cache_path = cache_path(&String::from_utf8_lossy(&info));
+
+        if let Some(cache_path) = cache_path.as_ref() {
+            if let Ok(reason) = std::fs::read_to_string(failure_marker_path(cache_path)) {
+                return Err(OptionTableError::PreviouslyFailed(reason));
+            }
+            if let Ok(cached) = std::fs::read(cache_path) {
+                match Self::from_flags_as_proto(&cached) {
+                    Ok(table) => return Ok(table),
+                    Err(e) => debug!("Ignoring the cached bazel flags at {:?}: {}", cache_path, e),
+                }
+            }
+        }
+
+        let flags_as_proto = run_bazel(bazel_binary, startup_args, &["help", "flags-as-proto"])
+            .await
+            .and_then(|output| Ok((Self::from_flags_as_proto(&output)?, output)));
+        match (flags_as_proto, cache_path) {
+            (Ok((table, flags_as_proto)), Some(cache_path)) => {
+                if let Err(e) = write_cache(&cache_path, &flags_as_proto) {
+                    debug!("Unable to cache the bazel flags at {:?}: {}", cache_path, e);
+                }
+                Ok(table)
+            }
+            // Only remember what this bazel version will always get wrong, anything else may work next time.
+            (Err(e), Some(cache_path))
+                if matches!(
+                    e,
+                    OptionTableError::MissingValueInfo | OptionTableError::Decode(_)
+                ) =>
+            {
+                let marker_path = failure_marker_path(&cache_path);
+                if let Err(e) = write_cache(&marker_path, e.to_string().as_bytes()) {
+                    debug!("Unable to cache the failure at {:?}: {}", marker_path, e);
+                }
+                Err(e)
+            }
+            (res, _) => res.map(|(table, _)| table),
+        }
+    }
+
+    /// Like `from_bazel`, but falls back to the compiled-in table when bazel can't tell us.
+    pub async fn load(bazel_binary: &Path, startup_args: &[String]) -> Self {
+        match Self::from_bazel(bazel_binary, startup_args).await {
+            Ok(table) => table,
+            Err(e @ OptionTableError::PreviouslyFailed(_)) => {
+                debug!("Using the compiled-in option table: {}", e);
+                Self::compiled_in()
+            }
+            Err(e) => {
+                warn!(
+                    "Unable to get the supported options from bazel, using the compiled-in table: {}",
+                    e
+                );
+                Self::compiled_in()
+            }
+        }
+    }
+
+    /// Makes this the table the command line parser uses from now on.
+    pub fn install(self) {
+        *ACTIVE.write().unwrap() = Arc::new(self);
+    }
+
+    pub fn startup_options(&self) -> &[BazelOption] {
+        &self.startup_options
+    }
+
+    pub fn options_for_action(&self, action: &BuiltInAction) -> &[BazelOption] {
+        self.action_options
+            .get(action)
+            .map(|e| e.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel_command_line_parser::parse_bazel_command_line_with_options;
+    use bazelfe_protos::bazel_flags::FlagInfo;
+
+    fn flag(name: &str, has_negative_flag: bool, commands: &[&str]) -> FlagInfo {
+        FlagInfo {
+            name: String::from(name),
+            has_negative_flag: Some(has_negative_flag),
+            commands: commands.iter().map(|e| e.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    fn flags_as_proto() -> Vec<u8> {
+        let flags = FlagCollection {
+            flag_infos: vec![
+                flag("output_base", false, &["startup"]),
+                flag("keep_going", true, &["build", "test"]),
+                flag("experimental_shiny_new_flag", false, &["build"]),
+            ],
+        };
+        base64::engine::general_purpose::STANDARD
+            .encode(flags.encode_to_vec())
+            .into_bytes()
+    }
+
+    #[test]
+    fn test_parse_with_runtime_table() {
+        let table = OptionTable::from_flags_as_proto(&flags_as_proto()).unwrap();
+        assert_eq!(
+            table.options_for_action(&BuiltInAction::Test),
+            &[BazelOption::BooleanOption(
+                String::from("keep_going"),
+                false
+            )]
+        );
+        assert!(table.options_for_action(&BuiltInAction::Query).is_empty());
+
+        let command_line: Vec<String> = vec![
+            "bazel",
+            "--output_base=/tmp/out",
+            "build",
+            "--experimental_shiny_new_flag",
+            "yes",
+            "--keep_going",
+            "//...",
+        ]
+        .into_iter()
+        .map(|e| e.to_string())
+        .collect();
+        let parsed =
+            parse_bazel_command_line_with_options(&command_line, Default::default(), &table)
+                .unwrap();
+        assert_eq!(
+            parsed.action_options,
+            vec![
+                BazelOption::OptionWithArg(
+                    String::from("experimental_shiny_new_flag"),
+                    String::from("yes")
+                ),
+                BazelOption::BooleanOption(String::from("keep_going"), true),
+            ]
+        );
+        assert_eq!(parsed.remaining_args, vec![String::from("//...")]);
+    }
+
+    #[test]
+    fn test_prefers_requires_value() {
+        let flags = FlagCollection {
+            flag_infos: vec![
+                FlagInfo {
+                    requires_value: Some(false),
+                    ..flag("experimental_expansion", false, &["build"])
+                },
+                FlagInfo {
+                    requires_value: Some(true),
+                    ..flag("output_groups", false, &["build"])
+                },
+            ],
+        };
+        let table = OptionTable::from_flag_collection(flags).unwrap();
+        assert_eq!(
+            table.options_for_action(&BuiltInAction::Build),
+            &[
+                BazelOption::BooleanOption(String::from("experimental_expansion"), false),
+                BazelOption::OptionWithArg(String::from("output_groups"), String::default()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_flags_without_value_info() {
+        let flags = FlagCollection {
+            flag_infos: vec![FlagInfo {
+                name: String::from("keep_going"),
+                commands: vec![String::from("build")],
+                ..Default::default()
+            }],
+        };
+        assert!(matches!(
+            OptionTable::from_flag_collection(flags),
+            Err(OptionTableError::MissingValueInfo)
+        ));
+    }
+
+    /// A bazel 7.1.0 that prints `flags_file` for `help`, logging each call to `help_calls`.
+    fn fake_bazel(dir: &Path, flags_file: &Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let bazel = dir.join("bazel");
+        std::fs::write(
+            &bazel,
+            format!(
+                "#!/bin/sh\n\
+                 if [ \"$1\" = info ]; then\n  echo 'release: release 7.1.0'\n  echo 'output_base: {}'\n\
+                 elif [ \"$1\" = help ]; then\n  echo help >> {}\n  cat {}\n\
+                 else\n  exit 1\nfi\n",
+                dir.join("output_base").display(),
+                dir.join("help_calls").display(),
+                flags_file.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&bazel, std::fs::Permissions::from_mode(0o755)).unwrap();
+        bazel
+    }
+
+    #[tokio::test]
+    async fn test_from_bazel_caches_per_version() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let output_base = temp_dir.path().join("output_base");
+        let flags_file = temp_dir.path().join("flags");
+        std::fs::write(&flags_file, flags_as_proto()).unwrap();
+        let bazel = fake_bazel(temp_dir.path(), &flags_file);
+
+        let table = OptionTable::from_bazel(&bazel, &[]).await.unwrap();
+        assert!(output_base.join("bazelfe/flags-as-proto-7.1.0").is_file());
+
+        // Served from the cache once bazel can no longer report its flags.
+        std::fs::remove_file(&flags_file).unwrap();
+        assert_eq!(OptionTable::from_bazel(&bazel, &[]).await.unwrap(), table);
+
+        assert_eq!(
+            OptionTable::load(&temp_dir.path().join("missing"), &[]).await,
+            OptionTable::compiled_in()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_bazel_caches_failures() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let flags_file = temp_dir.path().join("flags");
+        let flags = FlagCollection {
+            flag_infos: vec![flag("keep_going", false, &["build"])],
+        };
+        std::fs::write(
+            &flags_file,
+            base64::engine::general_purpose::STANDARD.encode(flags.encode_to_vec()),
+        )
+        .unwrap();
+        let bazel = fake_bazel(temp_dir.path(), &flags_file);
+
+        assert!(matches!(
+            OptionTable::from_bazel(&bazel, &[]).await,
+            Err(OptionTableError::MissingValueInfo)
+        ));
+        assert!(matches!(
+            OptionTable::from_bazel(&bazel, &[]).await,
+            Err(OptionTableError::PreviouslyFailed(_))
+        ));
+        assert_eq!(
+            OptionTable::load(&bazel, &[]).await,
+            OptionTable::compiled_in()
+        );
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.path().join("help_calls")).unwrap(),
+            "help\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_from_bazel_retries_a_failed_command() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let flags_file = temp_dir.path().join("flags");
+        let bazel = fake_bazel(temp_dir.path(), &flags_file);
+
+        // `bazel help` fails while the flags are missing, which needn't happen next time.
+        assert!(matches!(
+            OptionTable::from_bazel(&bazel, &[]).await,
+            Err(OptionTableError::CommandFailed(_, _))
+        ));
+        std::fs::write(&flags_file, flags_as_proto()).unwrap();
+        assert!(OptionTable::from_bazel(&bazel, &[]).await.is_ok());
+    }
+}
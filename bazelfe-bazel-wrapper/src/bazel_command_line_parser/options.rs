@@ -1030,6 +1030,10 @@ lazy_static! {
             String::from("bes_backend"),
             String::default(),
         ));
+        vec.push(BazelOption::OptionWithArg(
+            String::from("bes_header"),
+            String::default(),
+        ));
         vec.push(BazelOption::OptionWithArg(
             String::from("bes_keywords"),
             String::default(),
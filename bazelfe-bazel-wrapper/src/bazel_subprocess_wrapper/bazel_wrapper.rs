@@ -4,10 +4,12 @@ use crate::bazel_command_line_parser::ParsedCommandLine;
 
 use crate::bep::EventStreamListener;
 
+use std::path::Path;
 use std::sync::Arc;
 
 use super::ExecuteResult;
 
+use crate::bep::build_events::build_event_recording::{self, BuildEventRecorder};
 use crate::bep::build_events::build_event_server::BuildEventAction;
 use crate::bep::build_events::hydrated_stream::HydratedInfo;
 
@@ -16,8 +18,10 @@ use crate::bep::build_events::build_event_server::bazel_event;
 pub struct BazelWrapper<T> {
     sender_arc:
         Arc<Mutex<Option<async_channel::Sender<BuildEventAction<bazel_event::BazelBuildEvent>>>>>,
+    recorder: Arc<Mutex<Option<BuildEventRecorder>>>,
     pub aes: EventStreamListener<T>,
     bes_port: u16,
+    forwarding_upstream: bool,
 }
 
 impl<T> BazelWrapper<T>
@@ -28,13 +32,17 @@ where
         sender_arc: &Arc<
             Mutex<Option<async_channel::Sender<BuildEventAction<bazel_event::BazelBuildEvent>>>>,
         >,
+        recorder: &Arc<Mutex<Option<BuildEventRecorder>>>,
         aes: EventStreamListener<T>,
         bes_port: u16,
+        forwarding_upstream: bool,
     ) -> Self {
         Self {
             sender_arc: sender_arc.clone(),
+            recorder: recorder.clone(),
             aes,
             bes_port,
+            forwarding_upstream,
         }
     }
 
@@ -61,9 +69,13 @@ where
                 Ok(())
             });
 
-        let res =
-            super::execute_bazel_output_control(bazel_command_line, self.bes_port, pipe_output)
-                .await?;
+        let res = super::execute_bazel_output_control(
+            bazel_command_line,
+            self.bes_port,
+            pipe_output,
+            self.forwarding_upstream,
+        )
+        .await?;
         {
             let mut locked = self.sender_arc.lock().await;
             locked.take();
@@ -71,4 +83,45 @@ where
         recv_task.await.unwrap().unwrap();
         Ok(res)
     }
+
+    /// Records the build events from every later bazel invocation to `path`, see `replay_build_events`.
+    pub async fn record_build_events_to(&self, path: &Path) -> std::io::Result<()> {
+        let recorder = BuildEventRecorder::create(path)?;
+        *self.recorder.lock().await = Some(recorder);
+        Ok(())
+    }
+
+    /// Reads a recording made by `record_build_events_to`, one entry per bazel invocation it holds.
+    pub fn read_recorded_invocations(
+        path: &Path,
+    ) -> std::io::Result<Vec<Vec<BuildEventAction<bazel_event::BazelBuildEvent>>>> {
+        build_event_recording::read_recording(path)
+    }
+
+    /// The exit code bazel finished the recorded invocation with, `None` if the recording stops before then.
+    pub fn recorded_exit_code(
+        invocation: &[BuildEventAction<bazel_event::BazelBuildEvent>],
+    ) -> Option<i32> {
+        build_event_recording::recorded_exit_code(invocation)
+    }
+
+    /// Feeds one recorded invocation through the event handlers as though bazel had just sent it.
+    pub async fn replay_build_events(
+        &self,
+        invocation: Vec<BuildEventAction<bazel_event::BazelBuildEvent>>,
+        user_stream_handler: async_channel::Sender<T>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (tx, rx) = async_channel::unbounded();
+        for action in invocation {
+            tx.send(action).await?;
+        }
+        drop(tx);
+
+        let error_stream = HydratedInfo::build_transformer(rx);
+        let target_extracted_stream = self.aes.handle_stream(error_stream);
+        while let Ok(action) = target_extracted_stream.recv().await {
+            user_stream_handler.send(action).await?;
+        }
+        Ok(())
+    }
 }
@@ -4,6 +4,7 @@ use tonic::transport::Server;
 use bazelfe_protos::*;
 
 use crate::bep::build_events;
+use crate::bep::build_events::upstream_bes::UpstreamBes;
 use crate::bep::BazelEventHandler;
 use crate::bep::EventStreamListener;
 
@@ -14,6 +15,8 @@ use std::sync::Arc;
 pub struct BazelWrapperBuilder<T> {
     pub bes_server_bind_address: Option<std::net::SocketAddr>,
     pub processors: Vec<Arc<dyn BazelEventHandler<T>>>,
+    /// A build event service the user already had configured, every event is forwarded on to it.
+    pub upstream_bes: Option<UpstreamBes>,
 }
 impl<T> BazelWrapperBuilder<T>
 where
@@ -41,8 +44,11 @@ where
 
         debug!("Services listening on {}", addr);
 
-        let (bes, sender_arc, _) =
+        let (mut bes, sender_arc, _) =
             build_events::build_event_server::build_bazel_build_events_service();
+        bes.upstream = self.upstream_bes.map(Arc::new);
+        let forwarding_upstream = bes.upstream.is_some();
+        let recorder = Arc::clone(&bes.recorder);
 
         let bes_port: u16 = addr.port();
 
@@ -54,6 +60,12 @@ where
                 .unwrap();
         });
 
-        Ok(super::BazelWrapper::new(&sender_arc, aes, bes_port))
+        Ok(super::BazelWrapper::new(
+            &sender_arc,
+            &recorder,
+            aes,
+            bes_port,
+            forwarding_upstream,
+        ))
     }
 }
@@ -67,7 +67,12 @@ pub fn register_ctrlc_handler() {
     .expect("Error setting Ctrl-C handler");
 }
 
-fn add_custom_args(bazel_command_line: &mut ParsedCommandLine, srv_port: u16) {
+/// Options set by the user, on the command line or in their rc files, are left as they are.
+fn add_custom_args(
+    bazel_command_line: &mut ParsedCommandLine,
+    srv_port: u16,
+    forwarding_upstream: bool,
+) {
     bazel_command_line.add_action_option_if_unset(
         crate::bazel_command_line_parser::BazelOption::OptionWithArg(
             String::from("bes_timeout"),
@@ -82,12 +87,17 @@ fn add_custom_args(bazel_command_line: &mut ParsedCommandLine, srv_port: u16) {
         ),
     );
 
-    bazel_command_line.add_action_option_if_unset(
-        crate::bazel_command_line_parser::BazelOption::OptionWithArg(
-            String::from("experimental_build_event_upload_strategy"),
-            String::from("local"),
-        ),
+    // We read the outputs straight off local disk. When the events are forwarded on, the user's own
+    // upload strategy stays in charge of what the upstream service can see.
+    let upload_strategy = crate::bazel_command_line_parser::BazelOption::OptionWithArg(
+        String::from("experimental_build_event_upload_strategy"),
+        String::from("local"),
     );
+    if forwarding_upstream {
+        bazel_command_line.add_action_option_if_unset(upload_strategy);
+    } else {
+        bazel_command_line.set_action_option(upload_strategy);
+    }
 
     bazel_command_line.add_action_option_if_unset(
         crate::bazel_command_line_parser::BazelOption::BooleanOption(
@@ -103,7 +113,8 @@ fn add_custom_args(bazel_command_line: &mut ParsedCommandLine, srv_port: u16) {
         ),
     );
 
-    bazel_command_line.add_action_option_if_unset(
+    // Always ours, even over a backend from the rc files, bazelfe forwards on to that one itself.
+    bazel_command_line.set_action_option(
         crate::bazel_command_line_parser::BazelOption::OptionWithArg(
             String::from("bes_backend"),
             format!("grpc://127.0.0.1:{}", srv_port),
@@ -119,7 +130,7 @@ pub async fn execute_bazel(
     bazel_command_line: &ParsedCommandLine,
     bes_port: u16,
 ) -> Result<ExecuteResult, Box<dyn std::error::Error>> {
-    execute_bazel_output_control(bazel_command_line, bes_port, true).await
+    execute_bazel_output_control(bazel_command_line, bes_port, true, false).await
 }
 
 // use tokio when we aren't dealing with a tty.
@@ -240,10 +251,11 @@ pub async fn execute_bazel_output_control(
     bazel_command_line: &ParsedCommandLine,
     bes_port: u16,
     show_output: bool,
+    forwarding_upstream: bool,
 ) -> Result<ExecuteResult, Box<dyn std::error::Error>> {
     let mut bazel_command_line = bazel_command_line.clone();
 
-    add_custom_args(&mut bazel_command_line, bes_port);
+    add_custom_args(&mut bazel_command_line, bes_port, forwarding_upstream);
 
     debug!("{:#?}", bazel_command_line);
 
@@ -262,3 +274,44 @@ pub async fn execute_bazel_output_control(
         execute_tokio_subprocess(&bazel_command_line.bazel_binary, &args, show_output).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel_command_line_parser::{parse_bazel_command_line, BazelOption, BazelRc};
+
+    fn upload_strategies(forwarding_upstream: bool) -> Vec<BazelOption> {
+        let mut command_line = parse_bazel_command_line(
+            &["bazel", "build", "//..."]
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>(),
+            Default::default(),
+        )
+        .unwrap();
+        command_line.bazelrc = BazelRc::parse(
+            "build --experimental_build_event_upload_strategy=remote\n",
+            Path::new(".bazelrc"),
+            Path::new("/workspace"),
+        )
+        .unwrap();
+        add_custom_args(&mut command_line, 1985, forwarding_upstream);
+        command_line
+            .action_options
+            .into_iter()
+            .filter(|e| e.name() == "experimental_build_event_upload_strategy")
+            .collect()
+    }
+
+    #[test]
+    fn test_upload_strategy_only_kept_when_forwarding() {
+        assert!(upload_strategies(true).is_empty());
+        assert_eq!(
+            upload_strategies(false),
+            vec![BazelOption::OptionWithArg(
+                String::from("experimental_build_event_upload_strategy"),
+                String::from("local")
+            )]
+        );
+    }
+}
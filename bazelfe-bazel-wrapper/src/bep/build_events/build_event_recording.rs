@@ -0,0 +1,384 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use bazelfe_protos::*;
+use prost::Message;
+
+use super::build_event_server::{bazel_event, BuildEventAction};
+
+/// Writes the raw build events bazel sends us length-delimited, the same format as `--build_event_binary_file`.
+#[derive(Debug)]
+pub struct BuildEventRecorder {
+    writer: BufWriter<File>,
+}
+
+impl BuildEventRecorder {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Takes the encoded `build_event_stream.BuildEvent`, as carried in the build event service's `Any`.
+    ///
+    /// The output of failed actions and test logs are inlined, as the files bazel points at are gone by the time we replay.
+    pub fn record(&mut self, encoded_build_event: &[u8]) -> std::io::Result<()> {
+        let inlined = build_event_stream::BuildEvent::decode(encoded_build_event)
+            .ok()
+            .and_then(inline_referenced_output)
+            .map(|e| e.encode_to_vec());
+        let encoded_build_event = inlined.as_deref().unwrap_or(encoded_build_event);
+
+        let mut delimiter = Vec::default();
+        prost::encoding::encode_varint(encoded_build_event.len() as u64, &mut delimiter);
+        self.writer.write_all(&delimiter)?;
+        self.writer.write_all(encoded_build_event)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Bazel's actions write out their output in full, so past this we leave it as a path.
+const MAX_INLINED_OUTPUT_BYTES: u64 = 10 * 1024 * 1024;
+
+fn inline_file(file: &mut build_event_stream::file::File) -> bool {
+    let path = match file {
+        build_event_stream::file::File::Uri(uri) => match uri.strip_prefix("file://") {
+            Some(path) => Path::new(path).to_path_buf(),
+            None => return false,
+        },
+        build_event_stream::file::File::Contents(_) => return false,
+    };
+    match std::fs::metadata(&path) {
+        Ok(metadata) if metadata.len() < MAX_INLINED_OUTPUT_BYTES => (),
+        _ => return false,
+    }
+    match std::fs::read(&path) {
+        Ok(contents) => {
+            *file = build_event_stream::file::File::Contents(contents);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// The failed action with its `file://` stdout and stderr replaced by their contents, or the test result
+/// with its `test.log` replaced. `None` if nothing changed.
+fn inline_referenced_output(
+    mut build_event: build_event_stream::BuildEvent,
+) -> Option<build_event_stream::BuildEvent> {
+    let files: Vec<&mut build_event_stream::file::File> = match build_event.payload.as_mut() {
+        Some(build_event_stream::build_event::Payload::Action(action)) if !action.success => {
+            [action.stdout.as_mut(), action.stderr.as_mut()]
+                .into_iter()
+                .flatten()
+                .filter_map(|f| f.file.as_mut())
+                .collect()
+        }
+        // The runtime errors we repair are only ever read out of the test log.
+        Some(build_event_stream::build_event::Payload::TestResult(test_result)) => test_result
+            .test_action_output
+            .iter_mut()
+            .filter_map(|f| f.file.as_mut())
+            .filter(|f| {
+                matches!(f, build_event_stream::file::File::Uri(uri) if uri.ends_with("/test.log"))
+            })
+            .collect(),
+        _ => return None,
+    };
+    let mut inlined = false;
+    for file in files {
+        inlined |= inline_file(file);
+    }
+    if inlined {
+        Some(build_event)
+    } else {
+        None
+    }
+}
+
+/// The exit code bazel reported when the recorded invocation finished, if the recording got that far.
+pub fn recorded_exit_code(
+    invocation: &[BuildEventAction<bazel_event::BazelBuildEvent>],
+) -> Option<i32> {
+    invocation.iter().rev().find_map(|action| match action {
+        BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
+            event: bazel_event::Evt::BazelEvent(build_event),
+        }) => match build_event.payload.as_ref() {
+            Some(build_event_stream::build_event::Payload::Finished(finished)) => {
+                Some(match finished.exit_code.as_ref() {
+                    Some(exit_code) => exit_code.code,
+                    None if finished.overall_success => 0,
+                    None => 1,
+                })
+            }
+            _ => None,
+        },
+        _ => None,
+    })
+}
+
+fn is_build_started(build_event: &build_event_stream::BuildEvent) -> bool {
+    matches!(
+        build_event.id.as_ref().and_then(|e| e.id.as_ref()),
+        Some(build_event_stream::build_event_id::Id::Started(_))
+    )
+}
+
+/// Reads a recording back as the actions the build event service would have produced live.
+///
+/// A recording can hold several bazel invocations, each is returned on its own and ended with a `BuildCompleted`.
+pub fn read_recording(
+    path: &Path,
+) -> std::io::Result<Vec<Vec<BuildEventAction<bazel_event::BazelBuildEvent>>>> {
+    let data = std::fs::read(path)?;
+    let mut buf: &[u8] = &data;
+
+    let mut invocations: Vec<Vec<BuildEventAction<bazel_event::BazelBuildEvent>>> = Vec::default();
+    while !buf.is_empty() {
+        let build_event = build_event_stream::BuildEvent::decode_length_delimited(&mut buf)?;
+        if is_build_started(&build_event) || invocations.is_empty() {
+            invocations.push(Vec::default());
+        }
+        if let Some(invocation) = invocations.last_mut() {
+            invocation.push(BuildEventAction::BuildEvent(build_event.into()));
+        }
+    }
+    for invocation in invocations.iter_mut() {
+        invocation.push(BuildEventAction::BuildCompleted);
+    }
+    Ok(invocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn started() -> build_event_stream::BuildEvent {
+        build_event_stream::BuildEvent {
+            id: Some(build_event_stream::BuildEventId {
+                id: Some(build_event_stream::build_event_id::Id::Started(
+                    build_event_stream::build_event_id::BuildStartedId {},
+                )),
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn progress(stderr: &str) -> build_event_stream::BuildEvent {
+        build_event_stream::BuildEvent {
+            payload: Some(build_event_stream::build_event::Payload::Progress(
+                build_event_stream::Progress {
+                    stdout: String::default(),
+                    stderr: String::from(stderr),
+                },
+            )),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_trip_recording() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("build_events.bin");
+
+        let mut recorder = BuildEventRecorder::create(&path).unwrap();
+        for build_event in [started(), progress("first"), started(), progress("second")] {
+            recorder.record(&build_event.encode_to_vec()).unwrap();
+        }
+        recorder.flush().unwrap();
+
+        let invocations = read_recording(&path).unwrap();
+        assert_eq!(invocations.len(), 2);
+        let kinds: Vec<&str> = invocations
+            .iter()
+            .flatten()
+            .map(|e| match e {
+                BuildEventAction::BuildEvent(e) => match &e.event {
+                    bazel_event::Evt::Progress(p) => p.stderr.as_str(),
+                    _ => "event",
+                },
+                BuildEventAction::BuildCompleted => "completed",
+                BuildEventAction::LifecycleEvent(_) => "lifecycle",
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "event",
+                "first",
+                "completed",
+                "event",
+                "second",
+                "completed"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inlines_failed_action_output() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let stderr_path = temp_dir.path().join("stderr");
+        std::fs::write(&stderr_path, "error: symbol not found").unwrap();
+        let action = |success: bool| build_event_stream::BuildEvent {
+            id: Some(build_event_stream::BuildEventId {
+                id: Some(build_event_stream::build_event_id::Id::ActionCompleted(
+                    build_event_stream::build_event_id::ActionCompletedId {
+                        label: String::from("//src/main:main"),
+                        ..Default::default()
+                    },
+                )),
+            }),
+            payload: Some(build_event_stream::build_event::Payload::Action(
+                build_event_stream::ActionExecuted {
+                    success,
+                    stderr: Some(build_event_stream::File {
+                        file: Some(build_event_stream::file::File::Uri(format!(
+                            "file://{}",
+                            stderr_path.display()
+                        ))),
+                        ..Default::default()
+                    }),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+
+        let path = temp_dir.path().join("build_events.bin");
+        let mut recorder = BuildEventRecorder::create(&path).unwrap();
+        for build_event in [started(), action(false), action(true)] {
+            recorder.record(&build_event.encode_to_vec()).unwrap();
+        }
+        recorder.flush().unwrap();
+        std::fs::remove_file(&stderr_path).unwrap();
+
+        let stderrs: Vec<Option<build_event_stream::file::File>> = read_recording(&path)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .filter_map(|e| match e {
+                BuildEventAction::BuildEvent(e) => match e.event {
+                    bazel_event::Evt::ActionCompleted(a) => Some(a.stderr),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            stderrs,
+            vec![
+                Some(build_event_stream::file::File::Contents(
+                    b"error: symbol not found".to_vec()
+                )),
+                Some(build_event_stream::file::File::Uri(format!(
+                    "file://{}",
+                    stderr_path.display()
+                ))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_inlines_test_logs() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let test_log = temp_dir.path().join("test.log");
+        let test_xml = temp_dir.path().join("test.xml");
+        std::fs::write(&test_log, "java.lang.NoClassDefFoundError: com/example/Foo").unwrap();
+        std::fs::write(&test_xml, "<testsuites/>").unwrap();
+        let output = |path: &Path| build_event_stream::File {
+            file: Some(build_event_stream::file::File::Uri(format!(
+                "file://{}",
+                path.display()
+            ))),
+            ..Default::default()
+        };
+        let test_result = build_event_stream::BuildEvent {
+            id: Some(build_event_stream::BuildEventId {
+                id: Some(build_event_stream::build_event_id::Id::TestResult(
+                    build_event_stream::build_event_id::TestResultId {
+                        label: String::from("//src/test:test"),
+                        ..Default::default()
+                    },
+                )),
+            }),
+            payload: Some(build_event_stream::build_event::Payload::TestResult(
+                build_event_stream::TestResult {
+                    status: build_event_stream::TestStatus::Failed as i32,
+                    test_action_output: vec![output(&test_log), output(&test_xml)],
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+
+        let path = temp_dir.path().join("build_events.bin");
+        let mut recorder = BuildEventRecorder::create(&path).unwrap();
+        for build_event in [started(), test_result] {
+            recorder.record(&build_event.encode_to_vec()).unwrap();
+        }
+        recorder.flush().unwrap();
+        std::fs::remove_file(&test_log).unwrap();
+
+        let output_files: Vec<build_event_stream::file::File> = read_recording(&path)
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .filter_map(|e| match e {
+                BuildEventAction::BuildEvent(e) => match e.event {
+                    bazel_event::Evt::TestResult(t) => Some(t.output_files),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        assert_eq!(
+            output_files,
+            vec![
+                build_event_stream::file::File::Contents(
+                    b"java.lang.NoClassDefFoundError: com/example/Foo".to_vec()
+                ),
+                build_event_stream::file::File::Uri(format!("file://{}", test_xml.display())),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_recorded_exit_code() {
+        let finished = |code: i32| build_event_stream::BuildEvent {
+            id: Some(build_event_stream::BuildEventId {
+                id: Some(build_event_stream::build_event_id::Id::BuildFinished(
+                    build_event_stream::build_event_id::BuildFinishedId {},
+                )),
+            }),
+            payload: Some(build_event_stream::build_event::Payload::Finished(
+                build_event_stream::BuildFinished {
+                    exit_code: Some(build_event_stream::build_finished::ExitCode {
+                        name: String::from("BUILD_FAILURE"),
+                        code,
+                    }),
+                    ..Default::default()
+                },
+            )),
+            ..Default::default()
+        };
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("build_events.bin");
+        let mut recorder = BuildEventRecorder::create(&path).unwrap();
+        for build_event in [started(), finished(1), started(), finished(0), started()] {
+            recorder.record(&build_event.encode_to_vec()).unwrap();
+        }
+        recorder.flush().unwrap();
+
+        let exit_codes: Vec<Option<i32>> = read_recording(&path)
+            .unwrap()
+            .iter()
+            .map(|invocation| recorded_exit_code(invocation))
+            .collect();
+        assert_eq!(exit_codes, vec![Some(1), Some(0), None]);
+    }
+}
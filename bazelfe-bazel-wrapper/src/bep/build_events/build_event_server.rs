@@ -1,4 +1,4 @@
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 
 use tonic::{Request, Response, Status};
 
@@ -14,6 +14,9 @@ use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use super::build_event_recording::BuildEventRecorder;
+use super::upstream_bes::UpstreamBes;
+
 pub mod bazel_event {
     use super::*;
 
@@ -397,6 +400,9 @@ where
     pub write_channel: Arc<Mutex<Option<async_channel::Sender<BuildEventAction<T>>>>>,
     pub transform_fn:
         Arc<dyn Fn(&mut PublishBuildToolEventStreamRequest) -> Option<T> + Send + Sync>,
+    /// When set, bazel's events are forwarded on to this service too. Should it fail, we carry on without it.
+    pub upstream: Option<Arc<UpstreamBes>>,
+    pub recorder: Arc<Mutex<Option<BuildEventRecorder>>>,
 }
 
 fn transform_queue_error_to_status() -> Status {
@@ -413,6 +419,8 @@ pub fn build_bazel_build_events_service() -> (
     let server_instance = BuildEventService {
         write_channel: Arc::clone(&write_channel_arc),
         transform_fn: Arc::new(bazel_event::BazelBuildEvent::transform_from),
+        upstream: None,
+        recorder: Arc::new(Mutex::new(None)),
     };
     (server_instance, write_channel_arc, rx)
 }
@@ -442,10 +450,16 @@ where
         let cloned_v = sender_ref.clone();
         let second_writer = sender_ref;
         let transform_fn = Arc::clone(&self.transform_fn);
+        let mut upstream_forwarder = self.upstream.as_ref().map(|u| u.spawn_forwarder());
+        let recorder = Arc::clone(&self.recorder);
         let output = async_stream::try_stream! {
             while let Some(inbound_evt) = stream.next().await {
                 let mut inbound_evt = inbound_evt?;
 
+                if let Some(upstream_forwarder) = upstream_forwarder.as_mut() {
+                    upstream_forwarder.forward(inbound_evt.clone()).await;
+                }
+
                 match inbound_evt.ordered_build_event.as_ref() {
                     Some(build_event) => {
                         let sequence_number = build_event.sequence_number;
@@ -458,6 +472,19 @@ where
                     None => ()
                 };
 
+                if let Some(google::devtools::build::v1::build_event::Event::BazelEvent(e)) = inbound_evt
+                    .ordered_build_event
+                    .as_ref()
+                    .and_then(|e| e.event.as_ref())
+                    .and_then(|e| e.event.as_ref())
+                {
+                    if let Some(recorder) = recorder.lock().await.as_mut() {
+                        if let Err(e) = recorder.record(&e.value) {
+                            error!("Error recording build event {}", e);
+                        }
+                    }
+                }
+
                 let transformed_data = (transform_fn)(&mut inbound_evt);
 
                 if let Some(r) = transformed_data {
@@ -476,6 +503,16 @@ where
             }
 
 
+            if let Some(upstream_forwarder) = upstream_forwarder {
+                upstream_forwarder.finish().await;
+            }
+
+            if let Some(recorder) = recorder.lock().await.as_mut() {
+                if let Err(e) = recorder.flush() {
+                    error!("Error flushing build event recording {}", e);
+                }
+            }
+
             if let Some(tx) = second_writer {
                 tx.send(BuildEventAction::BuildCompleted).await.map_err(|_| transform_queue_error_to_status())?;
             }
@@ -498,8 +535,17 @@ where
             (*m).clone()
         };
 
+        let inner = request.into_inner();
+        if let Some(upstream) = self.upstream.as_ref() {
+            if let Err(e) = upstream.publish_lifecycle_event(inner.clone()).await {
+                warn!(
+                    "Unable to forward a lifecycle event to the upstream build event service: {}",
+                    e
+                );
+            }
+        }
+
         if let Some(tx) = cloned_v {
-            let inner = request.into_inner();
             debug!("life cycle event: {:?}", inner);
 
             tx.send(BuildEventAction::LifecycleEvent(inner))
@@ -575,6 +621,16 @@ mod tests {
     async fn make_test_server() -> (
         ServerStateHandler,
         publish_build_event_client::PublishBuildEventClient<tonic::transport::channel::Channel>,
+    ) {
+        make_test_server_with(None, None).await
+    }
+
+    async fn make_test_server_with(
+        upstream: Option<UpstreamBes>,
+        recorder: Option<BuildEventRecorder>,
+    ) -> (
+        ServerStateHandler,
+        publish_build_event_client::PublishBuildEventClient<tonic::transport::channel::Channel>,
     ) {
         let uds_temp_dir = tempdir().unwrap();
 
@@ -582,7 +638,9 @@ mod tests {
         let path_copy = path.clone();
         println!("Path: {:?}", path);
 
-        let (server_instance, _, rx) = build_bazel_build_events_service();
+        let (mut server_instance, _, rx) = build_bazel_build_events_service();
+        server_instance.upstream = upstream.map(Arc::new);
+        server_instance.recorder = Arc::new(Mutex::new(recorder));
 
         let (promise, completion_pinky) = PinkySwear::<()>::new();
         let server_state = ServerStateHandler {
@@ -700,4 +758,115 @@ mod tests {
         // assert_eq!(3, 5);
         // assert_eq!(event_stream, data_stream);
     }
+
+    #[tokio::test]
+    async fn test_forwards_to_upstream() {
+        let event_stream = load_proto("no_op_build.proto");
+
+        // Stand in for the user's build event service with another instance of ours.
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        let (upstream_instance, _, upstream_rx) = build_bazel_build_events_service();
+        tokio::spawn(
+            Server::builder()
+                .add_service(publish_build_event_server::PublishBuildEventServer::new(
+                    upstream_instance,
+                ))
+                .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener)),
+        );
+        let upstream = UpstreamBes::new(&format!("grpc://{}", upstream_addr), None).unwrap();
+
+        let (_state, mut client) = make_test_server_with(Some(upstream), None).await;
+
+        let acks: Vec<_> = client
+            .publish_build_tool_event_stream(Request::new(stream::iter(event_stream.clone())))
+            .await
+            .expect("service call should succeed")
+            .into_inner()
+            .collect()
+            .await;
+        assert_eq!(acks.len(), event_stream.len());
+        assert!(acks.iter().all(|e| e.is_ok()));
+
+        let mut upstream_events = 0;
+        while upstream_events < event_stream.len() {
+            match tokio::time::timeout(Duration::from_secs(5), upstream_rx.recv()).await {
+                Ok(Ok(BuildEventAction::BuildEvent(_))) => upstream_events += 1,
+                Ok(Ok(_)) => (),
+                _ => break,
+            }
+        }
+        assert_eq!(upstream_events, event_stream.len());
+    }
+
+    #[tokio::test]
+    async fn test_keeps_serving_when_upstream_unavailable() {
+        let event_stream = load_proto("no_op_build.proto");
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_addr = listener.local_addr().unwrap();
+        drop(listener);
+        let upstream = UpstreamBes::new(&format!("grpc://{}", closed_addr), None)
+            .unwrap()
+            .with_retries(2, Duration::from_millis(1));
+
+        let (_state, mut client) = make_test_server_with(Some(upstream), None).await;
+
+        let acks: Vec<_> = client
+            .publish_build_tool_event_stream(Request::new(stream::iter(event_stream.clone())))
+            .await
+            .expect("service call should succeed")
+            .into_inner()
+            .collect()
+            .await;
+        // A broken upstream doesn't stop bazel's own build from being served.
+        assert_eq!(acks.len(), event_stream.len());
+        assert!(acks.iter().all(|e| e.is_ok()));
+    }
+
+    #[tokio::test]
+    async fn test_records_build_events() {
+        let event_stream = load_proto("no_op_build.proto");
+        let temp_dir = tempdir().unwrap();
+        let recording_path = temp_dir.path().join("build_events.bin");
+        let recorder = BuildEventRecorder::create(&recording_path).unwrap();
+
+        let (_state, mut client) = make_test_server_with(None, Some(recorder)).await;
+
+        client
+            .publish_build_tool_event_stream(Request::new(stream::iter(event_stream.clone())))
+            .await
+            .expect("service call should succeed")
+            .into_inner()
+            .for_each(|_| future::ready(()))
+            .await;
+
+        let replayed =
+            super::super::build_event_recording::read_recording(&recording_path).unwrap();
+        let replayed_events = replayed
+            .iter()
+            .flatten()
+            .filter(|e| matches!(e, BuildEventAction::BuildEvent(_)))
+            .count();
+        // Only the bazel events are recorded, not the stream's own bookkeeping events.
+        let bazel_events = event_stream
+            .iter()
+            .filter(|e| {
+                matches!(
+                    e.ordered_build_event
+                        .as_ref()
+                        .and_then(|e| e.event.as_ref())
+                        .and_then(|e| e.event.as_ref()),
+                    Some(google::devtools::build::v1::build_event::Event::BazelEvent(
+                        _
+                    ))
+                )
+            })
+            .count();
+        assert_eq!(replayed_events, bazel_events);
+        assert_eq!(
+            replayed.last().and_then(|e| e.last()),
+            Some(&BuildEventAction::BuildCompleted)
+        );
+    }
 }
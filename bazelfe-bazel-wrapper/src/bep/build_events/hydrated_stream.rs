@@ -37,6 +37,18 @@ pub trait HasFiles {
             })
             .collect()
     }
+    /// Files sent with their contents rather than as a path, as a replayed recording does.
+    fn inline_contents(&self) -> Vec<String> {
+        self.uri_or_contents()
+            .into_iter()
+            .filter_map(|e| match e {
+                build_event_stream::file::File::Contents(c) => {
+                    Some(String::from_utf8_lossy(&c).to_string())
+                }
+                build_event_stream::file::File::Uri(_) => None,
+            })
+            .collect()
+    }
 }
 // This is keeping some state as we go through a stream to hydrate values with things like rule kinds
 // not on the indvidual events.
@@ -105,6 +117,8 @@ pub struct TargetCompleteInfo {
     pub success: bool,
     pub target_kind: Option<String>,
     pub output_files: Vec<build_event_stream::File>,
+    /// Files of the output groups resolved beyond the default one, keyed by group name.
+    pub other_output_groups: HashMap<String, Vec<build_event_stream::File>>,
 }
 
 // Broad strokes of the failure occured inside an action (most common)
@@ -137,6 +151,13 @@ fn recursive_lookup(
     true
 }
 
+/// The java rules only hand out their `.jdeps` files through this output group.
+pub const JDEPS_OUTPUT_GROUP: &str = "_hidden_top_level_INTERNAL_";
+
+/// A target complete event is held back until all of its groups we resolve are, so only the
+/// groups something uses are resolved.
+const RESOLVED_OUTPUT_GROUPS: &[&str] = &["default", JDEPS_OUTPUT_GROUP];
+
 fn tce_event(
     tce: bazel_event::TargetCompletedEvt,
     rule_kind_lookup: &HashMap<String, String>,
@@ -144,24 +165,35 @@ fn tce_event(
     to_revisit: &mut Vec<bazel_event::TargetCompletedEvt>,
 ) -> Option<TargetCompleteInfo> {
     let mut output_files = Vec::default();
-    let found_everything =
-        if let Some(output_grp) = &tce.output_groups.iter().find(|grp| grp.name == "default") {
-            recursive_lookup(
-                named_set_of_files_lookup,
-                &mut output_files,
-                output_grp
-                    .file_sets
-                    .iter()
-                    .map(|fs| fs.id.clone())
-                    .collect(),
-            )
+    let mut other_output_groups = HashMap::default();
+    let mut found_everything = true;
+    for output_grp in tce
+        .output_groups
+        .iter()
+        .filter(|grp| RESOLVED_OUTPUT_GROUPS.contains(&grp.name.as_str()))
+    {
+        let group_files = if output_grp.name == "default" {
+            &mut output_files
         } else {
-            true
+            other_output_groups
+                .entry(output_grp.name.clone())
+                .or_insert_with(Vec::default)
         };
+        found_everything &= recursive_lookup(
+            named_set_of_files_lookup,
+            group_files,
+            output_grp
+                .file_sets
+                .iter()
+                .map(|fs| fs.id.clone())
+                .collect(),
+        );
+    }
 
     if found_everything {
         let target_complete_info = TargetCompleteInfo {
             output_files,
+            other_output_groups,
             target_kind: rule_kind_lookup.get(&tce.label).cloned(),
             aspect: tce.aspect,
             label: tce.label,
@@ -551,4 +583,90 @@ mod tests {
             }))
         );
     }
+
+    #[tokio::test]
+    async fn test_target_complete_with_other_output_groups() {
+        use build_event_stream::build_event_id::NamedSetOfFilesId;
+        use build_event_stream::OutputGroup;
+
+        let (tx, rx) = async_channel::unbounded();
+        let mut child_rx = std::pin::pin!(HydratedInfo::build_transformer(rx));
+
+        let file_for = |name: &str| build_event_stream::File {
+            name: String::from(name),
+            path_prefix: Vec::default(),
+            digest: String::default(),
+            length: -1,
+            file: Some(build_event_stream::file::File::Uri(format!(
+                "file:///tmp/{}",
+                name
+            ))),
+        };
+        for (id, name) in [("1", "libfoo.jar"), ("2", "libfoo.jdeps")] {
+            tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
+                event: bazel_event::Evt::NamedSetOfFiles {
+                    id: String::from(id),
+                    named_set_of_files: build_event_stream::NamedSetOfFiles {
+                        files: vec![file_for(name)],
+                        file_sets: Vec::default(),
+                    },
+                },
+            }))
+            .await
+            .unwrap();
+        }
+
+        tx.send(BuildEventAction::BuildEvent(bazel_event::BazelBuildEvent {
+            event: bazel_event::Evt::TargetCompleted(bazel_event::TargetCompletedEvt {
+                label: String::from("//foo:foo"),
+                aspect: None,
+                success: true,
+                output_groups: vec![
+                    OutputGroup {
+                        name: String::from("default"),
+                        file_sets: vec![NamedSetOfFilesId {
+                            id: String::from("1"),
+                        }],
+                        incomplete: false,
+                    },
+                    OutputGroup {
+                        name: String::from("_hidden_top_level_INTERNAL_"),
+                        file_sets: vec![NamedSetOfFilesId {
+                            id: String::from("2"),
+                        }],
+                        incomplete: false,
+                    },
+                    // Never sent, but not needed either.
+                    OutputGroup {
+                        name: String::from("_source_jars"),
+                        file_sets: vec![NamedSetOfFilesId {
+                            id: String::from("3"),
+                        }],
+                        incomplete: false,
+                    },
+                ],
+            }),
+        }))
+        .await
+        .unwrap();
+
+        let received_res = child_rx.next().await.unwrap();
+
+        let mut other_output_groups = HashMap::default();
+        other_output_groups.insert(
+            String::from("_hidden_top_level_INTERNAL_"),
+            vec![file_for("libfoo.jdeps")],
+        );
+        assert_eq!(
+            received_res,
+            Some(HydratedInfo::TargetComplete(TargetCompleteInfo {
+                label: String::from("//foo:foo"),
+                aspect: None,
+                success: true,
+                target_kind: None,
+                output_files: vec![file_for("libfoo.jar")],
+                other_output_groups,
+            }))
+        );
+    }
 }
@@ -1,2 +1,4 @@
+pub mod build_event_recording;
 pub mod build_event_server;
 pub mod hydrated_stream;
+pub mod upstream_bes;
@@ -0,0 +1,479 @@
+use log::{debug, warn};
+
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tonic::metadata::{MetadataKey, MetadataValue};
+use tonic::transport::{Certificate, Channel, ClientTlsConfig, Endpoint};
+use tonic::{Request, Status, Streaming};
+
+use bazelfe_protos::*;
+
+use google::devtools::build::v1::publish_build_event_client::PublishBuildEventClient;
+use google::devtools::build::v1::{
+    PublishBuildToolEventStreamRequest, PublishBuildToolEventStreamResponse,
+    PublishLifecycleEventRequest,
+};
+
+use crate::bazel_command_line_parser::{BazelOption, ParsedCommandLine};
+
+#[derive(Error, Debug)]
+pub enum UpstreamBesError {
+    #[error("Unsupported bes_backend {0}, only grpc:// and grpcs:// backends can be forwarded to")]
+    UnsupportedBackend(String),
+    #[error("Invalid bes_header {0}, expected NAME=VALUE")]
+    InvalidHeader(String),
+    #[error("Unable to read the tls certificate at {0}: {1}")]
+    TlsCertificate(String, std::io::Error),
+    #[error(transparent)]
+    Transport(#[from] tonic::transport::Error),
+}
+
+/// The build event service bazel was originally pointed at, which bazelfe forwards every event on to.
+#[derive(Clone, Debug)]
+pub struct UpstreamBes {
+    endpoint: Endpoint,
+    /// Connected on first use, every request after shares the connection.
+    channel: OnceLock<Channel>,
+    headers: Vec<(String, String)>,
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+fn parse_header(header: &str) -> Result<(String, String), UpstreamBesError> {
+    match header.split_once('=') {
+        Some((name, value)) if !name.is_empty() => Ok((name.to_lowercase(), value.to_string())),
+        _ => Err(UpstreamBesError::InvalidHeader(header.to_string())),
+    }
+}
+
+fn sequence_number_of(request: &PublishBuildToolEventStreamRequest) -> i64 {
+    request
+        .ordered_build_event
+        .as_ref()
+        .map(|e| e.sequence_number)
+        .unwrap_or_default()
+}
+
+impl UpstreamBes {
+    pub fn new(backend: &str, tls_certificate: Option<&Path>) -> Result<Self, UpstreamBesError> {
+        // Like bazel, a backend without a scheme is grpcs.
+        let (uri, use_tls) = if let Some(rest) = backend.strip_prefix("grpc://") {
+            (format!("http://{}", rest), false)
+        } else if let Some(rest) = backend.strip_prefix("grpcs://") {
+            (format!("https://{}", rest), true)
+        } else if backend.contains("://") || backend.starts_with("unix:") {
+            return Err(UpstreamBesError::UnsupportedBackend(backend.to_string()));
+        } else {
+            (format!("https://{}", backend), true)
+        };
+
+        let mut endpoint = Endpoint::from_shared(uri)?;
+        if use_tls {
+            // The system's trust roots are always included, a --tls_certificate is trusted as well.
+            let mut tls_config = ClientTlsConfig::new();
+            if let Some(ca_path) = tls_certificate {
+                let pem = std::fs::read(ca_path).map_err(|e| {
+                    UpstreamBesError::TlsCertificate(ca_path.to_string_lossy().to_string(), e)
+                })?;
+                tls_config = tls_config.ca_certificate(Certificate::from_pem(pem));
+            }
+            endpoint = endpoint.tls_config(tls_config)?;
+        }
+
+        Ok(Self {
+            endpoint,
+            channel: OnceLock::new(),
+            headers: Vec::default(),
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+        })
+    }
+
+    /// Takes the user's `--bes_backend` and `--bes_header` off the command line so bazel can be pointed at bazelfe instead.
+    ///
+    /// These are read from the effective options, so a backend configured in a `.bazelrc` is forwarded to as well.
+    /// `--remote_header` also applies to the build event service in bazel, so it is forwarded too but left in place.
+    pub fn take_from_command_line(
+        command_line: &mut ParsedCommandLine,
+    ) -> Result<Option<Self>, UpstreamBesError> {
+        let effective_options = command_line.effective_action_options().unwrap_or_else(|e| {
+            warn!(
+                "Unable to apply the bazelrc, only using the command line: {}",
+                e
+            );
+            command_line.action_options.clone()
+        });
+        command_line.remove_action_option("bes_backend");
+        command_line.remove_action_option("bes_header");
+
+        let values_of = |name: &str| -> Vec<&String> {
+            effective_options
+                .iter()
+                .filter_map(|e| match e {
+                    BazelOption::OptionWithArg(n, v) if n == name => Some(v),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        let backend = match values_of("bes_backend").pop() {
+            Some(backend) if !backend.is_empty() => backend.clone(),
+            _ => return Ok(None),
+        };
+
+        let mut headers = Vec::default();
+        for header in values_of("bes_header")
+            .into_iter()
+            .chain(values_of("remote_header"))
+        {
+            headers.push(parse_header(header)?);
+        }
+
+        let tls_certificate = values_of("tls_certificate")
+            .pop()
+            .map(|e| Path::new(e).to_path_buf());
+
+        Ok(Some(
+            Self::new(&backend, tls_certificate.as_deref())?.with_headers(headers),
+        ))
+    }
+
+    pub fn with_headers(mut self, headers: Vec<(String, String)>) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    pub fn with_retries(mut self, max_attempts: u32, initial_backoff: Duration) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+    }
+
+    fn client(&self) -> PublishBuildEventClient<Channel> {
+        let channel = self.channel.get_or_init(|| self.endpoint.connect_lazy());
+        PublishBuildEventClient::new(channel.clone())
+    }
+
+    #[allow(clippy::result_large_err)]
+    fn request<M>(&self, message: M) -> Result<Request<M>, Status> {
+        let mut request = Request::new(message);
+        for (name, value) in self.headers.iter() {
+            let key = MetadataKey::from_bytes(name.as_bytes())
+                .map_err(|_| Status::invalid_argument(format!("Invalid header name {}", name)))?;
+            let value: MetadataValue<_> = value.parse().map_err(|_| {
+                Status::invalid_argument(format!("Invalid header value for {}", name))
+            })?;
+            request.metadata_mut().append(key, value);
+        }
+        Ok(request)
+    }
+
+    pub async fn publish_lifecycle_event(
+        &self,
+        event: PublishLifecycleEventRequest,
+    ) -> Result<(), Status> {
+        let mut attempt = 1;
+        loop {
+            match self
+                .client()
+                .publish_lifecycle_event(self.request(event.clone())?)
+                .await
+            {
+                Ok(_) => return Ok(()),
+                Err(e) if attempt < self.max_attempts => {
+                    warn!(
+                        "Upstream build event service rejected a lifecycle event, will retry: {}",
+                        e
+                    );
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn open_stream(&self) -> UpstreamStream {
+        UpstreamStream {
+            upstream: self.clone(),
+            connection: None,
+            unacked: VecDeque::default(),
+        }
+    }
+
+    /// Opens a stream which is forwarded to from a background task, so bazel's stream never waits on the upstream.
+    pub fn spawn_forwarder(&self) -> UpstreamForwarder {
+        let (tx, mut rx) = mpsc::channel(FORWARD_QUEUE_SIZE);
+        let mut stream = self.open_stream();
+        let task = tokio::spawn(async move {
+            while let Some(request) = rx.recv().await {
+                if let Err(e) = stream.forward(request).await {
+                    warn!(
+                        "Giving up on forwarding this build to the upstream build event service: {}",
+                        e
+                    );
+                    return;
+                }
+            }
+            stream.finish().await;
+        });
+        UpstreamForwarder { tx: Some(tx), task }
+    }
+}
+
+/// How many events can be waiting to be forwarded before bazel's stream waits on the upstream.
+const FORWARD_QUEUE_SIZE: usize = 1000;
+
+/// How many events are sent upstream before we wait on their acknowledgements.
+const MAX_UNACKED_EVENTS: usize = 100;
+
+/// The sending side of `UpstreamBes::spawn_forwarder`.
+pub struct UpstreamForwarder {
+    tx: Option<mpsc::Sender<PublishBuildToolEventStreamRequest>>,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl UpstreamForwarder {
+    /// Queues the event to be forwarded, once the upstream has failed this is a no-op.
+    pub async fn forward(&mut self, request: PublishBuildToolEventStreamRequest) {
+        if let Some(tx) = self.tx.as_ref() {
+            if tx.send(request).await.is_err() {
+                self.tx = None;
+            }
+        }
+    }
+
+    /// Waits for everything queued to be acknowledged by the upstream, or for the upstream to fail.
+    pub async fn finish(mut self) {
+        self.tx.take();
+        if let Err(e) = self.task.await {
+            warn!(
+                "Forwarding to the upstream build event service panicked: {}",
+                e
+            );
+        }
+    }
+}
+
+type UpstreamConnection = (
+    mpsc::UnboundedSender<PublishBuildToolEventStreamRequest>,
+    Streaming<PublishBuildToolEventStreamResponse>,
+);
+
+/// One build tool event stream forwarded upstream, reconnecting and replaying unacknowledged events on failure.
+pub struct UpstreamStream {
+    upstream: UpstreamBes,
+    connection: Option<UpstreamConnection>,
+    unacked: VecDeque<PublishBuildToolEventStreamRequest>,
+}
+
+impl UpstreamStream {
+    async fn connect(&mut self) -> Result<(), Status> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        // The upstream may wait for the first event before responding, so queue everything up front.
+        for request in self.unacked.iter() {
+            let _ = tx.send(request.clone());
+        }
+        let acks = self
+            .upstream
+            .client()
+            .publish_build_tool_event_stream(
+                self.upstream.request(UnboundedReceiverStream::new(rx))?,
+            )
+            .await?
+            .into_inner();
+        self.connection = Some((tx, acks));
+        Ok(())
+    }
+
+    /// Sends the event, if any, then waits until no more than `max_unacked` events are unacknowledged.
+    async fn try_forward(
+        &mut self,
+        request: Option<&PublishBuildToolEventStreamRequest>,
+        max_unacked: usize,
+    ) -> Result<(), Status> {
+        match (self.connection.as_ref(), request) {
+            (Some((tx, _)), Some(request)) => tx.send(request.clone()).map_err(|_| {
+                Status::unavailable("Upstream build event stream closed unexpectedly")
+            })?,
+            (Some(_), None) => (),
+            (None, _) => self.connect().await?,
+        }
+
+        let acks = &mut self.connection.as_mut().unwrap().1;
+        while self.unacked.len() > max_unacked {
+            match acks.message().await? {
+                Some(ack) => self
+                    .unacked
+                    .retain(|e| sequence_number_of(e) > ack.sequence_number),
+                None => {
+                    return Err(Status::unavailable(
+                        "Upstream closed the build event stream before acknowledging every event",
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn forward_with_retries(
+        &mut self,
+        request: Option<&PublishBuildToolEventStreamRequest>,
+        max_unacked: usize,
+    ) -> Result<(), Status> {
+        let mut attempt = 1;
+        loop {
+            match self.try_forward(request, max_unacked).await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt < self.upstream.max_attempts => {
+                    warn!(
+                        "Forwarding to the upstream build event service failed, will retry: {}",
+                        e
+                    );
+                    // Reconnecting replays everything still unacknowledged, including this event.
+                    self.connection = None;
+                    tokio::time::sleep(self.upstream.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Sends this event upstream, only waiting on acknowledgements once too many events are outstanding.
+    pub async fn forward(
+        &mut self,
+        request: PublishBuildToolEventStreamRequest,
+    ) -> Result<(), Status> {
+        self.unacked.push_back(request.clone());
+        self.forward_with_retries(Some(&request), MAX_UNACKED_EVENTS)
+            .await
+    }
+
+    /// Waits for every event to be acknowledged, then closes our side of the stream and waits for the upstream to finish.
+    pub async fn finish(mut self) {
+        if let Err(e) = self.forward_with_retries(None, 0).await {
+            warn!(
+                "Upstream build event service never acknowledged every event: {}",
+                e
+            );
+            return;
+        }
+        if let Some((tx, mut acks)) = self.connection.take() {
+            drop(tx);
+            loop {
+                match acks.message().await {
+                    Ok(Some(_)) => (),
+                    Ok(None) => break,
+                    Err(e) => {
+                        debug!("Upstream build event stream ended with {}", e);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel_command_line_parser::{parse_bazel_command_line, BazelRc};
+
+    #[test]
+    fn test_take_from_command_line() {
+        let mut command_line = parse_bazel_command_line(
+            &vec![
+                "bazel",
+                "build",
+                "--bes_backend=grpc://localhost:1985",
+                "--bes_header=X-Api-Key=abc123",
+                "--remote_header=Authorization=Bearer xyz",
+                "--keep_going",
+                "//...",
+            ]
+            .into_iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<String>>(),
+            Default::default(),
+        )
+        .unwrap();
+
+        let upstream = UpstreamBes::take_from_command_line(&mut command_line)
+            .unwrap()
+            .expect("Should find the bes backend");
+
+        assert_eq!(
+            upstream.headers,
+            vec![
+                (String::from("x-api-key"), String::from("abc123")),
+                (String::from("authorization"), String::from("Bearer xyz")),
+            ]
+        );
+        assert_eq!(
+            upstream.endpoint.uri().to_string(),
+            "http://localhost:1985/"
+        );
+        assert!(!command_line.is_action_option_set("bes_backend"));
+        assert!(!command_line.is_action_option_set("bes_header"));
+        assert!(command_line.is_action_option_set("remote_header"));
+        assert!(command_line.is_action_option_set("keep_going"));
+
+        assert!(UpstreamBes::take_from_command_line(&mut command_line)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_take_from_bazelrc() {
+        let mut command_line = parse_bazel_command_line(
+            &vec!["bazel", "build", "--config=ci", "//..."]
+                .into_iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<String>>(),
+            Default::default(),
+        )
+        .unwrap();
+        command_line.bazelrc = BazelRc::parse(
+            "build:ci --bes_backend=grpc://bes.example.com:1985 --bes_header=X-Api-Key=abc123\n",
+            Path::new(".bazelrc"),
+            Path::new("/workspace"),
+        )
+        .unwrap();
+
+        let upstream = UpstreamBes::take_from_command_line(&mut command_line)
+            .unwrap()
+            .expect("Should find the bes backend from the bazelrc");
+        assert_eq!(
+            upstream.endpoint.uri().to_string(),
+            "http://bes.example.com:1985/"
+        );
+        assert_eq!(
+            upstream.headers,
+            vec![(String::from("x-api-key"), String::from("abc123"))]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_backend() {
+        assert!(matches!(
+            UpstreamBes::new("unix:/tmp/bes.sock", None),
+            Err(UpstreamBesError::UnsupportedBackend(_))
+        ));
+        assert!(matches!(
+            parse_header("novalue"),
+            Err(UpstreamBesError::InvalidHeader(_))
+        ));
+    }
+}
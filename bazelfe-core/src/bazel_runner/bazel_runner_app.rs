@@ -1,8 +1,9 @@
 use clap::Parser;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use std::ffi::OsString;
 
+use bazelfe_bazel_wrapper::bazel_command_line_parser::{CommandLineParsingError, OptionTable};
 use bazelfe_core::bazel_runner;
 use bazelfe_core::bazel_runner::parse_commandline_with_custom_command_line_options;
 use bazelfe_core::config::load_config_file;
@@ -28,6 +29,28 @@ struct Opt {
     #[clap(long)]
     config: Option<String>,
 
+    /// Only suggest the BUILD file changes that would repair the build, without applying them.
+    /// Bazel is only run once, so only the errors of that first build get suggestions.
+    #[clap(long, env = "BAZELFE_DRY_RUN")]
+    dry_run: bool,
+
+    /// Write the suggested changes out as a buildozer command file, implies --dry-run.
+    #[clap(long)]
+    dry_run_buildozer_command_file: Option<PathBuf>,
+
+    /// Write a JSON report of the run, its attempts and the actions taken, to this file.
+    #[clap(long)]
+    report_json: Option<PathBuf>,
+
+    /// Record the build events bazel sends us to this file.
+    #[clap(long)]
+    record_build_events: Option<PathBuf>,
+
+    /// Feed a recorded build event file through the failure repair and indexing handlers instead of running bazel.
+    /// Implies --dry-run, and the index and repair history are left as they were.
+    #[clap(long)]
+    replay_build_events: Option<PathBuf>,
+
     #[clap(long)]
     validate_index_file: Option<PathBuf>,
 }
@@ -79,22 +102,66 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .into()),
         };
     }
-    // TODO IN HERE,
-    // fixed actions we intercept
-    // bail of a bes_backend is already configured.
-    let parsed_command_line = match parse_commandline_with_custom_command_line_options(&opt.passthrough_args) {
-        Ok(parsed_command_line) => {
-            if parsed_command_line.is_action_option_set("bes_backend") {
-                // Likely tooling is setting this, quietly exec bazel.
-                // since we can't invoke our usual behaviors if this is the case.
-                // Need to figure out some way to signal this occured probably to the dev productivity team somewhere.
-                return {
-                    passthrough_to_bazel(opt);
-                    Ok(())
-                };
-            }
-            parsed_command_line
+    let mut builder = pretty_env_logger::formatted_timed_builder();
+    builder.format_timestamp_nanos();
+    builder.target(pretty_env_logger::env_logger::Target::Stderr);
+    if let Ok(s) = ::std::env::var("RUST_LOG") {
+        let f = if s.contains("tarpc") {
+            s
+        } else {
+            format!("tarpc::client=error,{}", s)
+        };
+        builder.parse_filters(&f);
+    } else {
+        builder.parse_filters("warn,tarpc::client=error,bazelfe_core=info,bazel_runner=info");
+    }
+    builder.init();
+
+    let bazel_binary = opt.passthrough_args.first().map(Path::new);
+    let startup_args: Vec<String> = opt
+        .passthrough_args
+        .iter()
+        .skip(1)
+        .take_while(|e| e.starts_with("--"))
+        .cloned()
+        .collect();
+
+    let mut parsed_command_line =
+        parse_commandline_with_custom_command_line_options(&opt.passthrough_args);
+    // Only ask bazel which options it supports when the compiled-in table doesn't cover the
+    // command line, that costs a bazel invocation we'd rather not pay on every run.
+    let mut option_table_loaded = false;
+    if matches!(
+        parsed_command_line,
+        Err(CommandLineParsingError::MissingArgToOption(_))
+            | Err(CommandLineParsingError::UnknownArgument(_))
+    ) {
+        if let Some(bazel_binary) = bazel_binary {
+            OptionTable::load(bazel_binary, &startup_args)
+                .await
+                .install();
+            option_table_loaded = true;
+            parsed_command_line =
+                parse_commandline_with_custom_command_line_options(&opt.passthrough_args);
+        }
+    }
+
+    // The same goes for the rc files, their unknown flags would otherwise be skipped.
+    if let (Ok(parsed_command_line), Some(bazel_binary), false) =
+        (&parsed_command_line, bazel_binary, option_table_loaded)
+    {
+        let mut with_bazelrc = parsed_command_line.clone();
+        if with_bazelrc.load_bazelrc(&std::env::current_dir()?).is_ok()
+            && !with_bazelrc.unknown_rc_options().is_empty()
+        {
+            OptionTable::load(bazel_binary, &startup_args)
+                .await
+                .install();
         }
+    }
+
+    let parsed_command_line = match parsed_command_line {
+        Ok(parsed_command_line) => parsed_command_line,
         Err(cmd_line_parsing_failed) => {
             match cmd_line_parsing_failed {
                 bazelfe_bazel_wrapper::bazel_command_line_parser::CommandLineParsingError::MissingBazelPath => {
@@ -120,21 +187,6 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let mut builder = pretty_env_logger::formatted_timed_builder();
-    builder.format_timestamp_nanos();
-    builder.target(pretty_env_logger::env_logger::Target::Stderr);
-    if let Ok(s) = ::std::env::var("RUST_LOG") {
-        let f = if s.contains("tarpc") {
-            s
-        } else {
-            format!("tarpc::client=error,{}", s)
-        };
-        builder.parse_filters(&f);
-    } else {
-        builder.parse_filters("warn,tarpc::client=error,bazelfe_core=info,bazel_runner=info");
-    }
-    builder.init();
-
     let mut config = load_config_file(&opt.config.as_ref()).await?;
 
     config.buildozer_path = Some(opt.buildozer_path);
@@ -152,9 +204,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         config.disable_action_stories_on_success = opt.disable_action_stories_on_success;
     }
 
+    if opt.dry_run {
+        config.dry_run_config.enabled = true;
+    }
+
+    if opt.dry_run_buildozer_command_file.is_some() {
+        config.dry_run_config.enabled = true;
+        config.dry_run_config.buildozer_command_file = opt.dry_run_buildozer_command_file;
+    }
+
+    if opt.report_json.is_some() {
+        config.report_json = opt.report_json;
+    }
+
+    if opt.record_build_events.is_some() {
+        config.record_build_events_to = opt.record_build_events;
+    }
+
     let bazel_runner = bazel_runner::BazelRunner {
         config,
         bazel_command_line: parsed_command_line,
+        replay_build_events: opt.replay_build_events,
     };
 
     match bazel_runner.run().await {
@@ -2,7 +2,9 @@ use bazelfe_bazel_wrapper::bazel_command_line_parser::{self, ParsedCommandLine};
 use bazelfe_bazel_wrapper::bazel_subprocess_wrapper::{
     BazelWrapperBuilder, BazelWrapperError, UserReportError,
 };
+use bazelfe_bazel_wrapper::bep::build_events::upstream_bes::UpstreamBes;
 use std::env;
+use std::path::{Path, PathBuf};
 use tokio::sync::Mutex;
 
 use crate::bazel_query::{BazelQueryEngine, RealBazelQueryEngine};
@@ -12,7 +14,10 @@ use crate::bazel_runner::configured_bazel_runner::ConfiguredBazelRunner;
 use crate::buildozer_driver;
 use crate::config::Config;
 use crate::hydrated_stream_processors::index_new_results::IndexNewResults;
-use crate::hydrated_stream_processors::process_bazel_failures::ProcessBazelFailures;
+use crate::hydrated_stream_processors::process_bazel_failures::{
+    ProcessBazelFailures, RepairHistory,
+};
+use crate::jvm_indexer::bzlmod::{self, LazyRepoMapping};
 
 use std::sync::Arc;
 
@@ -58,6 +63,8 @@ impl From<BazelWrapperError> for BazelRunnerError {
 pub struct BazelRunner {
     pub config: Config,
     pub bazel_command_line: ParsedCommandLine,
+    /// Replay a recorded build event stream through the event handlers rather than running bazel.
+    pub replay_build_events: Option<PathBuf>,
 }
 
 impl BazelRunner {
@@ -75,7 +82,36 @@ impl BazelRunner {
             }
         }
 
+        // Bazel reads these itself, we only need them to see the options it will really run with.
+        let working_directory =
+            env::current_dir().map_err(|e| BazelRunnerError::Unknown(Box::new(e)))?;
+        if let Err(e) = self.bazel_command_line.load_bazelrc(&working_directory) {
+            warn!(
+                "Unable to read the bazelrc files, only the command line will be checked for a build event service to forward to: {}",
+                e
+            );
+        }
+
+        // bazelfe takes the place of any build event service already configured, and forwards on to it.
+        let upstream_bes = UpstreamBes::take_from_command_line(&mut self.bazel_command_line)
+            .map_err(|e| {
+                BazelRunnerError::UserErrorReport(UserReportError(format!(
+                    "Unable to forward to the configured build event service: {}",
+                    e
+                )))
+            })?;
+
+        // A replay only rehearses an old build: BUILD files are left alone, and neither the
+        // index nor the repair history learns from it.
+        let replaying = self.replay_build_events.is_some();
+        if replaying {
+            self.config.dry_run_config.enabled = true;
+        }
+
         let config = Arc::new(self.config);
+        let workspace_root =
+            bazel_command_line_parser::bazelrc::find_workspace_root(&working_directory)
+                .unwrap_or_else(|| working_directory.clone());
 
         debug!("Loading index..");
         let index_table = match &config.index_input_location {
@@ -92,81 +128,95 @@ impl BazelRunner {
 
         debug!("Index loading complete..");
 
-        let bazel_query: Arc<Mutex<Box<dyn crate::jvm_indexer::bazel_query::BazelQuery>>> =
-            Arc::new(Mutex::new(Box::new(
-                crate::jvm_indexer::bazel_query::from_binary_path(
-                    &self.bazel_command_line.bazel_binary.clone(),
-                ),
-            )));
+        let repair_history_path = config
+            .index_input_location
+            .as_ref()
+            .map(|p| RepairHistory::path_for_index(p));
+        let repair_history = repair_history_path
+            .as_ref()
+            .map(|p| RepairHistory::read(p))
+            .unwrap_or_default();
+        repair_history.apply_to_index(&index_table).await;
 
-        let bazel_query_engine: Arc<dyn BazelQueryEngine> =
-            Arc::new(RealBazelQueryEngine::new(bazel_query));
-        let process_build_failures = Arc::new(ProcessBazelFailures::new(
-            index_table.clone(),
-            buildozer_driver::from_binary_path(
-                config
-                    .buildozer_path
-                    .as_ref()
-                    .expect("Unable to find a config for buildozer, error."),
-            ),
-            crate::hydrated_stream_processors::process_bazel_failures::CommandLineRunnerImpl(),
-            Arc::clone(&config),
-            Arc::clone(&bazel_query_engine),
-        )?);
-
-        let addr: Option<std::net::SocketAddr> = config
-            .bes_server_bind_address
-            .map(|s| s.to_owned())
-            .or_else(|| {
-                env::var("BIND_ADDRESS")
-                    .ok()
-                    .map(|e| e.parse().expect("can't parse BIND_ADDRESS variable"))
-            });
-
-        let bazel_wrapper_builder = BazelWrapperBuilder {
-            bes_server_bind_address: addr,
-            processors: vec![
-                process_build_failures.clone(),
-                Arc::new(IndexNewResults::new(
-                    index_table.clone(),
-                    &config.indexer_config,
-                )),
-            ],
+        let bazel_query = crate::jvm_indexer::bazel_query::from_binary_path(
+            &self.bazel_command_line.bazel_binary.clone(),
+        );
+
+        // Labels in the build event stream are canonical, index them under the names users write.
+        // Only fetched once a label from an external repo turns up.
+        let repo_mapping = if bzlmod::is_bzlmod_workspace(&working_directory) {
+            LazyRepoMapping::new(
+                Box::new(bazel_query.clone()),
+                self.bazel_command_line
+                    .startup_options
+                    .iter()
+                    .flat_map(|e| e.to_arg())
+                    .collect(),
+                working_directory.clone(),
+            )
+        } else {
+            LazyRepoMapping::default()
         };
+        let repo_mapping = Arc::new(repo_mapping);
 
-        let bazel_wrapper = bazel_wrapper_builder.build().await?;
+        let bazel_query: Arc<Mutex<Box<dyn crate::jvm_indexer::bazel_query::BazelQuery>>> =
+            Arc::new(Mutex::new(Box::new(bazel_query)));
 
-        #[cfg(feature = "bazelfe-daemon")]
-        let runner_daemon = if let Some(bazel_command_line_parser::Action::BuiltIn(
-            bazel_command_line_parser::BuiltInAction::Shutdown,
-        )) = self.bazel_command_line.action
-        {
-            crate::bazel_runner_daemon::daemon_manager::try_kill_server_from_cfg(
-                &config.daemon_config,
+        let bazel_query_engine: Arc<dyn BazelQueryEngine> =
+            Arc::new(RealBazelQueryEngine::new(bazel_query));
+        let buildozer = buildozer_driver::from_binary_path(
+            config
+                .buildozer_path
+                .as_ref()
+                .expect("Unable to find a config for buildozer, error."),
+        );
+        let final_exit_code_res = if config.dry_run_config.enabled {
+            // Suggestions don't count as actions taken: with the BUILD files untouched another
+            // attempt would fail the same way, so a dry run stops after the first build.
+            let recording_buildozer = buildozer_driver::RecordingBuildozer::new(buildozer);
+            let (res, _) = run_with_buildozer(
+                &config,
+                &self.bazel_command_line,
+                &workspace_root,
+                &index_table,
+                bazel_query_engine,
+                repo_mapping,
+                repair_history,
+                recording_buildozer.clone(),
+                upstream_bes,
+                self.replay_build_events.as_deref(),
             )
-            .await;
-            None
+            .await?;
+            report_dry_run(&config, &workspace_root, &recording_buildozer).await?;
+            res
         } else {
-            crate::bazel_runner_daemon::daemon_manager::connect_to_server(
-                &config.daemon_config,
-                &self.bazel_command_line.bazel_binary.clone(),
+            let (res, repair_history) = run_with_buildozer(
+                &config,
+                &self.bazel_command_line,
+                &workspace_root,
+                &index_table,
+                bazel_query_engine,
+                repo_mapping,
+                repair_history,
+                buildozer,
+                upstream_bes,
+                self.replay_build_events.as_deref(),
             )
-            .await?
+            .await?;
+            // Nothing was applied in dry run mode, so only real runs have anything to remember.
+            if let Some(repair_history_path) = &repair_history_path {
+                debug!("Writing out repair history...");
+                if let Err(e) = repair_history.write(repair_history_path) {
+                    warn!(
+                        "Unable to write repair history to {:?}: {}",
+                        repair_history_path, e
+                    );
+                }
+            }
+            res
         };
 
-        let configured_bazel_runner = ConfiguredBazelRunner::new(
-            Arc::clone(&config),
-            bazel_wrapper,
-            #[cfg(feature = "bazelfe-daemon")]
-            runner_daemon,
-            index_table.clone(),
-            self.bazel_command_line.clone(),
-            process_build_failures,
-        );
-
-        let final_exit_code_res = configured_bazel_runner.run().await;
-
-        if index_table.is_mutated() {
+        if index_table.is_mutated() && !replaying {
             debug!("Writing out index file...");
 
             if let Some(target_path) = &config.index_input_location {
@@ -187,3 +237,119 @@ impl BazelRunner {
         Ok(final_exit_code_res?)
     }
 }
+
+#[allow(clippy::too_many_arguments)]
+async fn run_with_buildozer<T: buildozer_driver::Buildozer>(
+    config: &Arc<Config>,
+    bazel_command_line: &ParsedCommandLine,
+    workspace_root: &Path,
+    index_table: &crate::index_table::IndexTable,
+    bazel_query_engine: Arc<dyn BazelQueryEngine>,
+    repo_mapping: Arc<LazyRepoMapping>,
+    repair_history: RepairHistory,
+    buildozer: T,
+    upstream_bes: Option<UpstreamBes>,
+    replay_build_events: Option<&Path>,
+) -> Result<(Result<i32, BazelWrapperError>, RepairHistory), BazelRunnerError> {
+    let process_build_failures = Arc::new(
+        ProcessBazelFailures::new(
+            index_table.clone(),
+            buildozer,
+            crate::hydrated_stream_processors::process_bazel_failures::CommandLineRunnerImpl(),
+            Arc::clone(config),
+            bazel_query_engine,
+            workspace_root.to_path_buf(),
+        )?
+        .with_repair_history(repair_history),
+    );
+
+    let addr: Option<std::net::SocketAddr> = config
+        .bes_server_bind_address
+        .map(|s| s.to_owned())
+        .or_else(|| {
+            env::var("BIND_ADDRESS")
+                .ok()
+                .map(|e| e.parse().expect("can't parse BIND_ADDRESS variable"))
+        });
+
+    let bazel_wrapper_builder = BazelWrapperBuilder {
+        bes_server_bind_address: addr,
+        processors: vec![
+            process_build_failures.clone(),
+            Arc::new(
+                IndexNewResults::new(index_table.clone(), &config.indexer_config)
+                    .with_repo_mapping(repo_mapping),
+            ),
+        ],
+        upstream_bes,
+    };
+
+    let bazel_wrapper = bazel_wrapper_builder.build().await?;
+    if let Some(path) = &config.record_build_events_to {
+        bazel_wrapper
+            .record_build_events_to(path)
+            .await
+            .map_err(|e| BazelRunnerError::Unknown(Box::new(e)))?;
+    }
+
+    #[cfg(feature = "bazelfe-daemon")]
+    let runner_daemon = if let Some(bazel_command_line_parser::Action::BuiltIn(
+        bazel_command_line_parser::BuiltInAction::Shutdown,
+    )) = bazel_command_line.action
+    {
+        crate::bazel_runner_daemon::daemon_manager::try_kill_server_from_cfg(&config.daemon_config)
+            .await;
+        None
+    } else {
+        crate::bazel_runner_daemon::daemon_manager::connect_to_server(
+            &config.daemon_config,
+            &bazel_command_line.bazel_binary.clone(),
+        )
+        .await?
+    };
+
+    let configured_bazel_runner = ConfiguredBazelRunner::new(
+        Arc::clone(config),
+        bazel_wrapper,
+        #[cfg(feature = "bazelfe-daemon")]
+        runner_daemon,
+        index_table.clone(),
+        bazel_command_line.clone(),
+        Arc::clone(&process_build_failures),
+    );
+
+    let res = match replay_build_events {
+        Some(path) => configured_bazel_runner.replay(path).await,
+        None => configured_bazel_runner.run().await,
+    };
+    Ok((res, process_build_failures.repair_history().await))
+}
+
+async fn report_dry_run(
+    config: &Config,
+    workspace_root: &Path,
+    buildozer: &buildozer_driver::RecordingBuildozer<buildozer_driver::BuildozerBinaryImpl>,
+) -> Result<(), BazelRunnerError> {
+    // Written even when empty, so a command file left over from an earlier run is never picked up.
+    if let Some(command_file) = &config.dry_run_config.buildozer_command_file {
+        std::fs::write(command_file, buildozer.buildozer_command_file())
+            .map_err(|e| BazelRunnerError::Unknown(Box::new(e)))?;
+    }
+
+    if buildozer.recorded_edits().is_empty() {
+        return Ok(());
+    }
+
+    match buildozer.unified_diff(workspace_root).await {
+        Ok(diff) => {
+            eprintln!("--------------------Suggested BUILD changes--------------------");
+            eprint!("{}", diff);
+            eprintln!("---------------------------------------------------------------\n");
+        }
+        Err(e) => warn!(
+            "Unable to render the suggested BUILD changes as a diff: {}",
+            e.stderr
+        ),
+    }
+    Ok(())
+}
@@ -12,6 +12,7 @@ pub enum CustomAction {
     AutoTest,
     TestFile,
     BuildFile,
+    PruneDeps,
 }
 impl CustomAction {
     #[allow(dead_code)]
@@ -20,6 +21,7 @@ impl CustomAction {
             CustomAction::AutoTest => "autotest",
             CustomAction::TestFile => "test_file",
             CustomAction::BuildFile => "build_file",
+            CustomAction::PruneDeps => "prune_deps",
         }
         .to_string()
     }
@@ -32,6 +34,7 @@ pub fn parse_commandline_with_custom_command_line_options(
     m.insert("autotest".to_string(), BuiltInAction::Test);
     m.insert("test_file".to_string(), BuiltInAction::Test);
     m.insert("build_file".to_string(), BuiltInAction::Build);
+    m.insert("prune_deps".to_string(), BuiltInAction::Build);
     parse_bazel_command_line(command_line, m)
 }
 
@@ -40,6 +43,7 @@ pub fn parse_custom_action(input: &str) -> Result<CustomAction, RewriteCommandLi
         "autotest" => Ok(CustomAction::AutoTest),
         "test_file" => Ok(CustomAction::TestFile),
         "build_file" => Ok(CustomAction::BuildFile),
+        "prune_deps" => Ok(CustomAction::PruneDeps),
         _ => Err(RewriteCommandLineError::UserErrorReport(UserReportError(
             format!("Unknown custom command passed in {}", input),
         ))),
@@ -87,6 +91,8 @@ pub async fn rewrite_command_line<B: BazelQuery>(
                 )
                 .await;
             }
+            // Needs the build's outputs, so the runner drives this one itself.
+            CustomAction::PruneDeps => return Ok(()),
         }
     }
 
@@ -208,6 +214,7 @@ mod tests {
             action: Some(Action::BuiltIn(BuiltInAction::Test)),
             action_options: Vec::default(),
             remaining_args: vec!["bar".to_string()],
+            bazelrc: Default::default(),
         };
 
         rewrite_command_line(
@@ -228,6 +235,7 @@ mod tests {
                 action: Some(Action::BuiltIn(BuiltInAction::Test)),
                 action_options: Vec::default(),
                 remaining_args: vec!["bar".to_string()],
+                bazelrc: Default::default(),
             }
         );
     }
@@ -240,6 +248,7 @@ mod tests {
             action: Some(Action::BuiltIn(BuiltInAction::Test)),
             action_options: Vec::default(),
             remaining_args: vec![],
+            bazelrc: Default::default(),
         };
         let rewrite_config = CommandLineRewriter {
             test: TestActionMode::EmptyTestToLocalRepo(EmptyTestToLocalRepoCfg::default()),
@@ -262,6 +271,7 @@ mod tests {
                 action: Some(Action::BuiltIn(BuiltInAction::Test)),
                 action_options: Vec::default(),
                 remaining_args: vec!["//...".to_string()],
+                bazelrc: Default::default(),
             }
         );
     }
@@ -295,6 +305,7 @@ mod tests {
             action: Some(Action::BuiltIn(BuiltInAction::Test)),
             action_options: Vec::default(),
             remaining_args: vec![],
+            bazelrc: Default::default(),
         };
 
         let rewrite_config = CommandLineRewriter {
@@ -342,6 +353,7 @@ mod tests {
                     .to_string()
                     + "/foo.scala",
             ],
+            bazelrc: Default::default(),
         };
 
         let rewrite_config = Default::default();
@@ -384,6 +396,7 @@ mod tests {
                     .to_string()
                     + "/foo.scala",
             ],
+            bazelrc: Default::default(),
         };
 
         let rewrite_config = Default::default();
@@ -12,10 +12,52 @@ use bazelfe_bazel_wrapper::bazel_command_line_parser::ParsedCommandLine;
 use bazelfe_bazel_wrapper::bazel_subprocess_wrapper::BazelWrapper;
 use bazelfe_bazel_wrapper::bazel_subprocess_wrapper::{BazelWrapperError, ExecuteResult};
 use std::sync::Arc;
-
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
 
 use super::processor_activity::*;
+use super::run_report::RunReport;
+
+async fn collect_activity(rx: async_channel::Receiver<BuildEventResponse>) -> ProcessorActivity {
+    let mut jvm_segments_indexed = 0;
+    let mut actions_taken: u32 = 0;
+    let mut target_story_actions = HashMap::new();
+
+    while let Ok(action) = rx.recv().await {
+        match action {
+            crate::hydrated_stream_processors::BuildEventResponse::ProcessedBuildFailures(pbf) => {
+                let current_updates: u32 = pbf
+                    .target_story_entries
+                    .iter()
+                    .map(|e| match e.action {
+                        TargetStoryAction::Success => 0,
+                        TargetStoryAction::WouldHaveAddedDependency { .. } => 0,
+                        TargetStoryAction::WouldHaveRemovedDependency { .. } => 0,
+                        _ => 1,
+                    })
+                    .sum();
+                actions_taken += current_updates;
+                for story_entry in pbf.target_story_entries {
+                    match target_story_actions.get_mut(&story_entry.target) {
+                        None => {
+                            target_story_actions
+                                .insert(story_entry.target.clone(), vec![story_entry]);
+                        }
+                        Some(existing) => existing.push(story_entry),
+                    };
+                }
+            }
+            crate::hydrated_stream_processors::BuildEventResponse::IndexedResults(ir) => {
+                jvm_segments_indexed += ir.jvm_segments_indexed
+            }
+        }
+    }
+
+    ProcessorActivity {
+        jvm_segments_indexed,
+        actions_taken,
+        target_story_actions,
+    }
+}
 
 async fn run_bazel(
     configured_bazel: &BazelWrapper<BuildEventResponse>,
@@ -23,58 +65,13 @@ async fn run_bazel(
     pipe_output: bool,
 ) -> Result<(ProcessorActivity, ExecuteResult), Box<dyn std::error::Error>> {
     let (tx, rx) = async_channel::unbounded();
-    let results_data = Arc::new(RwLock::new(None));
-    let r_data = Arc::clone(&results_data);
-    let recv_task = tokio::spawn(async move {
-        let mut guard = r_data.write().await;
-        let mut jvm_segments_indexed = 0;
-        let mut actions_taken: u32 = 0;
-        let mut target_story_actions = HashMap::new();
-
-        while let Ok(action) = rx.recv().await {
-            match action {
-                crate::hydrated_stream_processors::BuildEventResponse::ProcessedBuildFailures(
-                    pbf,
-                ) => {
-                    let current_updates: u32 = pbf
-                        .target_story_entries
-                        .iter()
-                        .map(|e| match e.action {
-                            TargetStoryAction::Success => 0,
-                            TargetStoryAction::WouldHaveAddedDependency { .. } => 0,
-                            _ => 1,
-                        })
-                        .sum();
-                    actions_taken += current_updates;
-                    for story_entry in pbf.target_story_entries {
-                        match target_story_actions.get_mut(&story_entry.target) {
-                            None => {
-                                target_story_actions
-                                    .insert(story_entry.target.clone(), vec![story_entry]);
-                            }
-                            Some(existing) => existing.push(story_entry),
-                        };
-                    }
-                }
-                crate::hydrated_stream_processors::BuildEventResponse::IndexedResults(ir) => {
-                    jvm_segments_indexed += ir.jvm_segments_indexed
-                }
-            }
-        }
-
-        *guard = Some(ProcessorActivity {
-            jvm_segments_indexed,
-            actions_taken,
-            target_story_actions,
-        });
-    });
+    let recv_task = tokio::spawn(collect_activity(rx));
 
     let res = configured_bazel
         .spawn_bazel_attempt(bazel_command_line, pipe_output, tx)
         .await
         .map_err(|e| BazelWrapperError::Unknown(e))?;
-    recv_task.await.unwrap();
-    let r = results_data.write().await.take().unwrap();
+    let r = recv_task.await.unwrap();
     Ok((r, res))
 }
 
@@ -82,7 +79,7 @@ pub struct ConfiguredBazelRunner<
     T: buildozer_driver::Buildozer,
     U: crate::hydrated_stream_processors::process_bazel_failures::CommandLineRunner,
 > {
-    config: Arc<Config>,
+    pub config: Arc<Config>,
     pub configured_bazel: BazelWrapper<BuildEventResponse>,
     #[cfg(feature = "bazelfe-daemon")]
     pub runner_daemon: Option<
@@ -90,7 +87,7 @@ pub struct ConfiguredBazelRunner<
             tonic::transport::Channel,
         >,
     >,
-    _index_table: crate::index_table::IndexTable,
+    pub index_table: crate::index_table::IndexTable,
     pub bazel_command_line: ParsedCommandLine,
     process_build_failures: Arc<ProcessBazelFailures<T, U>>,
 }
@@ -117,12 +114,16 @@ impl<
             configured_bazel,
             #[cfg(feature = "bazelfe-daemon")]
             runner_daemon,
-            _index_table: index_table,
+            index_table,
             bazel_command_line,
             process_build_failures,
         }
     }
 
+    pub fn buildozer(&self) -> &T {
+        self.process_build_failures.buildozer()
+    }
+
     pub async fn run_command_line(
         &self,
         pipe_output: bool,
@@ -133,9 +134,13 @@ impl<
         let mut final_exit_code = 0;
         let disable_action_stories_on_success = self.config.disable_action_stories_on_success;
         let mut total_actions_taken: u32 = 0;
+        let mut attempt_results = Vec::default();
+        let mut all_target_stories: HashMap<String, Vec<TargetStory>> = HashMap::default();
+        let started = Instant::now();
         while attempts < 60 {
             attempts += 1;
             self.process_build_failures.advance_epoch().await;
+            let attempt_started = Instant::now();
             let (processor_activity, bazel_result) = run_bazel(
                 &self.configured_bazel,
                 &self.bazel_command_line,
@@ -143,7 +148,18 @@ impl<
             )
             .await?;
             let actions_taken = processor_activity.actions_taken;
+            attempt_results.push(AttemptResult {
+                exit_code: bazel_result.exit_code,
+                actions_taken,
+                duration: attempt_started.elapsed(),
+            });
             total_actions_taken += actions_taken;
+            for (target, stories) in processor_activity.target_story_actions.iter() {
+                all_target_stories
+                    .entry(target.clone())
+                    .or_default()
+                    .extend(stories.iter().cloned());
+            }
             running_total.merge(processor_activity, disable_action_stories_on_success);
             final_exit_code = bazel_result.exit_code;
             if bazel_result.exit_code == 0 || actions_taken == 0 {
@@ -155,11 +171,134 @@ impl<
             total_actions_taken,
             final_exit_code,
             running_total,
+            attempt_results,
+            all_target_stories,
+            started,
+        })
+    }
+
+    /// Runs a recorded build event stream back through the event handlers instead of invoking bazel.
+    pub async fn replay(self, path: &std::path::Path) -> Result<i32, BazelWrapperError> {
+        let res = self.replay_invocations(path).await;
+        self.report_outcome(res)
+    }
+
+    async fn replay_invocations(
+        &self,
+        path: &std::path::Path,
+    ) -> Result<RunCompleteState, BazelWrapperError> {
+        let invocations = BazelWrapper::<BuildEventResponse>::read_recorded_invocations(path)
+            .map_err(|e| BazelWrapperError::Unknown(Box::new(e)))?;
+        let started = Instant::now();
+        let attempts = invocations.len() as u16;
+        let disable_action_stories_on_success = self.config.disable_action_stories_on_success;
+        let mut activity = ProcessorActivity::default();
+        let mut attempt_results = Vec::default();
+        let mut all_target_stories: HashMap<String, Vec<TargetStory>> = HashMap::default();
+        let mut final_exit_code = 0;
+        // Each recorded invocation is a fresh attempt, as it was when the recording was made.
+        for invocation in invocations {
+            self.process_build_failures.advance_epoch().await;
+            let attempt_started = Instant::now();
+            // A recording cut short before bazel finished can only have been a failed build.
+            let exit_code =
+                BazelWrapper::<BuildEventResponse>::recorded_exit_code(&invocation).unwrap_or(1);
+            let (tx, rx) = async_channel::unbounded();
+            let recv_task = tokio::spawn(collect_activity(rx));
+            self.configured_bazel
+                .replay_build_events(invocation, tx)
+                .await
+                .map_err(BazelWrapperError::Unknown)?;
+            let processor_activity = recv_task.await.unwrap();
+
+            attempt_results.push(AttemptResult {
+                exit_code,
+                actions_taken: processor_activity.actions_taken,
+                duration: attempt_started.elapsed(),
+            });
+            for (target, stories) in processor_activity.target_story_actions.iter() {
+                all_target_stories
+                    .entry(target.clone())
+                    .or_default()
+                    .extend(stories.iter().cloned());
+            }
+            activity.merge(processor_activity, disable_action_stories_on_success);
+            final_exit_code = exit_code;
+        }
+
+        eprintln!("--------------------Bazel Runner Replay--------------------");
+        if !activity.target_story_actions.is_empty() {
+            print_target_stories(activity.target_story_actions.clone());
+        }
+        eprintln!("Actions taken: {}", activity.actions_taken);
+        eprintln!(
+            "Jvm fragments (classes/packages) added to index: {}",
+            activity.jvm_segments_indexed
+        );
+        eprintln!("------------------------------------------------------------\n");
+        Ok(RunCompleteState {
+            attempts,
+            total_actions_taken: activity.actions_taken,
+            final_exit_code,
+            all_target_stories,
+            running_total: activity,
+            attempt_results,
+            started,
         })
     }
 
     // todo, move me to the app, this is app specific
     pub async fn run(mut self) -> Result<i32, BazelWrapperError> {
+        let res = self.run_to_completion().await;
+        self.report_outcome(res)
+    }
+
+    /// Writes the `--report_json` report for however the run ended, bazel-runner exits with -1 on errors.
+    fn report_outcome(
+        &self,
+        res: Result<RunCompleteState, BazelWrapperError>,
+    ) -> Result<i32, BazelWrapperError> {
+        match res {
+            Ok(res_data) => {
+                self.write_report(&res_data);
+                Ok(res_data.final_exit_code)
+            }
+            Err(e) => {
+                self.write_report(&RunCompleteState::without_attempts(-1));
+                Err(e)
+            }
+        }
+    }
+
+    fn write_report(&self, res_data: &RunCompleteState) {
+        let report_path = match &self.config.report_json {
+            Some(report_path) => report_path,
+            None => return,
+        };
+        let command_line = match self.bazel_command_line.all_args_normalized() {
+            Ok(args) => {
+                let mut command_line = vec![self
+                    .bazel_command_line
+                    .bazel_binary
+                    .to_string_lossy()
+                    .to_string()];
+                command_line.extend(args);
+                command_line
+            }
+            Err(e) => {
+                warn!(
+                    "Unable to normalize the bazel command line, reporting our own arguments instead: {:?}",
+                    e
+                );
+                std::env::args().collect()
+            }
+        };
+        if let Err(e) = RunReport::new(command_line, res_data).write(report_path) {
+            warn!("Unable to write the run report to {:?}: {}", report_path, e);
+        }
+    }
+
+    async fn run_to_completion(&mut self) -> Result<RunCompleteState, BazelWrapperError> {
         let bq = crate::jvm_indexer::bazel_query::from_binary_path(
             &self.bazel_command_line.bazel_binary,
         );
@@ -174,20 +313,31 @@ impl<
         .map_err(|e| BazelWrapperError::Unknown(Box::new(e)))?;
 
         #[cfg(feature = "autotest-action")]
-        if super::auto_test_action::maybe_auto_test_mode(&mut self)
+        if super::auto_test_action::maybe_auto_test_mode(self)
             .await
             .map_err(|e| BazelWrapperError::Unknown(e))?
         {
-            return Ok(0);
+            return Ok(RunCompleteState::without_attempts(0));
         };
+
+        if let Some(res_data) = super::prune_deps_action::maybe_prune_deps_mode(self)
+            .await
+            .map_err(BazelWrapperError::Unknown)?
+        {
+            return Ok(res_data);
+        }
         let res_data = self
             .run_command_line(true)
             .await
             .map_err(|e| BazelWrapperError::Unknown(e))?;
+
         let disable_action_stories_on_success = self.config.disable_action_stories_on_success;
+        // In dry run mode nothing is ever applied, but the suggestions are the whole point of the run.
+        let has_dry_run_suggestions = self.config.dry_run_config.enabled
+            && !res_data.running_total.target_story_actions.is_empty();
 
         // we should be very quiet if the build is successful/we added nothing.
-        if res_data.total_actions_taken > 0
+        if (res_data.total_actions_taken > 0 || has_dry_run_suggestions)
             && !(res_data.final_exit_code == 0 && disable_action_stories_on_success)
         {
             eprintln!("--------------------Bazel Runner Report--------------------");
@@ -200,48 +350,7 @@ impl<
                 } else {
                     eprintln!("\nBuild succeeded, but documenting actions we took(some may have failed, but the build completed ok.):\n");
                 }
-                let mut v: Vec<(String, Vec<TargetStory>)> = res_data
-                    .running_total
-                    .target_story_actions
-                    .into_iter()
-                    .collect();
-                v.sort_by_key(|k| k.0.clone());
-                for (label, mut story_entries) in v.into_iter() {
-                    eprintln!("Target: {}", label);
-                    story_entries.sort_by_key(|e| e.when);
-                    for entry in story_entries.into_iter() {
-                        match entry.action {
-                            TargetStoryAction::AddedDependency { added_what, why } => {
-                                eprintln!("\tAdded Dependency {}\n\t\tReason: {}", added_what, why);
-                            }
-                            TargetStoryAction::RemovedDependency { removed_what, why } => {
-                                eprintln!(
-                                    "\tRemoved Dependency {}\n\t\tReason: {}",
-                                    removed_what, why
-                                );
-                            }
-                            TargetStoryAction::WouldHaveAddedDependency { what, why } => {
-                                eprintln!(
-                                    "\tWould have, but didn't Add Dependency {}\n\t\tReason: {}",
-                                    what, why
-                                );
-                            }
-                            TargetStoryAction::Success => eprintln!("\tTarget suceeded"),
-                            TargetStoryAction::RanUserAction {
-                                user_action_name,
-                                why,
-                                command_line,
-                                execution_result,
-                            } => {
-                                if execution_result.exit_success {
-                                    eprintln!("\tRan user action: {}\n\t\tReason: {}\n\t\tSuccess: true\n\t\tCommand line: {}", user_action_name, why, command_line);
-                                } else {
-                                    eprintln!("\tRan user action: {}\n\t\tReason: {}\n\t\tSuccess: false\n\t\tCommand line: {}\nstdout:\n{}\n\nstderr:\n{}\n\n", user_action_name, why, command_line, execution_result.stdout, execution_result.stderr);
-                                }
-                            }
-                        }
-                    }
-                }
+                print_target_stories(res_data.running_total.target_story_actions.clone());
             }
             eprintln!("Bazel exit code: {}", res_data.final_exit_code);
             eprintln!("Bazel build attempts: {}", res_data.attempts);
@@ -253,13 +362,83 @@ impl<
             eprintln!("------------------------------------------------------------\n");
         }
 
-        Ok(res_data.final_exit_code)
+        Ok(res_data)
     }
 }
 
+pub(super) fn print_target_stories(target_story_actions: HashMap<String, Vec<TargetStory>>) {
+    let mut v: Vec<(String, Vec<TargetStory>)> = target_story_actions.into_iter().collect();
+    v.sort_by_key(|k| k.0.clone());
+    for (label, mut story_entries) in v.into_iter() {
+        eprintln!("Target: {}", label);
+        story_entries.sort_by_key(|e| e.when);
+        for entry in story_entries.into_iter() {
+            match entry.action {
+                TargetStoryAction::AddedDependency { added_what, why } => {
+                    eprintln!("\tAdded Dependency {}\n\t\tReason: {}", added_what, why);
+                }
+                TargetStoryAction::RemovedDependency { removed_what, why } => {
+                    eprintln!("\tRemoved Dependency {}\n\t\tReason: {}", removed_what, why);
+                }
+                TargetStoryAction::WouldHaveAddedDependency { what, why } => {
+                    eprintln!(
+                        "\tWould have, but didn't Add Dependency {}\n\t\tReason: {}",
+                        what, why
+                    );
+                }
+                TargetStoryAction::WouldHaveRemovedDependency { what, why } => {
+                    eprintln!(
+                        "\tWould have, but didn't Remove Dependency {}\n\t\tReason: {}",
+                        what, why
+                    );
+                }
+                TargetStoryAction::Success => eprintln!("\tTarget suceeded"),
+                TargetStoryAction::RanUserAction {
+                    user_action_name,
+                    why,
+                    command_line,
+                    execution_result,
+                } => {
+                    if execution_result.exit_success {
+                        eprintln!("\tRan user action: {}\n\t\tReason: {}\n\t\tSuccess: true\n\t\tCommand line: {}", user_action_name, why, command_line);
+                    } else {
+                        eprintln!("\tRan user action: {}\n\t\tReason: {}\n\t\tSuccess: false\n\t\tCommand line: {}\nstdout:\n{}\n\nstderr:\n{}\n\n", user_action_name, why, command_line, execution_result.stdout, execution_result.stderr);
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub struct AttemptResult {
+    pub exit_code: i32,
+    pub actions_taken: u32,
+    pub duration: Duration,
+}
+
 pub struct RunCompleteState {
     pub attempts: u16,
     pub total_actions_taken: u32,
     pub final_exit_code: i32,
     pub running_total: ProcessorActivity,
+    pub attempt_results: Vec<AttemptResult>,
+    /// Every story from every attempt, `running_total` drops some depending on the config.
+    pub all_target_stories: HashMap<String, Vec<TargetStory>>,
+    /// When the first attempt started, target stories are reported relative to this.
+    pub started: Instant,
+}
+
+impl RunCompleteState {
+    /// For runs that ended without bazel building anything we track.
+    pub fn without_attempts(final_exit_code: i32) -> Self {
+        Self {
+            attempts: 0,
+            total_actions_taken: 0,
+            final_exit_code,
+            running_total: ProcessorActivity::default(),
+            attempt_results: Vec::default(),
+            all_target_stories: HashMap::default(),
+            started: Instant::now(),
+        }
+    }
 }
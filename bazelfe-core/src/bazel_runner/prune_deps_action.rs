@@ -0,0 +1,602 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use bazelfe_bazel_wrapper::bazel_command_line_parser::{
+    Action, BazelOption, BuiltInAction, ParsedCommandLine,
+};
+use bazelfe_bazel_wrapper::bep::build_events::hydrated_stream::{HydratedInfo, JDEPS_OUTPUT_GROUP};
+use bazelfe_bazel_wrapper::bep::BazelEventHandler;
+use bazelfe_protos::blaze_deps;
+use bazelfe_protos::build_event_stream::file::File::Uri;
+use prost::Message;
+use thiserror::Error;
+use tokio::sync::Mutex;
+
+use crate::buildozer_driver::{BazelAttrTarget, Buildozer};
+use crate::hydrated_stream_processors::process_bazel_failures::{
+    CommandLineRunner, TargetStory, TargetStoryAction, DRY_RUN_SUGGESTION,
+};
+use crate::hydrated_stream_processors::BuildEventResponse;
+use crate::index_table::IndexTable;
+use crate::label_utils::sanitize_label;
+
+use super::command_line_rewriter_action::{parse_custom_action, CustomAction};
+use super::configured_bazel_runner::{
+    print_target_stories, ConfiguredBazelRunner, RunCompleteState,
+};
+
+const UNUSED_REASON: &str = "No class it provides is referenced according to the jdeps output.";
+
+#[derive(Error, Debug)]
+pub enum PruneDepsActionError {
+    #[error("prune_deps needs at least one target to prune")]
+    NoTargets,
+    #[error("Buildozer failed to update {target}: {stderr}")]
+    Buildozer { target: String, stderr: String },
+}
+
+/// Remembers the `.jdeps` files of every target that built successfully.
+#[derive(Clone, Debug, Default)]
+struct JdepsCollector {
+    jdeps_for_label: Arc<Mutex<HashMap<String, Vec<PathBuf>>>>,
+}
+
+#[async_trait::async_trait]
+impl BazelEventHandler<BuildEventResponse> for JdepsCollector {
+    async fn process_event(
+        &self,
+        _bazel_run_id: usize,
+        event: &HydratedInfo,
+    ) -> Vec<BuildEventResponse> {
+        if let HydratedInfo::TargetComplete(tce) = event {
+            if tce.success {
+                let jdeps_files: Vec<PathBuf> = tce
+                    .output_files
+                    .iter()
+                    .chain(tce.other_output_groups.values().flatten())
+                    .filter_map(|f| match f.file.as_ref() {
+                        Some(Uri(u)) => u.strip_prefix("file://"),
+                        _ => None,
+                    })
+                    .filter(|p| p.ends_with(".jdeps"))
+                    .map(PathBuf::from)
+                    .collect();
+                if !jdeps_files.is_empty() {
+                    self.jdeps_for_label
+                        .lock()
+                        .await
+                        .insert(tce.label.clone(), jdeps_files);
+                }
+            }
+        }
+        Vec::default()
+    }
+}
+
+/// Paths, relative to the execution root, of the jars the compiler actually used.
+fn used_jars(jdeps_path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let bytes = std::fs::read(jdeps_path)?;
+    let dependencies = blaze_deps::Dependencies::decode(bytes.as_slice())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    Ok(dependencies
+        .dependency
+        .into_iter()
+        .filter(|d| d.kind() != blaze_deps::dependency::Kind::Unused)
+        .map(|d| PathBuf::from(d.path))
+        .collect())
+}
+
+fn execution_root(jdeps_path: &Path) -> Option<PathBuf> {
+    let path = jdeps_path.to_str()?;
+    path.find("/bazel-out/")
+        .map(|idx| PathBuf::from(&path[..idx]))
+}
+
+/// The labels whose jars provide the classes in `jar`, cached as most targets share much of their classpath.
+async fn labels_providing<'a>(
+    index_table: &IndexTable,
+    jar_labels: &'a mut HashMap<PathBuf, HashSet<String>>,
+    jar: PathBuf,
+) -> &'a HashSet<String> {
+    if !jar_labels.contains_key(&jar) {
+        let mut labels = HashSet::default();
+        if let Some(label) = crate::zip_parse::manifest_target_label(&jar) {
+            labels.insert(sanitize_label(label));
+        }
+        for class_name in crate::zip_parse::extract_classes_from_zip(jar.clone()) {
+            if let Some(targets) = index_table.get(class_name).await {
+                for e in targets.read_iter().await.iter() {
+                    if let Some(label) = index_table.decode_string(e.target).await {
+                        labels.insert(sanitize_label(label));
+                    }
+                }
+            }
+        }
+        jar_labels.insert(jar.clone(), labels);
+    }
+    &jar_labels[&jar]
+}
+
+/// Resolves a `deps` entry as written in the BUILD file of `target` into a full label.
+fn absolute_label(target: &str, dep: &str) -> String {
+    let target = sanitize_label(target.to_string());
+    let package = target.split(':').next().unwrap_or_default();
+    let label = if let Some(name) = dep.strip_prefix(':') {
+        format!("{}:{}", package, name)
+    } else if dep.starts_with("//") || dep.starts_with('@') {
+        dep.to_string()
+    } else {
+        format!("{}:{}", package, dep)
+    };
+    sanitize_label(label)
+}
+
+/// Deps none of the used jars came from. Only deps the index knows the classes of are candidates,
+/// for any other we can't tell whether it provided one of the jars.
+fn unused_deps(
+    target: &str,
+    deps: Vec<String>,
+    used_labels: &HashSet<String>,
+    indexed_labels: &HashSet<String>,
+) -> Vec<String> {
+    deps.into_iter()
+        .filter(|dep| {
+            let label = absolute_label(target, dep);
+            !used_labels.contains(&label) && indexed_labels.contains(&label)
+        })
+        .collect()
+}
+
+async fn find_unused_deps<T: Buildozer>(
+    buildozer: &T,
+    index_table: &IndexTable,
+    jdeps_for_label: HashMap<String, Vec<PathBuf>>,
+) -> Vec<(String, String)> {
+    let mut jdeps_for_label: Vec<(String, Vec<PathBuf>)> = jdeps_for_label.into_iter().collect();
+    jdeps_for_label.sort();
+
+    let indexed_labels: HashSet<String> = index_table
+        .targets()
+        .await
+        .into_iter()
+        .map(sanitize_label)
+        .collect();
+    let mut jar_labels = HashMap::default();
+
+    let mut candidates = Vec::default();
+    'label_loop: for (label, jdeps_files) in jdeps_for_label.into_iter() {
+        let mut used_labels = HashSet::default();
+        for jdeps_file in jdeps_files.iter() {
+            let execution_root = match execution_root(jdeps_file) {
+                Some(r) => r,
+                None => continue 'label_loop,
+            };
+            match used_jars(jdeps_file) {
+                Ok(jars) => {
+                    for jar in jars {
+                        used_labels.extend(
+                            labels_providing(
+                                index_table,
+                                &mut jar_labels,
+                                execution_root.join(jar),
+                            )
+                            .await
+                            .iter()
+                            .cloned(),
+                        );
+                    }
+                }
+                Err(e) => {
+                    // Without the full picture we'd only be guessing, so leave this target alone.
+                    warn!("Unable to read jdeps file {:?}: {}", jdeps_file, e);
+                    continue 'label_loop;
+                }
+            }
+        }
+
+        let deps = match buildozer.print_attr(&BazelAttrTarget::Deps, &label).await {
+            Ok(deps) => deps,
+            Err(e) => {
+                warn!("Unable to read the deps of {}: {}", label, e.stderr);
+                continue;
+            }
+        };
+        for dep in unused_deps(&label, deps, &used_labels, &indexed_labels) {
+            candidates.push((label.clone(), dep));
+        }
+    }
+    candidates
+}
+
+/// Bazel's own output is passed through, these builds can take as long as the first one.
+async fn confirm_build(
+    bazel_command_line: &ParsedCommandLine,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let args = bazel_command_line.all_args_normalized()?;
+    eprintln!("Running a build to confirm the pruned dependencies aren't needed...");
+    let status = tokio::process::Command::new(&bazel_command_line.bazel_binary)
+        .args(&args)
+        .status()
+        .await?;
+    Ok(status.success())
+}
+
+async fn edit_deps<T: Buildozer>(
+    buildozer: &T,
+    add: bool,
+    target: &String,
+    dep: &String,
+) -> Result<(), PruneDepsActionError> {
+    let res = if add {
+        buildozer.add_to(&BazelAttrTarget::Deps, target, dep).await
+    } else {
+        buildozer
+            .remove_from(&BazelAttrTarget::Deps, target, dep)
+            .await
+    };
+    res.map_err(|e| PruneDepsActionError::Buildozer {
+        target: target.clone(),
+        stderr: e.stderr,
+    })
+}
+
+fn story(target: &str, action: TargetStoryAction) -> TargetStory {
+    TargetStory {
+        target: target.to_string(),
+        action,
+        when: Instant::now(),
+    }
+}
+
+fn removed_story(target: &str, dep: String) -> TargetStory {
+    story(
+        target,
+        TargetStoryAction::RemovedDependency {
+            removed_what: dep,
+            why: UNUSED_REASON.to_string(),
+        },
+    )
+}
+
+/// Removes every candidate, then falls back to one removal at a time if the build no longer passes.
+/// On errors whatever was removed but not yet confirmed as unneeded is put back.
+async fn prune_and_confirm<T: Buildozer>(
+    buildozer: &T,
+    bazel_command_line: &ParsedCommandLine,
+    candidates: Vec<(String, String)>,
+) -> Result<Vec<TargetStory>, Box<dyn std::error::Error>> {
+    let mut pending = Vec::default();
+    let res = remove_unneeded(buildozer, bazel_command_line, candidates, &mut pending).await;
+    if res.is_err() {
+        for (target, dep) in pending.iter() {
+            if let Err(e) = edit_deps(buildozer, true, target, dep).await {
+                warn!("Unable to restore {} to the deps of {}: {}", dep, target, e);
+            }
+        }
+    }
+    res
+}
+
+async fn remove_unneeded<T: Buildozer>(
+    buildozer: &T,
+    bazel_command_line: &ParsedCommandLine,
+    candidates: Vec<(String, String)>,
+    pending: &mut Vec<(String, String)>,
+) -> Result<Vec<TargetStory>, Box<dyn std::error::Error>> {
+    for (target, dep) in candidates.iter() {
+        edit_deps(buildozer, false, target, dep).await?;
+        pending.push((target.clone(), dep.clone()));
+    }
+    if confirm_build(bazel_command_line).await? {
+        pending.clear();
+        return Ok(candidates
+            .into_iter()
+            .map(|(target, dep)| removed_story(&target, dep))
+            .collect());
+    }
+
+    while let Some((target, dep)) = pending.last() {
+        edit_deps(buildozer, true, target, dep).await?;
+        pending.pop();
+    }
+
+    // Deps the build turns out to need end up where they started, so there's nothing to report.
+    let mut stories = Vec::default();
+    for (target, dep) in candidates.into_iter() {
+        edit_deps(buildozer, false, &target, &dep).await?;
+        pending.push((target.clone(), dep.clone()));
+        let unneeded = confirm_build(bazel_command_line).await?;
+        if !unneeded {
+            edit_deps(buildozer, true, &target, &dep).await?;
+        }
+        pending.clear();
+        if unneeded {
+            stories.push(removed_story(&target, dep));
+        }
+    }
+    Ok(stories)
+}
+
+pub async fn maybe_prune_deps_mode<T: Buildozer, U: CommandLineRunner>(
+    configured_bazel_runner: &mut ConfiguredBazelRunner<T, U>,
+) -> Result<Option<RunCompleteState>, Box<dyn std::error::Error>> {
+    match configured_bazel_runner.bazel_command_line.action.as_ref() {
+        Some(Action::Custom(cust)) if parse_custom_action(cust)? == CustomAction::PruneDeps => (),
+        _ => return Ok(None),
+    }
+
+    let bazel_command_line = &mut configured_bazel_runner.bazel_command_line;
+    if bazel_command_line.remaining_args.is_empty() {
+        return Err(Box::new(PruneDepsActionError::NoTargets));
+    }
+    bazel_command_line.action = Some(Action::BuiltIn(BuiltInAction::Build));
+    // Always appended, the `+` adds to whichever output groups the user asked for.
+    bazel_command_line
+        .action_options
+        .push(BazelOption::OptionWithArg(
+            String::from("output_groups"),
+            format!("+{}", JDEPS_OUTPUT_GROUP),
+        ));
+
+    let jdeps_collector = JdepsCollector::default();
+    configured_bazel_runner
+        .configured_bazel
+        .aes
+        .add_event_handler(Arc::new(jdeps_collector.clone()));
+
+    let mut res_data = configured_bazel_runner.run_command_line(true).await?;
+    if res_data.final_exit_code != 0 {
+        eprintln!("Build failed, not pruning any dependencies.");
+        return Ok(Some(res_data));
+    }
+
+    let jdeps_for_label = jdeps_collector.jdeps_for_label.lock().await.clone();
+    let buildozer = configured_bazel_runner.buildozer().clone();
+    let candidates = find_unused_deps(
+        &buildozer,
+        &configured_bazel_runner.index_table,
+        jdeps_for_label,
+    )
+    .await;
+    if candidates.is_empty() {
+        eprintln!("No unused dependencies found.");
+        return Ok(Some(res_data));
+    }
+
+    let dry_run = configured_bazel_runner.config.dry_run_config.enabled;
+    let stories = if dry_run {
+        let mut stories = Vec::default();
+        for (target, dep) in candidates.into_iter() {
+            edit_deps(&buildozer, false, &target, &dep).await?;
+            stories.push(story(
+                &target,
+                TargetStoryAction::WouldHaveRemovedDependency {
+                    what: dep,
+                    why: format!("{} {}", DRY_RUN_SUGGESTION, UNUSED_REASON),
+                },
+            ));
+        }
+        stories
+    } else {
+        prune_and_confirm(
+            &buildozer,
+            &configured_bazel_runner.bazel_command_line,
+            candidates,
+        )
+        .await?
+    };
+
+    if !dry_run {
+        res_data.total_actions_taken += stories.len() as u32;
+    }
+    let mut target_story_actions: HashMap<String, Vec<TargetStory>> = HashMap::default();
+    for story in stories.into_iter() {
+        res_data
+            .all_target_stories
+            .entry(story.target.clone())
+            .or_default()
+            .push(story.clone());
+        target_story_actions
+            .entry(story.target.clone())
+            .or_default()
+            .push(story);
+    }
+    eprintln!("--------------------Pruned Dependencies--------------------");
+    print_target_stories(target_story_actions);
+    eprintln!("-----------------------------------------------------------\n");
+    Ok(Some(res_data))
+}
+
+#[cfg(test)]
+mod tests {
+    use bazelfe_bazel_wrapper::bazel_command_line_parser::bazelrc::BazelRc;
+
+    use super::*;
+    use crate::buildozer_driver::ExecuteResultError;
+
+    /// Keeps the deps of a single target in memory, failing to remove `unremovable`.
+    #[derive(Clone, Debug)]
+    struct InMemoryBuildozer {
+        deps: Arc<std::sync::Mutex<Vec<String>>>,
+        unremovable: Option<String>,
+    }
+
+    impl InMemoryBuildozer {
+        fn new(deps: &[&str], unremovable: Option<&str>) -> Self {
+            Self {
+                deps: Arc::new(std::sync::Mutex::new(
+                    deps.iter().map(|d| d.to_string()).collect(),
+                )),
+                unremovable: unremovable.map(|d| d.to_string()),
+            }
+        }
+
+        fn deps(&self) -> Vec<String> {
+            let mut deps = self.deps.lock().unwrap().clone();
+            deps.sort();
+            deps
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Buildozer for InMemoryBuildozer {
+        async fn print_attr(
+            &self,
+            _attr: &BazelAttrTarget,
+            _label: &String,
+        ) -> Result<Vec<String>, ExecuteResultError> {
+            Ok(self.deps())
+        }
+
+        async fn add_to(
+            &self,
+            _to_what: &BazelAttrTarget,
+            _target_to_operate_on: &String,
+            label_to_add: &String,
+        ) -> Result<(), ExecuteResultError> {
+            self.deps.lock().unwrap().push(label_to_add.clone());
+            Ok(())
+        }
+
+        async fn remove_from(
+            &self,
+            _from_what: &BazelAttrTarget,
+            _target_to_operate_on: &String,
+            label_to_remove: &String,
+        ) -> Result<(), ExecuteResultError> {
+            if self.unremovable.as_ref() == Some(label_to_remove) {
+                return Err(ExecuteResultError {
+                    exit_code: 1,
+                    stdout: String::default(),
+                    stderr: String::from("unable to remove"),
+                });
+            }
+            self.deps.lock().unwrap().retain(|d| d != label_to_remove);
+            Ok(())
+        }
+    }
+
+    /// Runs `bazel_binary` in place of bazel, `true` or `false` to pass or fail every build.
+    fn command_line(bazel_binary: &str) -> ParsedCommandLine {
+        ParsedCommandLine {
+            bazel_binary: PathBuf::from(bazel_binary),
+            startup_options: Vec::default(),
+            action: Some(Action::BuiltIn(BuiltInAction::Build)),
+            action_options: Vec::default(),
+            remaining_args: Vec::default(),
+            bazelrc: BazelRc::default(),
+        }
+    }
+
+    fn candidates(deps: &[&str]) -> Vec<(String, String)> {
+        deps.iter()
+            .map(|d| (String::from("//src/main:main"), d.to_string()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_restores_deps_when_pruning_fails() {
+        let buildozer = InMemoryBuildozer::new(&["//a:a", "//b:b", "//c:c"], Some("//b:b"));
+
+        let res = prune_and_confirm(
+            &buildozer,
+            &command_line("true"),
+            candidates(&["//a:a", "//b:b"]),
+        )
+        .await;
+
+        assert!(res.is_err());
+        assert_eq!(buildozer.deps(), vec!["//a:a", "//b:b", "//c:c"]);
+    }
+
+    #[tokio::test]
+    async fn test_needed_deps_are_not_reported() {
+        let buildozer = InMemoryBuildozer::new(&["//a:a", "//b:b"], None);
+
+        let stories = prune_and_confirm(
+            &buildozer,
+            &command_line("false"),
+            candidates(&["//a:a", "//b:b"]),
+        )
+        .await
+        .unwrap();
+
+        assert!(stories.is_empty());
+        assert_eq!(buildozer.deps(), vec!["//a:a", "//b:b"]);
+    }
+
+    #[test]
+    fn test_unused_deps() {
+        let used_labels: HashSet<String> =
+            vec!["//src/foo:foo", "//src/main:util", "@maven//:guava"]
+                .into_iter()
+                .map(|e| e.to_string())
+                .collect();
+        let indexed_labels: HashSet<String> = vec![
+            "//src/foo:foo",
+            "//src/main:util",
+            "@maven//:guava",
+            "//src/bar:bar",
+            "//src/main:helpers",
+        ]
+        .into_iter()
+        .map(|e| e.to_string())
+        .collect();
+        let deps = vec![
+            "//src/foo",
+            ":util",
+            "@maven//:guava",
+            "//src/bar:bar",
+            "helpers",
+            "//src/not_indexed",
+        ]
+        .into_iter()
+        .map(|e| e.to_string())
+        .collect();
+
+        assert_eq!(
+            unused_deps("//src/main:main", deps, &used_labels, &indexed_labels),
+            vec!["//src/bar:bar".to_string(), "helpers".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_used_jars_skips_unused_entries() {
+        let dependencies = blaze_deps::Dependencies {
+            dependency: vec![
+                blaze_deps::Dependency {
+                    path: String::from("bazel-out/k8-fastbuild/bin/src/foo/libfoo-hjar.jar"),
+                    kind: blaze_deps::dependency::Kind::Explicit as i32,
+                    location: Vec::default(),
+                },
+                blaze_deps::Dependency {
+                    path: String::from("bazel-out/k8-fastbuild/bin/src/bar/libbar-hjar.jar"),
+                    kind: blaze_deps::dependency::Kind::Unused as i32,
+                    location: Vec::default(),
+                },
+            ],
+            rule_label: Some(String::from("//src/main:main")),
+            success: Some(true),
+            contained_package: Vec::default(),
+            requires_reduced_classpath_fallback: None,
+        };
+        let temp_dir = tempfile::tempdir().unwrap();
+        let jdeps_path = temp_dir.path().join("libmain.jdeps");
+        std::fs::write(&jdeps_path, dependencies.encode_to_vec()).unwrap();
+
+        assert_eq!(
+            used_jars(&jdeps_path).unwrap(),
+            vec![PathBuf::from(
+                "bazel-out/k8-fastbuild/bin/src/foo/libfoo-hjar.jar"
+            )]
+        );
+        assert_eq!(
+            execution_root(Path::new(
+                "/tmp/execroot/_main/bazel-out/k8-fastbuild/bin/src/main/libmain.jdeps"
+            )),
+            Some(PathBuf::from("/tmp/execroot/_main"))
+        );
+    }
+}
@@ -0,0 +1,343 @@
+//! Machine readable report of a bazel-runner invocation, written with `--report_json`.
+//!
+//! The schema is stable within a `schema_version`: fields may be added, but are never renamed,
+//! removed or change meaning without bumping it. Durations are whole milliseconds, story entries
+//! carry their offset from the start of the run. An example:
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "command_line": ["bazel", "build", "--keep_going", "//src/..."],
+//!   "final_exit_code": 0,
+//!   "actions_taken": 1,
+//!   "jvm_segments_indexed": 42,
+//!   "attempts": [
+//!     { "exit_code": 1, "duration_ms": 5310, "actions_taken": 1 },
+//!     { "exit_code": 0, "duration_ms": 2120, "actions_taken": 0 }
+//!   ],
+//!   "targets": [
+//!     {
+//!       "target": "//src/main/scala/com/example:example",
+//!       "story": [
+//!         {
+//!           "offset_ms": 5302,
+//!           "kind": "added_dependency",
+//!           "dependency": "//src/main/scala/com/example/util:util",
+//!           "reason": "Class com.example.util.Helper not found"
+//!         },
+//!         { "offset_ms": 7431, "kind": "success" }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Story entry kinds are `added_dependency`, `removed_dependency`, `would_have_added_dependency`,
+//! `would_have_removed_dependency`, `ran_user_action` and `success`.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use super::configured_bazel_runner::RunCompleteState;
+use crate::hydrated_stream_processors::process_bazel_failures::{
+    ExecutionResult, TargetStory, TargetStoryAction,
+};
+
+pub const RUN_REPORT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RunReport {
+    pub schema_version: u32,
+    /// The bazel command line after bazelfe's rewriting, starting with the bazel binary.
+    pub command_line: Vec<String>,
+    pub final_exit_code: i32,
+    /// Changes made to BUILD files or user actions run, across all attempts.
+    pub actions_taken: u32,
+    /// Classes and packages added to the index.
+    pub jvm_segments_indexed: u32,
+    /// Every bazel invocation, in order.
+    pub attempts: Vec<AttemptReport>,
+    /// Sorted by target label.
+    pub targets: Vec<TargetReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AttemptReport {
+    pub exit_code: i32,
+    pub duration_ms: u64,
+    pub actions_taken: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TargetReport {
+    pub target: String,
+    /// Sorted by when they happened.
+    pub story: Vec<StoryEntryReport>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StoryEntryReport {
+    pub offset_ms: u64,
+    #[serde(flatten)]
+    pub action: StoryActionReport,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StoryActionReport {
+    AddedDependency {
+        dependency: String,
+        reason: String,
+    },
+    RemovedDependency {
+        dependency: String,
+        reason: String,
+    },
+    WouldHaveAddedDependency {
+        dependency: String,
+        reason: String,
+    },
+    WouldHaveRemovedDependency {
+        dependency: String,
+        reason: String,
+    },
+    RanUserAction {
+        name: String,
+        reason: String,
+        command_line: String,
+        execution_result: ExecutionResultReport,
+    },
+    Success,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ExecutionResultReport {
+    pub exit_success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis().try_into().unwrap_or(u64::MAX)
+}
+
+impl From<&ExecutionResult> for ExecutionResultReport {
+    fn from(execution_result: &ExecutionResult) -> Self {
+        Self {
+            exit_success: execution_result.exit_success,
+            stdout: execution_result.stdout.clone(),
+            stderr: execution_result.stderr.clone(),
+        }
+    }
+}
+
+impl From<&TargetStoryAction> for StoryActionReport {
+    fn from(action: &TargetStoryAction) -> Self {
+        match action {
+            TargetStoryAction::AddedDependency { added_what, why } => Self::AddedDependency {
+                dependency: added_what.clone(),
+                reason: why.clone(),
+            },
+            TargetStoryAction::RemovedDependency { removed_what, why } => Self::RemovedDependency {
+                dependency: removed_what.clone(),
+                reason: why.clone(),
+            },
+            TargetStoryAction::WouldHaveAddedDependency { what, why } => {
+                Self::WouldHaveAddedDependency {
+                    dependency: what.clone(),
+                    reason: why.clone(),
+                }
+            }
+            TargetStoryAction::WouldHaveRemovedDependency { what, why } => {
+                Self::WouldHaveRemovedDependency {
+                    dependency: what.clone(),
+                    reason: why.clone(),
+                }
+            }
+            TargetStoryAction::RanUserAction {
+                user_action_name,
+                why,
+                command_line,
+                execution_result,
+            } => Self::RanUserAction {
+                name: user_action_name.clone(),
+                reason: why.clone(),
+                command_line: command_line.clone(),
+                execution_result: execution_result.into(),
+            },
+            TargetStoryAction::Success => Self::Success,
+        }
+    }
+}
+
+fn target_report(target: &str, stories: &[TargetStory], started: Instant) -> TargetReport {
+    let mut stories: Vec<&TargetStory> = stories.iter().collect();
+    stories.sort_by_key(|e| e.when);
+    TargetReport {
+        target: target.to_string(),
+        story: stories
+            .into_iter()
+            .map(|e| StoryEntryReport {
+                offset_ms: millis(e.when.saturating_duration_since(started)),
+                action: (&e.action).into(),
+            })
+            .collect(),
+    }
+}
+
+impl RunReport {
+    pub fn new(command_line: Vec<String>, run_complete_state: &RunCompleteState) -> Self {
+        let mut targets: Vec<TargetReport> = run_complete_state
+            .all_target_stories
+            .iter()
+            .map(|(target, stories)| target_report(target, stories, run_complete_state.started))
+            .collect();
+        targets.sort_by(|a, b| a.target.cmp(&b.target));
+
+        Self {
+            schema_version: RUN_REPORT_SCHEMA_VERSION,
+            command_line,
+            final_exit_code: run_complete_state.final_exit_code,
+            actions_taken: run_complete_state.total_actions_taken,
+            jvm_segments_indexed: run_complete_state.running_total.jvm_segments_indexed,
+            attempts: run_complete_state
+                .attempt_results
+                .iter()
+                .map(|e| AttemptReport {
+                    exit_code: e.exit_code,
+                    duration_ms: millis(e.duration),
+                    actions_taken: e.actions_taken,
+                })
+                .collect(),
+            targets,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bazel_runner::configured_bazel_runner::AttemptResult;
+    use crate::bazel_runner::processor_activity::ProcessorActivity;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_report_schema() {
+        let started = Instant::now();
+        let story = |offset: u64, action: TargetStoryAction| TargetStory {
+            target: String::from("//src:lib"),
+            action,
+            when: started + Duration::from_millis(offset),
+        };
+        let mut target_story_actions = HashMap::new();
+        target_story_actions.insert(
+            String::from("//src:lib"),
+            vec![
+                story(20, TargetStoryAction::Success),
+                story(
+                    10,
+                    TargetStoryAction::RanUserAction {
+                        user_action_name: String::from("fmt"),
+                        why: String::from("formatting"),
+                        command_line: String::from("scalafmt"),
+                        execution_result: ExecutionResult {
+                            exit_success: true,
+                            stdout: String::from("ok"),
+                            stderr: String::default(),
+                        },
+                    },
+                ),
+            ],
+        );
+
+        // Trimmed by the stories config, the report should still have everything.
+        let run_complete_state = RunCompleteState {
+            attempts: 2,
+            total_actions_taken: 1,
+            final_exit_code: 0,
+            running_total: ProcessorActivity {
+                jvm_segments_indexed: 3,
+                actions_taken: 1,
+                target_story_actions: HashMap::default(),
+            },
+            attempt_results: vec![
+                AttemptResult {
+                    exit_code: 1,
+                    actions_taken: 1,
+                    duration: Duration::from_millis(15),
+                },
+                AttemptResult {
+                    exit_code: 0,
+                    actions_taken: 0,
+                    duration: Duration::from_millis(8),
+                },
+            ],
+            all_target_stories: target_story_actions,
+            started,
+        };
+
+        let report = RunReport::new(
+            vec![String::from("bazel"), String::from("build")],
+            &run_complete_state,
+        );
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "schema_version": 1,
+                "command_line": ["bazel", "build"],
+                "final_exit_code": 0,
+                "actions_taken": 1,
+                "jvm_segments_indexed": 3,
+                "attempts": [
+                    { "exit_code": 1, "duration_ms": 15, "actions_taken": 1 },
+                    { "exit_code": 0, "duration_ms": 8, "actions_taken": 0 }
+                ],
+                "targets": [{
+                    "target": "//src:lib",
+                    "story": [
+                        {
+                            "offset_ms": 10,
+                            "kind": "ran_user_action",
+                            "name": "fmt",
+                            "reason": "formatting",
+                            "command_line": "scalafmt",
+                            "execution_result": { "exit_success": true, "stdout": "ok", "stderr": "" }
+                        },
+                        { "offset_ms": 20, "kind": "success" }
+                    ]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_report_for_run_without_attempts() {
+        let report = RunReport::new(
+            vec![String::from("bazel"), String::from("prune_deps")],
+            &RunCompleteState::without_attempts(-1),
+        );
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            serde_json::json!({
+                "schema_version": 1,
+                "command_line": ["bazel", "prune_deps"],
+                "final_exit_code": -1,
+                "actions_taken": 0,
+                "jvm_segments_indexed": 0,
+                "attempts": [],
+                "targets": []
+            })
+        );
+    }
+}
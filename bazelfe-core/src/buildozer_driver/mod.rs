@@ -2,9 +2,16 @@ use bazelfe_protos::*;
 
 use ::prost::Message;
 use async_trait::async_trait;
-use std::{ffi::OsString, path::PathBuf};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
 use tokio::process::Command;
 
+mod recording_buildozer;
+mod unified_diff;
+pub use recording_buildozer::{EditKind, RecordedEdit, RecordingBuildozer};
+
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct ExecuteResultError {
     pub exit_code: i32,
@@ -31,6 +38,14 @@ pub enum BazelAttrTarget {
 }
 
 impl BazelAttrTarget {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "deps" => BazelAttrTarget::Deps,
+            "runtime_deps" => BazelAttrTarget::RuntimeDeps,
+            other => BazelAttrTarget::Other(other.to_string()),
+        }
+    }
+
     pub fn as_str(&self) -> &str {
         match self {
             BazelAttrTarget::Deps => "deps",
@@ -125,6 +140,28 @@ impl BuildozerBinaryImpl {
         let out = devtools::buildozer::Output::decode(&*command_result.stdout).unwrap();
         Ok((command, out))
     }
+
+    /// Runs a `buildozer -f` command file against the workspace rooted at `root_dir`.
+    pub async fn apply_command_file(&self, root_dir: &Path, command_file: &Path) -> Result<()> {
+        let command_result = Command::new(&self.buildozer_executable_path)
+            .arg("-root_dir")
+            .arg(root_dir)
+            .arg("-f")
+            .arg(command_file)
+            .output()
+            .await?;
+
+        // buildozer exits with 3 when the commands didn't change anything.
+        let exit_code = command_result.status.code().unwrap_or(-1);
+        if exit_code != 0 && exit_code != 3 {
+            return Err(ExecuteResultError {
+                exit_code,
+                stdout: BuildozerBinaryImpl::decode_str(&command_result.stdout),
+                stderr: BuildozerBinaryImpl::decode_str(&command_result.stderr),
+            });
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,273 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+
+use super::{unified_diff::unified_diff, BazelAttrTarget, Buildozer, BuildozerBinaryImpl, Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditKind {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedEdit {
+    pub kind: EditKind,
+    pub attr: BazelAttrTarget,
+    pub target: String,
+    pub label: String,
+}
+
+impl RecordedEdit {
+    /// The edit as a line of a `buildozer -f` command file.
+    pub fn to_buildozer_command(&self) -> String {
+        let op = match self.kind {
+            EditKind::Add => "add",
+            EditKind::Remove => "remove",
+        };
+        format!(
+            "{} {} {}|{}",
+            op,
+            self.attr.as_str(),
+            self.label,
+            self.target
+        )
+    }
+}
+
+/// A buildozer that reads through to `inner`, but only records edits in memory.
+/// Reads see the recorded edits, so the repair loop behaves as if they had been applied.
+#[derive(Debug, Clone)]
+pub struct RecordingBuildozer<T: Buildozer> {
+    inner: T,
+    edits: Arc<Mutex<Vec<RecordedEdit>>>,
+}
+
+impl<T: Buildozer> RecordingBuildozer<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            edits: Arc::new(Mutex::new(Vec::default())),
+        }
+    }
+
+    pub fn recorded_edits(&self) -> Vec<RecordedEdit> {
+        self.edits.lock().unwrap().clone()
+    }
+
+    pub fn buildozer_command_file(&self) -> String {
+        self.recorded_edits()
+            .iter()
+            .map(|e| format!("{}\n", e.to_buildozer_command()))
+            .collect()
+    }
+
+    fn record(&self, edit: RecordedEdit) {
+        let mut edits = self.edits.lock().unwrap();
+        // An add undone by a remove (or vice versa) nets out to no change to the BUILD file.
+        if let Some(pos) = edits.iter().position(|e| {
+            e.kind != edit.kind
+                && e.attr == edit.attr
+                && e.target == edit.target
+                && e.label == edit.label
+        }) {
+            edits.remove(pos);
+        } else {
+            edits.push(edit);
+        }
+    }
+}
+
+fn build_file_for_label(workspace_root: &Path, label: &str) -> Option<PathBuf> {
+    let package = label
+        .trim_start_matches("@@")
+        .trim_start_matches('@')
+        .strip_prefix("//")?
+        .split(':')
+        .next()?;
+    ["BUILD.bazel", "BUILD"]
+        .iter()
+        .map(|name| PathBuf::from(package).join(name))
+        .find(|p| workspace_root.join(p).exists())
+}
+
+impl RecordingBuildozer<BuildozerBinaryImpl> {
+    /// Applies the recorded edits to scratch copies of the affected BUILD files with the real buildozer,
+    /// returning a unified diff against the originals under `workspace_root`.
+    pub async fn unified_diff(&self, workspace_root: &Path) -> Result<String> {
+        let edits = self.recorded_edits();
+        if edits.is_empty() {
+            return Ok(String::default());
+        }
+
+        // Removed when dropped.
+        let scratch_root = tempfile::Builder::new()
+            .prefix("bazelfe-dry-run")
+            .tempdir()?;
+        self.diff_in_scratch_root(workspace_root, scratch_root.path(), &edits)
+            .await
+    }
+
+    async fn diff_in_scratch_root(
+        &self,
+        workspace_root: &Path,
+        scratch_root: &Path,
+        edits: &[RecordedEdit],
+    ) -> Result<String> {
+        std::fs::create_dir_all(scratch_root)?;
+        std::fs::write(scratch_root.join("WORKSPACE"), "")?;
+
+        let mut build_files: Vec<PathBuf> = Vec::default();
+        for edit in edits.iter() {
+            let target = crate::label_utils::sanitize_label(edit.target.clone());
+            match build_file_for_label(workspace_root, &target) {
+                Some(build_file) => {
+                    if !build_files.contains(&build_file) {
+                        let dest = scratch_root.join(&build_file);
+                        std::fs::create_dir_all(dest.parent().unwrap())?;
+                        std::fs::copy(workspace_root.join(&build_file), dest)?;
+                        build_files.push(build_file);
+                    }
+                }
+                None => warn!("Unable to find the BUILD file for {}", target),
+            }
+        }
+
+        let command_file = scratch_root.join("buildozer_commands.txt");
+        std::fs::write(&command_file, self.buildozer_command_file())?;
+        self.inner
+            .apply_command_file(scratch_root, &command_file)
+            .await?;
+
+        build_files.sort();
+        let mut diff = String::default();
+        for build_file in build_files.iter() {
+            let old = std::fs::read_to_string(workspace_root.join(build_file))?;
+            let new = std::fs::read_to_string(scratch_root.join(build_file))?;
+            diff.push_str(&unified_diff(&build_file.to_string_lossy(), &old, &new));
+        }
+        Ok(diff)
+    }
+}
+
+#[async_trait]
+impl<T: Buildozer> Buildozer for RecordingBuildozer<T> {
+    async fn print_attr(&self, attr: &BazelAttrTarget, label: &String) -> Result<Vec<String>> {
+        let mut values = self.inner.print_attr(attr, label).await?;
+        let label = crate::label_utils::sanitize_label(label.clone());
+        for edit in self.recorded_edits() {
+            if &edit.attr != attr || crate::label_utils::sanitize_label(edit.target) != label {
+                continue;
+            }
+            match edit.kind {
+                EditKind::Add => {
+                    if !values.contains(&edit.label) {
+                        values.push(edit.label);
+                    }
+                }
+                EditKind::Remove => values.retain(|v| v != &edit.label),
+            }
+        }
+        Ok(values)
+    }
+
+    async fn add_to(
+        &self,
+        to_what: &BazelAttrTarget,
+        target_to_operate_on: &String,
+        label_to_add: &String,
+    ) -> Result<()> {
+        self.record(RecordedEdit {
+            kind: EditKind::Add,
+            attr: to_what.clone(),
+            target: target_to_operate_on.clone(),
+            label: label_to_add.clone(),
+        });
+        Ok(())
+    }
+
+    async fn remove_from(
+        &self,
+        from_what: &BazelAttrTarget,
+        target_to_operate_on: &String,
+        label_to_remove: &String,
+    ) -> Result<()> {
+        self.record(RecordedEdit {
+            kind: EditKind::Remove,
+            attr: from_what.clone(),
+            target: target_to_operate_on.clone(),
+            label: label_to_remove.clone(),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct FixedDepsBuildozer(Vec<String>);
+
+    #[async_trait]
+    impl Buildozer for FixedDepsBuildozer {
+        async fn print_attr(
+            &self,
+            _attr: &BazelAttrTarget,
+            _label: &String,
+        ) -> Result<Vec<String>> {
+            Ok(self.0.clone())
+        }
+        async fn add_to(&self, _: &BazelAttrTarget, _: &String, _: &String) -> Result<()> {
+            panic!("The recording buildozer should never edit through")
+        }
+        async fn remove_from(&self, _: &BazelAttrTarget, _: &String, _: &String) -> Result<()> {
+            panic!("The recording buildozer should never edit through")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_without_editing() {
+        let buildozer = RecordingBuildozer::new(FixedDepsBuildozer(vec![String::from("//c:c")]));
+        let target = String::from("//src/foo");
+
+        buildozer
+            .add_to(&BazelAttrTarget::Deps, &target, &String::from("//a:a"))
+            .await
+            .unwrap();
+        buildozer
+            .add_to(&BazelAttrTarget::Deps, &target, &String::from("//b:b"))
+            .await
+            .unwrap();
+        assert!(buildozer
+            .remove_if_present_from(&BazelAttrTarget::Deps, &target, &String::from("//c:c"))
+            .await
+            .unwrap());
+        // Undoing an earlier suggestion drops it entirely.
+        buildozer
+            .remove_from(&BazelAttrTarget::Deps, &target, &String::from("//a:a"))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            buildozer
+                .print_attr(&BazelAttrTarget::Deps, &String::from("//src/foo:foo"))
+                .await
+                .unwrap(),
+            vec![String::from("//b:b")]
+        );
+        assert_eq!(
+            buildozer
+                .print_attr(&BazelAttrTarget::RuntimeDeps, &target)
+                .await
+                .unwrap(),
+            vec![String::from("//c:c")]
+        );
+        assert_eq!(
+            buildozer.buildozer_command_file(),
+            "add deps //b:b|//src/foo\nremove deps //c:c|//src/foo\n"
+        );
+    }
+}
@@ -0,0 +1,182 @@
+// Lines of unchanged context shown around each change, matching `diff -u`.
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(DiffOp, &'a str)> {
+    // Longest common subsequence table, BUILD files are small enough that the quadratic cost is fine.
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::default();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            ops.push((DiffOp::Equal, old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            ops.push((DiffOp::Delete, old[i]));
+            i += 1;
+        } else {
+            ops.push((DiffOp::Insert, new[j]));
+            j += 1;
+        }
+    }
+    ops.extend(old[i..].iter().map(|l| (DiffOp::Delete, *l)));
+    ops.extend(new[j..].iter().map(|l| (DiffOp::Insert, *l)));
+    ops
+}
+
+fn hunk_range(start: usize, len: usize) -> String {
+    // An empty range refers to the line before it, per the unified diff format.
+    let start = if len == 0 { start } else { start + 1 };
+    if len == 1 {
+        format!("{}", start)
+    } else {
+        format!("{},{}", start, len)
+    }
+}
+
+/// Renders a `diff -u` style patch turning `old` into `new`, empty if they match.
+pub fn unified_diff(path: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    let mut hunks = Vec::default();
+    let mut idx = 0;
+    while idx < ops.len() {
+        if ops[idx].0 == DiffOp::Equal {
+            idx += 1;
+            continue;
+        }
+        let start = idx.saturating_sub(CONTEXT);
+        let mut last_change = idx;
+        let mut cur = idx + 1;
+        while cur < ops.len() {
+            if ops[cur].0 != DiffOp::Equal {
+                last_change = cur;
+            } else if cur - last_change > 2 * CONTEXT {
+                break;
+            }
+            cur += 1;
+        }
+        let end = ops.len().min(last_change + CONTEXT + 1);
+        hunks.push(start..end);
+        idx = end;
+    }
+
+    if hunks.is_empty() {
+        return String::default();
+    }
+
+    let mut out = format!("--- a/{}\n+++ b/{}\n", path, path);
+    for hunk in hunks {
+        let before = &ops[..hunk.start];
+        let old_start = before.iter().filter(|e| e.0 != DiffOp::Insert).count();
+        let new_start = before.iter().filter(|e| e.0 != DiffOp::Delete).count();
+        let body = &ops[hunk];
+        let old_len = body.iter().filter(|e| e.0 != DiffOp::Insert).count();
+        let new_len = body.iter().filter(|e| e.0 != DiffOp::Delete).count();
+
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_start, old_len),
+            hunk_range(new_start, new_len)
+        ));
+        for (op, line) in body {
+            let marker = match op {
+                DiffOp::Equal => ' ',
+                DiffOp::Delete => '-',
+                DiffOp::Insert => '+',
+            };
+            out.push(marker);
+            out.push_str(line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_unified_diff() {
+        let old = "java_library(
+    name = \"foo\",
+    srcs = glob([\"*.java\"]),
+    deps = [
+        \"//a:a\",
+        \"//c:c\",
+    ],
+)
+";
+        let new = "java_library(
+    name = \"foo\",
+    srcs = glob([\"*.java\"]),
+    deps = [
+        \"//a:a\",
+        \"//b:b\",
+    ],
+)
+";
+        assert_eq!(
+            unified_diff("src/foo/BUILD", old, new),
+            "--- a/src/foo/BUILD
++++ b/src/foo/BUILD
+@@ -3,6 +3,6 @@
+     srcs = glob([\"*.java\"]),
+     deps = [
+         \"//a:a\",
+-        \"//c:c\",
++        \"//b:b\",
+     ],
+ )
+"
+        );
+        assert_eq!(unified_diff("src/foo/BUILD", old, old), "");
+    }
+
+    #[test]
+    fn test_separate_hunks() {
+        let old: String = (1..=20).map(|i| format!("line {}\n", i)).collect();
+        let new = old
+            .replace("line 2\n", "line 2\nadded\n")
+            .replace("line 19\n", "");
+        assert_eq!(
+            unified_diff("BUILD", &old, &new),
+            "--- a/BUILD
++++ b/BUILD
+@@ -1,5 +1,6 @@
+ line 1
+ line 2
++added
+ line 3
+ line 4
+ line 5
+@@ -16,5 +17,4 @@
+ line 16
+ line 17
+ line 18
+-line 19
+ line 20
+"
+        );
+    }
+}
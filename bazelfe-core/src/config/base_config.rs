@@ -1,6 +1,9 @@
 use super::error_processor::ErrorProcessor;
-use super::IndexerConfig;
 use super::{command_line_rewriter::CommandLineRewriter, DaemonConfig};
+use super::{
+    DependencyAttributesConfig, DependencyRepairConfig, DryRunConfig, IndexerConfig,
+    TargetGuessConfig,
+};
 use serde::{Deserialize, Deserializer};
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
@@ -36,6 +39,34 @@ pub struct Config {
 
     #[serde(rename = "DaemonConfig", default = "DaemonConfig::default")]
     pub daemon_config: DaemonConfig,
+
+    /// Suggest-only mode for the dependency repair loop, BUILD files are left untouched.
+    #[serde(rename = "DryRunConfig", default = "DryRunConfig::default")]
+    pub dry_run_config: DryRunConfig,
+
+    /// Which attribute dependency repairs edit, per rule kind.
+    #[serde(
+        rename = "DependencyAttributes",
+        default = "DependencyAttributesConfig::default"
+    )]
+    pub dependency_attributes: DependencyAttributesConfig,
+
+    /// Which labels dependency repairs may add, and which to favour.
+    #[serde(
+        rename = "DependencyRepair",
+        default = "DependencyRepairConfig::default"
+    )]
+    pub dependency_repair: DependencyRepairConfig,
+
+    /// Write a JSON report of each run to this file, see `bazel_runner::run_report` for the schema.
+    pub report_json: Option<std::path::PathBuf>,
+
+    /// Record the build events bazel sends us to this file, for replaying later.
+    pub record_build_events_to: Option<std::path::PathBuf>,
+
+    /// Labels to guess for classes the index doesn't know about.
+    #[serde(rename = "TargetGuessing", default = "TargetGuessConfig::default")]
+    pub target_guessing: TargetGuessConfig,
 }
 
 // We want to use the serde configured defaults for our default implemenation to not be
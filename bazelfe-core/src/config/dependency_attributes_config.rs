@@ -0,0 +1,74 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct DependencyAttributesConfig {
+    /// Attribute to add to when a target fails to compile, by rule kind. Defaults to `deps`.
+    /// Keys are the rule kind the build event stream reports, e.g. `scala_library`; macros are
+    /// expanded before that, so a target a macro created is keyed by the rule it instantiated.
+    #[serde(default)]
+    pub compile_time: HashMap<String, String>,
+
+    /// Attribute to add to when a test fails to load a class at runtime, by rule kind. Defaults to `runtime_deps`.
+    #[serde(default)]
+    pub runtime: HashMap<String, String>,
+}
+
+impl DependencyAttributesConfig {
+    pub fn compile_time_attribute(&self, target_kind: &Option<String>) -> &str {
+        target_kind
+            .as_ref()
+            .and_then(|kind| self.compile_time.get(kind))
+            .map(|attr| attr.as_str())
+            .unwrap_or("deps")
+    }
+
+    pub fn runtime_attribute(&self, target_kind: &Option<String>) -> &str {
+        target_kind
+            .as_ref()
+            .and_then(|kind| self.runtime.get(kind))
+            .map(|attr| attr.as_str())
+            .unwrap_or("runtime_deps")
+    }
+}
+
+impl Default for DependencyAttributesConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn with_attributes_specified() {
+        let dependency_attributes_config: DependencyAttributesConfig = toml::from_str(
+            r#"
+            compile_time = { scala_macro_library = "exports" }
+            runtime = { scala_test = "deps" }
+        "#,
+        )
+        .unwrap();
+
+        let kind = |k: &str| Some(String::from(k));
+        assert_eq!(
+            dependency_attributes_config.compile_time_attribute(&kind("scala_macro_library")),
+            "exports"
+        );
+        assert_eq!(
+            dependency_attributes_config.compile_time_attribute(&kind("java_library")),
+            "deps"
+        );
+        assert_eq!(
+            dependency_attributes_config.runtime_attribute(&kind("scala_test")),
+            "deps"
+        );
+        assert_eq!(
+            dependency_attributes_config.runtime_attribute(&None),
+            "runtime_deps"
+        );
+    }
+}
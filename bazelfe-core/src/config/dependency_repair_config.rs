@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct DependencyRepairConfig {
+    /// Labels never added to targets of a given rule kind, usually because the rule already depends on them implicitly.
+    #[serde(default = "default_forbidden_labels")]
+    pub forbidden_labels: HashMap<String, Vec<String>>,
+
+    /// Like `forbidden_labels`, but regexes that must match the whole label.
+    #[serde(default)]
+    pub forbidden_label_regexes: HashMap<String, Vec<String>>,
+
+    /// Regexes for labels to pick over the index's top candidate whenever they are among the candidates.
+    #[serde(default)]
+    pub prefer: Vec<String>,
+
+    /// Regexes for labels never added to any target.
+    #[serde(default)]
+    pub never_add: Vec<String>,
+}
+
+fn default_forbidden_labels() -> HashMap<String, Vec<String>> {
+    let scala_library = String::from("@third_party_jvm//3rdparty/jvm/org/scala_lang:scala_library");
+    let mut m = HashMap::new();
+    m.insert(String::from("scala_library"), vec![scala_library.clone()]);
+    m.insert(
+        String::from("scala_test"),
+        vec![
+            String::from("@third_party_jvm//3rdparty/jvm/org/scalatest"),
+            String::from("@third_party_jvm//3rdparty/jvm/org/scalatest:scalatest"),
+            scala_library,
+        ],
+    );
+    m
+}
+
+impl Default for DependencyRepairConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn with_rules_specified() {
+        let dependency_repair_config: DependencyRepairConfig = toml::from_str(
+            r#"
+            prefer = ["@maven//:.*"]
+            never_add = ["//legacy/.*"]
+
+            [forbidden_labels]
+            java_library = ["//tools:lombok"]
+
+            [forbidden_label_regexes]
+            scala_library = ["@io_bazel_rules_scala_.*"]
+        "#,
+        )
+        .unwrap();
+
+        let mut forbidden_labels = HashMap::new();
+        forbidden_labels.insert(
+            String::from("java_library"),
+            vec![String::from("//tools:lombok")],
+        );
+        let mut forbidden_label_regexes = HashMap::new();
+        forbidden_label_regexes.insert(
+            String::from("scala_library"),
+            vec![String::from("@io_bazel_rules_scala_.*")],
+        );
+        assert_eq!(
+            dependency_repair_config,
+            DependencyRepairConfig {
+                forbidden_labels,
+                forbidden_label_regexes,
+                prefer: vec![String::from("@maven//:.*")],
+                never_add: vec![String::from("//legacy/.*")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty_parse() {
+        let dependency_repair_config: DependencyRepairConfig = toml::from_str("").unwrap();
+
+        assert_eq!(
+            dependency_repair_config.forbidden_labels,
+            default_forbidden_labels()
+        );
+        assert!(dependency_repair_config.prefer.is_empty());
+    }
+}
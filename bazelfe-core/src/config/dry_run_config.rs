@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct DryRunConfig {
+    /// Run the dependency repair analysis, but only suggest BUILD file edits rather than applying them.
+    /// As nothing changes between attempts there is a single build, errors that would only show up once
+    /// the suggested edits are in place aren't covered.
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// If set, the suggested edits are also written here in `buildozer -f` command file format.
+    /// The file is always written, empty when there is nothing to suggest.
+    #[serde(default)]
+    pub buildozer_command_file: Option<PathBuf>,
+}
+
+impl Default for DryRunConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn with_command_file_specified() {
+        let dry_run_config: DryRunConfig = toml::from_str(
+            r#"
+            enabled = true
+            buildozer_command_file = "/tmp/suggested_edits.txt"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            dry_run_config,
+            DryRunConfig {
+                enabled: true,
+                buildozer_command_file: Some(PathBuf::from("/tmp/suggested_edits.txt")),
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty_parse() {
+        let dry_run_config: DryRunConfig = toml::from_str("").unwrap();
+
+        assert!(!dry_run_config.enabled);
+        assert_eq!(dry_run_config.buildozer_command_file, None);
+    }
+}
@@ -14,6 +14,18 @@ pub use daemon_config::DaemonConfig;
 mod indexer_config;
 pub use indexer_config::IndexerConfig;
 
+mod dry_run_config;
+pub use dry_run_config::DryRunConfig;
+
+mod dependency_attributes_config;
+pub use dependency_attributes_config::DependencyAttributesConfig;
+
+mod dependency_repair_config;
+pub use dependency_repair_config::DependencyRepairConfig;
+
+mod target_guess_config;
+pub use target_guess_config::{GuessTemplate, TargetGuessConfig};
+
 pub fn parse_config(input: &str) -> Result<Config, toml::de::Error> {
     toml::from_str(input)
 }
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+
+/// A label to try for a class missing from the index.
+///
+/// `{package_path}` is replaced with the class's package as a path (`com/example/foo`), `{last_segment}` with
+/// the package's last segment (`foo`) and `{class_name}` with the class name (`Bar`). A `*` path segment
+/// matches any directory in the workspace.
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct GuessTemplate {
+    pub template: String,
+    #[serde(default)]
+    pub priority: u16,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct TargetGuessConfig {
+    /// Tried in order, only guesses whose package has a BUILD or BUILD.bazel file are offered.
+    #[serde(default = "default_templates")]
+    pub templates: Vec<GuessTemplate>,
+}
+
+fn default_templates() -> Vec<GuessTemplate> {
+    vec![
+        GuessTemplate {
+            template: String::from("//src/main/scala/{package_path}:{last_segment}"),
+            priority: 0,
+        },
+        GuessTemplate {
+            template: String::from("//src/main/java/{package_path}:{last_segment}"),
+            priority: 0,
+        },
+    ]
+}
+
+impl Default for TargetGuessConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn with_templates_specified() {
+        let target_guess_config: TargetGuessConfig = toml::from_str(
+            r#"
+            [[templates]]
+            template = "//*/src/main/kotlin/{package_path}"
+            priority = 5
+
+            [[templates]]
+            template = "//*/src/main/java/{package_path}:{class_name}"
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            target_guess_config,
+            TargetGuessConfig {
+                templates: vec![
+                    GuessTemplate {
+                        template: String::from("//*/src/main/kotlin/{package_path}"),
+                        priority: 5,
+                    },
+                    GuessTemplate {
+                        template: String::from("//*/src/main/java/{package_path}:{class_name}"),
+                        priority: 0,
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn test_empty_parse() {
+        let target_guess_config: TargetGuessConfig = toml::from_str("").unwrap();
+
+        assert_eq!(target_guess_config.templates, default_templates());
+    }
+}
@@ -3,6 +3,8 @@ use regex::Regex;
 
 use super::JavaClassImportRequest;
 
+pub(super) const SRC_FN: &str = "indirect_dependency";
+
 // Example usage:
 // JAVA:
 // package com.example;
@@ -16,7 +18,7 @@ fn build_class_import_request(
         src_file_name: source_file_name,
         class_name,
         exact_only: false,
-        src_fn: "package_does_not_exist",
+        src_fn: SRC_FN,
         priority: 1,
     }
 }
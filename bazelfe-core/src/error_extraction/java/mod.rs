@@ -17,6 +17,11 @@ pub struct JavaClassImportRequest {
     pub priority: i32,
 }
 
+/// Strict deps errors, the same lines also name the exact label to add.
+pub fn is_indirect_dependency(src_fn: &str) -> bool {
+    src_fn.strip_prefix("java::") == Some(error_indirect_dependency::SRC_FN)
+}
+
 impl JavaClassImportRequest {
     pub fn to_class_import_request(self) -> super::ClassImportRequest {
         super::ClassImportRequest {
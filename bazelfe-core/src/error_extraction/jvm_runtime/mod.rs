@@ -0,0 +1,77 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::{ActionRequest, ClassImportRequest};
+
+// Class loading failures from a running JVM, as seen in test logs:
+// java.lang.ClassNotFoundException: com.example.foo.Bar
+// Caused by: java.lang.NoClassDefFoundError: com/example/foo/Bar$Inner
+// These name the exact class missing from the runtime classpath.
+
+pub const SRC_FN_PREFIX: &str = "jvm_runtime::";
+
+fn build_class_import_request(class_name: String, src_fn: &str) -> ClassImportRequest {
+    ClassImportRequest {
+        class_name,
+        exact_only: false,
+        src_fn: format!("{}{}", SRC_FN_PREFIX, src_fn),
+        priority: 20,
+    }
+}
+
+pub fn extract_errors(input: &str) -> Vec<ActionRequest> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"java\.lang\.(ClassNotFoundException|NoClassDefFoundError): ([A-Za-z0-9_$]+(?:[./][A-Za-z0-9_$]+)+)"
+        )
+        .unwrap();
+    }
+
+    let mut result: Vec<ActionRequest> = Vec::default();
+    for ln in input.lines() {
+        if let Some(captures) = RE.captures(ln) {
+            let src_fn = match captures.get(1).unwrap().as_str() {
+                "ClassNotFoundException" => "class_not_found",
+                _ => "no_class_def_found",
+            };
+            let class_name = captures.get(2).unwrap().as_str().replace(['/', '$'], ".");
+            let request = ActionRequest::Prefix(build_class_import_request(class_name, src_fn));
+            if !result.contains(&request) {
+                result.push(request);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_class_loading_failures() {
+        let sample_output =
+            "Exception in thread \"main\" java.lang.NoClassDefFoundError: com/example/foo/Bar$Inner
+	at com.example.Main.main(Main.java:5)
+Caused by: java.lang.ClassNotFoundException: com.example.foo.Bar$Inner
+	at java.base/jdk.internal.loader.BuiltinClassLoader.loadClass(BuiltinClassLoader.java:641)
+java.lang.NoClassDefFoundError: Could not initialize class com.example.Baz
+java.lang.ClassNotFoundException: com.example.foo.Bar$Inner
+";
+
+        assert_eq!(
+            extract_errors(sample_output),
+            vec![
+                ActionRequest::Prefix(build_class_import_request(
+                    String::from("com.example.foo.Bar.Inner"),
+                    "no_class_def_found"
+                )),
+                ActionRequest::Prefix(build_class_import_request(
+                    String::from("com.example.foo.Bar.Inner"),
+                    "class_not_found"
+                )),
+            ]
+        );
+        assert!(extract_errors(sample_output).iter().all(|e| e.is_runtime()));
+    }
+}
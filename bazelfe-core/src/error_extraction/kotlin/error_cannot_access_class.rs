@@ -0,0 +1,76 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::KotlinClassImportRequest;
+
+// Example usage:
+// KOTLIN:
+// package com.example
+// class Foo : com.example.a.ATrait
+// where ATrait extends a class from a dependency we only have transitively.
+
+fn build_class_import_request(
+    source_file_name: String,
+    class_name: String,
+) -> KotlinClassImportRequest {
+    KotlinClassImportRequest {
+        src_file_name: source_file_name,
+        class_name,
+        exact_only: true,
+        src_fn: "extract_cannot_access_class",
+        priority: 20,
+    }
+}
+
+pub(in crate::error_extraction::kotlin) fn extract(
+    input: &str,
+) -> Option<Vec<KotlinClassImportRequest>> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"^(?:e: )?(?:file://)?(.*\.kts?)[:(].*[Cc]annot access class '([A-Za-z0-9_.$]+)'\. Check your module classpath"
+        )
+        .unwrap();
+    }
+
+    let mut result = None;
+    for ln in input.lines() {
+        if let Some(captures) = RE.captures(ln) {
+            let src_file_name = captures.get(1).unwrap().as_str();
+            // Nested classes are reported with a `$`, the index holds them as `Outer.Inner`.
+            let class_name = captures.get(2).unwrap().as_str().replace('$', ".");
+            let class_import_request =
+                build_class_import_request(src_file_name.to_string(), class_name);
+            result
+                .get_or_insert_with(Vec::default)
+                .push(class_import_request);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    #[test]
+    fn test_cannot_access_class() {
+        let sample_output = "src/main/kotlin/com/example/Foo.kt:7:7: error: cannot access class 'com.example.a.ATrait'. Check your module classpath for missing or conflicting dependencies
+class Foo : com.example.b.BTrait {
+      ^
+e: file:///home/me/proj/src/main/kotlin/com/example/Bar.kt:3:1 Cannot access class 'com.example.c.Outer$Inner'. Check your module classpath for missing or conflicting dependencies.
+";
+        assert_eq!(
+            extract(sample_output),
+            Some(vec![
+                build_class_import_request(
+                    String::from("src/main/kotlin/com/example/Foo.kt"),
+                    String::from("com.example.a.ATrait"),
+                ),
+                build_class_import_request(
+                    String::from("/home/me/proj/src/main/kotlin/com/example/Bar.kt"),
+                    String::from("com.example.c.Outer.Inner"),
+                ),
+            ])
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::source_dependencies::{Import, ParsedFile, SelectorType};
+
+use super::KotlinClassImportRequest;
+
+// Example usage:
+// KOTLIN:
+// package com.example
+// import com.google.common.collect.ImmutableList
+// import com.example.foo.Bar as FooBar
+// import kotlinx.coroutines.*
+
+fn build_class_import_request(
+    source_file_name: String,
+    class_name: String,
+    exact_only: bool,
+    priority: i32,
+) -> KotlinClassImportRequest {
+    KotlinClassImportRequest {
+        src_file_name: source_file_name,
+        class_name,
+        exact_only,
+        src_fn: "extract_unresolved_reference",
+        priority,
+    }
+}
+
+// The failing line is itself an import, so whatever it names is what is missing.
+fn requests_for_import_line(src_file_name: &str, e: &Import) -> Vec<KotlinClassImportRequest> {
+    match &e.suffix {
+        SelectorType::SelectorList(lst) => lst
+            .iter()
+            .map(|(orig, _)| {
+                build_class_import_request(
+                    src_file_name.to_string(),
+                    format!("{}.{}", e.prefix_section, orig),
+                    false,
+                    10,
+                )
+            })
+            .collect(),
+        SelectorType::WildcardSelector => vec![build_class_import_request(
+            src_file_name.to_string(),
+            e.prefix_section.to_string(),
+            false,
+            1,
+        )],
+        SelectorType::NoSelector => vec![build_class_import_request(
+            src_file_name.to_string(),
+            e.prefix_section.to_string(),
+            false,
+            50,
+        )],
+    }
+}
+
+// The reference is in the body of the file, so work out what the name could have been imported as.
+fn requests_for_reference(
+    src_file_name: &str,
+    reference: &str,
+    file_data: &ParsedFile,
+) -> Vec<KotlinClassImportRequest> {
+    let mut v = Vec::default();
+    for e in file_data.imports.iter() {
+        match &e.suffix {
+            SelectorType::SelectorList(lst) => {
+                for (orig, alias) in lst {
+                    if alias.as_deref().unwrap_or(orig) == reference {
+                        v.push(build_class_import_request(
+                            src_file_name.to_string(),
+                            format!("{}.{}", e.prefix_section, orig),
+                            false,
+                            10,
+                        ));
+                    }
+                }
+            }
+            SelectorType::NoSelector => {
+                if e.prefix_section.rsplit('.').next() == Some(reference) {
+                    v.push(build_class_import_request(
+                        src_file_name.to_string(),
+                        e.prefix_section.to_string(),
+                        false,
+                        10,
+                    ));
+                }
+            }
+            SelectorType::WildcardSelector => (),
+        }
+    }
+    if !v.is_empty() {
+        return v;
+    }
+
+    for e in file_data.imports.iter() {
+        if e.suffix == SelectorType::WildcardSelector {
+            v.push(build_class_import_request(
+                src_file_name.to_string(),
+                format!("{}.{}", e.prefix_section, reference),
+                true,
+                1,
+            ));
+        }
+    }
+    if let Some(pkg) = file_data.package_name.as_ref() {
+        v.push(build_class_import_request(
+            src_file_name.to_string(),
+            format!("{}.{}", pkg, reference),
+            true,
+            -3,
+        ));
+    }
+    v
+}
+
+pub(in crate::error_extraction::kotlin) fn extract(
+    input: &str,
+    file_parse_cache: &mut super::FileParseCache,
+) -> Option<Vec<KotlinClassImportRequest>> {
+    lazy_static! {
+        // Covers both the K1 and K2 compiler output formats:
+        // src/Foo.kt:3:8: error: unresolved reference: Bar
+        // e: src/Foo.kt: (3, 8): Unresolved reference: Bar
+        // e: file:///src/Foo.kt:3:8 Unresolved reference 'Bar'.
+        static ref RE: Regex = Regex::new(
+            r"^(?:e: )?(?:file://)?(.*?\.kts?):\s*\(?(\d+)(?:[:,]\s*\d+)?\)?:?\s+(?:error:\s+)?[Uu]nresolved reference:?\s+'?`?([A-Za-z0-9_]+)`?'?"
+        )
+        .unwrap();
+    }
+
+    let mut result = None;
+    let lines: Vec<&str> = input.lines().collect();
+    for (pos, ln) in lines.iter().enumerate() {
+        let captures = match RE.captures(ln) {
+            None => continue,
+            Some(captures) => captures,
+        };
+        let src_file_name = captures.get(1).unwrap().as_str();
+        let src_line_number: u32 = captures.get(2).unwrap().as_str().parse().unwrap();
+        let reference = captures.get(3).unwrap().as_str();
+
+        let class_import_request =
+            if let Some(file_data) = file_parse_cache.load_file(src_file_name) {
+                match file_data
+                    .imports
+                    .iter()
+                    .find(|e| e.line_number == src_line_number)
+                {
+                    Some(e) => requests_for_import_line(src_file_name, e),
+                    None => requests_for_reference(src_file_name, reference, file_data),
+                }
+            } else {
+                // Without the source, the K1 output still echoes the offending line after the error.
+                lines
+                    .get(pos + 1)
+                    .and_then(|target_line| {
+                        crate::source_dependencies::kotlin::parse_imports(target_line).ok()
+                    })
+                    .and_then(|matched| matched.into_iter().next())
+                    .map(|e| requests_for_import_line(src_file_name, &e))
+                    .unwrap_or_default()
+            };
+
+        result
+            .get_or_insert_with(Vec::default)
+            .extend(class_import_request);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn file_cache_at(path: &str) -> super::super::FileParseCache {
+        super::super::FileParseCache::init_from_par(
+            path.to_string(),
+            crate::source_dependencies::kotlin::parse_file(
+                "package com.example
+
+import com.google.common.collect.ImmutableList
+import com.example.foo.Bar as FooBar
+import kotlinx.coroutines.*
+
+class Example(val b: FooBar) {
+  val l = ImmutableList.of(1)
+  val c = Baz()
+}
+",
+            )
+            .unwrap(),
+        )
+    }
+
+    fn file_cache() -> super::super::FileParseCache {
+        file_cache_at("src/main/kotlin/com/example/Example.kt")
+    }
+
+    #[test]
+    fn test_unresolved_reference_on_import() {
+        let sample_output =
+            "src/main/kotlin/com/example/Example.kt:3:12: error: unresolved reference: google
+import com.google.common.collect.ImmutableList
+           ^
+";
+        assert_eq!(
+            extract(sample_output, &mut file_cache()),
+            Some(vec![build_class_import_request(
+                String::from("src/main/kotlin/com/example/Example.kt"),
+                String::from("com.google.common.collect.ImmutableList"),
+                false,
+                50
+            )])
+        );
+    }
+
+    #[test]
+    fn test_unresolved_reference_without_source() {
+        let sample_output =
+            "src/main/kotlin/com/example/Missing.kt:3:12: error: unresolved reference: google
+import com.google.common.collect.ImmutableList
+           ^
+";
+        assert_eq!(
+            extract(sample_output, &mut file_cache()),
+            Some(vec![build_class_import_request(
+                String::from("src/main/kotlin/com/example/Missing.kt"),
+                String::from("com.google.common.collect.ImmutableList"),
+                false,
+                50
+            )])
+        );
+    }
+
+    #[test]
+    fn test_unresolved_reference_in_body() {
+        let sample_output =
+            "e: file:///src/main/kotlin/com/example/Example.kt:7:22 Unresolved reference 'FooBar'.
+e: src/main/kotlin/com/example/Example.kt: (9, 11): Unresolved reference: Baz
+";
+        assert_eq!(
+            extract(
+                sample_output,
+                &mut file_cache_at("/src/main/kotlin/com/example/Example.kt")
+            ),
+            Some(vec![build_class_import_request(
+                String::from("/src/main/kotlin/com/example/Example.kt"),
+                String::from("com.example.foo.Bar"),
+                false,
+                10
+            )])
+        );
+
+        assert_eq!(
+            extract(sample_output, &mut file_cache()),
+            Some(vec![
+                build_class_import_request(
+                    String::from("src/main/kotlin/com/example/Example.kt"),
+                    String::from("kotlinx.coroutines.Baz"),
+                    true,
+                    1
+                ),
+                build_class_import_request(
+                    String::from("src/main/kotlin/com/example/Example.kt"),
+                    String::from("com.example.Baz"),
+                    true,
+                    -3
+                ),
+            ])
+        );
+    }
+}
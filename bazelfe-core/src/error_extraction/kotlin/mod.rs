@@ -0,0 +1,90 @@
+use std::{collections::HashMap, path::Path};
+
+use crate::source_dependencies::ParsedFile;
+
+mod error_cannot_access_class;
+mod error_unresolved_reference;
+
+#[derive(Debug, PartialEq, Clone, PartialOrd, Ord, Eq)]
+pub struct KotlinClassImportRequest {
+    pub src_file_name: String,
+    pub class_name: String,
+    pub exact_only: bool,
+    pub src_fn: &'static str,
+    pub priority: i32,
+}
+
+impl KotlinClassImportRequest {
+    pub fn to_class_import_request(self) -> super::ClassImportRequest {
+        super::ClassImportRequest {
+            class_name: self.class_name,
+            exact_only: self.exact_only,
+            src_fn: format!("kotlin::{}", self.src_fn),
+            priority: self.priority,
+        }
+    }
+}
+
+// A missing file or one we can't parse just has nothing to offer, failing to read one is an error.
+fn do_load_file(path_str: &str) -> std::io::Result<Option<ParsedFile>> {
+    let file_contents = match std::fs::read_to_string(Path::new(path_str)) {
+        Ok(file_contents) => file_contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    Ok(crate::source_dependencies::kotlin::parse_file(&file_contents).ok())
+}
+
+pub(in crate::error_extraction) struct FileParseCache {
+    file_parse_cache: HashMap<String, ParsedFile>,
+}
+impl FileParseCache {
+    pub fn new() -> Self {
+        Self {
+            file_parse_cache: HashMap::new(),
+        }
+    }
+    // used in tests
+    #[allow(dead_code)]
+    pub fn init_from_par(key: String, v: ParsedFile) -> Self {
+        let mut map = HashMap::new();
+        map.insert(key, v);
+        Self {
+            file_parse_cache: map,
+        }
+    }
+    pub fn load_file(&mut self, file_path: &str) -> Option<&ParsedFile> {
+        if !self.file_parse_cache.contains_key(file_path) {
+            match do_load_file(file_path) {
+                Ok(Some(parsed_file)) => {
+                    self.file_parse_cache
+                        .insert(file_path.to_string(), parsed_file);
+                }
+                Ok(None) => (),
+                Err(e) => warn!("Unable to read {} to look up its imports: {}", file_path, e),
+            }
+        }
+        self.file_parse_cache.get(file_path)
+    }
+}
+
+pub fn extract_errors(input: &str) -> Vec<super::ActionRequest> {
+    let mut file_parse_cache: FileParseCache = FileParseCache::new();
+    vec![
+        error_unresolved_reference::extract(input, &mut file_parse_cache),
+        error_cannot_access_class::extract(input),
+    ]
+    .into_iter()
+    .flat_map(|e| e.into_iter().flat_map(|inner| inner.into_iter()))
+    .filter_map(|o| {
+        // A bare name on its own would match far too many classes to be useful.
+        if o.class_name.find('.').is_none() {
+            None
+        } else {
+            let r = o.to_class_import_request();
+            debug!("Found class import request: {:#?}", r);
+            Some(super::ActionRequest::Prefix(r))
+        }
+    })
+    .collect()
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct ClassImportRequest {
     pub class_name: String,
     pub exact_only: bool,
@@ -22,14 +24,14 @@ impl Ord for ClassImportRequest {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct ClassSuffixMatch {
     pub suffix: String,
     pub src_fn: String,
     pub priority: i32,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum ActionRequest {
     Prefix(ClassImportRequest),
     Suffix(ClassSuffixMatch),
@@ -41,11 +43,37 @@ impl ActionRequest {
             ActionRequest::Suffix(s) => s.priority,
         }
     }
+
+    pub fn src_fn(&self) -> &str {
+        match self {
+            ActionRequest::Prefix(p) => &p.src_fn,
+            ActionRequest::Suffix(s) => &s.src_fn,
+        }
+    }
+
+    /// Found in a strict deps error, which strict deps processing can repair without the index.
+    pub fn is_strict_deps_error(&self) -> bool {
+        java::is_indirect_dependency(self.src_fn())
+    }
+
+    /// Found in a test log rather than a compiler error, so repaired through the runtime deps.
+    pub fn is_runtime(&self) -> bool {
+        self.src_fn().starts_with(jvm_runtime::SRC_FN_PREFIX)
+    }
 }
 
+/// Highest priority first, ties are broken on the request itself so the order is total.
 impl Ord for ActionRequest {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.priority().cmp(&other.priority()).reverse()
+        self.priority()
+            .cmp(&other.priority())
+            .reverse()
+            .then_with(|| match (self, other) {
+                (ActionRequest::Prefix(a), ActionRequest::Prefix(b)) => a.cmp(b),
+                (ActionRequest::Suffix(a), ActionRequest::Suffix(b)) => a.cmp(b),
+                (ActionRequest::Prefix(_), ActionRequest::Suffix(_)) => std::cmp::Ordering::Less,
+                (ActionRequest::Suffix(_), ActionRequest::Prefix(_)) => std::cmp::Ordering::Greater,
+            })
     }
 }
 impl PartialOrd for ActionRequest {
@@ -55,6 +83,8 @@ impl PartialOrd for ActionRequest {
 }
 
 pub mod java;
+pub mod jvm_runtime;
+pub mod kotlin;
 pub mod scala;
 
 pub fn extract_errors(target_kind: &Option<String>, input: &str) -> Vec<ActionRequest> {
@@ -70,6 +100,12 @@ pub fn extract_errors(target_kind: &Option<String>, input: &str) -> Vec<ActionRe
         "java_library" => Some(java::extract_errors(input)),
         "java_test" => Some(java::extract_errors(input)),
         "java_binary" => Some(java::extract_errors(input)),
+        // Any of them can have java sources compiled alongside the kotlin ones.
+        kind if kind.starts_with("kt_jvm_") => {
+            let mut errors = kotlin::extract_errors(input);
+            errors.extend(java::extract_errors(input));
+            Some(errors)
+        }
 
         _ => None,
     });
@@ -79,6 +115,30 @@ pub fn extract_errors(target_kind: &Option<String>, input: &str) -> Vec<ActionRe
     } else {
         let mut v = scala::extract_errors(input);
         v.extend(java::extract_errors(input));
+        v.extend(kotlin::extract_errors(input));
         v
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kotlin_targets_extract_java_errors() {
+        let sample_output =
+            "src/main/java/com/example/Example.java:3: error: package com.google.common.base does not exist
+    import com.google.common.base.Preconditions;
+";
+        let java_errors = java::extract_errors(sample_output);
+        assert!(!java_errors.is_empty());
+        for kind in ["kt_jvm_library", "kt_jvm_test", "kt_jvm_binary"] {
+            assert_eq!(
+                extract_errors(&Some(String::from(kind)), sample_output),
+                java_errors,
+                "{} should run the java extractors too",
+                kind
+            );
+        }
+    }
+}
@@ -1,10 +1,10 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{collections::HashSet, path::PathBuf, sync::Arc};
 
 use bazelfe_bazel_wrapper::bep::BazelEventHandler;
 use bazelfe_protos::build_event_stream;
 use build_event_stream::file::File::Uri;
 
-use crate::{config::IndexerConfig, index_table};
+use crate::{config::IndexerConfig, index_table, jvm_indexer::bzlmod::LazyRepoMapping};
 
 use super::BuildEventResponse;
 
@@ -25,6 +25,7 @@ impl Response {
 pub struct IndexNewResults {
     index_table: index_table::IndexTable,
     blacklist_target_kind: HashSet<String>,
+    repo_mapping: Arc<LazyRepoMapping>,
 }
 
 #[async_trait::async_trait]
@@ -47,8 +48,15 @@ impl IndexNewResults {
         Self {
             index_table,
             blacklist_target_kind,
+            repo_mapping: Default::default(),
         }
     }
+
+    /// Index labels under the apparent repo names from `repo_mapping` rather than bzlmod's canonical ones.
+    pub fn with_repo_mapping(mut self, repo_mapping: Arc<LazyRepoMapping>) -> Self {
+        self.repo_mapping = repo_mapping;
+        self
+    }
     pub async fn process(
         &self,
         event: &bazelfe_bazel_wrapper::bep::build_events::hydrated_stream::HydratedInfo,
@@ -68,14 +76,21 @@ impl IndexNewResults {
                 let label = tce.label.clone();
                 let mut files = Vec::default();
 
+                // Outputs live under the canonical repo name, so match on that before rewriting the label.
                 let external_match = if label.starts_with('@') {
-                    let idx = label.find('/').unwrap();
-                    let repo = &label[1..idx];
-                    let path_segment = format!("external/{}", repo);
-                    Some(path_segment)
+                    let repo = label.trim_start_matches('@');
+                    let idx = repo.find('/').unwrap();
+                    let repo = &repo[..idx];
+                    if repo.is_empty() {
+                        None
+                    } else {
+                        let path_segment = format!("external/{}", repo);
+                        Some(path_segment)
+                    }
                 } else {
                     None
                 };
+                let label = self.repo_mapping.to_apparent_label(&label).await;
 
                 for of in tce.output_files.iter() {
                     if let Some(Uri(e)) = of.file.as_ref() {
@@ -0,0 +1,112 @@
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+
+use crate::config::DependencyRepairConfig;
+
+/// `DependencyRepairConfig` with its regexes compiled, consulted for every candidate dependency.
+#[derive(Clone, Debug)]
+pub struct DependencyRules {
+    forbidden_labels: HashMap<String, HashSet<String>>,
+    forbidden_label_regexes: HashMap<String, Vec<Regex>>,
+    prefer: Vec<Regex>,
+    never_add: Vec<Regex>,
+}
+
+fn full_match(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+fn compile_all(patterns: &[String]) -> Result<Vec<Regex>, regex::Error> {
+    patterns.iter().map(|p| full_match(p)).collect()
+}
+
+impl DependencyRules {
+    pub fn from_config(config: &DependencyRepairConfig) -> Result<Self, regex::Error> {
+        let mut forbidden_label_regexes = HashMap::default();
+        for (kind, patterns) in config.forbidden_label_regexes.iter() {
+            forbidden_label_regexes.insert(kind.clone(), compile_all(patterns)?);
+        }
+        Ok(Self {
+            forbidden_labels: config
+                .forbidden_labels
+                .iter()
+                .map(|(kind, labels)| (kind.clone(), labels.iter().cloned().collect()))
+                .collect(),
+            forbidden_label_regexes,
+            prefer: compile_all(&config.prefer)?,
+            never_add: compile_all(&config.never_add)?,
+        })
+    }
+
+    /// Whether `label` may be added as a dependency of a target of `target_kind`.
+    pub fn allows(&self, target_kind: &Option<String>, label: &str) -> bool {
+        if self.never_add.iter().any(|r| r.is_match(label)) {
+            return false;
+        }
+        if let Some(kind) = target_kind.as_ref() {
+            if let Some(forbidden) = self.forbidden_labels.get(kind) {
+                if forbidden.contains(label) {
+                    return false;
+                }
+            }
+            if let Some(forbidden) = self.forbidden_label_regexes.get(kind) {
+                if forbidden.iter().any(|r| r.is_match(label)) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    pub fn is_preferred(&self, label: &str) -> bool {
+        self.prefer.iter().any(|r| r.is_match(label))
+    }
+}
+
+impl Default for DependencyRules {
+    fn default() -> Self {
+        Self::from_config(&DependencyRepairConfig::default()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rules_from_config() {
+        let config: DependencyRepairConfig = toml::from_str(
+            r#"
+            prefer = ["@maven//:com_google_.*"]
+            never_add = ["//legacy/.*"]
+
+            [forbidden_label_regexes]
+            java_library = ["@maven//:org_projectlombok_.*"]
+        "#,
+        )
+        .unwrap();
+        let rules = DependencyRules::from_config(&config).unwrap();
+        let java_library = Some(String::from("java_library"));
+
+        assert!(!rules.allows(&None, "//legacy/foo:foo"));
+        assert!(rules.allows(&None, "//src/legacy/foo:foo"));
+        assert!(!rules.allows(&java_library, "@maven//:org_projectlombok_lombok"));
+        assert!(rules.allows(&None, "@maven//:org_projectlombok_lombok"));
+        // The default forbidden labels still apply.
+        assert!(!rules.allows(
+            &Some(String::from("scala_library")),
+            "@third_party_jvm//3rdparty/jvm/org/scala_lang:scala_library"
+        ));
+
+        assert!(rules.is_preferred("@maven//:com_google_guava_guava"));
+        assert!(!rules.is_preferred("//third_party:com_google_guava_guava"));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        let config: DependencyRepairConfig =
+            toml::from_str(r#"never_add = ["//foo/(.*"]"#).unwrap();
+        assert!(DependencyRules::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+
+use crate::config::GuessTemplate;
+
+// Splits a fully qualified class name into its package segments and the outermost class name, if any.
+fn split_class_name(class_name: &str) -> (Vec<&str>, Option<&str>) {
+    let mut sections: Vec<&str> = class_name.split('.').collect();
+
+    // heuristic looking for a class name, to ignore separate from the package...
+
+    let mut idx = 0;
+    let mut found = false;
+    while idx < sections.len() {
+        let ele = &sections[idx];
+        if ele.starts_with(|ch: char| ch.is_uppercase()) {
+            found = true;
+            break;
+        }
+        idx += 1;
+    }
+
+    let class_name = if found {
+        let class_name = sections[idx];
+        sections.truncate(idx);
+        Some(class_name)
+    } else {
+        None
+    };
+    (sections, class_name)
+}
+
+/// Fills in the templates for a class name, without checking the guesses exist.
+fn expand_templates(templates: &[GuessTemplate], class_name: &str) -> Vec<(u16, String)> {
+    let (sections, class_name) = split_class_name(class_name);
+
+    if sections.len() < 3 {
+        return vec![];
+    }
+
+    let package_path = sections.join("/");
+    let last_segment = sections.last().unwrap();
+
+    let mut guesses: Vec<(u16, String)> = Vec::default();
+    for template in templates.iter() {
+        let mut guess = template
+            .template
+            .replace("{package_path}", &package_path)
+            .replace("{last_segment}", last_segment);
+        if guess.contains("{class_name}") {
+            match class_name {
+                Some(class_name) => guess = guess.replace("{class_name}", class_name),
+                None => continue,
+            }
+        }
+        if !guesses.iter().any(|(_, e)| e == &guess) {
+            guesses.push((template.priority, guess));
+        }
+    }
+    guesses
+}
+
+fn is_build_package(path: &Path) -> bool {
+    path.join("BUILD").is_file() || path.join("BUILD.bazel").is_file()
+}
+
+// Walks the package path, with each `*` segment matching any workspace directory.
+fn expand_package_path(current: PathBuf, remaining: &[&str], results: &mut Vec<PathBuf>) {
+    match remaining.split_first() {
+        None => {
+            if is_build_package(&current) {
+                results.push(current);
+            }
+        }
+        Some((&"*", rest)) => {
+            let mut children: Vec<PathBuf> = match std::fs::read_dir(&current) {
+                Ok(entries) => entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| {
+                        let name = e.file_name();
+                        let name = name.to_string_lossy();
+                        !name.starts_with('.') && !name.starts_with("bazel-")
+                    })
+                    .map(|e| e.path())
+                    .filter(|p| p.is_dir())
+                    .collect(),
+                Err(_) => return,
+            };
+            children.sort();
+            for child in children.into_iter() {
+                expand_package_path(child, rest, results);
+            }
+        }
+        Some((segment, rest)) => expand_package_path(current.join(segment), rest, results),
+    }
+}
+
+/// Resolves a guessed label against the workspace, returning the labels whose package has a BUILD file.
+/// Labels in external repositories can't be checked here so are kept as is.
+fn resolve_in_workspace(workspace_root: &Path, label: &str) -> Vec<String> {
+    let label_body = match label.strip_prefix("//") {
+        Some(body) => body,
+        None => return vec![label.to_string()],
+    };
+    let (package, target) = match label_body.split_once(':') {
+        Some((package, target)) => (package, Some(target)),
+        None => (label_body, None),
+    };
+    let segments: Vec<&str> = package.split('/').filter(|s| !s.is_empty()).collect();
+
+    let mut packages = Vec::default();
+    expand_package_path(workspace_root.to_path_buf(), &segments, &mut packages);
+
+    packages
+        .into_iter()
+        .filter_map(|p| {
+            let relative = p.strip_prefix(workspace_root).ok()?;
+            let relative = relative.to_string_lossy();
+            Some(match target {
+                Some(target) => format!("//{}:{}", relative, target),
+                None => format!("//{}", relative),
+            })
+        })
+        .collect()
+}
+
+fn get_guesses_for_class_name(
+    templates: &[GuessTemplate],
+    workspace_root: &Path,
+    class_name: &str,
+) -> Vec<(u16, String)> {
+    let mut guesses: Vec<(u16, String)> = Vec::default();
+    for (priority, guess) in expand_templates(templates, class_name).into_iter() {
+        for label in resolve_in_workspace(workspace_root, &guess).into_iter() {
+            if !guesses.iter().any(|(_, e)| e == &label) {
+                guesses.push((priority, label));
+            }
+        }
+    }
+    guesses
+}
+
+/// Guesses labels for classes missing from the index from the configured templates,
+/// keeping only those whose package exists in the workspace.
+#[derive(Clone, Debug)]
+pub struct TargetGuesser {
+    templates: Vec<GuessTemplate>,
+    workspace_root: PathBuf,
+}
+
+impl TargetGuesser {
+    pub fn new(templates: Vec<GuessTemplate>, workspace_root: PathBuf) -> Self {
+        Self {
+            templates,
+            workspace_root,
+        }
+    }
+
+    pub fn guesses_for_class_name(&self, class_name: &str) -> Vec<(u16, String)> {
+        get_guesses_for_class_name(&self.templates, &self.workspace_root, class_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TargetGuessConfig;
+
+    fn default_guesses(class_name: &str) -> Vec<(u16, String)> {
+        expand_templates(&TargetGuessConfig::default().templates, class_name)
+    }
+
+    #[test]
+    fn test_guess_for_class_name() {
+        assert_eq!(
+            default_guesses("com.example.foo.bar.baz"),
+            vec![
+                (
+                    0,
+                    String::from("//src/main/scala/com/example/foo/bar/baz:baz")
+                ),
+                (
+                    0,
+                    String::from("//src/main/java/com/example/foo/bar/baz:baz")
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_guess_for_class_name_too_short() {
+        assert_eq!(default_guesses("com.example"), Vec::<(u16, String)>::new());
+    }
+
+    #[test]
+    fn test_guess_for_class_name_strip_class_name() {
+        assert_eq!(
+            default_guesses("com.example.foo.bar.baz.MyObject.InnerObject"),
+            vec![
+                (
+                    0,
+                    String::from("//src/main/scala/com/example/foo/bar/baz:baz")
+                ),
+                (
+                    0,
+                    String::from("//src/main/java/com/example/foo/bar/baz:baz")
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn test_guess_for_class_name_too_short_post_strip() {
+        assert_eq!(
+            default_guesses("com.example.MyObject.MyObject.InnerObject"),
+            Vec::<(u16, String)>::new()
+        );
+    }
+
+    #[test]
+    fn test_guess_for_class_start_with_class_name() {
+        assert_eq!(
+            default_guesses("MyObject.MyObject.InnerObject"),
+            Vec::<(u16, String)>::new()
+        );
+    }
+
+    #[test]
+    fn test_guess_with_class_name_template() {
+        let templates = vec![
+            GuessTemplate {
+                template: String::from("//{package_path}:{class_name}"),
+                priority: 3,
+            },
+            GuessTemplate {
+                template: String::from("@maven//:{last_segment}"),
+                priority: 0,
+            },
+        ];
+        assert_eq!(
+            expand_templates(&templates, "com.example.foo.Bar.Inner"),
+            vec![
+                (3, String::from("//com/example/foo:Bar")),
+                (0, String::from("@maven//:foo"))
+            ]
+        );
+
+        // No class name to fill in, so only the template without it applies.
+        assert_eq!(
+            expand_templates(&templates, "com.example.foo"),
+            vec![(0, String::from("@maven//:foo"))]
+        );
+    }
+
+    #[test]
+    fn test_guesses_validated_against_build_files() {
+        let workspace = tempfile::tempdir().unwrap();
+        let root = workspace.path();
+        for (package, build_file) in &[
+            ("src/main/java/com/example/foo", "BUILD"),
+            ("service_a/src/main/java/com/example/foo", "BUILD.bazel"),
+            ("service_b/src/main/java/com/example/foo", ""),
+            ("bazel-out/src/main/java/com/example/foo", "BUILD"),
+        ] {
+            let dir = root.join(package);
+            std::fs::create_dir_all(&dir).unwrap();
+            if !build_file.is_empty() {
+                std::fs::write(dir.join(build_file), "").unwrap();
+            }
+        }
+
+        let templates = vec![
+            GuessTemplate {
+                template: String::from("//*/src/main/java/{package_path}:{last_segment}"),
+                priority: 2,
+            },
+            GuessTemplate {
+                template: String::from("//src/main/scala/{package_path}:{last_segment}"),
+                priority: 1,
+            },
+            GuessTemplate {
+                template: String::from("//src/main/java/{package_path}:{last_segment}"),
+                priority: 0,
+            },
+        ];
+
+        assert_eq!(
+            get_guesses_for_class_name(&templates, root, "com.example.foo.Bar"),
+            vec![
+                (
+                    2,
+                    String::from("//service_a/src/main/java/com/example/foo:foo")
+                ),
+                (0, String::from("//src/main/java/com/example/foo:foo"))
+            ]
+        );
+    }
+}
@@ -1,37 +1,54 @@
-use std::{collections::HashMap, collections::HashSet, sync::Arc, time::Instant};
+use std::{collections::HashMap, collections::HashSet, path::PathBuf, sync::Arc, time::Instant};
 
 use bazelfe_bazel_wrapper::bep::BazelEventHandler;
 use tokio::sync::{Mutex, RwLock};
 
 use crate::bazel_query::BazelQueryEngine;
 
+use bazelfe_bazel_wrapper::bep::build_events::build_event_server::bazel_event::TestStatus;
 use bazelfe_bazel_wrapper::bep::build_events::hydrated_stream;
 
-use crate::{buildozer_driver::Buildozer, config::Config, index_table};
-
-use self::{
-    command_line_runner::ExecutionResult,
-    process_user_defined_actions::UserDefinedActionsStateCache,
+use crate::{
+    buildozer_driver::{BazelAttrTarget, Buildozer},
+    config::Config,
+    index_table,
 };
 
+use self::process_user_defined_actions::UserDefinedActionsStateCache;
+
 mod command_line_runner;
+mod dependency_rules;
+mod expand_target_to_guesses;
 mod process_action_failure_error;
 mod process_build_abort_errors;
 mod process_missing_dependency_errors;
+mod process_strict_deps_errors;
 mod process_user_defined_actions;
+mod repair_history;
 mod shared_utils;
 
 pub use command_line_runner::CommandLineRunner;
 pub use command_line_runner::CommandLineRunnerImpl;
+pub use command_line_runner::ExecutionResult;
+pub use dependency_rules::DependencyRules;
+pub use expand_target_to_guesses::TargetGuesser;
+pub use repair_history::{MissingReference, RepairHistory, SuccessfulRepair};
 
 use super::BuildEventResponse;
 
+/// Prefixed to the reason of every edit only suggested in dry run mode.
+pub const DRY_RUN_SUGGESTION: &str = "Suggested in dry run mode, not applied.";
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum TargetStoryAction {
     WouldHaveAddedDependency {
         what: String,
         why: String,
     },
+    WouldHaveRemovedDependency {
+        what: String,
+        why: String,
+    },
     AddedDependency {
         added_what: String,
         why: String,
@@ -48,6 +65,27 @@ pub enum TargetStoryAction {
     },
     Success,
 }
+impl TargetStoryAction {
+    /// In dry run mode edits are only recorded, so report them as suggestions rather than changes made.
+    fn into_suggestion(self) -> Self {
+        match self {
+            TargetStoryAction::AddedDependency { added_what, why } => {
+                TargetStoryAction::WouldHaveAddedDependency {
+                    what: added_what,
+                    why: format!("{} {}", DRY_RUN_SUGGESTION, why),
+                }
+            }
+            TargetStoryAction::RemovedDependency { removed_what, why } => {
+                TargetStoryAction::WouldHaveRemovedDependency {
+                    what: removed_what,
+                    why: format!("{} {}", DRY_RUN_SUGGESTION, why),
+                }
+            }
+            other => other,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct TargetStory {
     pub target: String,
@@ -81,9 +119,12 @@ pub struct ProcessBazelFailures<T: Buildozer, U: CommandLineRunner> {
     epoch: Arc<RwLock<usize>>,
     buildozer: T,
     command_line_runner: U,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     user_defined_action_cache: Arc<UserDefinedActionsStateCache>,
     bazel_query_engine: Arc<dyn BazelQueryEngine>,
+    repair_history: Arc<RwLock<RepairHistory>>,
+    dependency_rules: Arc<DependencyRules>,
+    target_guesser: Arc<TargetGuesser>,
 }
 
 #[async_trait::async_trait]
@@ -105,21 +146,50 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
         command_line_runner: U,
         config: Arc<Config>,
         bazel_query_engine: Arc<dyn BazelQueryEngine>,
+        workspace_root: PathBuf,
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let user_defined_action_cache =
             Arc::new(UserDefinedActionsStateCache::from_config(&config)?);
+        let dependency_rules = Arc::new(DependencyRules::from_config(&config.dependency_repair)?);
+        let target_guesser = Arc::new(TargetGuesser::new(
+            config.target_guessing.templates.clone(),
+            workspace_root,
+        ));
         Ok(Self {
             previous_global_seen: Arc::new(RwLock::new(HashMap::default())),
             index_table,
             buildozer,
             command_line_runner,
             epoch: Arc::new(RwLock::new(0)),
-            _config: config,
+            config,
             user_defined_action_cache,
             bazel_query_engine,
+            repair_history: Arc::new(RwLock::new(RepairHistory::default())),
+            dependency_rules,
+            target_guesser,
         })
     }
 
+    /// Resume from the state a previous invocation left behind.
+    pub fn with_repair_history(mut self, repair_history: RepairHistory) -> Self {
+        self.repair_history = Arc::new(RwLock::new(repair_history));
+        self
+    }
+
+    pub fn buildozer(&self) -> &T {
+        &self.buildozer
+    }
+
+    /// The loaded history updated with everything learned during this invocation.
+    pub async fn repair_history(&self) -> RepairHistory {
+        let mut repair_history = self.repair_history.read().await.clone();
+        let previous_global_seen = self.previous_global_seen.read().await;
+        for (label, state) in previous_global_seen.iter() {
+            repair_history.update_target(label, &*state.lock().await);
+        }
+        repair_history
+    }
+
     pub async fn advance_epoch(&self) {
         let mut e = self.epoch.write().await;
         *e += 1;
@@ -133,8 +203,11 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
             Some(e) => Arc::clone(e),
             None => {
                 drop(handle);
+                let initial_state = self.repair_history.read().await.current_state_for(label);
                 let mut handle = arc.write().await;
-                handle.insert(label.to_string(), Default::default());
+                handle
+                    .entry(label.to_string())
+                    .or_insert_with(|| Arc::new(Mutex::new(initial_state)));
                 drop(handle);
                 let handle = arc.read().await;
                 Arc::clone(handle.get(label).unwrap())
@@ -142,6 +215,53 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
         }
     }
 
+    /// Whatever we added for a request that is still in the attribute it was added to fixed it.
+    /// Compile time repairs are confirmed once the target builds, runtime ones once its test passes.
+    async fn record_successful_repairs(
+        &self,
+        label: &str,
+        target_kind: &Option<String>,
+        runtime: bool,
+    ) {
+        let state = match self.previous_global_seen.read().await.get(label) {
+            Some(state) => Arc::clone(state),
+            None => return,
+        };
+        let mut state = state.lock().await;
+        if !state
+            .added_target_for_class
+            .keys()
+            .any(|req| req.is_runtime() == runtime)
+        {
+            return;
+        }
+        let dependency_attributes = &self.config.dependency_attributes;
+        let attr = BazelAttrTarget::from_name(if runtime {
+            dependency_attributes.runtime_attribute(target_kind)
+        } else {
+            dependency_attributes.compile_time_attribute(target_kind)
+        });
+        let current_deps: HashSet<String> = self
+            .buildozer
+            .print_attr(&attr, &label.to_string())
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+
+        let mut repair_history = self.repair_history.write().await;
+        state.added_target_for_class.retain(|req, added| {
+            if req.is_runtime() != runtime {
+                return true;
+            }
+            let fixes: Vec<&String> = added.iter().filter(|e| current_deps.contains(*e)).collect();
+            for fix in fixes.iter() {
+                repair_history.record_success(req, target_kind, fix);
+            }
+            fixes.is_empty()
+        });
+    }
+
     pub async fn process(
         &self,
         event: &hydrated_stream::HydratedInfo,
@@ -160,16 +280,38 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
                 )
                 .await;
 
-                let missing_dependencies_response =
+                let (strict_deps_response, handled_by_strict_deps) =
+                    process_strict_deps_errors::process_action_failed(
+                        self.buildozer.clone(),
+                        action_failed_error_info,
+                        &self.dependency_rules,
+                    )
+                    .await;
+
+                // The same failure can carry missing class errors strict deps doesn't cover,
+                // the ones it does are left to it.
+                let missing_dependencies_response = {
+                    let repair_history = self.repair_history.read().await;
+                    let attr = BazelAttrTarget::from_name(
+                        self.config
+                            .dependency_attributes
+                            .compile_time_attribute(&action_failed_error_info.target_kind),
+                    );
                     process_missing_dependency_errors::process_missing_dependency_errors(
                         &mut prev_data,
                         self.buildozer.clone(),
                         action_failed_error_info,
+                        handled_by_strict_deps,
                         &self.index_table,
                         epoch,
                         Arc::clone(&self.bazel_query_engine),
+                        &repair_history,
+                        &self.dependency_rules,
+                        &self.target_guesser,
+                        &attr,
                     )
-                    .await;
+                    .await
+                };
 
                 let user_defined_action_failure =
                     process_user_defined_actions::process_action_failed(
@@ -181,6 +323,7 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
 
                 vec![
                     action_failed_response,
+                    strict_deps_response,
                     missing_dependencies_response,
                     user_defined_action_failure,
                 ]
@@ -209,6 +352,8 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
             }
             hydrated_stream::HydratedInfo::TargetComplete(tce) => {
                 if tce.success && !tce.label.is_empty() {
+                    self.record_successful_repairs(&tce.label, &tce.target_kind, false)
+                        .await;
                     vec![Response::new(vec![TargetStory {
                         target: tce.label.clone(),
                         action: TargetStoryAction::Success,
@@ -252,11 +397,54 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
 
                 res
             }
-            hydrated_stream::HydratedInfo::TestResult(_) => {
-                vec![]
+            hydrated_stream::HydratedInfo::TestResult(test_result_info) => {
+                if test_result_info.test_summary_event.test_status == TestStatus::Passed {
+                    self.record_successful_repairs(
+                        &test_result_info.test_summary_event.label,
+                        &test_result_info.target_kind,
+                        true,
+                    )
+                    .await;
+                }
+                let arc_resp = self
+                    .label_to_prev_data_arc(test_result_info.test_summary_event.label.as_str())
+                    .await;
+                let mut prev_data = arc_resp.lock().await;
+                let epoch = *self.epoch.read().await;
+                let repair_history = self.repair_history.read().await;
+                let attr = BazelAttrTarget::from_name(
+                    self.config
+                        .dependency_attributes
+                        .runtime_attribute(&test_result_info.target_kind),
+                );
+
+                vec![
+                    process_missing_dependency_errors::process_missing_runtime_dependency_errors(
+                        &mut prev_data,
+                        self.buildozer.clone(),
+                        test_result_info,
+                        &self.index_table,
+                        epoch,
+                        Arc::clone(&self.bazel_query_engine),
+                        &repair_history,
+                        &self.dependency_rules,
+                        &self.target_guesser,
+                        &attr,
+                    )
+                    .await,
+                ]
             }
         };
+        let dry_run = self.config.dry_run_config.enabled;
         r.into_iter()
+            .map(|mut e| {
+                if dry_run {
+                    for story in e.target_story_entries.iter_mut() {
+                        story.action = story.action.clone().into_suggestion();
+                    }
+                }
+                e
+            })
             .filter_map(|e| {
                 if !e.target_story_entries.is_empty() {
                     Some(super::BuildEventResponse::ProcessedBuildFailures(e))
@@ -267,3 +455,134 @@ impl<T: Buildozer, U: CommandLineRunner> ProcessBazelFailures<T, U> {
             .collect()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use bazelfe_protos::build_event_stream;
+
+    use super::*;
+    use crate::buildozer_driver::{EditKind, ExecuteResultError, RecordingBuildozer};
+
+    #[derive(Clone, Debug)]
+    struct EmptyBuildozer();
+
+    #[async_trait::async_trait]
+    impl Buildozer for EmptyBuildozer {
+        async fn print_attr(
+            &self,
+            _attr: &BazelAttrTarget,
+            _label: &String,
+        ) -> Result<Vec<String>, ExecuteResultError> {
+            Ok(Vec::default())
+        }
+        async fn add_to(
+            &self,
+            _: &BazelAttrTarget,
+            _: &String,
+            _: &String,
+        ) -> Result<(), ExecuteResultError> {
+            panic!("Edits should only be recorded")
+        }
+        async fn remove_from(
+            &self,
+            _: &BazelAttrTarget,
+            _: &String,
+            _: &String,
+        ) -> Result<(), ExecuteResultError> {
+            panic!("Edits should only be recorded")
+        }
+    }
+
+    #[derive(Debug)]
+    struct NoOpBazelQueryEngine();
+
+    #[async_trait::async_trait]
+    impl BazelQueryEngine for NoOpBazelQueryEngine {
+        async fn deps(&self, _target: &str) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+            Ok(HashSet::default())
+        }
+
+        async fn allrdeps(
+            &self,
+            _target: &str,
+        ) -> Result<HashSet<String>, Box<dyn std::error::Error>> {
+            Ok(HashSet::default())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strict_deps_errors_skip_the_index() {
+        let javac_output = "src/main/java/com/example/Example.java:3: error: [strict] Using type com.google.protobuf.util.JsonFormat from an indirect dependency (TOOL_INFO: \"@com_google_protobuf//:protobuf_java_util\"). See command below **
+  private static final JsonFormat.Printer JSON_PRINTER = JsonFormat.printer();
+                       ^
+ ** Please add the following dependencies:
+  @com_google_protobuf//:protobuf_java_util to //src/main/java/com/example:example
+ ** You can use the following buildozer command:
+buildozer 'add deps @com_google_protobuf//:protobuf_java_util' //src/main/java/com/example:example
+
+1 error
+";
+        let mut stderr = tempfile::NamedTempFile::new().unwrap();
+        stderr.write_all(javac_output.as_bytes()).unwrap();
+        let stderr = stderr.into_temp_path();
+
+        // The index would have gone with a different target for the class.
+        let index_table = index_table::IndexTable::default();
+        index_table
+            .insert(
+                "com.google.protobuf.util.JsonFormat",
+                (
+                    10,
+                    String::from("@maven//:com_google_protobuf_protobuf_java_util"),
+                ),
+            )
+            .await;
+
+        let workspace = tempfile::tempdir().unwrap();
+        let buildozer = RecordingBuildozer::new(EmptyBuildozer());
+        let process_bazel_failures = ProcessBazelFailures::new(
+            index_table,
+            buildozer.clone(),
+            CommandLineRunnerImpl(),
+            Arc::new(Config::default()),
+            Arc::new(NoOpBazelQueryEngine()),
+            workspace.path().to_path_buf(),
+        )
+        .unwrap();
+        process_bazel_failures.advance_epoch().await;
+
+        let event =
+            hydrated_stream::HydratedInfo::ActionFailed(hydrated_stream::ActionFailedErrorInfo {
+                label: String::from("//src/main/java/com/example:example"),
+                stdout: None,
+                stderr: Some(build_event_stream::File {
+                    name: String::from("stderr"),
+                    path_prefix: Vec::default(),
+                    digest: String::default(),
+                    length: -1,
+                    file: Some(build_event_stream::file::File::Uri(format!(
+                        "file://{}",
+                        stderr.to_str().unwrap()
+                    ))),
+                }),
+                target_kind: Some(String::from("java_library")),
+            });
+        process_bazel_failures.process(&event).await;
+
+        let edits: Vec<(EditKind, String, String)> = buildozer
+            .recorded_edits()
+            .into_iter()
+            .map(|e| (e.kind, e.target, e.label))
+            .collect();
+        assert_eq!(
+            edits,
+            vec![(
+                EditKind::Add,
+                String::from("//src/main/java/com/example:example"),
+                String::from("@com_google_protobuf//:protobuf_java_util"),
+            )]
+        );
+    }
+}
@@ -6,9 +6,11 @@ use std::{
     time::Instant,
 };
 
-use lazy_static::lazy_static;
-
-use bazelfe_bazel_wrapper::bep::build_events::hydrated_stream::{ActionFailedErrorInfo, HasFiles};
+use bazelfe_bazel_wrapper::bep::build_events::build_event_server::bazel_event::TestStatus;
+use bazelfe_bazel_wrapper::bep::build_events::hydrated_stream::{
+    ActionFailedErrorInfo, HasFiles, TestResultInfo,
+};
+use bazelfe_protos::build_event_stream::file::File::{Contents, Uri};
 
 use crate::{
     bazel_query::BazelQueryEngine,
@@ -17,39 +19,15 @@ use crate::{
     index_table,
 };
 
-use super::CurrentState;
-
-fn is_potentially_valid_target(target_kind: &Option<String>, label: &str) -> bool {
-    lazy_static! {
-      // These are things that are already implicit dependencencies so we should ensure they are not included
-        static ref FORBIDDEN_TARGETS_BY_TYPE: HashMap<String, HashSet<String>> = {
-            let mut m = HashMap::new();
-            let mut cur_s = HashSet::new();
-            cur_s.insert(String::from(
-                "@third_party_jvm//3rdparty/jvm/org/scala_lang:scala_library",
-            ));
-            m.insert(String::from("scala_library"), cur_s);
-
-            let mut cur_s = HashSet::new();
-            cur_s.insert(String::from("@third_party_jvm//3rdparty/jvm/org/scalatest"));
-            cur_s.insert(String::from(
-                "@third_party_jvm//3rdparty/jvm/org/scalatest:scalatest",
-            ));
-            cur_s.insert(String::from(
-                "@third_party_jvm//3rdparty/jvm/org/scala_lang:scala_library",
-            ));
-            m.insert(String::from("scala_test"), cur_s);
-            m
-        };
-    }
+use super::{CurrentState, DependencyRules, RepairHistory, TargetGuesser};
 
-    if let Some(forbidden_targets) = target_kind
-        .as_ref()
-        .and_then(|nme| FORBIDDEN_TARGETS_BY_TYPE.get(nme))
-    {
-        if forbidden_targets.contains(label) {
-            return false;
-        }
+fn is_potentially_valid_target(
+    dependency_rules: &DependencyRules,
+    target_kind: &Option<String>,
+    label: &str,
+) -> bool {
+    if !dependency_rules.allows(target_kind, label) {
+        return false;
     }
 
     let prepared_path = label.strip_prefix("//").and_then(|e| e.split(':').next());
@@ -78,28 +56,25 @@ async fn path_to_import_requests(
 pub async fn load_up_ignore_references<T: Buildozer + Clone + Send + Sync + 'static>(
     global_previous_seen: &mut HashSet<String>,
     buildozer: &T,
-    action_failed_error_info: &ActionFailedErrorInfo,
+    label: &str,
+    attrs: &[&BazelAttrTarget],
 ) -> HashSet<String> {
     let mut to_ignore = HashSet::new();
-    let d = buildozer
-        .print_attr(&BazelAttrTarget::Deps, &action_failed_error_info.label)
-        .await
-        .unwrap();
-    d.into_iter().for_each(|dep| {
-        to_ignore.insert(crate::label_utils::sanitize_label(dep));
-    });
+    let label = label.to_string();
+    for attr in attrs {
+        let d = buildozer.print_attr(attr, &label).await.unwrap();
+        d.into_iter().for_each(|dep| {
+            to_ignore.insert(crate::label_utils::sanitize_label(dep));
+        });
+    }
 
     global_previous_seen.iter().for_each(|dep| {
         to_ignore.insert(crate::label_utils::sanitize_label(dep.to_string()));
     });
 
-    to_ignore.insert(crate::label_utils::sanitize_label(
-        action_failed_error_info.label.clone(),
-    ));
+    to_ignore.insert(crate::label_utils::sanitize_label(label.clone()));
 
-    global_previous_seen.insert(crate::label_utils::sanitize_label(
-        action_failed_error_info.label.clone(),
-    ));
+    global_previous_seen.insert(crate::label_utils::sanitize_label(label));
 
     to_ignore
 }
@@ -155,15 +130,52 @@ async fn generate_all_action_requests(
     for path in action_failed_error_info.path_bufs().into_iter() {
         path_to_import_requests(action_failed_error_info, &path, &mut action_requests).await
     }
+    for content in action_failed_error_info.inline_contents() {
+        action_requests.extend(error_extraction::extract_errors(
+            &action_failed_error_info.target_kind,
+            &content,
+        ));
+    }
     expand_candidate_import_requests(action_requests)
 }
+
+async fn generate_runtime_action_requests(test_result_info: &TestResultInfo) -> Vec<ActionRequest> {
+    let mut action_requests: Vec<ActionRequest> = vec![];
+    for output_file in test_result_info.test_summary_event.output_files.iter() {
+        match output_file {
+            Uri(uri) => {
+                if let Some(path) = uri.strip_prefix("file://") {
+                    if path.ends_with("test.log") {
+                        if let Ok(loaded_path) = tokio::fs::read_to_string(path).await {
+                            action_requests.extend(error_extraction::jvm_runtime::extract_errors(
+                                &loaded_path,
+                            ));
+                        }
+                    }
+                }
+            }
+            // Recordings inline the test log, see `BuildEventRecorder::record`.
+            Contents(contents) => action_requests.extend(
+                error_extraction::jvm_runtime::extract_errors(&String::from_utf8_lossy(contents)),
+            ),
+        }
+    }
+    expand_candidate_import_requests(action_requests)
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn process_missing_dependency_errors<T: Buildozer>(
     current_state: &mut CurrentState,
     buildozer: T,
     action_failed_error_info: &ActionFailedErrorInfo,
+    handled_by_strict_deps: bool,
     index_table: &index_table::IndexTable,
     epoch: usize,
     bazel_query_engine: Arc<dyn BazelQueryEngine>,
+    repair_history: &RepairHistory,
+    dependency_rules: &DependencyRules,
+    target_guesser: &TargetGuesser,
+    attr: &BazelAttrTarget,
 ) -> super::Response {
     if epoch <= current_state.epoch {
         return super::Response::new(Vec::default());
@@ -171,11 +183,15 @@ pub async fn process_missing_dependency_errors<T: Buildozer>(
     let ignore_dep_references: HashSet<String> = load_up_ignore_references(
         &mut current_state.ignore_list,
         &buildozer,
-        action_failed_error_info,
+        &action_failed_error_info.label,
+        &[&BazelAttrTarget::Deps, attr],
     )
     .await;
-    let all_requests: Vec<ActionRequest> =
+    let mut all_requests: Vec<ActionRequest> =
         generate_all_action_requests(action_failed_error_info).await;
+    if handled_by_strict_deps {
+        all_requests.retain(|req| !req.is_strict_deps_error());
+    }
     debug!("generate_all_action_requests: {:#?}", all_requests);
     let (response, local_previous_seen, remove_from_ignore_list) =
         inner_process_missing_dependency_errors(
@@ -187,6 +203,10 @@ pub async fn process_missing_dependency_errors<T: Buildozer>(
             ignore_dep_references,
             &mut current_state.added_target_for_class,
             bazel_query_engine,
+            repair_history,
+            dependency_rules,
+            target_guesser,
+            attr,
         )
         .await;
 
@@ -202,6 +222,67 @@ pub async fn process_missing_dependency_errors<T: Buildozer>(
     current_state.epoch = epoch;
     response
 }
+
+/// Like `process_missing_dependency_errors`, but for classes a test failed to load at runtime.
+#[allow(clippy::too_many_arguments)]
+pub async fn process_missing_runtime_dependency_errors<T: Buildozer>(
+    current_state: &mut CurrentState,
+    buildozer: T,
+    test_result_info: &TestResultInfo,
+    index_table: &index_table::IndexTable,
+    epoch: usize,
+    bazel_query_engine: Arc<dyn BazelQueryEngine>,
+    repair_history: &RepairHistory,
+    dependency_rules: &DependencyRules,
+    target_guesser: &TargetGuesser,
+    attr: &BazelAttrTarget,
+) -> super::Response {
+    if epoch <= current_state.epoch
+        || test_result_info.test_summary_event.test_status != TestStatus::Failed
+    {
+        return super::Response::new(Vec::default());
+    }
+    let all_requests: Vec<ActionRequest> = generate_runtime_action_requests(test_result_info).await;
+    if all_requests.is_empty() {
+        return super::Response::new(Vec::default());
+    }
+    debug!("generate_runtime_action_requests: {:#?}", all_requests);
+    let label = &test_result_info.test_summary_event.label;
+    let ignore_dep_references: HashSet<String> = load_up_ignore_references(
+        &mut current_state.ignore_list,
+        &buildozer,
+        label,
+        &[&BazelAttrTarget::Deps, attr],
+    )
+    .await;
+    let (response, local_previous_seen, remove_from_ignore_list) =
+        inner_process_missing_dependency_errors(
+            buildozer,
+            label,
+            &test_result_info.target_kind,
+            index_table,
+            all_requests,
+            ignore_dep_references,
+            &mut current_state.added_target_for_class,
+            bazel_query_engine,
+            repair_history,
+            dependency_rules,
+            target_guesser,
+            attr,
+        )
+        .await;
+
+    for e in local_previous_seen.into_iter() {
+        current_state.ignore_list.insert(e);
+    }
+    for e in remove_from_ignore_list.into_iter() {
+        current_state.ignore_list.remove(&e);
+    }
+
+    current_state.epoch = epoch;
+    response
+}
+#[allow(clippy::too_many_arguments)]
 async fn inner_process_missing_dependency_errors<'a, T: Buildozer>(
     buildozer: T,
     label: &'a str,
@@ -211,6 +292,10 @@ async fn inner_process_missing_dependency_errors<'a, T: Buildozer>(
     ignore_dep_references: HashSet<String>,
     previous_added: &'a mut HashMap<ActionRequest, HashSet<String>>,
     bazel_query_engine: Arc<dyn BazelQueryEngine>,
+    repair_history: &'a RepairHistory,
+    dependency_rules: &'a DependencyRules,
+    target_guesser: &'a TargetGuesser,
+    attr: &'a BazelAttrTarget,
 ) -> (super::Response, HashSet<String>, HashSet<String>) {
     let mut local_previous_seen: HashSet<String> = HashSet::new();
     let mut local_previous_seen_prefix: HashSet<String> = HashSet::new();
@@ -238,7 +323,11 @@ async fn inner_process_missing_dependency_errors<'a, T: Buildozer>(
                         .await
                         .unwrap_or_default()
                 } else {
-                    index_table.get_or_guess(&prefix.class_name).await
+                    index_table
+                        .get_or_guess(&prefix.class_name, |class_name| {
+                            target_guesser.guesses_for_class_name(class_name)
+                        })
+                        .await
                 }
             }
         };
@@ -262,20 +351,14 @@ async fn inner_process_missing_dependency_errors<'a, T: Buildozer>(
         };
 
         for prev in previous_added_for_req.iter() {
-            let prev_deps = buildozer
-                .print_attr(&BazelAttrTarget::Deps, &label)
-                .await
-                .unwrap();
+            let prev_deps = buildozer.print_attr(attr, &label).await.unwrap();
 
             if prev_deps.contains(prev) {
                 debug!(
                     "Buildozer action: remove dependency {:?} to {:?}",
                     prev, &label
                 );
-                buildozer
-                    .remove_from(&BazelAttrTarget::Deps, &label, prev)
-                    .await
-                    .unwrap();
+                buildozer.remove_from(attr, &label, prev).await.unwrap();
 
                 target_stories.push(super::TargetStory {
                     target: unsanitized_label.to_string(),
@@ -298,6 +381,7 @@ async fn inner_process_missing_dependency_errors<'a, T: Buildozer>(
             }
         }
 
+        let preferred_fix = repair_history.preferred_fix(&req, target_kind);
         let mut target_to_add = None;
         for target_entry in &candidates.read_iter().await {
             let target: String = index_table
@@ -305,7 +389,7 @@ async fn inner_process_missing_dependency_errors<'a, T: Buildozer>(
                 .await
                 .unwrap();
             if !ignore_dep_references.contains(&target)
-                && is_potentially_valid_target(target_kind, &target)
+                && is_potentially_valid_target(dependency_rules, target_kind, &target)
             {
                 // If our top candidate hits to be a local previous seen stop
                 // processing this class
@@ -317,12 +401,24 @@ async fn inner_process_missing_dependency_errors<'a, T: Buildozer>(
                     continue 'req_point;
                 }
 
-                if target_to_add.is_none() {
-                    target_to_add = Some(target.clone());
+                // A fix that worked before for this kind of target beats the configured preferences,
+                // which in turn beat the index ordering.
+                let rank = if preferred_fix == Some(target.as_str()) {
+                    2
+                } else if dependency_rules.is_preferred(&target) {
+                    1
+                } else {
+                    0
+                };
+                if target_to_add
+                    .as_ref()
+                    .is_none_or(|(_, best_rank)| rank > *best_rank)
+                {
+                    target_to_add = Some((target.clone(), rank));
                 }
             }
         }
-        if let Some(target_to_add) = target_to_add {
+        if let Some((target_to_add, _)) = target_to_add {
             // otherwise... add the dependency with buildozer here
             // then add it ot the local seen dependencies
 
@@ -352,7 +448,7 @@ async fn inner_process_missing_dependency_errors<'a, T: Buildozer>(
                 previous_added_for_req.insert(target_to_add.clone());
 
                 buildozer
-                    .add_to(&BazelAttrTarget::Deps, &label, &target_to_add)
+                    .add_to(attr, &label, &target_to_add)
                     .await
                     .unwrap();
                 target_stories.push(super::TargetStory {
@@ -396,6 +492,7 @@ mod tests {
 
     use crate::{
         buildozer_driver::ExecuteResultError,
+        config::TargetGuessConfig,
         error_extraction::{ActionRequest, ClassImportRequest, ClassSuffixMatch},
     };
 
@@ -421,19 +518,33 @@ mod tests {
 
     #[test]
     fn test_is_potentially_valid_target() {
-        assert!(is_potentially_valid_target(&None, "@foo/bar/baz"));
+        let dependency_rules = DependencyRules::default();
+        assert!(is_potentially_valid_target(
+            &dependency_rules,
+            &None,
+            "@foo/bar/baz"
+        ));
 
-        assert!(!is_potentially_valid_target(&None, "//foo/bar/foo"));
+        assert!(!is_potentially_valid_target(
+            &dependency_rules,
+            &None,
+            "//foo/bar/foo"
+        ));
 
         let mut d = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         d.push("resources/tests/bazel/sample_build");
         let built_path = format!("//{}", d.to_str().unwrap());
-        assert!(is_potentially_valid_target(&None, &built_path));
+        assert!(is_potentially_valid_target(
+            &dependency_rules,
+            &None,
+            &built_path
+        ));
     }
 
     #[test]
     fn test_is_potentially_valid_target_forbidden_by_type() {
         assert!(!is_potentially_valid_target(
+            &DependencyRules::default(),
             &Some(String::from("scala_library")),
             "@third_party_jvm//3rdparty/jvm/org/scala_lang:scala_library"
         ));
@@ -740,9 +851,17 @@ mod tests {
             &mut global_previous_seen,
             buildozer.clone(),
             &action_failed_error_info,
+            false,
             &index_table,
             1,
             Arc::new(NoOpMBazelQueryEngine()),
+            &RepairHistory::default(),
+            &DependencyRules::default(),
+            &TargetGuesser::new(
+                TargetGuessConfig::default().templates,
+                working_bazel_tempdir.path().to_path_buf(),
+            ),
+            &BazelAttrTarget::Deps,
         )
         .await;
 
@@ -797,6 +916,13 @@ mod tests {
                 ignore_dep_references,
                 &mut previous_added,
                 Arc::new(NoOpMBazelQueryEngine()),
+                &RepairHistory::default(),
+                &DependencyRules::default(),
+                &TargetGuesser::new(
+                    TargetGuessConfig::default().templates,
+                    working_bazel_tempdir.path().to_path_buf(),
+                ),
+                &BazelAttrTarget::Deps,
             )
             .await;
 
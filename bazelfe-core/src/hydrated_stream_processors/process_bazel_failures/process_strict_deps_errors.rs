@@ -0,0 +1,509 @@
+use lazy_static::lazy_static;
+
+use crate::buildozer_driver::{BazelAttrTarget, Buildozer};
+use crate::label_utils::sanitize_label;
+use bazelfe_bazel_wrapper::bep::build_events::hydrated_stream;
+use regex::Regex;
+use std::time::Instant;
+
+use super::dependency_rules::DependencyRules;
+use super::shared_utils::text_logs_from_failure;
+
+// Strict and unused deps checkers name the exact label to add or remove, so unlike missing
+// class errors there is nothing to look up in the index here.
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum BuildozerDepCmd {
+    Add {
+        target_to_operate_on: String,
+        dependency_to_add: String,
+    },
+    Remove {
+        target_to_operate_on: String,
+        dependency_to_remove: String,
+    },
+}
+
+fn push_unique(command_stream: &mut Vec<BuildozerDepCmd>, cmd: BuildozerDepCmd) {
+    if !command_stream.contains(&cmd) {
+        command_stream.push(cmd);
+    }
+}
+
+fn push_add(command_stream: &mut Vec<BuildozerDepCmd>, target: &str, dependency: &str) {
+    push_unique(
+        command_stream,
+        BuildozerDepCmd::Add {
+            target_to_operate_on: target.to_string(),
+            dependency_to_add: dependency.to_string(),
+        },
+    )
+}
+
+fn push_remove(command_stream: &mut Vec<BuildozerDepCmd>, target: &str, dependency: &str) {
+    push_unique(
+        command_stream,
+        BuildozerDepCmd::Remove {
+            target_to_operate_on: target.to_string(),
+            dependency_to_remove: dependency.to_string(),
+        },
+    )
+}
+
+// rules_scala and javac both end their strict deps errors with:
+// buildozer 'add deps @maven//:com_google_guava_guava' //src/main/scala/com/example:foo
+// and rules_scala its unused deps errors with:
+// buildozer 'remove deps @maven//:com_google_guava_guava' //src/main/scala/com/example:foo
+fn extract_buildozer_hints(
+    input_error_streams: &[String],
+    command_stream: &mut Vec<BuildozerDepCmd>,
+) {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"^\s*buildozer '(add|remove) deps ([^']+)' ([^ ]+)\s*$").unwrap();
+    }
+
+    for stream in input_error_streams {
+        for ln in stream.lines() {
+            if let Some(captures) = RE.captures(ln) {
+                let target = captures.get(3).unwrap().as_str();
+                for dependency in captures.get(2).unwrap().as_str().split_whitespace() {
+                    if captures.get(1).unwrap().as_str() == "add" {
+                        push_add(command_stream, target, dependency);
+                    } else {
+                        push_remove(command_stream, target, dependency);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// rules_scala's unused deps checker without a hint:
+// error: Target '@maven//:com_google_guava_guava' is specified as a dependency to //src/main/scala/com/example:foo but isn't used, please remove it from the deps.
+fn extract_unused_dependencies(
+    input_error_streams: &[String],
+    command_stream: &mut Vec<BuildozerDepCmd>,
+) {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r"(?:error|warning): Target '([^']+)' is specified as a dependency to ([^ ]+) but isn't used, please remove it from the deps\."
+        )
+        .unwrap();
+    }
+
+    for stream in input_error_streams {
+        for ln in stream.lines() {
+            if let Some(captures) = RE.captures(ln) {
+                push_remove(
+                    command_stream,
+                    captures.get(2).unwrap().as_str(),
+                    captures.get(1).unwrap().as_str(),
+                );
+            }
+        }
+    }
+}
+
+// javac without a buildozer hint:
+//  ** Please add the following dependencies:
+//   @com_google_protobuf//:protobuf_java_util to //src/main/java/com/example:example
+fn extract_please_add_dependencies(
+    input_error_streams: &[String],
+    command_stream: &mut Vec<BuildozerDepCmd>,
+) {
+    lazy_static! {
+        static ref HEADER_RE: Regex =
+            Regex::new(r"^\s*\*\* Please add the following dependencies:\s*$").unwrap();
+        static ref ENTRY_RE: Regex = Regex::new(r"^\s+([^ ]+) to ([^ ]+)\s*$").unwrap();
+    }
+
+    for stream in input_error_streams {
+        let mut in_block = false;
+        for ln in stream.lines() {
+            if HEADER_RE.is_match(ln) {
+                in_block = true;
+            } else if in_block {
+                match ENTRY_RE.captures(ln) {
+                    Some(captures) => push_add(
+                        command_stream,
+                        captures.get(2).unwrap().as_str(),
+                        captures.get(1).unwrap().as_str(),
+                    ),
+                    None => in_block = false,
+                }
+            }
+        }
+    }
+}
+
+// Lines that only name the label, the target is the one whose action failed:
+// Foo.java:3: error: [strict] Using type com.foo.Bar from an indirect dependency (TOOL_INFO: "//src/foo:bar"). See command below **
+// error: Target '@maven//:com_google_guava_guava' is used but isn't explicitly declared, please add it to the deps.
+fn extract_label_only_errors(
+    action_label: &str,
+    input_error_streams: &[String],
+    command_stream: &mut Vec<BuildozerDepCmd>,
+) {
+    lazy_static! {
+        static ref JAVA_RE: Regex = Regex::new(
+            r#"error: \[strict\] Using type [^ ]+ from an indirect dependency \(TOOL_INFO: "([^"]+)"\)"#
+        )
+        .unwrap();
+        static ref SCALA_RE: Regex = Regex::new(
+            r"error: Target '([^']+)' is used but isn't explicitly declared, please add it to the deps\."
+        )
+        .unwrap();
+    }
+
+    for stream in input_error_streams {
+        for ln in stream.lines() {
+            if let Some(captures) = JAVA_RE.captures(ln).or_else(|| SCALA_RE.captures(ln)) {
+                push_add(
+                    command_stream,
+                    action_label,
+                    captures.get(1).unwrap().as_str(),
+                );
+            }
+        }
+    }
+}
+
+fn extract_strict_deps_errors(
+    action_failed_error_info: &hydrated_stream::ActionFailedErrorInfo,
+    input_error_streams: &[String],
+) -> Vec<BuildozerDepCmd> {
+    let mut command_stream = Vec::default();
+    extract_buildozer_hints(input_error_streams, &mut command_stream);
+    extract_please_add_dependencies(input_error_streams, &mut command_stream);
+    extract_unused_dependencies(input_error_streams, &mut command_stream);
+    // The explicit forms above carry the target too, only fall back to the action's label without them.
+    if command_stream.is_empty() {
+        extract_label_only_errors(
+            &action_failed_error_info.label,
+            input_error_streams,
+            &mut command_stream,
+        );
+    }
+    command_stream
+}
+
+// Only the failed target's kind is known, dependencies suggested for other targets are checked
+// against the kind independent rules alone.
+fn allowed(
+    dependency_rules: &DependencyRules,
+    action_failed_error_info: &hydrated_stream::ActionFailedErrorInfo,
+    target: &str,
+    dependency: &str,
+) -> bool {
+    let target_kind = if sanitize_label(target.to_string())
+        == sanitize_label(action_failed_error_info.label.clone())
+    {
+        action_failed_error_info.target_kind.clone()
+    } else {
+        None
+    };
+    dependency_rules.allows(&target_kind, dependency)
+}
+
+fn contains_label(current_deps: &[String], label: &str) -> bool {
+    let sanitized = sanitize_label(label.to_string());
+    current_deps
+        .iter()
+        .any(|d| d == label || sanitize_label(d.clone()) == sanitized)
+}
+
+async fn apply_candidates<T: Buildozer + Clone + Send + Sync + 'static>(
+    candidate_correction_commands: Vec<BuildozerDepCmd>,
+    buildozer: T,
+    action_failed_error_info: &hydrated_stream::ActionFailedErrorInfo,
+    dependency_rules: &DependencyRules,
+) -> super::Response {
+    let mut target_stories = Vec::default();
+    for cmd in candidate_correction_commands.into_iter() {
+        match cmd {
+            BuildozerDepCmd::Add {
+                target_to_operate_on,
+                dependency_to_add,
+            } => {
+                if !allowed(
+                    dependency_rules,
+                    action_failed_error_info,
+                    &target_to_operate_on,
+                    &dependency_to_add,
+                ) {
+                    debug!(
+                        "Not adding strict dependency {:?} to {:?}, the dependency rules forbid it",
+                        dependency_to_add, target_to_operate_on
+                    );
+                    continue;
+                }
+                let current_deps = buildozer
+                    .print_attr(&BazelAttrTarget::Deps, &target_to_operate_on)
+                    .await
+                    .unwrap_or_default();
+                if contains_label(&current_deps, &dependency_to_add) {
+                    // Adding it again won't change anything, keep going and we'd loop forever.
+                    continue;
+                }
+
+                debug!(
+                    "Buildozer action: add strict dependency {:?} to {:?}",
+                    dependency_to_add, target_to_operate_on
+                );
+                match buildozer
+                    .add_to(
+                        &BazelAttrTarget::Deps,
+                        &target_to_operate_on,
+                        &dependency_to_add,
+                    )
+                    .await
+                {
+                    Ok(_) => target_stories.push(super::TargetStory {
+                        target: target_to_operate_on,
+                        action: super::TargetStoryAction::AddedDependency {
+                            added_what: dependency_to_add,
+                            why: String::from(
+                                "High confidence fix: strict deps reported it as a missing direct dependency",
+                            ),
+                        },
+                        when: Instant::now(),
+                    }),
+                    Err(_) => warn!("Buildozer command failed"),
+                }
+            }
+            BuildozerDepCmd::Remove {
+                target_to_operate_on,
+                dependency_to_remove,
+            } => {
+                let current_deps = buildozer
+                    .print_attr(&BazelAttrTarget::Deps, &target_to_operate_on)
+                    .await
+                    .unwrap_or_default();
+                if !contains_label(&current_deps, &dependency_to_remove) {
+                    // Already gone, e.g. removed for the plain unused deps error.
+                    continue;
+                }
+
+                debug!(
+                    "Buildozer action: remove unused dependency {:?} from {:?}",
+                    dependency_to_remove, target_to_operate_on
+                );
+                match buildozer
+                    .remove_from(
+                        &BazelAttrTarget::Deps,
+                        &target_to_operate_on,
+                        &dependency_to_remove,
+                    )
+                    .await
+                {
+                    Ok(_) => target_stories.push(super::TargetStory {
+                        target: target_to_operate_on,
+                        action: super::TargetStoryAction::RemovedDependency {
+                            removed_what: dependency_to_remove,
+                            why: String::from(
+                                "High confidence fix: unused deps reported it as not used",
+                            ),
+                        },
+                        when: Instant::now(),
+                    }),
+                    Err(_) => warn!("Buildozer command failed"),
+                }
+            }
+        }
+    }
+    super::Response::new(target_stories)
+}
+
+/// Also returns whether the failure had strict deps errors to repair, the labels they name are
+/// better than anything the index could come up with.
+pub async fn process_action_failed<T: Buildozer + Clone + Send + Sync + 'static>(
+    buildozer: T,
+    action_failed_error_info: &hydrated_stream::ActionFailedErrorInfo,
+    dependency_rules: &DependencyRules,
+) -> (super::Response, bool) {
+    let error_streams = text_logs_from_failure(action_failed_error_info).await;
+    let candidate_correction_commands =
+        extract_strict_deps_errors(action_failed_error_info, &error_streams);
+    let found_strict_deps_errors = !candidate_correction_commands.is_empty();
+    let response = apply_candidates(
+        candidate_correction_commands,
+        buildozer,
+        action_failed_error_info,
+        dependency_rules,
+    )
+    .await;
+    (response, found_strict_deps_errors)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use hydrated_stream::ActionFailedErrorInfo;
+
+    fn action_failed(label: &str, target_kind: &str) -> ActionFailedErrorInfo {
+        ActionFailedErrorInfo {
+            label: String::from(label),
+            target_kind: Some(String::from(target_kind)),
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    fn add_dep(target: &str, dependency: &str) -> BuildozerDepCmd {
+        BuildozerDepCmd::Add {
+            target_to_operate_on: String::from(target),
+            dependency_to_add: String::from(dependency),
+        }
+    }
+
+    fn remove_dep(target: &str, dependency: &str) -> BuildozerDepCmd {
+        BuildozerDepCmd::Remove {
+            target_to_operate_on: String::from(target),
+            dependency_to_remove: String::from(dependency),
+        }
+    }
+
+    #[test]
+    fn test_extract_scala_strict_deps() {
+        let error_streams = vec![String::from(
+            "src/main/scala/com/example/Foo.scala:3: error: Target '@maven//:com_google_guava_guava' is used but isn't explicitly declared, please add it to the deps.
+You can use the following buildozer command:
+buildozer 'add deps @maven//:com_google_guava_guava' //src/main/scala/com/example:foo
+import com.google.common.collect.ImmutableList",
+        )];
+
+        assert_eq!(
+            extract_strict_deps_errors(
+                &action_failed("//src/main/scala/com/example:foo", "scala_library"),
+                &error_streams
+            ),
+            vec![add_dep(
+                "//src/main/scala/com/example:foo",
+                "@maven//:com_google_guava_guava"
+            )]
+        );
+
+        // Without the hint the label is still enough, applied to the failed target.
+        let error_streams = vec![String::from(
+            "src/main/scala/com/example/Foo.scala:3: error: Target '@maven//:com_google_guava_guava' is used but isn't explicitly declared, please add it to the deps.",
+        )];
+        assert_eq!(
+            extract_strict_deps_errors(
+                &action_failed("//src/main/scala/com/example:foo", "scala_library"),
+                &error_streams
+            ),
+            vec![add_dep(
+                "//src/main/scala/com/example:foo",
+                "@maven//:com_google_guava_guava"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_extract_java_strict_deps() {
+        let error_streams = vec![String::from(
+            "src/main/java/com/example/Example.java:3: error: [strict] Using type com.google.protobuf.util.JsonFormat.Printer from an indirect dependency (TOOL_INFO: \"@com_google_protobuf//:protobuf_java_util\"). See command below **
+  private static final Printer JSON_PRINTER =
+src/main/java/com/example/Example.java:4: error: [strict] Using type com.example.foo.Foo from an indirect dependency (TOOL_INFO: \"//src/main/java/com/example/foo\"). See command below **
+ ** Please add the following dependencies:
+  @com_google_protobuf//:protobuf_java_util to //src/main/java/com/example:example
+  //src/main/java/com/example/foo to //src/main/java/com/example:example
+ ** You can use the following buildozer command:
+buildozer 'add deps @com_google_protobuf//:protobuf_java_util //src/main/java/com/example/foo' //src/main/java/com/example:example",
+        )];
+
+        assert_eq!(
+            extract_strict_deps_errors(
+                &action_failed("//src/main/java/com/example:example", "java_library"),
+                &error_streams
+            ),
+            vec![
+                add_dep(
+                    "//src/main/java/com/example:example",
+                    "@com_google_protobuf//:protobuf_java_util"
+                ),
+                add_dep(
+                    "//src/main/java/com/example:example",
+                    "//src/main/java/com/example/foo"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_scala_unused_deps() {
+        let error_streams = vec![String::from(
+            "error: Target '@maven//:com_google_guava_guava' is specified as a dependency to //src/main/scala/com/example:foo but isn't used, please remove it from the deps.
+You can use the following buildozer command:
+buildozer 'remove deps @maven//:com_google_guava_guava' //src/main/scala/com/example:foo",
+        )];
+
+        assert_eq!(
+            extract_strict_deps_errors(
+                &action_failed("//src/main/scala/com/example:foo", "scala_library"),
+                &error_streams
+            ),
+            vec![remove_dep(
+                "//src/main/scala/com/example:foo",
+                "@maven//:com_google_guava_guava"
+            )]
+        );
+
+        let error_streams = vec![String::from(
+            "error: Target '//src/main/scala/com/example/bar' is specified as a dependency to //src/main/scala/com/example:foo but isn't used, please remove it from the deps.",
+        )];
+        assert_eq!(
+            extract_strict_deps_errors(
+                &action_failed("//src/main/scala/com/example:foo", "scala_library"),
+                &error_streams
+            ),
+            vec![remove_dep(
+                "//src/main/scala/com/example:foo",
+                "//src/main/scala/com/example/bar"
+            )]
+        );
+    }
+
+    #[test]
+    fn test_dependency_rules_apply() {
+        let config: crate::config::DependencyRepairConfig = toml::from_str(
+            r#"
+            never_add = ["//legacy/.*"]
+
+            [forbidden_labels]
+            scala_library = ["@maven//:org_scala_lang_scala_library"]
+            "#,
+        )
+        .unwrap();
+        let rules = DependencyRules::from_config(&config).unwrap();
+        let failed = action_failed("//src/main/scala/com/example:foo", "scala_library");
+
+        assert!(!allowed(
+            &rules,
+            &failed,
+            "//src/main/scala/com/example:foo",
+            "//legacy/foo"
+        ));
+        assert!(!allowed(
+            &rules,
+            &failed,
+            "//src/main/scala/com/example:foo",
+            "@maven//:org_scala_lang_scala_library"
+        ));
+        // The kind of any other target isn't known, only the kind independent rules apply.
+        assert!(allowed(
+            &rules,
+            &failed,
+            "//src/main/scala/com/example:bar",
+            "@maven//:org_scala_lang_scala_library"
+        ));
+        assert!(allowed(
+            &rules,
+            &failed,
+            "//src/main/scala/com/example:foo",
+            "@maven//:com_google_guava_guava"
+        ));
+    }
+}
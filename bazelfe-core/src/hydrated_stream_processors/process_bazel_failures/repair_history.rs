@@ -0,0 +1,357 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{error_extraction::ActionRequest, index_table};
+
+use super::CurrentState;
+
+/// What was missing when a repair was made, independent of which error extractor found it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MissingReference {
+    Class(String),
+    Suffix(String),
+}
+
+impl MissingReference {
+    pub fn from_request(request: &ActionRequest) -> Self {
+        match request {
+            ActionRequest::Prefix(p) => MissingReference::Class(p.class_name.clone()),
+            ActionRequest::Suffix(s) => MissingReference::Suffix(s.suffix.clone()),
+        }
+    }
+}
+
+/// This class was fixed by adding `added_dependency` to a target of kind `target_kind`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SuccessfulRepair {
+    pub missing: MissingReference,
+    pub target_kind: Option<String>,
+    pub added_dependency: String,
+    pub times_succeeded: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct TargetRepairHistory {
+    ignore_list: BTreeSet<String>,
+    added_target_for_class: Vec<(ActionRequest, BTreeSet<String>)>,
+}
+
+/// The repair loop state that is worth keeping between `bazel-runner` invocations.
+/// Stored next to the index so a later run doesn't retry candidates we already know to be wrong.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct RepairHistory {
+    #[serde(default)]
+    targets: BTreeMap<String, TargetRepairHistory>,
+    #[serde(default)]
+    successful_repairs: Vec<SuccessfulRepair>,
+}
+
+impl RepairHistory {
+    pub fn path_for_index(index_path: &Path) -> PathBuf {
+        index_path.with_extension("repair_history.json")
+    }
+
+    /// A missing or unreadable history just means we start afresh.
+    pub fn read(path: &Path) -> RepairHistory {
+        if !path.exists() {
+            return RepairHistory::default();
+        }
+        match std::fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+        {
+            Ok(history) => history,
+            Err(e) => {
+                warn!("Ignoring unreadable repair history at {:?}: {}", path, e);
+                RepairHistory::default()
+            }
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut temp_path = path.to_path_buf();
+        temp_path.set_extension("tmp");
+        std::fs::write(&temp_path, serde_json::to_vec(self)?)?;
+        std::fs::rename(temp_path, path)
+    }
+
+    pub fn current_state_for(&self, label: &str) -> CurrentState {
+        match self.targets.get(label) {
+            None => CurrentState::default(),
+            Some(target_history) => CurrentState {
+                ignore_list: target_history.ignore_list.iter().cloned().collect(),
+                added_target_for_class: target_history
+                    .added_target_for_class
+                    .iter()
+                    .map(|(req, added)| (req.clone(), added.iter().cloned().collect()))
+                    .collect(),
+                epoch: 0,
+            },
+        }
+    }
+
+    /// Only targets still mid repair are kept, once fixed there is nothing left worth replaying.
+    pub fn update_target(&mut self, label: &str, state: &CurrentState) {
+        let mut added_target_for_class: Vec<(ActionRequest, BTreeSet<String>)> = state
+            .added_target_for_class
+            .iter()
+            .filter(|(_, added)| !added.is_empty())
+            .map(|(req, added)| (req.clone(), added.iter().cloned().collect()))
+            .collect();
+        if added_target_for_class.is_empty() {
+            self.targets.remove(label);
+            return;
+        }
+        // Keep the file stable between runs, HashMap iteration order isn't.
+        added_target_for_class.sort_by(|(a, _), (b, _)| a.cmp(b));
+        self.targets.insert(
+            label.to_string(),
+            TargetRepairHistory {
+                ignore_list: state.ignore_list.iter().cloned().collect(),
+                added_target_for_class,
+            },
+        );
+    }
+
+    pub fn record_success(
+        &mut self,
+        request: &ActionRequest,
+        target_kind: &Option<String>,
+        added_dependency: &str,
+    ) {
+        let missing = MissingReference::from_request(request);
+        match self.successful_repairs.iter_mut().find(|e| {
+            e.missing == missing
+                && &e.target_kind == target_kind
+                && e.added_dependency == added_dependency
+        }) {
+            Some(existing) => existing.times_succeeded = existing.times_succeeded.saturating_add(1),
+            None => self.successful_repairs.push(SuccessfulRepair {
+                missing,
+                target_kind: target_kind.clone(),
+                added_dependency: added_dependency.to_string(),
+                times_succeeded: 1,
+            }),
+        }
+    }
+
+    /// The dependency that most often fixed this request on targets of the same kind.
+    pub fn preferred_fix(
+        &self,
+        request: &ActionRequest,
+        target_kind: &Option<String>,
+    ) -> Option<&str> {
+        let missing = MissingReference::from_request(request);
+        self.successful_repairs
+            .iter()
+            .filter(|e| e.missing == missing && &e.target_kind == target_kind)
+            .max_by_key(|e| e.times_succeeded)
+            .map(|e| e.added_dependency.as_str())
+    }
+
+    /// Ranks past fixes above whatever else the index has for those classes, most successful first.
+    /// The index isn't per target kind, so successes are summed across kinds here, `preferred_fix`
+    /// is what favours the fix seen on the same kind of target.
+    pub async fn apply_to_index(&self, index_table: &index_table::IndexTable) {
+        let mut successes_by_class: BTreeMap<&str, BTreeMap<&str, u32>> = BTreeMap::default();
+        for repair in self.successful_repairs.iter() {
+            if let MissingReference::Class(class_name) = &repair.missing {
+                let times_succeeded = successes_by_class
+                    .entry(class_name.as_str())
+                    .or_default()
+                    .entry(repair.added_dependency.as_str())
+                    .or_default();
+                *times_succeeded = times_succeeded.saturating_add(repair.times_succeeded);
+            }
+        }
+        for (class_name, successes) in successes_by_class {
+            let mut successes: Vec<(&str, u32)> = successes.into_iter().collect();
+            successes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+            let targets: Vec<String> = successes.iter().map(|(t, _)| t.to_string()).collect();
+            index_table.promote_targets(class_name, &targets).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+    use crate::error_extraction::{ClassImportRequest, ClassSuffixMatch};
+
+    fn class_request(class_name: &str) -> ActionRequest {
+        ActionRequest::Prefix(ClassImportRequest {
+            class_name: class_name.to_string(),
+            exact_only: false,
+            src_fn: String::from("scala::extract_object_not_found"),
+            priority: 1,
+        })
+    }
+
+    #[test]
+    fn test_round_trip_target_state() {
+        let mut history = RepairHistory::default();
+        let mut state = CurrentState::default();
+        state
+            .ignore_list
+            .insert(String::from("//src/main/com/example/foo:foo"));
+        state.added_target_for_class.insert(
+            class_request("com.example.a.A"),
+            vec![String::from("//src/main/com/example/a:a")]
+                .into_iter()
+                .collect(),
+        );
+        state.added_target_for_class.insert(
+            ActionRequest::Suffix(ClassSuffixMatch {
+                suffix: String::from("example.B"),
+                src_fn: String::from("scala::extract_value_not_found"),
+                priority: 0,
+            }),
+            HashSet::default(),
+        );
+        history.update_target("//src/main/com/example/foo:foo", &state);
+
+        let tmp = tempfile::tempdir().unwrap();
+        let path = RepairHistory::path_for_index(&tmp.path().join("index"));
+        history.write(&path).unwrap();
+        let history = RepairHistory::read(&path);
+
+        let restored = history.current_state_for("//src/main/com/example/foo:foo");
+        assert_eq!(restored.ignore_list, state.ignore_list);
+        let mut expected: HashMap<ActionRequest, HashSet<String>> = HashMap::default();
+        expected.insert(
+            class_request("com.example.a.A"),
+            vec![String::from("//src/main/com/example/a:a")]
+                .into_iter()
+                .collect(),
+        );
+        assert_eq!(restored.added_target_for_class, expected);
+
+        // Nothing left in flight, so it's dropped.
+        let mut history = history;
+        history.update_target("//src/main/com/example/foo:foo", &CurrentState::default());
+        assert_eq!(history, RepairHistory::default());
+    }
+
+    #[test]
+    fn test_update_target_is_stable() {
+        let class_names = ["com.example.a.A", "com.example.b.B", "com.example.c.C"];
+        let serialized = |order: &[usize]| {
+            let mut state = CurrentState::default();
+            for idx in order {
+                state.added_target_for_class.insert(
+                    class_request(class_names[*idx]),
+                    vec![format!("//src/main/com/example:{}", idx)]
+                        .into_iter()
+                        .collect(),
+                );
+            }
+            let mut history = RepairHistory::default();
+            history.update_target("//src/main/com/example/foo:foo", &state);
+            serde_json::to_string(&history).unwrap()
+        };
+
+        assert_eq!(serialized(&[0, 1, 2]), serialized(&[2, 0, 1]));
+        assert_eq!(serialized(&[0, 1, 2]), serialized(&[1, 2, 0]));
+    }
+
+    #[tokio::test]
+    async fn test_successful_repairs_outrank_index() {
+        let mut history = RepairHistory::default();
+        let req = class_request("com.example.a.A");
+        let scala = Some(String::from("scala_library"));
+        history.record_success(&req, &scala, "//src/main/com/example/a:a");
+        history.record_success(&req, &scala, "//src/main/com/example/a:a");
+        history.record_success(&req, &scala, "//src/main/com/example/other:other");
+
+        assert_eq!(
+            history.preferred_fix(&req, &scala),
+            Some("//src/main/com/example/a:a")
+        );
+        assert_eq!(
+            history.preferred_fix(&req, &Some(String::from("java_library"))),
+            None
+        );
+
+        let index_table = index_table::IndexTable::default();
+        index_table
+            .insert(
+                "com.example.a.A",
+                (100, String::from("//src/main/com/example/guess:guess")),
+            )
+            .await;
+        history.apply_to_index(&index_table).await;
+
+        let entries = index_table.get("com.example.a.A").await.unwrap();
+        let mut ranked = Vec::default();
+        for e in entries.read_iter().await.iter() {
+            ranked.push(index_table.decode_string(e.target).await.unwrap());
+        }
+        assert_eq!(
+            ranked,
+            vec![
+                String::from("//src/main/com/example/a:a"),
+                String::from("//src/main/com/example/other:other"),
+                String::from("//src/main/com/example/guess:guess"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_apply_to_index_is_idempotent() {
+        let mut history = RepairHistory::default();
+        let req = class_request("com.example.a.A");
+        let scala = Some(String::from("scala_library"));
+        let java = Some(String::from("java_library"));
+        // Each kind prefers a different fix, the index gets their combined ranking.
+        history.record_success(&req, &scala, "//src/main/com/example/a:a");
+        history.record_success(&req, &java, "//src/main/com/example/b:b");
+        history.record_success(&req, &java, "//src/main/com/example/b:b");
+        history.record_success(&req, &scala, "//src/main/com/example/a:a");
+        history.record_success(&req, &scala, "//src/main/com/example/a:a");
+
+        let index_table = index_table::IndexTable::from_vec(vec![(
+            String::from("com.example.a.A"),
+            vec![(100, String::from("//src/main/com/example/guess:guess"))],
+        )]);
+        let ranked = || async {
+            let entries = index_table.get("com.example.a.A").await.unwrap();
+            let mut ranked = Vec::default();
+            for e in entries.read_iter().await.iter() {
+                ranked.push((
+                    index_table.decode_string(e.target).await.unwrap(),
+                    e.priority.0,
+                ));
+            }
+            ranked
+        };
+
+        history.apply_to_index(&index_table).await;
+        assert!(index_table.is_mutated());
+        let first_run = ranked().await;
+        assert_eq!(
+            first_run,
+            vec![
+                (String::from("//src/main/com/example/a:a"), 102),
+                (String::from("//src/main/com/example/b:b"), 101),
+                (String::from("//src/main/com/example/guess:guess"), 100),
+            ]
+        );
+
+        // A later run against the written index changes nothing.
+        let mut buf = Vec::default();
+        index_table.write(&mut buf).await;
+        let index_table = index_table::IndexTable::read(&mut buf.as_slice()).unwrap();
+        history.apply_to_index(&index_table).await;
+        assert!(!index_table.is_mutated());
+        assert_eq!(ranked().await, first_run);
+    }
+}
@@ -35,5 +35,6 @@ pub(in crate::hydrated_stream_processors::process_bazel_failures) async fn text_
             }
         }
     }
+    error_data.extend(action_failed_error_info.inline_contents());
     error_data
 }
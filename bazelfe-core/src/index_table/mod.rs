@@ -3,7 +3,6 @@ use std::{
     sync::atomic::Ordering, sync::Arc, time::SystemTime,
 };
 use tokio::sync::RwLock;
-mod expand_target_to_guesses;
 mod index_table_value;
 pub use index_table_value::*;
 use std::io::Write;
@@ -384,6 +383,29 @@ impl<'a> IndexTable {
             0
         }
     }
+    /// Indexes classes found by parsing a target's sources, for targets which haven't been built.
+    pub async fn index_declared_classes(
+        &self,
+        target_name: String,
+        classes: Vec<String>,
+        priority: u16,
+    ) -> u32 {
+        let key_id = self.maybe_insert_target_string(target_name).await;
+
+        let mut jvm_segments_indexed = 0;
+        for e in classes.into_iter() {
+            if self.insert_with_id(&e, key_id, priority).await {
+                jvm_segments_indexed += 1;
+            }
+            for clazz in crate::label_utils::class_name_to_prefixes(e.as_str(), true) {
+                if self.insert_with_id(clazz, key_id, priority).await {
+                    jvm_segments_indexed += 1;
+                }
+            }
+        }
+        jvm_segments_indexed
+    }
+
     async fn maybe_insert_target_string(&self, str: String) -> usize {
         self.maybe_insert_target_bytes(str.as_bytes().to_vec())
             .await
@@ -448,6 +470,16 @@ impl<'a> IndexTable {
             .map(|e| unsafe { std::str::from_utf8_unchecked(e).to_string() })
     }
 
+    /// Every target the index has an entry for.
+    pub async fn targets(&self) -> Vec<String> {
+        self.id_to_target_vec
+            .read()
+            .await
+            .iter()
+            .map(|e| String::from_utf8_lossy(e).to_string())
+            .collect()
+    }
+
     pub async fn replace_with_id<'b, S>(&self, key: S, target_id: usize, priority: u16) -> bool
     where
         S: Into<Cow<'b, str>>,
@@ -528,16 +560,58 @@ impl<'a> IndexTable {
         self.insert_with_id(key, key_id, freq).await;
     }
 
-    pub async fn get_or_guess<'b, S>(&self, key: S) -> IndexTableValue
+    /// Ranks `targets`, most preferred first, above every other entry for `key`, e.g. when they are known
+    /// to have fixed this class before. Priorities are only ever raised, so once the entries are in that
+    /// order this is a no-op and the index isn't marked as mutated.
+    pub async fn promote_targets<'b, S>(&self, key: S, targets: &[String]) -> bool
+    where
+        S: Into<Cow<'b, str>>,
+    {
+        let k: Cow<'b, str> = key.into();
+        let mut target_ids = Vec::with_capacity(targets.len());
+        for target in targets.iter() {
+            target_ids.push(self.maybe_insert_target_string(target.clone()).await);
+        }
+        let current: Vec<IndexTableValueEntry> = match self.get(k.clone()).await {
+            Some(v) => v.read_iter().await.iter().cloned().collect(),
+            None => Vec::default(),
+        };
+        let current_priority = |target_id: usize| {
+            current
+                .iter()
+                .find(|e| e.target == target_id)
+                .map(|e| e.priority.0)
+                .unwrap_or(0)
+        };
+        let top_other_priority = current
+            .iter()
+            .filter(|e| !target_ids.contains(&e.target))
+            .map(|e| e.priority.0)
+            .max()
+            .unwrap_or(0);
+
+        let mut floor = top_other_priority;
+        let mut did_update = false;
+        for target_id in target_ids.into_iter().rev() {
+            let priority = floor.saturating_add(1).max(current_priority(target_id));
+            did_update |= self.insert_with_id(k.clone(), target_id, priority).await;
+            floor = priority;
+        }
+        did_update
+    }
+
+    /// Looks up `key`, falling back to the targets `guess` comes up with when it isn't indexed.
+    pub async fn get_or_guess<'b, S, F>(&self, key: S, guess: F) -> IndexTableValue
     where
         S: Into<Cow<'b, str>>,
+        F: FnOnce(&str) -> Vec<(u16, String)>,
     {
         let cow_k = key.into();
 
         match self.get(cow_k.clone()).await {
             Some(v) => v,
             None => {
-                let guesses = expand_target_to_guesses::get_guesses_for_class_name(&cow_k);
+                let guesses = guess(&cow_k);
 
                 let mut guesses2 = Vec::default();
                 for (k, v) in guesses.into_iter() {
@@ -0,0 +1,430 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::bazel_query::BazelQuery;
+
+/// Bzlmod is in play whenever the workspace root has a `MODULE.bazel`.
+pub fn is_bzlmod_workspace(workspace_root: &Path) -> bool {
+    workspace_root.join("MODULE.bazel").exists()
+}
+
+/// The name given to the root module in `MODULE.bazel`, if any.
+pub fn parse_module_name(module_content: &str) -> Option<String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(
+            r#"(?m)^\s*module\(\s*(?:[^)]*?[,\s])?name\s*=\s*("|')([A-Za-z0-9_.-]*)("|')"#
+        )
+        .unwrap();
+    }
+    RE.captures(module_content)
+        .map(|c| c.get(2).unwrap().as_str().to_string())
+}
+
+/// The repositories visible from the main repo, as reported by `bazel mod dump_repo_mapping ''`.
+/// Lets us turn the canonical names bazel reports (e.g. `rules_jvm_external~~maven~maven`)
+/// back into the apparent names (e.g. `maven`) our BUILD files use.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepoMapping {
+    apparent_to_canonical: BTreeMap<String, String>,
+    canonical_to_apparent: HashMap<String, String>,
+}
+
+impl RepoMapping {
+    pub fn parse_dump_repo_mapping(json: &str) -> Result<RepoMapping, serde_json::Error> {
+        let apparent_to_canonical: BTreeMap<String, String> = serde_json::from_str(json.trim())?;
+
+        let mut canonical_to_apparent: HashMap<String, String> = HashMap::default();
+        // Several apparent names can point at the same repo, e.g. the root module is both `""` and
+        // its module name. Iterating a BTreeMap keeps which one we pick stable, and `""` wins for the main repo.
+        for (apparent, canonical) in apparent_to_canonical.iter() {
+            canonical_to_apparent
+                .entry(canonical.clone())
+                .or_insert_with(|| apparent.clone());
+        }
+        Ok(RepoMapping {
+            apparent_to_canonical,
+            canonical_to_apparent,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.apparent_to_canonical.is_empty()
+    }
+
+    /// (apparent, canonical) names of every external repo, excluding the main repo.
+    pub fn external_repos(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.apparent_to_canonical
+            .iter()
+            .filter(|(apparent, canonical)| !apparent.is_empty() && !canonical.is_empty())
+            .map(|(a, c)| (a.as_str(), c.as_str()))
+    }
+
+    /// Rewrites `@@canonical//pkg:target` (or the older `@canonical//pkg:target`) into the
+    /// form used from the main repo, leaving labels we don't know about untouched.
+    pub fn to_apparent_label(&self, label: &str) -> String {
+        let (repo, rest) = match split_repo(label) {
+            Some(split) => split,
+            None => return label.to_string(),
+        };
+        match self.canonical_to_apparent.get(repo) {
+            Some(apparent) if apparent.is_empty() => rest.to_string(),
+            Some(apparent) => format!("@{}{}", apparent, rest),
+            None => label.to_string(),
+        }
+    }
+}
+
+/// Splits `@repo//pkg:target` or `@@repo//pkg:target` into the repo and the `//pkg:target` remainder.
+fn split_repo(label: &str) -> Option<(&str, &str)> {
+    let without_at = label.trim_start_matches('@');
+    if without_at.len() == label.len() {
+        return None;
+    }
+    let idx = without_at.find("//")?;
+    Some((&without_at[..idx], &without_at[idx..]))
+}
+
+/// A repo mapping only fetched from bazel the first time a label from an external repo needs it,
+/// so runs that never see one don't pay for the extra bazel invocations.
+#[derive(Debug, Default)]
+pub struct LazyRepoMapping {
+    fetch: Option<RepoMappingFetch>,
+    mapping: tokio::sync::OnceCell<RepoMapping>,
+}
+
+#[derive(Debug)]
+struct RepoMappingFetch {
+    bazel_query: Box<dyn BazelQuery>,
+    startup_options: Vec<String>,
+    workspace_root: PathBuf,
+}
+
+impl LazyRepoMapping {
+    /// Fetches the mapping on first use, running bazel with the user's `startup_options`.
+    pub fn new(
+        bazel_query: Box<dyn BazelQuery>,
+        startup_options: Vec<String>,
+        workspace_root: PathBuf,
+    ) -> Self {
+        Self {
+            fetch: Some(RepoMappingFetch {
+                bazel_query,
+                startup_options,
+                workspace_root,
+            }),
+            mapping: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    /// A mapping that has already been loaded.
+    pub fn loaded(mapping: RepoMapping) -> Self {
+        Self {
+            fetch: None,
+            mapping: tokio::sync::OnceCell::new_with(Some(mapping)),
+        }
+    }
+
+    pub async fn to_apparent_label(&self, label: &str) -> String {
+        match split_repo(label) {
+            None => label.to_string(),
+            // The main repo needs no mapping to be written from the main repo.
+            Some(("", rest)) => rest.to_string(),
+            Some(_) => self.get().await.to_apparent_label(label),
+        }
+    }
+
+    async fn get(&self) -> &RepoMapping {
+        self.mapping
+            .get_or_init(|| async {
+                let fetch = match &self.fetch {
+                    Some(fetch) => fetch,
+                    None => return RepoMapping::default(),
+                };
+                match fetch_repo_mapping(
+                    fetch.bazel_query.as_ref(),
+                    &fetch.startup_options,
+                    &fetch.workspace_root,
+                )
+                .await
+                {
+                    Ok(mapping) => mapping,
+                    Err(e) => {
+                        warn!("Unable to load the bzlmod repo mapping, new results will be indexed under canonical repo names: {}", e);
+                        RepoMapping::default()
+                    }
+                }
+            })
+            .await
+    }
+}
+
+/// Where the mapping for the current `MODULE.bazel` and its lockfile is cached in the output base.
+fn cache_path(output_base: &Path, workspace_root: &Path) -> PathBuf {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    for file in ["MODULE.bazel", "MODULE.bazel.lock"] {
+        hasher.update(std::fs::read(workspace_root.join(file)).unwrap_or_default());
+        hasher.update([0u8]);
+    }
+    output_base
+        .join("bazelfe")
+        .join(format!("repo-mapping-{:x}.json", hasher.finalize()))
+}
+
+fn write_cache(path: &Path, content: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temp_path = path.to_path_buf();
+    temp_path.set_extension("tmp");
+    std::fs::write(&temp_path, content)?;
+    std::fs::rename(temp_path, path)
+}
+
+/// Asks bazel for the main repo's mapping, requires bazel 7.1+.
+///
+/// The answer is cached in the output base, keyed on the contents of `MODULE.bazel` and its lockfile.
+pub async fn fetch_repo_mapping<B: BazelQuery + ?Sized>(
+    bazel_query: &B,
+    startup_options: &[String],
+    workspace_root: &Path,
+) -> Result<RepoMapping, String> {
+    let with_startup_options = |args: &[&str]| -> Vec<String> {
+        startup_options
+            .iter()
+            .cloned()
+            .chain(args.iter().map(|e| e.to_string()))
+            .collect()
+    };
+
+    let info = bazel_query
+        .execute(&with_startup_options(&["info", "output_base"]))
+        .await;
+    let cache_path = if info.exit_code == 0 {
+        Some(cache_path(Path::new(info.stdout.trim()), workspace_root))
+    } else {
+        None
+    };
+    if let Some(content) = cache_path
+        .as_ref()
+        .and_then(|p| std::fs::read_to_string(p).ok())
+    {
+        if let Ok(mapping) = RepoMapping::parse_dump_repo_mapping(&content) {
+            return Ok(mapping);
+        }
+    }
+
+    let res = bazel_query
+        .execute(&with_startup_options(&["mod", "dump_repo_mapping", ""]))
+        .await;
+    if res.exit_code != 0 {
+        return Err(format!(
+            "bazel mod dump_repo_mapping exited with {}: {}",
+            res.exit_code, res.stderr
+        ));
+    }
+    let mapping = RepoMapping::parse_dump_repo_mapping(&res.stdout)
+        .map_err(|e| format!("Unable to parse repo mapping {}: {}", res.stdout, e))?;
+    if let Some(cache_path) = cache_path {
+        if let Err(e) = write_cache(&cache_path, &res.stdout) {
+            warn!(
+                "Unable to cache the repo mapping at {:?}: {}",
+                cache_path, e
+            );
+        }
+    }
+    Ok(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jvm_indexer::bazel_query::ExecuteResult;
+    use std::sync::{Arc, Mutex};
+
+    const DUMP: &str = r#"{"":"","maven":"rules_jvm_external~~maven~maven"}"#;
+
+    /// Answers `info output_base` and `mod dump_repo_mapping`, recording every invocation.
+    #[derive(Debug)]
+    struct RecordingBazelQuery {
+        output_base: PathBuf,
+        invocations: Arc<Mutex<Vec<Vec<String>>>>,
+    }
+
+    #[async_trait::async_trait]
+    impl BazelQuery for RecordingBazelQuery {
+        async fn execute(&self, args: &[String]) -> ExecuteResult {
+            self.invocations.lock().unwrap().push(args.to_vec());
+            let stdout = if args.iter().any(|e| e == "info") {
+                format!("{}\n", self.output_base.display())
+            } else {
+                DUMP.to_string()
+            };
+            ExecuteResult {
+                exit_code: 0,
+                stdout,
+                stdout_raw: Default::default(),
+                stderr: String::default(),
+                stderr_raw: Default::default(),
+            }
+        }
+    }
+
+    fn mapping() -> RepoMapping {
+        RepoMapping::parse_dump_repo_mapping(
+            r#"{"":"","my_project":"","bazel_tools":"bazel_tools","maven":"rules_jvm_external~~maven~maven","rules_jvm_external":"rules_jvm_external~","io_grpc_grpc_java":"grpc-java~"}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_to_apparent_label() {
+        let mapping = mapping();
+        assert_eq!(
+            mapping.to_apparent_label("@@rules_jvm_external~~maven~maven//:com_google_guava_guava"),
+            "@maven//:com_google_guava_guava"
+        );
+        assert_eq!(
+            mapping.to_apparent_label("@grpc-java~//core:core"),
+            "@io_grpc_grpc_java//core:core"
+        );
+        assert_eq!(
+            mapping.to_apparent_label("@@//src/main/java/com/foo:foo"),
+            "//src/main/java/com/foo:foo"
+        );
+        assert_eq!(
+            mapping.to_apparent_label("//src/main/java/com/foo:foo"),
+            "//src/main/java/com/foo:foo"
+        );
+        assert_eq!(
+            mapping.to_apparent_label("@@some_unmapped~repo//:bar"),
+            "@@some_unmapped~repo//:bar"
+        );
+    }
+
+    #[test]
+    fn test_external_repos() {
+        let mapping = mapping();
+        let repos: Vec<(&str, &str)> = mapping.external_repos().collect();
+        assert_eq!(
+            repos,
+            vec![
+                ("bazel_tools", "bazel_tools"),
+                ("io_grpc_grpc_java", "grpc-java~"),
+                ("maven", "rules_jvm_external~~maven~maven"),
+                ("rules_jvm_external", "rules_jvm_external~"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_module_name() {
+        assert_eq!(
+            parse_module_name("module(name = \"my_project\", version = \"1.0\")\n"),
+            Some(String::from("my_project"))
+        );
+        assert_eq!(
+            parse_module_name(
+                "# comment\nmodule(\n    version = \"1.0\",\n    name = 'my-project',\n)\n"
+            ),
+            Some(String::from("my-project"))
+        );
+        assert_eq!(
+            parse_module_name("bazel_dep(name = \"rules_jvm_external\", version = \"6.0\")\n"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lazy_repo_mapping_only_fetches_for_external_labels() {
+        let output_base = tempfile::tempdir().unwrap();
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(
+            workspace.path().join("MODULE.bazel"),
+            "module(name = \"a\")\n",
+        )
+        .unwrap();
+        let invocations = Arc::new(Mutex::new(Vec::default()));
+        let lazy = LazyRepoMapping::new(
+            Box::new(RecordingBazelQuery {
+                output_base: output_base.path().to_path_buf(),
+                invocations: invocations.clone(),
+            }),
+            vec![String::from("--output_user_root=/tmp/foo")],
+            workspace.path().to_path_buf(),
+        );
+
+        assert_eq!(
+            lazy.to_apparent_label("//src/foo:foo").await,
+            "//src/foo:foo"
+        );
+        assert_eq!(
+            lazy.to_apparent_label("@@//src/foo:foo").await,
+            "//src/foo:foo"
+        );
+        assert!(invocations.lock().unwrap().is_empty());
+
+        assert_eq!(
+            lazy.to_apparent_label("@@rules_jvm_external~~maven~maven//:guava")
+                .await,
+            "@maven//:guava"
+        );
+        assert_eq!(
+            lazy.to_apparent_label("@@rules_jvm_external~~maven~maven//:jsr305")
+                .await,
+            "@maven//:jsr305"
+        );
+        let invocations = invocations.lock().unwrap().clone();
+        assert_eq!(invocations.len(), 2);
+        assert!(invocations
+            .iter()
+            .all(|args| args[0] == "--output_user_root=/tmp/foo"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_repo_mapping_is_cached_in_the_output_base() {
+        let output_base = tempfile::tempdir().unwrap();
+        let workspace = tempfile::tempdir().unwrap();
+        std::fs::write(
+            workspace.path().join("MODULE.bazel"),
+            "module(name = \"a\")\n",
+        )
+        .unwrap();
+        let invocations = Arc::new(Mutex::new(Vec::default()));
+        let bazel_query = RecordingBazelQuery {
+            output_base: output_base.path().to_path_buf(),
+            invocations: invocations.clone(),
+        };
+
+        let first = fetch_repo_mapping(&bazel_query, &[], workspace.path())
+            .await
+            .unwrap();
+        let second = fetch_repo_mapping(&bazel_query, &[], workspace.path())
+            .await
+            .unwrap();
+        assert_eq!(first, second);
+        let dumps = |invocations: &Mutex<Vec<Vec<String>>>| {
+            invocations
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|args| args.iter().any(|e| e == "dump_repo_mapping"))
+                .count()
+        };
+        assert_eq!(dumps(&invocations), 1);
+
+        // A changed MODULE.bazel can change the mapping, so it isn't served from the cache.
+        std::fs::write(
+            workspace.path().join("MODULE.bazel"),
+            "module(name = \"b\")\n",
+        )
+        .unwrap();
+        fetch_repo_mapping(&bazel_query, &[], workspace.path())
+            .await
+            .unwrap();
+        assert_eq!(dumps(&invocations), 2);
+    }
+}
@@ -1,5 +1,6 @@
 use bazelfe_bazel_wrapper::bazel_subprocess_wrapper::{BazelWrapper, BazelWrapperBuilder};
 
+use bazelfe_bazel_wrapper::bep::build_events::upstream_bes::UpstreamBes;
 use bazelfe_bazel_wrapper::bep::target_completed_tracker::TargetCompletedTracker;
 
 use bazelfe_core::config::load_config_file;
@@ -12,7 +13,7 @@ use regex::Regex;
 
 use lazy_static::lazy_static;
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Instant;
 
 use std::env;
@@ -20,6 +21,7 @@ use std::env;
 use bazelfe_bazel_wrapper::bazel_command_line_parser::parse_bazel_command_line;
 use bazelfe_bazel_wrapper::bazel_command_line_parser::{self, ParsedCommandLine};
 use bazelfe_core::jvm_indexer::bazel_query::BazelQuery;
+use bazelfe_core::jvm_indexer::bzlmod::{self, LazyRepoMapping, RepoMapping};
 
 use std::collections::{HashMap, HashSet};
 use std::io::Write;
@@ -56,6 +58,10 @@ struct Opt {
     /// and may include classes from other targets.
     #[clap(long)]
     blacklist_targets_from_index: Option<Vec<String>>,
+
+    /// Don't parse the srcs of first party targets, only index classes from the jars built.
+    #[clap(long)]
+    skip_source_indexing: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -79,6 +85,7 @@ async fn run_query_chunk<B: BazelQuery>(
     bazel_query: &B,
     all_targets_to_use: &mut HashMap<String, HashSet<String>>,
     banned_roots: &HashSet<String>,
+    extra_query_flags: &[String],
 ) -> bazelfe_core::jvm_indexer::bazel_query::ExecuteResult {
     let union_with_spaces_bytes = " union ".as_bytes();
 
@@ -100,16 +107,16 @@ async fn run_query_chunk<B: BazelQuery>(
         }
         String::from_utf8(buffer).unwrap()
     };
-    let res = bazel_query
-        .execute(&[
-            String::from("query"),
-            String::from("--keep_going"),
-            String::from("--noimplicit_deps"),
-            String::from("--output"),
-            String::from("label_kind"),
-            merged,
-        ])
-        .await;
+    let mut args = vec![
+        String::from("query"),
+        String::from("--keep_going"),
+        String::from("--noimplicit_deps"),
+        String::from("--output"),
+        String::from("label_kind"),
+    ];
+    args.extend(extra_query_flags.iter().cloned());
+    args.push(merged);
+    let res = bazel_query.execute(&args).await;
 
     for ln in res.stdout.lines() {
         let entries: Vec<&str> = ln.split_whitespace().collect();
@@ -124,7 +131,7 @@ async fn run_query_chunk<B: BazelQuery>(
     res
 }
 
-fn parse_current_repo_name() -> Option<String> {
+fn parse_current_repo_name() -> std::io::Result<Option<String>> {
     lazy_static! {
         static ref RE: Regex =
             Regex::new(r#"^\s*workspace\(\s*name\s*=\s*("|')\s*([A-Za-z0-9_-]*)("|').*$"#).unwrap();
@@ -132,31 +139,31 @@ fn parse_current_repo_name() -> Option<String> {
 
     let workspace_path = PathBuf::from("WORKSPACE");
     if workspace_path.exists() {
-        let workspace_content = std::fs::read_to_string(workspace_path).unwrap();
+        let workspace_content = std::fs::read_to_string(workspace_path)?;
         let ln = workspace_content
             .lines()
             .find(|e| e.starts_with("workspace("));
         if let Some(line) = ln {
             if let Some(captures) = RE.captures(line) {
-                return Some(String::from(captures.get(2).unwrap().as_str()));
+                return Ok(Some(String::from(captures.get(2).unwrap().as_str())));
             }
         }
     }
-    None
+
+    let module_path = PathBuf::from("MODULE.bazel");
+    if module_path.exists() {
+        let module_content = std::fs::read_to_string(module_path)?;
+        return Ok(bzlmod::parse_module_name(&module_content));
+    }
+    Ok(None)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let opt = Opt::parse();
 
-    let parsed_command_line = match parse_bazel_command_line(&[opt.bazel_binary_path.to_string_lossy().to_string()], Default::default()) {
-        Ok(parsed_command_line) => {
-            if parsed_command_line.is_action_option_set("bes_backend") {
-                eprintln!("Bes backend already set, must exit since we can't add another safely.");
-                std::process::exit(-1);
-            }
-            parsed_command_line
-        }
+    let mut parsed_command_line = match parse_bazel_command_line(&[opt.bazel_binary_path.to_string_lossy().to_string()], Default::default()) {
+        Ok(parsed_command_line) => parsed_command_line,
         Err(cmd_line_parsing_failed) => {
             match cmd_line_parsing_failed {
                 bazelfe_bazel_wrapper::bazel_command_line_parser::CommandLineParsingError::MissingBazelPath => {
@@ -177,6 +184,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let config = load_config_file(&opt.config.as_ref()).await?;
 
+    // Every batch runs as a build, so the rc options that apply are the `build` ones.
+    parsed_command_line.set_action(Some(bazel_command_line_parser::Action::BuiltIn(
+        bazel_command_line_parser::BuiltInAction::Build,
+    )));
+    if let Err(e) = parsed_command_line.load_bazelrc(&std::env::current_dir()?) {
+        eprintln!(
+            "Unable to read the bazelrc files, only using the command line: {}",
+            e
+        );
+    }
+
+    // Forward on to any build event service already configured, rather than replacing it.
+    let upstream_bes = UpstreamBes::take_from_command_line(&mut parsed_command_line)?;
+
     let _rng = rand::thread_rng();
     let mut builder = pretty_env_logger::formatted_timed_builder();
     builder.format_timestamp_nanos();
@@ -200,6 +221,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "scala_macro_library",
         "java_proto_library",
         "_java_grpc_library",
+        "kt_jvm_library",
+        "kt_jvm_import",
     ]
     .into_iter()
     .map(|e| e.to_string())
@@ -211,6 +234,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let _union_with_spaces_bytes = " union ".as_bytes();
 
+    let repo_mapping = if bzlmod::is_bzlmod_workspace(Path::new(".")) {
+        info!("Found a MODULE.bazel, will discover external repos through bzlmod");
+        let startup_options: Vec<String> = parsed_command_line
+            .startup_options
+            .iter()
+            .flat_map(|e| e.to_arg())
+            .collect();
+        match bzlmod::fetch_repo_mapping(&bazel_query, &startup_options, Path::new(".")).await {
+            Ok(repo_mapping) => repo_mapping,
+            Err(e) => {
+                warn!("Unable to load the bzlmod repo mapping, only repos in //external will be indexed: {}", e);
+                RepoMapping::default()
+            }
+        }
+    } else {
+        RepoMapping::default()
+    };
+    let repo_mapping = Arc::new(repo_mapping);
+
+    // Have query report canonical labels, matching what we see in the build event stream.
+    let extra_query_flags = if repo_mapping.is_empty() {
+        Vec::default()
+    } else {
+        vec![String::from("--consistent_labels")]
+    };
+
     let all_targets_to_use = {
         info!("Executing initial query to find all external repos in this bazel repository");
 
@@ -233,7 +282,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             String::from("io_bazel"),
         ];
 
-        if let Some(r) = parse_current_repo_name() {
+        if let Some(r) = parse_current_repo_name()? {
             info!("Current repo name identified as {}", r);
             blacklist_repos.push(r);
         }
@@ -249,26 +298,31 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             blacklist_repos.join(",")
         );
 
+        let is_blacklisted = |repo: &str| {
+            blacklist_repos
+                .iter()
+                .any(|root| repo.starts_with(root.as_str()))
+        };
+
         for line in res.stdout.lines() {
             if let Some(ln) = line.strip_prefix("//external:") {
-                let mut ok = true;
-                'inner: for root in blacklist_repos.iter() {
-                    if ln.starts_with(root) {
-                        ok = false;
-                        break 'inner;
-                    }
-                }
                 // Some externals are bind mounts
-                if ln.contains('/') {
-                    ok = false;
-                }
-
-                if ok {
+                if !is_blacklisted(ln) && !ln.contains('/') {
                     target_roots.push(format!("@{}//...", ln));
                 }
             }
         }
 
+        // Under bzlmod //external only holds repos still defined in the WORKSPACE, if any.
+        // Only the apparent name is checked, the canonical names of module extension repos start
+        // with the module's, e.g. `rules_jvm_external~~maven~maven` for `@maven`.
+        for (apparent, _) in repo_mapping.external_repos() {
+            let root = format!("@{}//...", apparent);
+            if !is_blacklisted(apparent) && !target_roots.contains(&root) {
+                target_roots.push(root);
+            }
+        }
+
         if res.exit_code != 0 {
             info!("The bazel query returned something other than exit code zero, this unfortunately can often happen, so we will continue with the data received. We have identified {} target roots", target_roots.len());
         } else {
@@ -311,6 +365,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     &bazel_query,
                     &mut all_targets_to_use,
                     &global_banned_roots,
+                    &extra_query_flags,
                 )
                 .await;
                 processed_count += 1;
@@ -420,6 +475,25 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         index_table.add_target_to_blacklist(e).await
     }
 
+    if !opt.skip_source_indexing {
+        info!("Indexing classes declared in the sources of first party targets");
+        match bazelfe_core::jvm_indexer::source_indexer::index_sources(
+            &bazel_query,
+            &index_table,
+            &allowed_rule_kinds,
+            &extra_query_flags,
+            Path::new("."),
+        )
+        .await
+        {
+            Ok(jvm_segments_indexed) => info!(
+                "Indexed {} entries from first party sources",
+                jvm_segments_indexed
+            ),
+            Err(e) => warn!("Unable to index first party sources: {}", e),
+        }
+    }
+
     let target_completed_tracker = TargetCompletedTracker::new(all_found_targets);
 
     let popularity_data =
@@ -437,12 +511,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let bazel_wrapper_builder = BazelWrapperBuilder {
         bes_server_bind_address: addr,
         processors: vec![
-            Arc::new(IndexNewResults::new(
-                index_table.clone(),
-                &config.indexer_config,
-            )),
+            Arc::new(
+                IndexNewResults::new(index_table.clone(), &config.indexer_config)
+                    .with_repo_mapping(Arc::new(LazyRepoMapping::loaded(
+                        repo_mapping.as_ref().clone(),
+                    ))),
+            ),
             Arc::new(target_completed_tracker.clone()),
         ],
+        upstream_bes,
     };
 
     let bazel_wrapper = bazel_wrapper_builder.build().await?;
@@ -1,2 +1,4 @@
 pub mod bazel_query;
+pub mod bzlmod;
 pub mod popularity_parser;
+pub mod source_indexer;
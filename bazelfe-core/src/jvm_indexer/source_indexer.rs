@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+
+use bazelfe_protos::blaze_query;
+
+use super::bazel_query::BazelQuery;
+use crate::index_table::IndexTable;
+use crate::source_dependencies::{java, kotlin, scala};
+
+/// Priority given to classes found in sources, jars that get built later can still rank above these.
+pub const SOURCE_INDEX_PRIORITY: u16 = 1;
+
+/// Fully qualified names of the top level types declared in a java, scala or kotlin source file.
+pub fn declared_classes(file_name: &Path, contents: &str) -> Vec<String> {
+    match file_name.extension().and_then(|e| e.to_str()) {
+        Some("java") => java::parse_declarations(contents),
+        Some("scala") => scala::parse_declarations(contents),
+        Some("kt") => kotlin::parse_declarations(contents),
+        _ => Vec::default(),
+    }
+}
+
+/// Path relative to the workspace root of a source file label in the main repository.
+pub fn source_label_to_path(label: &str) -> Option<PathBuf> {
+    // --consistent_labels reports the main repository as @@
+    let label = label.trim_start_matches('@').strip_prefix("//")?;
+    let (package, file) = label.split_once(':')?;
+    Some(Path::new(package).join(file))
+}
+
+fn source_files_by_target(
+    query_result: &blaze_query::QueryResult,
+    workspace_root: &Path,
+) -> Vec<(String, Vec<PathBuf>)> {
+    let mut results = Vec::default();
+    for target in query_result.target.iter() {
+        if let Some(rule) = target.rule.as_ref() {
+            let srcs: Vec<PathBuf> = rule
+                .attribute
+                .iter()
+                .filter(|attr| attr.name == "srcs")
+                .flat_map(|attr| attr.string_list_value.iter())
+                .filter_map(|label| source_label_to_path(label))
+                .map(|path| workspace_root.join(path))
+                // Generated sources don't exist until built, the jar will cover these.
+                .filter(|path| path.is_file())
+                .collect();
+            if !srcs.is_empty() {
+                results.push((rule.name.clone(), srcs));
+            }
+        }
+    }
+    results
+}
+
+async fn index_query_result(
+    query_result: &blaze_query::QueryResult,
+    workspace_root: &Path,
+    index_table: &IndexTable,
+) -> u32 {
+    let mut jvm_segments_indexed = 0;
+    for (label, srcs) in source_files_by_target(query_result, workspace_root) {
+        let mut classes = Vec::default();
+        for src in srcs.iter() {
+            match tokio::fs::read_to_string(src).await {
+                Ok(contents) => classes.extend(declared_classes(src, &contents)),
+                Err(e) => debug!("Unable to read {:?}: {}", src, e),
+            }
+        }
+        jvm_segments_indexed += index_table
+            .index_declared_classes(label, classes, SOURCE_INDEX_PRIORITY)
+            .await;
+    }
+    jvm_segments_indexed
+}
+
+/// Indexes the classes declared in the srcs of first party targets of the given kinds, without building them.
+pub async fn index_sources<B: BazelQuery>(
+    bazel_query: &B,
+    index_table: &IndexTable,
+    rule_kinds: &[String],
+    extra_query_flags: &[String],
+    workspace_root: &Path,
+) -> Result<u32, Box<dyn std::error::Error>> {
+    let query = rule_kinds
+        .iter()
+        .map(|kind| format!("kind({}, //...)", kind))
+        .collect::<Vec<String>>()
+        .join(" union ");
+    let mut extra_args: Vec<&str> = vec!["--noimplicit_deps"];
+    extra_args.extend(extra_query_flags.iter().map(|e| e.as_str()));
+
+    let query_result =
+        crate::bazel_query::graph_query(bazel_query, query, &extra_args, false).await?;
+
+    Ok(index_query_result(&query_result, workspace_root, index_table).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule_with_srcs(name: &str, srcs: &[&str]) -> blaze_query::Target {
+        blaze_query::Target {
+            r#type: blaze_query::target::Discriminator::Rule as i32,
+            rule: Some(blaze_query::Rule {
+                name: String::from(name),
+                rule_class: String::from("java_library"),
+                attribute: vec![blaze_query::Attribute {
+                    name: String::from("srcs"),
+                    r#type: blaze_query::attribute::Discriminator::LabelList as i32,
+                    string_list_value: srcs.iter().map(|e| e.to_string()).collect(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_source_label_to_path() {
+        assert_eq!(
+            source_label_to_path("//src/main/java/com/example:Foo.java"),
+            Some(PathBuf::from("src/main/java/com/example/Foo.java"))
+        );
+        assert_eq!(
+            source_label_to_path("@@//src/main/java/com/example:foo/Foo.java"),
+            Some(PathBuf::from("src/main/java/com/example/foo/Foo.java"))
+        );
+        assert_eq!(
+            source_label_to_path("@maven//:com_google_guava_guava"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_sources_from_query_result() {
+        let workspace = tempfile::tempdir().unwrap();
+        let root = workspace.path();
+        let package = root.join("src/main/java/com/example");
+        std::fs::create_dir_all(&package).unwrap();
+        std::fs::write(
+            package.join("Foo.java"),
+            "package com.example;\n\npublic class Foo {\n  static class Inner {}\n}\n",
+        )
+        .unwrap();
+
+        let query_result = blaze_query::QueryResult {
+            target: vec![rule_with_srcs(
+                "//src/main/java/com/example:example",
+                &[
+                    "//src/main/java/com/example:Foo.java",
+                    "//src/main/java/com/example:Generated.java",
+                ],
+            )],
+        };
+
+        let index_table = IndexTable::default();
+        assert!(index_query_result(&query_result, root, &index_table).await > 0);
+
+        let entries = index_table.get("com.example.Foo").await.unwrap();
+        let entries = entries.read_iter().await;
+        let found: Vec<(u16, usize)> = entries.iter().map(|e| (e.priority.0, e.target)).collect();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, SOURCE_INDEX_PRIORITY);
+        assert_eq!(
+            index_table.decode_string(found[0].1).await,
+            Some(String::from("//src/main/java/com/example:example"))
+        );
+        assert!(index_table.get("com.example.Foo.Inner").await.is_none());
+    }
+}
@@ -117,6 +117,25 @@ pub fn parse_imports(input: &str) -> Result<Vec<Import>> {
     Ok(results_vec)
 }
 
+/// Fully qualified names of the top level types declared in the file.
+pub fn parse_declarations(input: &str) -> Vec<String> {
+    extract_top_level_declarations(
+        input,
+        &["class", "interface", "enum", "record", "@interface"],
+        &[
+            "public",
+            "protected",
+            "private",
+            "abstract",
+            "final",
+            "static",
+            "sealed",
+            "non-sealed",
+            "strictfp",
+        ],
+    )
+}
+
 pub fn parse_file(input: &str) -> Result<ParsedFile> {
     let package = extract_package_from_file(input)?;
 
@@ -270,4 +289,50 @@ mod tests {
         let parsed_result = parse_imports(sample_input).unwrap();
         assert_eq!(parsed_result, expected_results);
     }
+
+    #[test]
+    fn parse_top_level_declarations() {
+        let sample_input = r#"package com.example;
+
+import java.util.List;
+
+/* class NotMe {} */
+@Deprecated
+public final class Example<T> implements Runnable {
+    private static final String BRACE = "}";
+    private static final char OPEN = '{';
+
+    public static class Inner {}
+
+    public void run() {}
+}
+
+interface Helper {}
+"#;
+        assert_eq!(
+            parse_declarations(sample_input),
+            vec![
+                "com.example.Example".to_string(),
+                "com.example.Helper".to_string()
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_single_line_annotated_declarations() {
+        let sample_input = r#"package com.example;
+
+@SuppressWarnings("unchecked") public class Foo {}
+@Retention(RetentionPolicy.RUNTIME) @Target({ElementType.TYPE}) public @interface Marker {}
+@com.example.Generated(value = "x)", date = "2024") final class Bar {}
+"#;
+        assert_eq!(
+            parse_declarations(sample_input),
+            vec![
+                "com.example.Foo".to_string(),
+                "com.example.Marker".to_string(),
+                "com.example.Bar".to_string()
+            ]
+        );
+    }
 }
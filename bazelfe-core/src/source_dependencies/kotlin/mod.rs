@@ -0,0 +1,258 @@
+use crate::source_dependencies::parser_helpers::*;
+use crate::source_dependencies::{Import, ParsedFile, Result, SelectorType};
+
+use nom::branch::alt;
+use nom::character::complete::{multispace0, space0, space1};
+use nom::combinator::recognize;
+use nom::error::ParseError;
+use nom::multi::separated_list1;
+use nom::sequence::delimited;
+use nom::{
+    bytes::complete::tag,
+    combinator::{map, opt},
+    sequence::tuple,
+    IResult,
+};
+
+fn is_valid_import_segment_item(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Kotlin lets keywords be used as names by wrapping them in backticks, e.g. import org.mockito.Mockito.`when`
+fn import_segment<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, &'a str, E> {
+    alt((
+        delimited(
+            tag("`"),
+            nom::bytes::complete::take_while1(|c: char| c != '`' && not_end_of_line(c)),
+            tag("`"),
+        ),
+        nom::bytes::complete::take_while1(is_valid_import_segment_item),
+    ))(input)
+}
+
+fn end_of_statement<'a, E: ParseError<&'a str> + 'a>(input: &'a str) -> IResult<&'a str, (), E> {
+    parser_to_unit(tuple((
+        space0,
+        opt(tag(";")),
+        space0,
+        alt((
+            parser_to_unit(tag("//")),
+            parser_to_unit(tag("/*")),
+            parser_to_unit(nom::character::complete::line_ending),
+            parser_to_unit(nom::combinator::eof),
+        )),
+    )))(input)
+}
+
+pub fn parse_import(line_number: u32, input: &str) -> IResult<&str, Import> {
+    let (input, _) = tuple((multispace0, tag("import"), space1))(input)?;
+
+    let (input, (segments, opt_wildcard, opt_alias)) = tuple((
+        separated_list1(tag("."), import_segment),
+        opt(tag(".*")),
+        opt(map(
+            tuple((space1, tag("as"), space1, recognize(import_segment))),
+            |r| r.3.trim_matches('`'),
+        )),
+    ))(input)?;
+    let (input, _) = end_of_statement(input)?;
+
+    let (prefix_section, suffix) = match (opt_wildcard, opt_alias) {
+        (Some(_), _) => (segments.join("."), SelectorType::WildcardSelector),
+        // Model aliases like scala's `import a.b.{C => D}`
+        (None, Some(alias)) if segments.len() > 1 => {
+            let (last, prefix) = segments.split_last().unwrap();
+            (
+                prefix.join("."),
+                SelectorType::SelectorList(vec![(last.to_string(), Some(alias.to_string()))]),
+            )
+        }
+        _ => (segments.join("."), SelectorType::NoSelector),
+    };
+
+    Ok((
+        input,
+        Import {
+            line_number,
+            prefix_section,
+            suffix,
+        },
+    ))
+}
+
+fn extract_package_from_line(ln: &str) -> Result<&str> {
+    let (_, res) = map(
+        tuple((
+            space0,
+            tag("package"),
+            space1,
+            nom::bytes::complete::take_while1(|chr: char| {
+                chr.is_alphanumeric() || chr == '.' || chr == '_'
+            }),
+            end_of_statement,
+        )),
+        |tup| tup.3,
+    )(ln)?;
+    Ok(res)
+}
+
+fn extract_package_from_file(file_lines: &str) -> Result<Option<&str>> {
+    for ln in file_lines.lines() {
+        if ln.contains("package") {
+            if let Ok(pkg) = extract_package_from_line(ln) {
+                return Ok(Some(pkg));
+            }
+        }
+    }
+    Ok(None)
+}
+
+pub fn parse_imports(input: &str) -> Result<Vec<Import>> {
+    let mut results_vec = Vec::new();
+    for (idx, ln) in input.lines().enumerate() {
+        if ln.contains("import") {
+            if let Ok((_, found)) = parse_import(idx as u32 + 1, ln) {
+                results_vec.push(found)
+            }
+        }
+    }
+    Ok(results_vec)
+}
+
+/// Fully qualified names of the top level types declared in the file.
+pub fn parse_declarations(input: &str) -> Vec<String> {
+    extract_top_level_declarations(
+        input,
+        &["class", "object", "interface"],
+        &[
+            "abstract",
+            "annotation",
+            "data",
+            "enum",
+            "fun",
+            "inline",
+            "inner",
+            "internal",
+            "open",
+            "private",
+            "public",
+            "sealed",
+            "value",
+        ],
+    )
+}
+
+pub fn parse_file(input: &str) -> Result<ParsedFile> {
+    let package = extract_package_from_file(input)?;
+
+    let imports = parse_imports(input)?;
+
+    Ok(ParsedFile {
+        package_name: package.map(|e| e.to_string()),
+        imports,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_header_line() {
+        assert_eq!(
+            extract_package_from_file(
+                "
+            @file:JvmName(\"Foo\")
+            package foo.bar.baz
+            asdf"
+            )
+            .unwrap(),
+            Some("foo.bar.baz")
+        );
+
+        assert_eq!(
+            extract_package_from_file("package foo.bar.baz; // comment\n").unwrap(),
+            Some("foo.bar.baz")
+        );
+
+        assert_eq!(
+            extract_package_from_file("val x = \"package foo.bar.baz is invalid\"\n").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_multiple_lines_input() {
+        let sample_input = "package com.example
+
+import com.google.common.collect.ImmutableList
+import kotlinx.coroutines.*
+import com.example.foo.Bar as FooBar // the other Bar
+import org.mockito.Mockito.`when`;
+
+class Foo
+";
+        let expected_results = vec![
+            Import {
+                line_number: 3,
+                prefix_section: "com.google.common.collect.ImmutableList".to_string(),
+                suffix: SelectorType::NoSelector,
+            },
+            Import {
+                line_number: 4,
+                prefix_section: "kotlinx.coroutines".to_string(),
+                suffix: SelectorType::WildcardSelector,
+            },
+            Import {
+                line_number: 5,
+                prefix_section: "com.example.foo".to_string(),
+                suffix: SelectorType::SelectorList(vec![(
+                    "Bar".to_string(),
+                    Some("FooBar".to_string()),
+                )]),
+            },
+            Import {
+                line_number: 6,
+                prefix_section: "org.mockito.Mockito.when".to_string(),
+                suffix: SelectorType::NoSelector,
+            },
+        ];
+
+        let parsed = parse_file(sample_input).unwrap();
+        assert_eq!(parsed.package_name, Some(String::from("com.example")));
+        assert_eq!(parsed.imports, expected_results);
+    }
+
+    #[test]
+    fn test_not_an_import() {
+        assert_eq!(
+            parse_imports("val important = importer.import(foo)\n").unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn parse_top_level_declarations() {
+        let sample_input = r#"package com.example
+
+import kotlinx.coroutines.flow.Flow
+
+data class User(val name: String) {
+    companion object {}
+}
+
+enum class Color { RED, GREEN }
+
+fun topLevel(): String = "{"
+
+internal object Registry
+"#;
+        assert_eq!(
+            parse_declarations(sample_input),
+            vec![
+                "com.example.User".to_string(),
+                "com.example.Color".to_string(),
+                "com.example.Registry".to_string(),
+            ]
+        );
+    }
+}
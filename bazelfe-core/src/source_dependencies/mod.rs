@@ -43,4 +43,5 @@ pub struct ParsedFile {
 }
 
 pub mod java;
+pub mod kotlin;
 pub mod scala;
@@ -1,4 +1,9 @@
+use nom::branch::alt;
+use nom::bytes::complete::{escaped, is_not, tag, take_until, take_while, take_while1};
+use nom::character::complete::{anychar, char, one_of};
+use nom::combinator::{opt, recognize, rest, value};
 use nom::error::ParseError;
+use nom::sequence::pair;
 
 use nom::{combinator::map, sequence::tuple, IResult};
 
@@ -27,3 +32,240 @@ pub(in crate::source_dependencies) fn eat_till_end_of_line(
         |r| (r.0, r.2),
     )(input)
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token<'a> {
+    Word(&'a str),
+    At,
+    Dot,
+    OpenBrace,
+    CloseBrace,
+    OpenParen,
+    CloseParen,
+    /// A line break or `;`.
+    EndOfStatement,
+    /// Literals and punctuation we have no use for.
+    Other,
+}
+
+fn is_word_char(chr: char) -> bool {
+    chr.is_alphanumeric() || chr == '_' || chr == '$' || chr == '-'
+}
+
+/// Runs until `end`, or the end of the input when it's missing.
+fn delimited_by<'a>(
+    start: &'static str,
+    end: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str> {
+    recognize(tuple((
+        tag(start),
+        alt((take_until(end), rest)),
+        opt(tag(end)),
+    )))
+}
+
+fn string_literal(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        char('"'),
+        opt(escaped(is_not("\\\"\n"), '\\', anychar)),
+        opt(char('"')),
+    )))(input)
+}
+
+// Scala symbols like 'foo aren't closed, so aren't mistaken for one.
+fn char_literal(input: &str) -> IResult<&str, &str> {
+    recognize(tuple((
+        char('\''),
+        alt((
+            recognize(pair(
+                char('\\'),
+                take_while(|chr| chr != '\'' && chr != '\n'),
+            )),
+            recognize(anychar),
+        )),
+        char('\''),
+    )))(input)
+}
+
+/// The next token, `None` for whitespace and comments.
+fn token(input: &str) -> IResult<&str, Option<Token<'_>>> {
+    alt((
+        value(
+            None,
+            alt((
+                take_while1(|chr| chr == ' ' || chr == '\t' || chr == '\r'),
+                recognize(pair(tag("//"), take_while(not_end_of_line))),
+                delimited_by("/*", "*/"),
+            )),
+        ),
+        value(
+            Some(Token::Other),
+            alt((
+                delimited_by("\"\"\"", "\"\"\""),
+                string_literal,
+                char_literal,
+            )),
+        ),
+        value(Some(Token::EndOfStatement), one_of("\n;")),
+        value(Some(Token::OpenBrace), char('{')),
+        value(Some(Token::CloseBrace), char('}')),
+        value(Some(Token::OpenParen), char('(')),
+        value(Some(Token::CloseParen), char(')')),
+        value(Some(Token::At), char('@')),
+        value(Some(Token::Dot), char('.')),
+        map(take_while1(is_word_char), |word| Some(Token::Word(word))),
+        value(Some(Token::Other), anychar),
+    ))(input)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DeclarationState {
+    /// Only modifiers and annotations seen so far.
+    Start,
+    Annotation,
+    AfterAnnotation,
+    Name,
+    PackageName,
+    AfterPackageName,
+    /// A scala `package object`, its members belong to the package it's named after.
+    PackageObjectName,
+    /// Not a declaration, wait for the next statement.
+    Skip,
+}
+
+fn qualified_name(package: &[&str], name: String) -> String {
+    if package.is_empty() || name.is_empty() {
+        name
+    } else {
+        format!("{}.{}", package.join("."), name)
+    }
+}
+
+/// Fully qualified names of the types declared outside of any braces, e.g. `com.example.Foo` for
+/// `final case class Foo(a: Int) {` after `package com.example`. The braces of a scala `package foo {`
+/// block don't count, what they hold is still top level. Package clauses nest, in scala
+/// `package a; package b { class C }` declares `a.b.C`, and `package a; package object b` declares
+/// `a.b.package`.
+///
+/// A declaration is a `keyword` preceded only by `modifiers` or annotations.
+pub(in crate::source_dependencies) fn extract_top_level_declarations(
+    input: &str,
+    keywords: &[&str],
+    modifiers: &[&str],
+) -> Vec<String> {
+    let mut results: Vec<String> = Vec::default();
+    // For each open brace that is a package block, the package to go back to once it's closed.
+    let mut braces: Vec<Option<usize>> = Vec::default();
+    let mut package: Vec<&str> = Vec::default();
+    let mut package_clause: Vec<&str> = Vec::default();
+    let mut paren_depth = 0;
+    let mut state = DeclarationState::Start;
+
+    let mut remaining_input = input;
+    while let Ok((r, token)) = token(remaining_input) {
+        remaining_input = r;
+        let token = match token {
+            Some(token) => token,
+            None => continue,
+        };
+        let at_top_level = braces.iter().all(|package_block| package_block.is_some());
+
+        match token {
+            Token::OpenBrace => {
+                if state == DeclarationState::AfterPackageName && paren_depth == 0 {
+                    braces.push(Some(package.len()));
+                    package.append(&mut package_clause);
+                } else {
+                    braces.push(None);
+                }
+                state = DeclarationState::Start;
+                continue;
+            }
+            Token::CloseBrace => {
+                if let Some(Some(enclosing_package)) = braces.pop() {
+                    package.truncate(enclosing_package);
+                }
+                state = DeclarationState::Start;
+                continue;
+            }
+            Token::OpenParen => {
+                paren_depth += 1;
+                if state != DeclarationState::AfterAnnotation {
+                    state = DeclarationState::Skip;
+                }
+                continue;
+            }
+            Token::CloseParen => {
+                paren_depth -= 1;
+                if paren_depth <= 0 {
+                    paren_depth = 0;
+                    if state == DeclarationState::AfterAnnotation {
+                        state = DeclarationState::Start;
+                    }
+                }
+                continue;
+            }
+            _ if paren_depth > 0 => continue,
+            _ => (),
+        }
+
+        if state == DeclarationState::AfterAnnotation && token != Token::Dot {
+            state = DeclarationState::Start;
+        }
+        // Without a block the package clause covers the rest of the enclosing one.
+        if state == DeclarationState::AfterPackageName && token != Token::Dot {
+            package.append(&mut package_clause);
+        }
+        state = match (state, token) {
+            (_, Token::EndOfStatement) => DeclarationState::Start,
+            (DeclarationState::Start, Token::Word("package")) if at_top_level => {
+                package_clause.clear();
+                DeclarationState::PackageName
+            }
+            (DeclarationState::Start, Token::Word(word)) if keywords.contains(&word) => {
+                DeclarationState::Name
+            }
+            (DeclarationState::Start, Token::Word(word)) if modifiers.contains(&word) => {
+                DeclarationState::Start
+            }
+            (DeclarationState::Start, Token::At) => DeclarationState::Annotation,
+            (DeclarationState::Annotation, Token::Word(word))
+                if keywords.contains(&format!("@{}", word).as_str()) =>
+            {
+                DeclarationState::Name
+            }
+            (DeclarationState::Annotation, Token::Word(_)) => DeclarationState::AfterAnnotation,
+            (DeclarationState::AfterAnnotation, Token::Dot) => DeclarationState::Annotation,
+            (DeclarationState::Name, Token::Word(word)) => {
+                let name: String = word
+                    .chars()
+                    .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+                    .collect();
+                let name = qualified_name(&package, name);
+                if at_top_level && !name.is_empty() && !results.contains(&name) {
+                    results.push(name);
+                }
+                DeclarationState::Skip
+            }
+            (DeclarationState::PackageName, Token::Word("object"))
+                if package_clause.is_empty() && keywords.contains(&"object") =>
+            {
+                DeclarationState::PackageObjectName
+            }
+            (DeclarationState::PackageObjectName, Token::Word(word)) => {
+                let name = qualified_name(&package, format!("{}.package", word));
+                if !results.contains(&name) {
+                    results.push(name);
+                }
+                DeclarationState::Skip
+            }
+            (DeclarationState::PackageName, Token::Word(word)) => {
+                package_clause.push(word);
+                DeclarationState::AfterPackageName
+            }
+            (DeclarationState::AfterPackageName, Token::Dot) => DeclarationState::PackageName,
+            _ => DeclarationState::Skip,
+        };
+    }
+    results
+}
@@ -191,6 +191,24 @@ pub fn parse_imports(input: &str) -> Result<Vec<Import>> {
     Ok(results_vec)
 }
 
+/// Fully qualified names of the top level types declared in the file.
+pub fn parse_declarations(input: &str) -> Vec<String> {
+    extract_top_level_declarations(
+        input,
+        &["class", "object", "trait", "enum"],
+        &[
+            "abstract",
+            "case",
+            "final",
+            "implicit",
+            "sealed",
+            "private",
+            "protected",
+            "open",
+        ],
+    )
+}
+
 pub fn parse_file(input: &str) -> Result<ParsedFile> {
     let package = extract_package_from_file(input)?;
 
@@ -390,4 +408,90 @@ mod tests {
         assert_eq!(parsed_result.1, Some("DestTpe"));
         assert_eq!(remaining, "");
     }
+
+    #[test]
+    fn parse_top_level_declarations() {
+        let sample_input = r#"package com.example
+
+import scala.util.Try
+
+sealed trait Shape
+final case class Circle(radius: Double) extends Shape {
+  val label = """}"""
+  case class Nested(i: Int)
+}
+object Shape {
+  def apply(): Shape = Circle(1.0)
+}
+"#;
+        assert_eq!(
+            parse_declarations(sample_input),
+            vec![
+                "com.example.Shape".to_string(),
+                "com.example.Circle".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_declarations_in_package_blocks() {
+        let sample_input = r#"package com.example
+package util {
+  @deprecated("use Other") class Legacy
+  object Helpers {
+    class NotMe
+  }
+}
+package other { trait Other }
+class AfterBlocks
+"#;
+        assert_eq!(
+            parse_declarations(sample_input),
+            vec![
+                "com.example.util.Legacy".to_string(),
+                "com.example.util.Helpers".to_string(),
+                "com.example.other.Other".to_string(),
+                "com.example.AfterBlocks".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_package_object_declarations() {
+        let sample_input = r#"package com.example
+
+package object util {
+  type Id = String
+  class NotMe
+}
+
+class AfterPackageObject
+"#;
+        assert_eq!(
+            parse_declarations(sample_input),
+            vec![
+                "com.example.util.package".to_string(),
+                "com.example.AfterPackageObject".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_declarations_in_chained_packages() {
+        let sample_input = r#"package com.example; package util { class Helper }
+package nested
+package deeper {
+  object Deep
+}
+class Chained
+"#;
+        assert_eq!(
+            parse_declarations(sample_input),
+            vec![
+                "com.example.util.Helper".to_string(),
+                "com.example.nested.deeper.Deep".to_string(),
+                "com.example.nested.Chained".to_string(),
+            ]
+        );
+    }
 }
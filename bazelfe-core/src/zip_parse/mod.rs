@@ -1,4 +1,5 @@
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 fn extract_paths_from_zip(path: PathBuf) -> Vec<String> {
     if !path.exists() {
@@ -54,6 +55,42 @@ pub fn extract_classes_from_zip(path: PathBuf) -> Vec<String> {
     transform_file_names_into_class_names(extract_paths_from_zip(path))
 }
 
+/// The `Target-Label` bazel's jvm rules stamp into the manifest of the jars they build.
+pub fn manifest_target_label(path: &Path) -> Option<String> {
+    let file = std::fs::File::open(path).ok()?;
+    let mut archive = zip::ZipArchive::new(file).ok()?;
+    let mut manifest = String::default();
+    archive
+        .by_name("META-INF/MANIFEST.MF")
+        .ok()?
+        .read_to_string(&mut manifest)
+        .ok()?;
+    parse_manifest_target_label(&manifest)
+}
+
+fn parse_manifest_target_label(manifest: &str) -> Option<String> {
+    let mut lines = manifest.lines();
+    let mut value = lines
+        .find_map(|ln| ln.strip_prefix("Target-Label:"))?
+        .trim_start()
+        .to_string();
+    // Manifest lines are wrapped at 72 bytes, continuations start with a single space.
+    for ln in lines {
+        match ln.strip_prefix(' ') {
+            Some(continuation) => value.push_str(continuation),
+            None => break,
+        }
+    }
+    let value = value.trim_end();
+    // Labels in the main repo are indexed without any repo prefix.
+    let value = value
+        .strip_prefix("@@")
+        .or_else(|| value.strip_prefix('@'))
+        .filter(|e| e.starts_with("//"))
+        .unwrap_or(value);
+    Some(value.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,4 +158,20 @@ mod tests {
             expected_results
         );
     }
+
+    #[test]
+    fn parse_target_label_from_manifest() {
+        let manifest = "Manifest-Version: 1.0\r\nCreated-By: bazel\r\nTarget-Label: @@//src/main/java/com/example/some/very/long/package/name:a_very_lo\r\n ng_target_name\r\nInjecting-Rule-Kind: java_library\r\n\r\n";
+        assert_eq!(
+            parse_manifest_target_label(manifest),
+            Some(String::from(
+                "//src/main/java/com/example/some/very/long/package/name:a_very_long_target_name"
+            ))
+        );
+        assert_eq!(
+            parse_manifest_target_label("Target-Label: @maven//:com_google_guava_guava\n"),
+            Some(String::from("@maven//:com_google_guava_guava"))
+        );
+        assert_eq!(parse_manifest_target_label("Manifest-Version: 1.0\n"), None);
+    }
 }
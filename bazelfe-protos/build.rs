@@ -7,6 +7,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &[
                 "proto/upstream_other/build_event_stream/build_event_stream.proto",
                 "proto/upstream_other/blaze_query/build.proto",
+                "proto/upstream_other/bazel_flags/bazel_flags.proto",
+                "proto/upstream_other/blaze_deps/deps.proto",
                 "proto/upstream_other/devtools/buildozer/api.proto",
                 "proto/googleapis/google/bytestream/bytestream.proto",
                 "proto/googleapis/google/devtools/build/v1/publish_build_event.proto",
@@ -62,6 +62,14 @@ pub mod blaze_query {
     tonic::include_proto!("blaze_query");
 }
 
+pub mod bazel_flags {
+    tonic::include_proto!("bazel_flags");
+}
+
+pub mod blaze_deps {
+    tonic::include_proto!("blaze_deps");
+}
+
 pub mod devtools {
     pub mod buildozer {
         tonic::include_proto!("devtools.buildozer");
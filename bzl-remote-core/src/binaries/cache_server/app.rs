@@ -4,6 +4,7 @@ use bzl_remote_core::cache_service::capabilities_service::CapabilitiesService;
 use bzl_remote_core::cache_service::content_addressable_storage_service::ContentAddressableStorageService;
 
 use bazelfe_protos::build::bazel::remote::asset::v1::fetch_server::FetchServer;
+use bazelfe_protos::build::bazel::remote::asset::v1::push_server::PushServer;
 use bazelfe_protos::build::bazel::remote::execution::v2::action_cache_server::ActionCacheServer;
 use bazelfe_protos::build::bazel::remote::execution::v2::capabilities_server::CapabilitiesServer;
 use bazelfe_protos::build::bazel::remote::execution::v2::content_addressable_storage_server::ContentAddressableStorageServer;
@@ -11,9 +12,14 @@ use bazelfe_protos::google::bytestream::byte_stream_server::ByteStreamServer;
 use bzl_remote_core::cache_service::fetch_service::FetchServiceStruct;
 use bzl_remote_core::cache_service::http_endpoint::HttpEndpoint;
 use bzl_remote_core::cache_service::metadata_service::MetadataService;
-use bzl_remote_core::server::{EitherBody, GrpcErrorTraceLayer};
+use bzl_remote_core::cache_service::push_service::PushServiceStruct;
+use bzl_remote_core::config::mirror_config::MirrorUpstream;
+use bzl_remote_core::server::{
+    tls_acceptor_from_config, AuthLayer, Authorizer, EitherBody, GrpcErrorTraceLayer,
+};
+use bzl_remote_core::storage_backend::StorageBackend;
 use clap::Parser;
-use futures::future::{self, Either, TryFutureExt};
+use futures::future::{self, BoxFuture, Either, TryFutureExt};
 use http::version::Version;
 
 use hyper::{service::make_service_fn, Server};
@@ -22,11 +28,15 @@ use std::convert::Infallible;
 use std::error::Error;
 use std::path::PathBuf;
 use std::sync::Arc;
+use tonic::body::BoxBody;
 use tonic::transport::Server as TonicServer;
 use tower::Service;
 
 type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
+const ACCEPT_BACKOFF: std::time::Duration = std::time::Duration::from_millis(100);
+type ServerResponse = hyper::Response<EitherBody<hyper::Body, BoxBody>>;
+
 #[derive(Parser, Debug)]
 #[clap(name = "basic")]
 struct ServerAppArgs {
@@ -40,6 +50,94 @@ struct ServerAppArgs {
     logs_root: Option<PathBuf>,
 }
 
+fn build_service(
+    storage_backend: Arc<dyn StorageBackend>,
+    send_buffer_size: usize,
+    max_archive_decompressed_bytes: u64,
+    mirror_upstreams: Arc<Vec<MirrorUpstream>>,
+    auth_layer: AuthLayer,
+) -> impl tower::Service<
+    hyper::Request<hyper::Body>,
+    Response = ServerResponse,
+    Error = BoxError,
+    Future = BoxFuture<'static, Result<ServerResponse, BoxError>>,
+> + Clone
+       + Send
+       + 'static {
+    let action_cache_service = ActionCacheService::new(storage_backend.clone());
+    let contentaddressablestorage_service =
+        ContentAddressableStorageService::new(storage_backend.clone());
+    let bytestream_service = ByteStreamService::new(storage_backend.clone(), send_buffer_size);
+
+    let fetch_srv = FetchServiceStruct::new(storage_backend.clone())
+        .with_max_archive_decompressed_bytes(max_archive_decompressed_bytes);
+    let push_srv = PushServiceStruct::new(storage_backend.clone());
+    let metadata_service = MetadataService::new(storage_backend.clone());
+
+    let layer = tower::ServiceBuilder::new()
+        // Apply our own middleware
+        .layer(GrpcErrorTraceLayer)
+        .into_inner();
+
+    let capabilites_service = CapabilitiesService::new();
+    let mut grpc_server = TonicServer::builder()
+                .max_concurrent_streams(1000)
+                .tcp_nodelay(true)
+                .initial_stream_window_size(16384*2)
+                .trace_fn(|r| {
+                    let ip_info = r.extensions()
+                    .get::<tonic::transport::server::TcpConnectInfo>()
+                    .and_then(|i| i.remote_addr())
+                    .or_else(|| {
+                        r.extensions()
+                            .get::<tonic::transport::server::TlsConnectInfo<tonic::transport::server::TcpConnectInfo>>()
+                            .and_then(|i| i.get_ref().remote_addr())
+                    });
+
+
+                    let remote_ip = ip_info.map(|e| e.ip().to_string());
+                    let remote_str = remote_ip.as_ref();
+                    tracing::info_span!("bzl-cache", remote_client=?remote_str)
+                })
+                .max_frame_size(6194304)
+                .layer(layer)
+                .add_service(ActionCacheServer::new(action_cache_service))
+                .add_service(bazelfe_protos::bzl_remote::metadata_service::metadata_service_server::MetadataServiceServer::new(metadata_service))
+                .add_service(CapabilitiesServer::new(capabilites_service))
+                .add_service(ContentAddressableStorageServer::new(
+                    contentaddressablestorage_service,
+                ))
+                .add_service(ByteStreamServer::new(bytestream_service))
+                .add_service(FetchServer::new(fetch_srv))
+                .add_service(PushServer::new(push_srv))
+                .into_service();
+
+    let http_endpoint = Arc::new(HttpEndpoint::new(storage_backend.clone(), mirror_upstreams));
+    tower::ServiceBuilder::new()
+        .layer(auth_layer)
+        .service(tower::service_fn(
+            move |req: hyper::Request<hyper::Body>| {
+                let http_endpoint = http_endpoint.clone();
+                match req.version() {
+                    Version::HTTP_11 | Version::HTTP_10 => Either::Left(async move {
+                        http_endpoint
+                            .dispatch(req)
+                            .map_ok(|res| res.map(EitherBody::Left))
+                            .map_err(BoxError::from)
+                            .await
+                    }),
+                    Version::HTTP_2 => Either::Right(
+                        grpc_server
+                            .call(req)
+                            .map_ok(|res| res.map(EitherBody::Right))
+                            .map_err(BoxError::from),
+                    ),
+                    _ => unimplemented!(),
+                }
+            },
+        ))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let opt = ServerAppArgs::parse();
@@ -91,82 +189,72 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         };
 
-    Server::bind(&bind_address)
-        .serve(make_service_fn(|_| {
-
-            let action_cache_service = ActionCacheService::new(storage_backend.clone());
-            let contentaddressablestorage_service =
-                ContentAddressableStorageService::new(storage_backend.clone());
-            let bytestream_service =
-                ByteStreamService::new(storage_backend.clone(), config.send_buffer_size);
-
-            let fetch_srv = FetchServiceStruct::new(storage_backend.clone());
-            let metadata_service = MetadataService::new(storage_backend.clone());
+    let authorizer = Arc::new(Authorizer::new(&config.auth_config));
+    let send_buffer_size = config.send_buffer_size;
+    let max_archive_decompressed_bytes = config.max_archive_decompressed_bytes;
+    let mirror_upstreams = Arc::new(config.mirror_upstreams.clone());
 
-            let layer = tower::ServiceBuilder::new()
-                // Apply our own middleware
-                .layer(GrpcErrorTraceLayer)
-                .into_inner();
-
-            let capabilites_service = CapabilitiesService::new();
-            let mut grpc_server = TonicServer::builder()
-                .max_concurrent_streams(1000)
-                .tcp_nodelay(true)
-                .initial_stream_window_size(16384*2)
-                .trace_fn(|r| {
-                    let ip_info = r.extensions()
-                    .get::<tonic::transport::server::TcpConnectInfo>()
-                    .and_then(|i| i.remote_addr())
-                    .or_else(|| {
-                        r.extensions()
-                            .get::<tonic::transport::server::TlsConnectInfo<tonic::transport::server::TcpConnectInfo>>()
-                            .and_then(|i| i.get_ref().remote_addr())
-                    });
-
-
-                    let remote_ip = ip_info.map(|e| e.ip().to_string());
-                    let remote_str = remote_ip.as_ref();
-                    tracing::info_span!("bzl-cache", remote_client=?remote_str)
-                })
-                .max_frame_size(6194304)
-                .layer(layer)
-                .add_service(ActionCacheServer::new(action_cache_service))
-                .add_service(bazelfe_protos::bzl_remote::metadata_service::metadata_service_server::MetadataServiceServer::new(metadata_service))
-                .add_service(CapabilitiesServer::new(capabilites_service))
-                .add_service(ContentAddressableStorageServer::new(
-                    contentaddressablestorage_service,
-                ))
-                .add_service(ByteStreamServer::new(bytestream_service))
-                .add_service(FetchServer::new(fetch_srv))
-                .into_service();
+    if let Some(tls_config) = config.tls_config.as_ref() {
+        let tls_acceptor = tls_acceptor_from_config(tls_config)?;
+        let listener = tokio::net::TcpListener::bind(&bind_address).await?;
+        loop {
+            let (tcp_stream, remote_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("Failed to accept a connection: {}", e);
+                    // Out of file descriptors, give the connections being served a chance to finish.
+                    if matches!(e.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE)) {
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                    }
+                    continue;
+                }
+            };
+            let tls_acceptor = tls_acceptor.clone();
+            let storage_backend = storage_backend.clone();
+            let authorizer = authorizer.clone();
+            let mirror_upstreams = mirror_upstreams.clone();
+            tokio::spawn(async move {
+                let tls_stream = match tls_acceptor.accept(tcp_stream).await {
+                    Ok(s) => s,
+                    Err(e) => {
+                        tracing::warn!("TLS handshake with {} failed: {}", remote_addr, e);
+                        return;
+                    }
+                };
+                let client_certificate = tls_stream
+                    .get_ref()
+                    .1
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                    .map(|cert| cert.0.clone());
+                let service = build_service(
+                    storage_backend,
+                    send_buffer_size,
+                    max_archive_decompressed_bytes,
+                    mirror_upstreams,
+                    AuthLayer::new(authorizer, client_certificate.as_deref()),
+                );
+                if let Err(e) = hyper::server::conn::Http::new()
+                    .serve_connection(tls_stream, service)
+                    .await
+                {
+                    tracing::warn!("Error serving connection from {}: {}", remote_addr, e);
+                }
+            });
+        }
+    }
 
-                let http_endpoint = Arc::new(HttpEndpoint::new(storage_backend.clone()));
-                future::ok::<_, Infallible>(tower::service_fn(
-
-                    move |req: hyper::Request<hyper::Body>| {
-                        let http_endpoint = http_endpoint.clone();
-                        match req.version() {
-                        Version::HTTP_11 | Version::HTTP_10 =>
-                            Either::Left(async move {
-                            http_endpoint.dispatch(req)
-                                .map_ok(|res| res.map(EitherBody::Left))
-                                .map_err(BoxError::from)
-                                .await
-                            }
-
-                        )
-                    ,
-                        Version::HTTP_2 => Either::Right(
-                            grpc_server
-                                .call(req)
-                                .map_ok(|res| res.map(EitherBody::Right))
-                                .map_err(BoxError::from),
-                        ),
-                        _ => unimplemented!(),
-                    }},
-                ))
-    }))
-    .await?;
+    Server::bind(&bind_address)
+        .serve(make_service_fn(|_| {
+            future::ok::<_, Infallible>(build_service(
+                storage_backend.clone(),
+                send_buffer_size,
+                max_archive_decompressed_bytes,
+                mirror_upstreams.clone(),
+                AuthLayer::new(authorizer.clone(), None),
+            ))
+        }))
+        .await?;
 
     Ok(())
 }
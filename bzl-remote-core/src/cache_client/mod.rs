@@ -1,5 +1,8 @@
 use bazelfe_protos::{
-    build::bazel::remote::execution::v2::{self as execution},
+    build::bazel::remote::execution::v2::{
+        self as execution, action_cache_client::ActionCacheClient,
+        content_addressable_storage_client::ContentAddressableStorageClient,
+    },
     bzl_remote::metadata_service::metadata_service_client::MetadataServiceClient,
     google::{
         self,
@@ -7,7 +10,7 @@ use bazelfe_protos::{
     },
 };
 use std::{path::Path, str::FromStr};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tonic::{
     transport::{Channel, Endpoint},
     Request,
@@ -15,15 +18,26 @@ use tonic::{
 
 #[derive(Clone, Debug)]
 pub struct CacheClient {
+    action_cache: ActionCache,
     content_addressable_store: ContentAddressableStore,
 }
 
 impl CacheClient {
     fn new(channel: Channel) -> CacheClient {
         CacheClient {
+            action_cache: ActionCache::new(channel.clone()),
             content_addressable_store: ContentAddressableStore::new(channel.clone()),
         }
     }
+
+    /// A client sharing this one's connection that addresses the given REAPI instance.
+    pub fn with_instance_name<S: Into<String>>(&self, instance_name: S) -> CacheClient {
+        let instance_name = instance_name.into();
+        let mut cache_client = self.clone();
+        cache_client.action_cache.instance_name = instance_name.clone();
+        cache_client.content_addressable_store.instance_name = instance_name;
+        cache_client
+    }
     pub async fn connect<S: AsRef<str>>(
         connect_url: S,
     ) -> Result<CacheClient, Box<dyn std::error::Error + Send + Sync>> {
@@ -38,22 +52,120 @@ impl CacheClient {
     pub fn cas(&self) -> &ContentAddressableStore {
         &self.content_addressable_store
     }
+
+    pub fn action_cache(&self) -> &ActionCache {
+        &self.action_cache
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ActionCache {
+    action_cache_cli: ActionCacheClient<Channel>,
+    instance_name: String,
+}
+
+impl ActionCache {
+    fn new(channel: Channel) -> ActionCache {
+        ActionCache {
+            action_cache_cli: ActionCacheClient::new(channel),
+            instance_name: String::default(),
+        }
+    }
+
+    pub async fn get_action_result(
+        &self,
+        action_digest: &execution::Digest,
+    ) -> Result<Option<execution::ActionResult>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut cli = self.action_cache_cli.clone();
+        let response = cli
+            .get_action_result(execution::GetActionResultRequest {
+                instance_name: self.instance_name.clone(),
+                action_digest: Some(action_digest.clone()),
+                ..Default::default()
+            })
+            .await;
+
+        match response {
+            Ok(response) => Ok(Some(response.into_inner())),
+            Err(err) => {
+                if err.code() == tonic::Code::NotFound {
+                    Ok(None)
+                } else {
+                    Err(err.into())
+                }
+            }
+        }
+    }
+
+    pub async fn update_action_result(
+        &self,
+        action_digest: &execution::Digest,
+        action_result: &execution::ActionResult,
+    ) -> Result<execution::ActionResult, Box<dyn std::error::Error + Send + Sync>> {
+        let mut cli = self.action_cache_cli.clone();
+        Ok(cli
+            .update_action_result(execution::UpdateActionResultRequest {
+                instance_name: self.instance_name.clone(),
+                action_digest: Some(action_digest.clone()),
+                action_result: Some(action_result.clone()),
+                ..Default::default()
+            })
+            .await?
+            .into_inner())
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct ContentAddressableStore {
     bytestream_cli: ByteStreamClient<Channel>,
+    cas_cli: ContentAddressableStorageClient<Channel>,
     metadata_cli: MetadataServiceClient<Channel>,
+    instance_name: String,
 }
 
 impl ContentAddressableStore {
     fn new(channel: Channel) -> ContentAddressableStore {
         ContentAddressableStore {
             bytestream_cli: ByteStreamClient::new(channel.clone()),
+            cas_cli: ContentAddressableStorageClient::new(channel.clone()),
             metadata_cli: MetadataServiceClient::new(channel.clone()),
+            instance_name: String::default(),
+        }
+    }
+
+    fn resource_name(&self, resource: String) -> String {
+        if self.instance_name.is_empty() {
+            resource
+        } else {
+            format!("{}/{}", self.instance_name, resource)
         }
     }
 
+    pub async fn find_missing_blobs(
+        &self,
+        digests: Vec<execution::Digest>,
+    ) -> Result<Vec<execution::Digest>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut cli = self.cas_cli.clone();
+        Ok(cli
+            .find_missing_blobs(execution::FindMissingBlobsRequest {
+                instance_name: self.instance_name.clone(),
+                blob_digests: digests,
+            })
+            .await?
+            .into_inner()
+            .missing_blob_digests)
+    }
+
+    pub async fn upload_bytes(
+        &self,
+        digest: &execution::Digest,
+        data: Vec<u8>,
+        send_buffer_size: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.upload_from(digest, std::io::Cursor::new(data), send_buffer_size)
+            .await
+    }
+
     pub async fn find_digest(
         &self,
         digest_hash: &str,
@@ -81,31 +193,39 @@ impl ContentAddressableStore {
         path: &Path,
         send_buffer_size: u64,
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut cli = self.bytestream_cli.clone();
+        let file = tokio::fs::File::open(path).await?;
+        self.upload_from(digest, file, send_buffer_size).await
+    }
 
-        let mut file = tokio::fs::File::open(path).await?;
+    // Writes digest.size_bytes bytes from the reader in chunks of at most send_buffer_size.
+    async fn upload_from<R: AsyncRead + Unpin + Send + 'static>(
+        &self,
+        digest: &execution::Digest,
+        mut reader: R,
+        send_buffer_size: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut cli = self.bytestream_cli.clone();
         let len = digest.size_bytes as usize;
 
-        let resource_url = format!(
+        let resource_url = self.resource_name(format!(
             "uploads/936DA01F9ABD4d9d80C702AF85C822A8{}/blobs/{}/{}",
             rand::random::<usize>(),
             digest.hash,
             digest.size_bytes
-        );
-        let send_buffer_size = send_buffer_size as usize;
+        ));
+        let send_buffer_size = std::cmp::max(send_buffer_size as usize, 1);
 
         let outbound = async_stream::stream! {
             let mut remaining = len;
             let mut write_offset = 0;
 
-
             loop {
-                let next_buff_size = std::cmp::min(send_buffer_size, remaining) as usize;
+                let next_buff_size = std::cmp::min(send_buffer_size, remaining);
                 let mut buf = vec![0; next_buff_size];
 
                 let mut bytes_read = 0;
                 while bytes_read < next_buff_size {
-                    match file.read(&mut buf).await {
+                    match reader.read(&mut buf[bytes_read..]).await {
                         Err(e) => {
                             tracing::error!("Attempted to read from file, but failed with error: {:?}",e);
                             break;
@@ -121,21 +241,22 @@ impl ContentAddressableStore {
                 }
 
                 remaining -= bytes_read;
-
+                // A short read leaves the upload incomplete, the server rejects the size mismatch.
+                let finish_write = remaining == 0 || bytes_read < next_buff_size;
 
                 if buf.len() != bytes_read {
                     buf.truncate(bytes_read);
                 }
                 let buf = google::bytestream::WriteRequest {
                     data: buf,
-                    finish_write: remaining == 0,
+                    finish_write,
                     resource_name: if write_offset == 0 { resource_url.clone() } else { String::from("") },
                     write_offset: write_offset as i64
                 };
                 write_offset += bytes_read;
 
                 yield buf;
-                if remaining == 0 {
+                if finish_write {
                     break;
                 }
             }
@@ -156,7 +277,8 @@ impl ContentAddressableStore {
 
         let read_response = cli
             .read(Request::new(ReadRequest {
-                resource_name: format!("blobs/{}/{}", digest.hash, digest.size_bytes),
+                resource_name: self
+                    .resource_name(format!("blobs/{}/{}", digest.hash, digest.size_bytes)),
                 ..Default::default()
             }))
             .await;
@@ -169,6 +291,7 @@ impl ContentAddressableStore {
                     output_file.write_all(&resp.data).await?;
                     written += resp.data.len();
                 }
+                output_file.flush().await?;
                 if written != digest.size_bytes as usize {
                     return Err(anyhow::anyhow!("Got wrong size result back").into());
                 }
@@ -194,7 +317,8 @@ impl ContentAddressableStore {
 
         let read_response = cli
             .read(Request::new(ReadRequest {
-                resource_name: format!("blobs/{}/{}", digest.hash, digest.size_bytes),
+                resource_name: self
+                    .resource_name(format!("blobs/{}/{}", digest.hash, digest.size_bytes)),
                 ..Default::default()
             }))
             .await;
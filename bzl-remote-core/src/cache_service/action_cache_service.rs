@@ -4,6 +4,7 @@ use execution::action_cache_server;
 use tonic::{Request, Response};
 
 use super::OptionAsStatusError;
+use crate::metrics::METRICS;
 use crate::storage_backend::StorageBackend;
 
 #[derive(Debug)]
@@ -42,8 +43,16 @@ where
             .get_action_result(&action_digest)
             .await?
         {
+            METRICS
+                .action_cache_requests
+                .with_labels(&[("result", "hit")])
+                .inc();
             Ok(Response::new(action_r.as_ref().to_owned()))
         } else {
+            METRICS
+                .action_cache_requests
+                .with_labels(&[("result", "miss")])
+                .inc();
             tracing::info!("Cache miss for digest : {}", action_digest.hash);
             Err(tonic::Status::not_found("Unable to find in cache"))
         }
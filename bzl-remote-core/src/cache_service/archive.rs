@@ -0,0 +1,452 @@
+use std::cell::Cell;
+use std::io::{Cursor, Read};
+
+use thiserror::Error;
+
+const TAR_BLOCK_SIZE: usize = 512;
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Error, Debug)]
+pub enum ArchiveError {
+    #[error("Unrecognized archive format, expected a tar, tar.gz or zip")]
+    UnknownFormat,
+
+    #[error("Invalid path in archive: {0}")]
+    InvalidPath(String),
+
+    #[error("Archive contains {0} more than once")]
+    DuplicateEntry(String),
+
+    #[error("Symlink {0} points outside of the archive with target {1}")]
+    InvalidSymlink(String, String),
+
+    #[error("Archive expands to more than the allowed {0} bytes")]
+    TooLarge(u64),
+
+    #[error("IO error reading archive: {0}")]
+    IOError(#[from] std::io::Error),
+
+    #[error("Error reading zip archive: {0}")]
+    ZipError(#[from] zip::result::ZipError),
+
+    #[error("Unable to store archive contents: {0}")]
+    Storage(#[from] crate::storage_backend::StorageBackendError),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveEntryKind {
+    File {
+        data: Vec<u8>,
+        is_executable: bool,
+    },
+    Directory,
+    Symlink {
+        target: String,
+    },
+    /// Shares the contents of an earlier entry, `target` is normalized like `path`.
+    HardLink {
+        target: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Normalized, relative and `/` separated.
+    pub path: String,
+    pub kind: ArchiveEntryKind,
+}
+
+/// Calls `on_entry` with every entry of a tar, gzip compressed tar or zip archive in turn,
+/// sniffing the format from its contents. Only one entry's contents are held in memory at a time.
+pub fn for_each_archive_entry<F>(
+    data: &[u8],
+    max_decompressed_bytes: u64,
+    on_entry: F,
+) -> Result<(), ArchiveError>
+where
+    F: FnMut(ArchiveEntry) -> Result<(), ArchiveError>,
+{
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let exceeded = Cell::new(false);
+        let decoder = LimitedReader {
+            inner: flate2::read::GzDecoder::new(data),
+            remaining: max_decompressed_bytes,
+            exceeded: &exceeded,
+        };
+        extract_tar(decoder, max_decompressed_bytes, on_entry).map_err(|e| {
+            if exceeded.get() {
+                ArchiveError::TooLarge(max_decompressed_bytes)
+            } else {
+                e
+            }
+        })
+    } else if data.starts_with(b"PK\x03\x04") || data.starts_with(b"PK\x05\x06") {
+        extract_zip(data, max_decompressed_bytes, on_entry)
+    } else if data.len() >= TAR_BLOCK_SIZE && &data[257..262] == b"ustar" {
+        extract_tar(data, max_decompressed_bytes, on_entry)
+    } else {
+        Err(ArchiveError::UnknownFormat)
+    }
+}
+
+/// Fails reads once more than `remaining` bytes came through, flagging `exceeded` so the
+/// error can be told apart from the tar reader's own once it has wrapped it.
+struct LimitedReader<'a, R> {
+    inner: R,
+    remaining: u64,
+    exceeded: &'a Cell<bool>,
+}
+
+impl<R: Read> Read for LimitedReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        match self.remaining.checked_sub(read as u64) {
+            Some(remaining) => {
+                self.remaining = remaining;
+                Ok(read)
+            }
+            None => {
+                self.exceeded.set(true);
+                Err(std::io::Error::other("archive decompresses past the limit"))
+            }
+        }
+    }
+}
+
+/// Reads at most `remaining` bytes, `limit` is the overall budget reported when we run out.
+fn read_limited<R: Read>(reader: R, remaining: u64, limit: u64) -> Result<Vec<u8>, ArchiveError> {
+    let mut contents = Vec::default();
+    reader
+        .take(remaining.saturating_add(1))
+        .read_to_end(&mut contents)?;
+    if contents.len() as u64 > remaining {
+        return Err(ArchiveError::TooLarge(limit));
+    }
+    Ok(contents)
+}
+
+pub fn normalize_path(raw: &str) -> Result<Option<String>, ArchiveError> {
+    let mut components = Vec::default();
+    for component in raw.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => return Err(ArchiveError::InvalidPath(raw.to_string())),
+            c => components.push(c),
+        }
+    }
+    if components.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(components.join("/")))
+    }
+}
+
+fn emit<F>(on_entry: &mut F, raw_path: &str, kind: ArchiveEntryKind) -> Result<(), ArchiveError>
+where
+    F: FnMut(ArchiveEntry) -> Result<(), ArchiveError>,
+{
+    match normalize_path(raw_path)? {
+        Some(path) => on_entry(ArchiveEntry { path, kind }),
+        None => Ok(()),
+    }
+}
+
+fn extract_zip<F>(
+    data: &[u8],
+    max_decompressed_bytes: u64,
+    mut on_entry: F,
+) -> Result<(), ArchiveError>
+where
+    F: FnMut(ArchiveEntry) -> Result<(), ArchiveError>,
+{
+    let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+    let mut remaining = max_decompressed_bytes;
+    for idx in 0..archive.len() {
+        let mut file = archive.by_index(idx)?;
+        let name = file.name().to_string();
+        if file.is_dir() {
+            emit(&mut on_entry, &name, ArchiveEntryKind::Directory)?;
+            continue;
+        }
+        let mode = file.unix_mode().unwrap_or(0o644);
+        // The sizes in the zip headers are untrusted, so we only stop on what actually inflates.
+        let contents = read_limited(&mut file, remaining, max_decompressed_bytes)?;
+        remaining -= contents.len() as u64;
+        let kind = if mode & S_IFMT == S_IFLNK {
+            ArchiveEntryKind::Symlink {
+                target: String::from_utf8_lossy(&contents).to_string(),
+            }
+        } else {
+            ArchiveEntryKind::File {
+                data: contents,
+                is_executable: mode & 0o111 != 0,
+            }
+        };
+        emit(&mut on_entry, &name, kind)?;
+    }
+    Ok(())
+}
+
+fn extract_tar<R, F>(
+    reader: R,
+    max_decompressed_bytes: u64,
+    mut on_entry: F,
+) -> Result<(), ArchiveError>
+where
+    R: Read,
+    F: FnMut(ArchiveEntry) -> Result<(), ArchiveError>,
+{
+    let mut archive = tar::Archive::new(reader);
+    let mut remaining = max_decompressed_bytes;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let link_name = entry
+            .link_name_bytes()
+            .map(|l| String::from_utf8_lossy(&l).to_string())
+            .unwrap_or_default();
+        let mode = entry.header().mode()?;
+
+        let kind = match entry.header().entry_type() {
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let data = read_limited(&mut entry, remaining, max_decompressed_bytes)?;
+                remaining -= data.len() as u64;
+                ArchiveEntryKind::File {
+                    data,
+                    is_executable: mode & 0o111 != 0,
+                }
+            }
+            tar::EntryType::Directory => ArchiveEntryKind::Directory,
+            tar::EntryType::Symlink => ArchiveEntryKind::Symlink { target: link_name },
+            tar::EntryType::Link => ArchiveEntryKind::HardLink {
+                target: normalize_path(&link_name)?
+                    .ok_or_else(|| ArchiveError::InvalidPath(link_name.clone()))?,
+            },
+            // Device nodes, fifos and the like have no place in a cached directory.
+            _ => continue,
+        };
+        emit(&mut on_entry, &name, kind)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    const LIMIT: u64 = 1024 * 1024;
+
+    fn extract_archive(data: &[u8], limit: u64) -> Result<Vec<ArchiveEntry>, ArchiveError> {
+        let mut entries = Vec::default();
+        for_each_archive_entry(data, limit, |e| {
+            entries.push(e);
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    pub(crate) fn tar_header(entry_type: tar::EntryType, mode: u32, size: u64) -> tar::Header {
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(entry_type);
+        header.set_mode(mode);
+        header.set_size(size);
+        header
+    }
+
+    /// Builds a tar of (path, mode, contents), paths ending in `/` are directories.
+    pub(crate) fn build_tar(files: &[(&str, u32, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::default());
+        for (name, mode, contents) in files {
+            let entry_type = if name.ends_with('/') {
+                tar::EntryType::Directory
+            } else {
+                tar::EntryType::Regular
+            };
+            let mut header = tar_header(entry_type, *mode, contents.len() as u64);
+            builder.append_data(&mut header, name, *contents).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Appends a symlink, or with `tar::EntryType::Link` a hard link, at `name` to the end of `tar`.
+    pub(crate) fn append_link(
+        tar: Vec<u8>,
+        entry_type: tar::EntryType,
+        name: &str,
+        target: &str,
+    ) -> Vec<u8> {
+        // Drop the end of archive marker so the builder can carry on after it.
+        let mut tar = tar;
+        tar.truncate(tar.len() - TAR_BLOCK_SIZE * 2);
+        let mut builder = tar::Builder::new(tar);
+        let mut header = tar_header(entry_type, 0o777, 0);
+        builder.append_link(&mut header, name, target).unwrap();
+        builder.into_inner().unwrap()
+    }
+
+    pub(crate) fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_extract_tar_gz() -> Result<(), Box<dyn std::error::Error>> {
+        let tar = build_tar(&[
+            ("repo-1.0/", 0o755, b""),
+            ("repo-1.0/BUILD", 0o644, b"exports_files([])"),
+            ("repo-1.0/bin/tool.sh", 0o755, b"#!/bin/sh\necho hi\n"),
+        ]);
+        let tar = append_link(tar, tar::EntryType::Symlink, "repo-1.0/link", "BUILD");
+        let tar = append_link(
+            tar,
+            tar::EntryType::Link,
+            "repo-1.0/BUILD.bazel",
+            "repo-1.0/BUILD",
+        );
+
+        let entries = extract_archive(&gzip(&tar), LIMIT)?;
+        assert_eq!(
+            entries,
+            vec![
+                ArchiveEntry {
+                    path: String::from("repo-1.0"),
+                    kind: ArchiveEntryKind::Directory
+                },
+                ArchiveEntry {
+                    path: String::from("repo-1.0/BUILD"),
+                    kind: ArchiveEntryKind::File {
+                        data: b"exports_files([])".to_vec(),
+                        is_executable: false
+                    }
+                },
+                ArchiveEntry {
+                    path: String::from("repo-1.0/bin/tool.sh"),
+                    kind: ArchiveEntryKind::File {
+                        data: b"#!/bin/sh\necho hi\n".to_vec(),
+                        is_executable: true
+                    }
+                },
+                ArchiveEntry {
+                    path: String::from("repo-1.0/link"),
+                    kind: ArchiveEntryKind::Symlink {
+                        target: String::from("BUILD")
+                    }
+                },
+                ArchiveEntry {
+                    path: String::from("repo-1.0/BUILD.bazel"),
+                    kind: ArchiveEntryKind::HardLink {
+                        target: String::from("repo-1.0/BUILD")
+                    }
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_tar_long_names() -> Result<(), Box<dyn std::error::Error>> {
+        let long_name = format!("{}/file.txt", "d".repeat(150));
+        // The builder writes a GNU long name entry for names that don't fit the header.
+        let tar = build_tar(&[(long_name.as_str(), 0o644, b"data")]);
+
+        let entries = extract_archive(&tar, LIMIT)?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].path, long_name);
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_zip() -> Result<(), Box<dyn std::error::Error>> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        writer.add_directory("pkg/", zip::write::FileOptions::default())?;
+        writer.start_file(
+            "pkg/run",
+            zip::write::FileOptions::default().unix_permissions(0o755),
+        )?;
+        writer.write_all(b"run me")?;
+        let data = writer.finish()?.into_inner();
+
+        let entries = extract_archive(&data, LIMIT)?;
+        assert_eq!(
+            entries,
+            vec![
+                ArchiveEntry {
+                    path: String::from("pkg"),
+                    kind: ArchiveEntryKind::Directory
+                },
+                ArchiveEntry {
+                    path: String::from("pkg/run"),
+                    kind: ArchiveEntryKind::File {
+                        data: b"run me".to_vec(),
+                        is_executable: true
+                    }
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_escaping_paths() {
+        // The builder refuses to write these, so poke the name straight into the header.
+        let mut header = tar_header(tar::EntryType::Regular, 0o644, 1);
+        header.as_old_mut().name[..7].copy_from_slice(b"../evil");
+        header.set_cksum();
+        let mut builder = tar::Builder::new(Vec::default());
+        builder.append(&header, &b"x"[..]).unwrap();
+        let tar = builder.into_inner().unwrap();
+        assert!(matches!(
+            extract_archive(&tar, LIMIT),
+            Err(ArchiveError::InvalidPath(_))
+        ));
+    }
+
+    #[test]
+    fn test_rejects_sizes_past_the_end() {
+        // A base-256 size that wraps the offset around if added unchecked.
+        let mut header = tar_header(tar::EntryType::Regular, 0o644, u64::MAX - 100);
+        header.set_path("huge").unwrap();
+        header.set_cksum();
+        let mut tar = header.as_bytes().to_vec();
+        tar.extend_from_slice(&[b'x'; TAR_BLOCK_SIZE]);
+        assert!(matches!(
+            extract_archive(&tar, LIMIT),
+            Err(ArchiveError::IOError(_))
+        ));
+    }
+
+    #[test]
+    fn test_limits_decompressed_size() -> Result<(), Box<dyn std::error::Error>> {
+        let contents = vec![0_u8; 64 * 1024];
+        let tar_gz = gzip(&build_tar(&[("zeros", 0o644, &contents)]));
+        assert!(tar_gz.len() < 1024);
+        assert!(matches!(
+            extract_archive(&tar_gz, 16 * 1024),
+            Err(ArchiveError::TooLarge(16384))
+        ));
+        // The tar itself fits, but not the file contents.
+        let tar = build_tar(&[("zeros", 0o644, &contents)]);
+        assert!(matches!(
+            extract_archive(&tar, 64 * 1024 - 1),
+            Err(ArchiveError::TooLarge(_))
+        ));
+
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for name in ["a", "b"] {
+            writer.start_file(name, zip::write::FileOptions::default())?;
+            writer.write_all(&contents)?;
+        }
+        let zip = writer.finish()?.into_inner();
+        assert!(matches!(
+            extract_archive(&zip, 100 * 1024),
+            Err(ArchiveError::TooLarge(_))
+        ));
+        assert_eq!(extract_archive(&zip, 128 * 1024)?.len(), 2);
+        Ok(())
+    }
+}
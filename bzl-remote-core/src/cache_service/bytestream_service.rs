@@ -9,6 +9,7 @@ use tracing::{Instrument, Span};
 use std::pin::Pin;
 use std::time::Instant;
 
+use crate::metrics::METRICS;
 use crate::storage_backend::{StorageBackend, UploadType};
 
 use google::bytestream;
@@ -139,6 +140,10 @@ where
                     tx.send_async(Ok(buf)).await.unwrap();
                 }
                 let complete = Instant::now();
+                METRICS
+                    .bytestream_duration_seconds
+                    .with_labels(&[("method", "read")])
+                    .observe(complete.duration_since(start).as_secs_f64());
                 // if slice_ref.len() > 4 * 1024 * 1024 {
 
                 tracing::info!(
@@ -248,6 +253,10 @@ where
                 start_time.elapsed().as_secs()
             );
         }
+        METRICS
+            .bytestream_duration_seconds
+            .with_labels(&[("method", "write")])
+            .observe(start_time.elapsed().as_secs_f64());
 
         Ok(Response::new(bytestream::WriteResponse { committed_size }))
     }
@@ -5,6 +5,9 @@ use bazelfe_protos::*;
 
 use tonic::Request;
 
+/// Largest total payload we accept/return in a single batch CAS request.
+pub const MAX_BATCH_TOTAL_SIZE_BYTES: i64 = 4 * 1024 * 1024;
+
 pub struct CapabilitiesService {}
 
 impl CapabilitiesService {
@@ -38,7 +41,7 @@ impl capabilities_server::Capabilities for CapabilitiesService {
                         max_priority: 100,
                     }],
                 }),
-                max_batch_total_size_bytes: 4 * 1024 * 1024,
+                max_batch_total_size_bytes: MAX_BATCH_TOTAL_SIZE_BYTES,
                 symlink_absolute_path_strategy:
                     execution::symlink_absolute_path_strategy::Value::Disallowed as i32,
             }),
@@ -1,12 +1,22 @@
 use bazelfe_protos::build::bazel::remote::execution::v2::{self as execution};
+use bazelfe_protos::google::rpc;
 
 use futures::Stream;
+use prost::Message;
 
+use std::collections::{HashSet, VecDeque};
 use std::pin::Pin;
 
 use tonic::{Request, Response};
+use tracing::{Instrument, Span};
 
-use crate::storage_backend::StorageBackend;
+use super::capabilities_service::MAX_BATCH_TOTAL_SIZE_BYTES;
+use super::OptionAsStatusError;
+use crate::hash::sha256_value::Sha256Value;
+use crate::metrics::METRICS;
+use crate::storage_backend::{StorageBackend, StorageBackendError, UploadType};
+
+const DEFAULT_GET_TREE_PAGE_SIZE: usize = 1000;
 
 #[derive(Debug)]
 pub struct ContentAddressableStorageService<T> {
@@ -20,13 +30,171 @@ where
     pub fn new(storage_backend: T) -> ContentAddressableStorageService<T> {
         ContentAddressableStorageService { storage_backend }
     }
+
+    async fn update_one_blob(
+        &self,
+        digest: &execution::Digest,
+        data: Vec<u8>,
+    ) -> Result<(), StorageBackendError> {
+        validate_inbound_blob(digest, &data)?;
+        self.storage_backend
+            .cas_insert(digest, UploadType::InMemory(data))
+            .await
+    }
+
+    // Pops directories off the breadth first frontier until we have a page worth of them.
+    // Directories missing from the CAS are omitted along with their children, as per the REAPI spec.
+    async fn walk_tree_page(
+        &self,
+        cursor: &mut TreeCursor,
+        page_size: usize,
+    ) -> Result<Vec<execution::Directory>, StorageBackendError> {
+        let mut results = Vec::default();
+
+        while results.len() < page_size {
+            let digest = match cursor.to_visit.pop_front() {
+                Some(d) => d,
+                None => break,
+            };
+            let directory = match self.storage_backend.cas_get_data(&digest).await? {
+                Some(data) => execution::Directory::decode(data.as_ref().as_ref())?,
+                None => {
+                    tracing::debug!(
+                        "Directory {}/{} was missing from the CAS while walking a tree",
+                        digest.hash,
+                        digest.size_bytes
+                    );
+                    continue;
+                }
+            };
+
+            for child in directory.directories.iter() {
+                if let Some(child_digest) = child.digest.as_ref() {
+                    if cursor.seen.insert(child_digest.clone()) {
+                        cursor.to_visit.push_back(child_digest.clone());
+                    }
+                }
+            }
+            results.push(directory);
+        }
+        cursor.returned += results.len() as u64;
+        Ok(results)
+    }
+}
+
+// The breadth first frontier of a GetTree walk. The walk from a given root is deterministic, so
+// our page tokens are just the root digest and how many directories were already returned.
+// Resuming re-walks from the root and skips those, which keeps tokens small and bound to their root.
+#[derive(Debug, Default)]
+struct TreeCursor {
+    to_visit: VecDeque<execution::Digest>,
+    seen: HashSet<execution::Digest>,
+    returned: u64,
+}
+
+impl TreeCursor {
+    fn new(root_digest: execution::Digest) -> TreeCursor {
+        let mut cursor = TreeCursor::default();
+        cursor.seen.insert(root_digest.clone());
+        cursor.to_visit.push_back(root_digest);
+        cursor
+    }
+
+    // Returns how many directories the token says were already returned for this root.
+    fn parse_page_token(
+        root_digest: &execution::Digest,
+        page_token: &str,
+    ) -> Result<u64, tonic::Status> {
+        let invalid =
+            || tonic::Status::invalid_argument(format!("Invalid page token: '{}'", page_token));
+        let mut parts = page_token.splitn(3, '/');
+        let (hash, size, returned) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(hash), Some(size), Some(returned)) => (hash, size, returned),
+            _ => return Err(invalid()),
+        };
+        if hash != root_digest.hash || size.parse::<i64>().ok() != Some(root_digest.size_bytes) {
+            return Err(tonic::Status::invalid_argument(format!(
+                "Page token '{}' does not belong to root directory {}/{}",
+                page_token, root_digest.hash, root_digest.size_bytes
+            )));
+        }
+        returned.parse::<u64>().map_err(|_e| invalid())
+    }
+
+    fn next_page_token(&self, root_digest: &execution::Digest) -> String {
+        if self.to_visit.is_empty() {
+            String::default()
+        } else {
+            format!(
+                "{}/{}/{}",
+                root_digest.hash, root_digest.size_bytes, self.returned
+            )
+        }
+    }
+}
+
+fn validate_inbound_blob(
+    digest: &execution::Digest,
+    data: &[u8],
+) -> Result<(), StorageBackendError> {
+    let expected: Sha256Value = digest.try_into()?;
+    if digest.size_bytes != data.len() as i64 {
+        return Err(StorageBackendError::InvalidSizeForDataInbound(
+            expected,
+            digest.size_bytes,
+            data.len(),
+        ));
+    }
+    let actual: Sha256Value = data.try_into()?;
+    if actual != expected {
+        return Err(StorageBackendError::InvalidDigestForDataInbound(
+            expected, actual,
+        ));
+    }
+    Ok(())
+}
+
+fn ok_rpc_status() -> rpc::Status {
+    rpc::Status {
+        code: rpc::Code::Ok as i32,
+        ..Default::default()
+    }
+}
+
+fn to_rpc_status(status: tonic::Status) -> rpc::Status {
+    rpc::Status {
+        code: status.code() as i32,
+        message: status.message().to_string(),
+        ..Default::default()
+    }
+}
+
+fn check_batch_size(sizes: impl Iterator<Item = i64>) -> Result<(), tonic::Status> {
+    let mut total_size: i64 = 0;
+    for size in sizes {
+        if size < 0 {
+            return Err(tonic::Status::invalid_argument(format!(
+                "Batch request has a negative size of {} bytes",
+                size
+            )));
+        }
+        total_size = total_size.saturating_add(size);
+    }
+    if total_size > MAX_BATCH_TOTAL_SIZE_BYTES {
+        Err(tonic::Status::invalid_argument(format!(
+            "Batch request of {} bytes exceeds the max_batch_total_size_bytes of {}",
+            total_size, MAX_BATCH_TOTAL_SIZE_BYTES
+        )))
+    } else {
+        Ok(())
+    }
 }
 
 #[tonic::async_trait]
 impl<T> execution::content_addressable_storage_server::ContentAddressableStorage
     for ContentAddressableStorageService<T>
 where
-    T: StorageBackend + 'static,
+    T: StorageBackend + Clone + 'static,
 {
     type GetTreeStream = Pin<
         Box<
@@ -43,11 +211,19 @@ where
     ) -> Result<tonic::Response<execution::FindMissingBlobsResponse>, tonic::Status> {
         let request = request.into_inner();
         let mut blob_digests = request.blob_digests;
+        let requested = blob_digests.len();
 
         self.storage_backend
             .cas_filter_for_missing(&mut blob_digests)
             .await?;
 
+        METRICS.find_missing_blobs_digests.observe(requested as f64);
+        if requested > 0 {
+            METRICS
+                .find_missing_blobs_missing_ratio
+                .observe(blob_digests.len() as f64 / requested as f64);
+        }
+
         Ok(Response::new(execution::FindMissingBlobsResponse {
             missing_blob_digests: blob_digests,
         }))
@@ -57,29 +233,413 @@ where
         &self,
         request: Request<execution::BatchUpdateBlobsRequest>,
     ) -> Result<tonic::Response<execution::BatchUpdateBlobsResponse>, tonic::Status> {
-        tracing::error!(
-            "Unimplmented: batch_update_blobs - {:#?}",
-            request.into_inner()
-        );
-        todo!()
+        let request = request.into_inner();
+        check_batch_size(request.requests.iter().map(|r| r.data.len() as i64))?;
+
+        let mut responses = Vec::with_capacity(request.requests.len());
+        for blob_request in request.requests.into_iter() {
+            let digest = match blob_request.digest {
+                Some(digest) => digest,
+                None => {
+                    responses.push(execution::batch_update_blobs_response::Response {
+                        digest: None,
+                        status: Some(to_rpc_status(tonic::Status::invalid_argument(
+                            "Missing digest for blob in batch update",
+                        ))),
+                    });
+                    continue;
+                }
+            };
+
+            let status = match self.update_one_blob(&digest, blob_request.data).await {
+                Ok(()) => ok_rpc_status(),
+                Err(e) => to_rpc_status(e.into()),
+            };
+
+            responses.push(execution::batch_update_blobs_response::Response {
+                digest: Some(digest),
+                status: Some(status),
+            });
+        }
+
+        Ok(Response::new(execution::BatchUpdateBlobsResponse {
+            responses,
+        }))
     }
 
     async fn batch_read_blobs(
         &self,
         request: Request<execution::BatchReadBlobsRequest>,
     ) -> Result<tonic::Response<execution::BatchReadBlobsResponse>, tonic::Status> {
-        tracing::error!(
-            "Unimplmented: - batch_read_blobs {:#?}",
-            request.into_inner()
-        );
-        todo!()
+        let request = request.into_inner();
+        check_batch_size(request.digests.iter().map(|d| d.size_bytes))?;
+
+        let mut responses = Vec::with_capacity(request.digests.len());
+        for digest in request.digests.into_iter() {
+            let (data, status) = match self.storage_backend.cas_get_data(&digest).await {
+                Ok(Some(data)) => (data.as_ref().as_ref().to_vec(), ok_rpc_status()),
+                Ok(None) => (
+                    Vec::default(),
+                    to_rpc_status(tonic::Status::not_found(format!(
+                        "Unable to find blob {}/{}",
+                        digest.hash, digest.size_bytes
+                    ))),
+                ),
+                Err(e) => (Vec::default(), to_rpc_status(e.into())),
+            };
+
+            responses.push(execution::batch_read_blobs_response::Response {
+                digest: Some(digest),
+                data,
+                status: Some(status),
+            });
+        }
+
+        Ok(Response::new(execution::BatchReadBlobsResponse {
+            responses,
+        }))
     }
 
     async fn get_tree(
         &self,
         request: Request<execution::GetTreeRequest>,
     ) -> Result<tonic::Response<Self::GetTreeStream>, tonic::Status> {
-        tracing::error!("Unimplmented: get_tree {:#?}", request.into_inner());
-        todo!()
+        let mut request = request.into_inner();
+        let root_digest = request.root_digest.take_or_error()?;
+
+        let already_returned = if request.page_token.is_empty() {
+            0
+        } else {
+            TreeCursor::parse_page_token(&root_digest, &request.page_token)?
+        };
+        if !self.storage_backend.cas_exists(&root_digest).await? {
+            return Err(tonic::Status::not_found(format!(
+                "Unable to find root directory {}/{}",
+                root_digest.hash, root_digest.size_bytes
+            )));
+        }
+
+        let page_size = if request.page_size > 0 {
+            request.page_size as usize
+        } else {
+            DEFAULT_GET_TREE_PAGE_SIZE
+        };
+
+        let (tx, rx) = flume::bounded(2);
+        let svc = ContentAddressableStorageService::new(self.storage_backend.clone());
+        tokio::spawn(
+            async move {
+                let mut cursor = TreeCursor::new(root_digest.clone());
+                while cursor.returned < already_returned && !cursor.to_visit.is_empty() {
+                    let skip = (already_returned - cursor.returned).min(page_size as u64);
+                    if let Err(e) = svc.walk_tree_page(&mut cursor, skip as usize).await {
+                        let _ = tx.send_async(Err(tonic::Status::from(e))).await;
+                        return;
+                    }
+                }
+                while !cursor.to_visit.is_empty() {
+                    let page = match svc.walk_tree_page(&mut cursor, page_size).await {
+                        Ok(directories) => Ok(execution::GetTreeResponse {
+                            directories,
+                            next_page_token: cursor.next_page_token(&root_digest),
+                        }),
+                        Err(e) => Err(tonic::Status::from(e)),
+                    };
+                    let failed = page.is_err();
+                    if tx.send_async(page).await.is_err() || failed {
+                        break;
+                    }
+                }
+            }
+            .instrument(Span::current()),
+        );
+
+        Ok(Response::new(
+            Box::pin(rx.into_stream()) as Self::GetTreeStream
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage_backend::InMemoryStorageBackend;
+    use execution::content_addressable_storage_server::ContentAddressableStorage;
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    fn digest_for(data: &[u8]) -> execution::Digest {
+        let sha_v: Sha256Value = data.try_into().expect("Should be able to hash");
+        execution::Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        }
+    }
+
+    async fn insert_directory(
+        backend: &InMemoryStorageBackend,
+        directory: &execution::Directory,
+    ) -> execution::Digest {
+        let bytes = directory.encode_to_vec();
+        let digest = digest_for(&bytes);
+        backend
+            .cas_insert(&digest, UploadType::InMemory(bytes))
+            .await
+            .expect("Should be able to insert");
+        digest
+    }
+
+    #[tokio::test]
+    async fn test_batch_update_then_read() -> Result<(), Box<dyn std::error::Error>> {
+        let svc =
+            ContentAddressableStorageService::new(Arc::new(InMemoryStorageBackend::default()));
+
+        let good_data = b"hello world".to_vec();
+        let good_digest = digest_for(&good_data);
+        let mut bad_size_digest = digest_for(b"other data");
+        bad_size_digest.size_bytes += 1;
+        let bad_hash_digest = execution::Digest {
+            hash: digest_for(b"not this data").hash,
+            size_bytes: 4,
+        };
+
+        let update_response = svc
+            .batch_update_blobs(Request::new(execution::BatchUpdateBlobsRequest {
+                requests: vec![
+                    execution::batch_update_blobs_request::Request {
+                        digest: Some(good_digest.clone()),
+                        data: good_data.clone(),
+                    },
+                    execution::batch_update_blobs_request::Request {
+                        digest: Some(bad_size_digest.clone()),
+                        data: b"other data".to_vec(),
+                    },
+                    execution::batch_update_blobs_request::Request {
+                        digest: Some(bad_hash_digest.clone()),
+                        data: b"data".to_vec(),
+                    },
+                ],
+                ..Default::default()
+            }))
+            .await?
+            .into_inner();
+
+        let codes: Vec<i32> = update_response
+            .responses
+            .iter()
+            .map(|r| r.status.as_ref().map(|s| s.code).unwrap_or(-1))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![
+                rpc::Code::Ok as i32,
+                rpc::Code::InvalidArgument as i32,
+                rpc::Code::InvalidArgument as i32
+            ]
+        );
+
+        let read_response = svc
+            .batch_read_blobs(Request::new(execution::BatchReadBlobsRequest {
+                digests: vec![good_digest.clone(), bad_size_digest.clone()],
+                ..Default::default()
+            }))
+            .await?
+            .into_inner();
+
+        assert_eq!(read_response.responses.len(), 2);
+        assert_eq!(read_response.responses[0].data, good_data);
+        assert_eq!(
+            read_response.responses[0].status.as_ref().map(|s| s.code),
+            Some(rpc::Code::Ok as i32)
+        );
+        assert_eq!(
+            read_response.responses[1].status.as_ref().map(|s| s.code),
+            Some(rpc::Code::NotFound as i32)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_batch_too_large() {
+        let svc =
+            ContentAddressableStorageService::new(Arc::new(InMemoryStorageBackend::default()));
+        let res = svc
+            .batch_read_blobs(Request::new(execution::BatchReadBlobsRequest {
+                digests: vec![execution::Digest {
+                    hash: String::from("abc"),
+                    size_bytes: MAX_BATCH_TOTAL_SIZE_BYTES + 1,
+                }],
+                ..Default::default()
+            }))
+            .await;
+        assert_eq!(
+            res.expect_err("Should fail").code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_negative_size() {
+        let svc =
+            ContentAddressableStorageService::new(Arc::new(InMemoryStorageBackend::default()));
+        let res = svc
+            .batch_read_blobs(Request::new(execution::BatchReadBlobsRequest {
+                digests: vec![
+                    execution::Digest {
+                        hash: String::from("abc"),
+                        size_bytes: MAX_BATCH_TOTAL_SIZE_BYTES,
+                    },
+                    execution::Digest {
+                        hash: String::from("def"),
+                        size_bytes: -MAX_BATCH_TOTAL_SIZE_BYTES,
+                    },
+                ],
+                ..Default::default()
+            }))
+            .await;
+        assert_eq!(
+            res.expect_err("Should fail").code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+
+    #[tokio::test]
+    async fn test_batch_update_missing_digest() -> Result<(), Box<dyn std::error::Error>> {
+        let svc =
+            ContentAddressableStorageService::new(Arc::new(InMemoryStorageBackend::default()));
+        let good_data = b"hello world".to_vec();
+        let update_response = svc
+            .batch_update_blobs(Request::new(execution::BatchUpdateBlobsRequest {
+                requests: vec![
+                    execution::batch_update_blobs_request::Request {
+                        digest: None,
+                        data: b"no digest".to_vec(),
+                    },
+                    execution::batch_update_blobs_request::Request {
+                        digest: Some(digest_for(&good_data)),
+                        data: good_data,
+                    },
+                ],
+                ..Default::default()
+            }))
+            .await?
+            .into_inner();
+
+        let codes: Vec<i32> = update_response
+            .responses
+            .iter()
+            .map(|r| r.status.as_ref().map(|s| s.code).unwrap_or(-1))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![rpc::Code::InvalidArgument as i32, rpc::Code::Ok as i32]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_tree_paging() -> Result<(), Box<dyn std::error::Error>> {
+        let backend = InMemoryStorageBackend::default();
+
+        let mut root = execution::Directory::default();
+        for idx in 0..3 {
+            let leaf = execution::Directory {
+                files: vec![execution::FileNode {
+                    name: format!("file_{}", idx),
+                    digest: Some(digest_for(format!("{}", idx).as_bytes())),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            };
+            let leaf_digest = insert_directory(&backend, &leaf).await;
+            root.directories.push(execution::DirectoryNode {
+                name: format!("dir_{}", idx),
+                digest: Some(leaf_digest),
+            });
+        }
+        // A directory which is referenced but not present in the CAS is skipped.
+        root.directories.push(execution::DirectoryNode {
+            name: String::from("missing"),
+            digest: Some(digest_for(b"missing")),
+        });
+        let root_digest = insert_directory(&backend, &root).await;
+
+        let backend = Arc::new(backend);
+        let svc = ContentAddressableStorageService::new(backend.clone());
+
+        let pages: Vec<execution::GetTreeResponse> = svc
+            .get_tree(Request::new(execution::GetTreeRequest {
+                root_digest: Some(root_digest.clone()),
+                page_size: 3,
+                ..Default::default()
+            }))
+            .await?
+            .into_inner()
+            .map(|e| e.expect("Should be ok"))
+            .collect()
+            .await;
+
+        assert_eq!(pages.len(), 2);
+        assert_eq!(pages[0].directories.len(), 3);
+        assert_eq!(pages[0].directories[0], root);
+        // The token is the root and how many directories were already returned.
+        assert_eq!(
+            pages[0].next_page_token,
+            format!("{}/{}/3", root_digest.hash, root_digest.size_bytes)
+        );
+        assert_eq!(pages[1].directories.len(), 1);
+        assert_eq!(pages[1].next_page_token, "");
+
+        let resumed: Vec<execution::GetTreeResponse> = svc
+            .get_tree(Request::new(execution::GetTreeRequest {
+                root_digest: Some(root_digest.clone()),
+                page_size: 3,
+                page_token: pages[0].next_page_token.clone(),
+                ..Default::default()
+            }))
+            .await?
+            .into_inner()
+            .map(|e| e.expect("Should be ok"))
+            .collect()
+            .await;
+        assert_eq!(resumed, vec![pages[1].clone()]);
+
+        let bad_token = svc
+            .get_tree(Request::new(execution::GetTreeRequest {
+                root_digest: Some(digest_for(b"missing")),
+                page_token: String::from("3"),
+                ..Default::default()
+            }))
+            .await;
+        assert_eq!(
+            bad_token.err().expect("Should fail").code(),
+            tonic::Code::InvalidArgument
+        );
+
+        // A token handed out for one root can't be replayed against another.
+        let other_root = insert_directory(&backend, &execution::Directory::default()).await;
+        let wrong_root = svc
+            .get_tree(Request::new(execution::GetTreeRequest {
+                root_digest: Some(other_root),
+                page_token: pages[0].next_page_token.clone(),
+                ..Default::default()
+            }))
+            .await;
+        assert_eq!(
+            wrong_root.err().expect("Should fail").code(),
+            tonic::Code::InvalidArgument
+        );
+
+        let missing = svc
+            .get_tree(Request::new(execution::GetTreeRequest {
+                root_digest: Some(digest_for(b"missing")),
+                ..Default::default()
+            }))
+            .await;
+        assert_eq!(
+            missing.err().expect("Should fail").code(),
+            tonic::Code::NotFound
+        );
+        Ok(())
     }
 }
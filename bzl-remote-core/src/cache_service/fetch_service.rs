@@ -1,18 +1,25 @@
-use crate::storage_backend::{BackendIOHelpers, StorageBackendError};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::archive::{for_each_archive_entry, ArchiveEntry, ArchiveEntryKind, ArchiveError};
+use super::push_service::lookup_pushed_asset;
+use crate::storage_backend::{BackendIOHelpers, StorageBackendError, UploadType};
 use crate::{hash::sha256_value::Sha256Value, storage_backend::StorageBackend};
 use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
 use bazelfe_protos::build::bazel::remote::{
     asset::v1::{
         fetch_server, FetchBlobRequest, FetchBlobResponse, FetchDirectoryRequest,
-        FetchDirectoryResponse,
+        FetchDirectoryResponse, Qualifier,
     },
-    execution::v2::Digest,
+    execution::v2::{Digest, Directory, DirectoryNode, FileNode, SymlinkNode},
 };
+use bazelfe_protos::bzl_remote::bazelfe_kv::remote_asset_key::AssetKind;
+use prost::Message;
 use tonic::Request;
 
 #[derive(Debug)]
 pub struct FetchServiceStruct<T> {
     storage_backend: T,
+    max_archive_decompressed_bytes: u64,
 }
 
 impl<T> FetchServiceStruct<T>
@@ -20,7 +27,16 @@ where
     T: StorageBackend,
 {
     pub fn new(storage_backend: T) -> FetchServiceStruct<T> {
-        FetchServiceStruct { storage_backend }
+        FetchServiceStruct {
+            storage_backend,
+            max_archive_decompressed_bytes: crate::config::DEFAULT_MAX_ARCHIVE_DECOMPRESSED_BYTES,
+        }
+    }
+
+    /// Directory fetches fail when the archive expands to more than this.
+    pub fn with_max_archive_decompressed_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_archive_decompressed_bytes = max_bytes;
+        self
     }
 
     async fn fetch_one(&self, uris: Vec<String>) -> Result<(Digest, String), StorageBackendError> {
@@ -39,10 +55,39 @@ where
         // if we got here we never hit the return Ok
         Err(err)
     }
+
+    /// Returns the digest of the blob with the given hash, downloading it from `uris` if we don't have it yet.
+    async fn fetch_or_lookup(
+        &self,
+        expected_hash: &String,
+        uris: Vec<String>,
+    ) -> Result<(Digest, Option<String>), tonic::Status> {
+        let digest = self
+            .storage_backend
+            .build_digest_from_hash_if_present(expected_hash)
+            .await?;
+
+        match digest {
+            None => match self.fetch_one(uris).await {
+                Ok((d, url)) => {
+                    if &d.hash != expected_hash {
+                        return Err(tonic::Status::internal(format!("Tried to fetch/download the hash {}, but ended up with a digest of {:?} which is incorrect", expected_hash, d)));
+                    }
+                    Ok((d, Some(url)))
+                }
+                Err(ex) => Err(tonic::Status::internal(format!(
+                    "Unable to fetch with error {:#?}",
+                    ex
+                ))),
+            },
+            Some(d) => Ok((d, None)),
+        }
+    }
 }
 
-fn extract_req_digest(fetch_blob_request: &FetchBlobRequest) -> Result<String, tonic::Status> {
-    for dig in fetch_blob_request.qualifiers.iter() {
+/// The sha256 a `checksum.sri` qualifier pins the content to, if there is one.
+pub(crate) fn sri_sha256(qualifiers: &[Qualifier]) -> Result<Option<String>, tonic::Status> {
+    for dig in qualifiers.iter() {
         if dig.name == "checksum.sri" {
             if let Some(sha_v) = dig.value.strip_prefix("sha256-") {
                 let v = B64
@@ -51,14 +96,239 @@ fn extract_req_digest(fetch_blob_request: &FetchBlobRequest) -> Result<String, t
                 // The result is a binary sha256 value
                 match Sha256Value::new_from_slice(&v) {
                     Err(e) => return Err(tonic::Status::invalid_argument(format!("{:#?}", e))),
-                    Ok(o) => return Ok(o.to_string()),
+                    Ok(o) => return Ok(Some(o.to_string())),
                 }
             }
         }
     }
-    Err(tonic::Status::invalid_argument(
-        "Request had no sha256 digest info included.",
-    ))
+    Ok(None)
+}
+
+fn extract_req_digest(qualifiers: &[Qualifier]) -> Result<String, tonic::Status> {
+    sri_sha256(qualifiers)?.ok_or_else(|| {
+        tonic::Status::invalid_argument("Request had no sha256 digest info included.")
+    })
+}
+
+fn digest_for_data(data: &[u8]) -> Result<Digest, StorageBackendError> {
+    let sha_v: Sha256Value = data.try_into()?;
+    Ok(Digest {
+        hash: sha_v.to_string(),
+        size_bytes: data.len() as i64,
+    })
+}
+
+// How many extracted blobs can wait to be written into the CAS before extraction blocks.
+const CAS_WRITE_QUEUE_LENGTH: usize = 16;
+
+#[derive(Debug)]
+enum DirNode {
+    File(Digest, bool),
+    Directory,
+    Symlink(String),
+}
+
+#[derive(Debug, Default)]
+struct DirBuilder {
+    files: BTreeMap<String, (Digest, bool)>,
+    directories: BTreeMap<String, DirBuilder>,
+    symlinks: BTreeMap<String, String>,
+}
+
+impl DirBuilder {
+    fn contains(&self, name: &str) -> bool {
+        self.files.contains_key(name)
+            || self.directories.contains_key(name)
+            || self.symlinks.contains_key(name)
+    }
+
+    /// Adds a node at `path`, failing if anything but a directory already lives there.
+    fn insert(&mut self, path: &str, node: DirNode) -> Result<(), ArchiveError> {
+        let mut components: Vec<&str> = path.split('/').collect();
+        let name = components.pop().unwrap_or_default().to_string();
+        let mut current = self;
+        for c in components {
+            if current.files.contains_key(c) || current.symlinks.contains_key(c) {
+                return Err(ArchiveError::DuplicateEntry(path.to_string()));
+            }
+            current = current.directories.entry(c.to_string()).or_default();
+        }
+        if let DirNode::Directory = node {
+            if current.files.contains_key(&name) || current.symlinks.contains_key(&name) {
+                return Err(ArchiveError::DuplicateEntry(path.to_string()));
+            }
+            current.directories.entry(name).or_default();
+            return Ok(());
+        }
+        if current.contains(&name) {
+            return Err(ArchiveError::DuplicateEntry(path.to_string()));
+        }
+        match node {
+            DirNode::File(digest, is_executable) => {
+                current.files.insert(name, (digest, is_executable));
+            }
+            DirNode::Symlink(target) => {
+                check_symlink_target(path, &target)?;
+                current.symlinks.insert(name, target);
+            }
+            DirNode::Directory => (),
+        }
+        Ok(())
+    }
+
+    /// Builds the `Directory` protos bottom up, handing each one to `store` on the way.
+    fn build<F>(self, store: &mut F) -> Result<Digest, ArchiveError>
+    where
+        F: FnMut(Digest, Vec<u8>) -> Result<(), ArchiveError>,
+    {
+        let mut directory = Directory::default();
+        for (name, (digest, is_executable)) in self.files {
+            directory.files.push(FileNode {
+                name,
+                digest: Some(digest),
+                is_executable,
+                ..Default::default()
+            });
+        }
+        for (name, child) in self.directories {
+            directory.directories.push(DirectoryNode {
+                name,
+                digest: Some(child.build(store)?),
+            });
+        }
+        for (name, target) in self.symlinks {
+            directory.symlinks.push(SymlinkNode {
+                name,
+                target,
+                ..Default::default()
+            });
+        }
+        let data = directory.encode_to_vec();
+        let digest = digest_for_data(&data)?;
+        store(digest.clone(), data)?;
+        Ok(digest)
+    }
+}
+
+/// We advertise absolute symlinks as disallowed, and relative ones may not climb out of the tree.
+fn check_symlink_target(path: &str, target: &str) -> Result<(), ArchiveError> {
+    let invalid = || ArchiveError::InvalidSymlink(path.to_string(), target.to_string());
+    if target.starts_with('/') {
+        return Err(invalid());
+    }
+    // The number of directories between the root and where the target has led us so far.
+    let mut depth = path.split('/').count() - 1;
+    for component in target.split('/') {
+        match component {
+            "" | "." => (),
+            ".." => depth = depth.checked_sub(1).ok_or_else(invalid)?,
+            _ => depth += 1,
+        }
+    }
+    Ok(())
+}
+
+/// Turns archive entries into a directory tree as they are read, passing every blob on to be
+/// written into the CAS rather than holding the whole extracted archive in memory.
+struct ArchiveToDirectory {
+    strip_prefix: Option<String>,
+    found_prefix: bool,
+    root: DirBuilder,
+    // Keyed by their path in the archive, hard links refer to files this way.
+    files: HashMap<String, (Digest, bool)>,
+    sent: HashSet<String>,
+    blobs: tokio::sync::mpsc::Sender<(Digest, Vec<u8>)>,
+}
+
+impl ArchiveToDirectory {
+    fn new(
+        strip_prefix: Option<String>,
+        blobs: tokio::sync::mpsc::Sender<(Digest, Vec<u8>)>,
+    ) -> ArchiveToDirectory {
+        ArchiveToDirectory {
+            strip_prefix: strip_prefix
+                .map(|p| p.trim_matches('/').to_string())
+                .filter(|p| !p.is_empty()),
+            found_prefix: false,
+            root: DirBuilder::default(),
+            files: HashMap::default(),
+            sent: HashSet::default(),
+            blobs,
+        }
+    }
+
+    fn send_blob(&mut self, digest: Digest, data: Vec<u8>) -> Result<(), ArchiveError> {
+        if self.sent.insert(digest.hash.clone()) {
+            self.blobs.blocking_send((digest, data)).map_err(|_| {
+                StorageBackendError::Unknown(String::from(
+                    "Stopped writing the extracted archive into the CAS",
+                ))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Drops everything outside of `strip_prefix` and makes the remaining paths relative to it.
+    fn strip(&mut self, path: &str) -> Option<String> {
+        let prefix = match self.strip_prefix.as_ref() {
+            None => return Some(path.to_string()),
+            Some(p) => p,
+        };
+        if path == prefix {
+            self.found_prefix = true;
+            None
+        } else {
+            let stripped = path
+                .strip_prefix(prefix.as_str())
+                .and_then(|p| p.strip_prefix('/'))
+                .map(|p| p.to_string());
+            self.found_prefix |= stripped.is_some();
+            stripped
+        }
+    }
+
+    fn add(&mut self, entry: ArchiveEntry) -> Result<(), ArchiveError> {
+        let node = match entry.kind {
+            ArchiveEntryKind::File {
+                data,
+                is_executable,
+            } => {
+                let digest = digest_for_data(&data)?;
+                self.files
+                    .insert(entry.path.clone(), (digest.clone(), is_executable));
+                self.send_blob(digest.clone(), data)?;
+                DirNode::File(digest, is_executable)
+            }
+            ArchiveEntryKind::HardLink { target } => {
+                let (digest, is_executable) =
+                    self.files.get(&target).cloned().ok_or_else(|| {
+                        ArchiveError::InvalidPath(format!(
+                            "{} is a hard link to unknown entry {}",
+                            entry.path, target
+                        ))
+                    })?;
+                self.files
+                    .insert(entry.path.clone(), (digest.clone(), is_executable));
+                DirNode::File(digest, is_executable)
+            }
+            ArchiveEntryKind::Directory => DirNode::Directory,
+            ArchiveEntryKind::Symlink { target } => DirNode::Symlink(target),
+        };
+        match self.strip(&entry.path) {
+            Some(path) => self.root.insert(&path, node),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns None if nothing in the archive lived under the strip prefix.
+    fn finish(mut self) -> Result<Option<Digest>, ArchiveError> {
+        if self.strip_prefix.is_some() && !self.found_prefix {
+            return Ok(None);
+        }
+        let root = std::mem::take(&mut self.root);
+        root.build(&mut |digest, data| self.send_blob(digest, data))
+            .map(Some)
+    }
 }
 
 #[tonic::async_trait]
@@ -72,30 +342,27 @@ where
     ) -> Result<tonic::Response<FetchBlobResponse>, tonic::Status> {
         let fetch_blob_request = req.into_inner();
 
-        let found_digest = extract_req_digest(&fetch_blob_request)?;
+        if let Some((digest, uri)) = lookup_pushed_asset(
+            &self.storage_backend,
+            AssetKind::Blob,
+            &fetch_blob_request.uris,
+            &fetch_blob_request.qualifiers,
+        )
+        .await?
+        {
+            tracing::info!("Serving pushed blob {} for {}", digest.hash, uri);
+            return Ok(tonic::Response::new(FetchBlobResponse {
+                blob_digest: Some(digest),
+                uri,
+                ..Default::default()
+            }));
+        }
 
-        let digest = self
-            .storage_backend
-            .build_digest_from_hash_if_present(&found_digest)
-            .await?;
+        let found_digest = extract_req_digest(&fetch_blob_request.qualifiers)?;
 
-        let (digest, found_url) = match digest {
-            None => match self.fetch_one(fetch_blob_request.uris).await {
-                Ok((d, url)) => {
-                    if d.hash != found_digest {
-                        return Err(tonic::Status::internal(format!("Tried to fetch/download the hash {}, but ended up with a digest of {:?} which is incorrect", found_digest, digest)));
-                    }
-                    (d, Some(url))
-                }
-                Err(ex) => {
-                    return Err(tonic::Status::internal(format!(
-                        "Unable to fetch with error {:#?}",
-                        ex
-                    )))
-                }
-            },
-            Some(d) => (d, None),
-        };
+        let (digest, found_url) = self
+            .fetch_or_lookup(&found_digest, fetch_blob_request.uris)
+            .await?;
 
         tracing::info!(
             "Asked to a fetch to find remote resource for hash: {digest:?}, if downloaded url: {url:?}",
@@ -109,8 +376,242 @@ where
     }
     async fn fetch_directory(
         &self,
-        _: Request<FetchDirectoryRequest>,
+        req: Request<FetchDirectoryRequest>,
     ) -> std::result::Result<tonic::Response<FetchDirectoryResponse>, tonic::Status> {
-        todo!("fetch directory not yet implemented")
+        let fetch_directory_request = req.into_inner();
+
+        if let Some((digest, uri)) = lookup_pushed_asset(
+            &self.storage_backend,
+            AssetKind::Directory,
+            &fetch_directory_request.uris,
+            &fetch_directory_request.qualifiers,
+        )
+        .await?
+        {
+            tracing::info!("Serving pushed directory {} for {}", digest.hash, uri);
+            return Ok(tonic::Response::new(FetchDirectoryResponse {
+                root_directory_digest: Some(digest),
+                uri,
+                ..Default::default()
+            }));
+        }
+
+        let found_digest = extract_req_digest(&fetch_directory_request.qualifiers)?;
+        let strip_prefix = fetch_directory_request
+            .qualifiers
+            .iter()
+            .find(|q| q.name == "strip_prefix")
+            .map(|q| q.value.clone());
+
+        let (archive_digest, found_url) = self
+            .fetch_or_lookup(&found_digest, fetch_directory_request.uris)
+            .await?;
+
+        let archive_data = self
+            .storage_backend
+            .cas_get_data(&archive_digest)
+            .await?
+            .ok_or_else(|| {
+                tonic::Status::internal(format!(
+                    "Archive {} went missing from the CAS after fetching it",
+                    archive_digest.hash
+                ))
+            })?;
+
+        let (blob_tx, mut blob_rx) = tokio::sync::mpsc::channel(CAS_WRITE_QUEUE_LENGTH);
+        let max_archive_decompressed_bytes = self.max_archive_decompressed_bytes;
+        let prefix = strip_prefix.clone();
+        let extraction = tokio::task::spawn_blocking(move || {
+            let mut builder = ArchiveToDirectory::new(prefix, blob_tx);
+            for_each_archive_entry(
+                archive_data.as_ref().as_ref(),
+                max_archive_decompressed_bytes,
+                |entry| builder.add(entry),
+            )?;
+            builder.finish()
+        });
+
+        // Returning early drops the receiver, which stops the extraction too.
+        while let Some((digest, data)) = blob_rx.recv().await {
+            if !self.storage_backend.cas_exists(&digest).await? {
+                self.storage_backend
+                    .cas_insert(&digest, UploadType::InMemory(data))
+                    .await?;
+            }
+        }
+
+        let extracted = extraction.await.map_err(|e| {
+            tonic::Status::internal(format!(
+                "Extracting archive {} failed: {}",
+                archive_digest.hash, e
+            ))
+        })?;
+        let root_directory_digest = match extracted {
+            Ok(Some(digest)) => digest,
+            Ok(None) => {
+                return Err(tonic::Status::invalid_argument(format!(
+                    "strip_prefix {} did not match any path in the archive",
+                    strip_prefix.unwrap_or_default()
+                )))
+            }
+            Err(ArchiveError::Storage(e)) => return Err(e.into()),
+            Err(e) => {
+                return Err(tonic::Status::invalid_argument(format!(
+                    "Unable to extract archive {}: {}",
+                    archive_digest.hash, e
+                )))
+            }
+        };
+
+        tracing::info!(
+            "Fetched directory for archive hash: {digest:?}, if downloaded url: {url:?}, root directory: {root:?}",
+            digest = archive_digest.hash,
+            url = found_url,
+            root = root_directory_digest.hash
+        );
+        Ok(tonic::Response::new(FetchDirectoryResponse {
+            root_directory_digest: Some(root_directory_digest),
+            uri: found_url.unwrap_or_default(),
+            ..Default::default()
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use fetch_server::Fetch;
+    use sha2::Digest as _;
+
+    use super::super::archive::tests::{build_tar, gzip};
+    use super::*;
+    use crate::storage_backend::InMemoryStorageBackend;
+
+    async fn get_directory(
+        backend: &InMemoryStorageBackend,
+        digest: &Digest,
+    ) -> Result<Directory, Box<dyn std::error::Error>> {
+        let data = backend.cas_get_data(digest).await?.unwrap();
+        Ok(Directory::decode(data.as_ref().as_ref())?)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_directory_with_strip_prefix() -> Result<(), Box<dyn std::error::Error>> {
+        let backend = Arc::new(InMemoryStorageBackend::default());
+        let archive = gzip(&build_tar(&[
+            ("rules_foo-1.2/", 0o755, b""),
+            ("rules_foo-1.2/BUILD", 0o644, b""),
+            ("rules_foo-1.2/WORKSPACE", 0o644, b""),
+            ("rules_foo-1.2/tools/run.sh", 0o755, b"#!/bin/sh\n"),
+        ]));
+        let archive_digest = digest_for_data(&archive)?;
+        backend
+            .cas_insert(&archive_digest, UploadType::InMemory(archive.clone()))
+            .await?;
+
+        let checksum_sri = format!("sha256-{}", B64.encode(sha2::Sha256::digest(&archive)));
+        let fetch_service = FetchServiceStruct::new(Arc::clone(&backend));
+        let response = fetch_service
+            .fetch_directory(Request::new(FetchDirectoryRequest {
+                uris: vec![String::from("http://localhost:1/unused.tar.gz")],
+                qualifiers: vec![
+                    Qualifier {
+                        name: String::from("checksum.sri"),
+                        value: checksum_sri.clone(),
+                    },
+                    Qualifier {
+                        name: String::from("strip_prefix"),
+                        value: String::from("rules_foo-1.2"),
+                    },
+                ],
+                ..Default::default()
+            }))
+            .await?
+            .into_inner();
+
+        let root =
+            get_directory(&backend, response.root_directory_digest.as_ref().unwrap()).await?;
+        let file_names: Vec<&str> = root.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(file_names, vec!["BUILD", "WORKSPACE"]);
+        assert_eq!(root.directories.len(), 1);
+        assert_eq!(root.directories[0].name, "tools");
+
+        let tools = get_directory(&backend, root.directories[0].digest.as_ref().unwrap()).await?;
+        assert_eq!(tools.files[0].name, "run.sh");
+        assert!(tools.files[0].is_executable);
+        let run_sh = backend
+            .cas_get_data(tools.files[0].digest.as_ref().unwrap())
+            .await?
+            .unwrap();
+        assert_eq!(run_sh.as_ref().as_ref(), b"#!/bin/sh\n");
+
+        let bad_prefix = fetch_service
+            .fetch_directory(Request::new(FetchDirectoryRequest {
+                qualifiers: vec![
+                    Qualifier {
+                        name: String::from("checksum.sri"),
+                        value: checksum_sri.clone(),
+                    },
+                    Qualifier {
+                        name: String::from("strip_prefix"),
+                        value: String::from("rules_bar"),
+                    },
+                ],
+                ..Default::default()
+            }))
+            .await;
+        assert_eq!(bad_prefix.unwrap_err().code(), tonic::Code::InvalidArgument);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejects_duplicate_entries() {
+        let digest = digest_for_data(b"x").unwrap();
+        let mut builder = DirBuilder::default();
+        builder
+            .insert("pkg/a", DirNode::File(digest.clone(), false))
+            .unwrap();
+        builder.insert("pkg", DirNode::Directory).unwrap();
+
+        for (path, node) in [
+            ("pkg/a", DirNode::File(digest.clone(), true)),
+            ("pkg/a", DirNode::Directory),
+            ("pkg/a", DirNode::Symlink(String::from("b"))),
+            ("pkg/a/b", DirNode::File(digest.clone(), false)),
+            ("pkg", DirNode::File(digest.clone(), false)),
+        ] {
+            assert!(
+                matches!(
+                    builder.insert(path, node),
+                    Err(ArchiveError::DuplicateEntry(_))
+                ),
+                "{} should have been a duplicate",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_symlinks_out_of_the_tree() {
+        assert!(check_symlink_target("a/link", "b").is_ok());
+        assert!(check_symlink_target("a/link", "../b").is_ok());
+        assert!(check_symlink_target("a/b/link", "../../c/./d").is_ok());
+        for (path, target) in [
+            ("link", "/etc/passwd"),
+            ("link", "../b"),
+            ("a/link", "../../b"),
+            ("a/link", "c/../../../b"),
+        ] {
+            assert!(
+                matches!(
+                    check_symlink_target(path, target),
+                    Err(ArchiveError::InvalidSymlink(_, _))
+                ),
+                "{} -> {} should have been rejected",
+                path,
+                target
+            );
+        }
     }
 }
@@ -21,7 +21,9 @@ use tempfile::NamedTempFile;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 
+use crate::config::mirror_config::MirrorUpstream;
 use crate::hash::sha256_value::Sha256Value;
+use crate::metrics::METRICS;
 use crate::storage_backend::BackendIOHelpers;
 use crate::storage_backend::{StorageBackend, StorageBackendError, UploadType};
 static NOTFOUND: &[u8] = b"Not Found";
@@ -61,6 +63,9 @@ pub enum HttpEndpointError {
     #[error("External IO failure talking to remote host: {0} with : {1}")]
     ExternalIOFailure(String, String),
 
+    #[error("Not allowed to mirror: {0}")]
+    MirrorNotAllowed(String),
+
     #[error("Seen Hyper error")]
     HyperError(#[from] hyper::Error),
 }
@@ -114,6 +119,7 @@ where
 {
     storage_backend: T,
     health_status: Arc<Mutex<HealthStatus>>,
+    mirror_upstreams: Arc<Vec<MirrorUpstream>>,
 }
 
 async fn parse_cas_get(uri: &Uri) -> Result<execution::Digest, HttpEndpointError> {
@@ -167,31 +173,25 @@ fn parse_bazel_fe_key(uri: &Uri) -> Result<BazelFeIndexLookupKey, HttpEndpointEr
 }
 
 #[derive(Debug, PartialEq, Eq)]
-enum MirrorRequestUpstream {
-    Github,
-}
-
-impl MirrorRequestUpstream {
-    pub fn to_uri_root(&self) -> &str {
-        match self {
-            MirrorRequestUpstream::Github => "https://github.com",
-        }
-    }
-}
-
-#[derive(Debug, PartialEq, Eq)]
-struct MirrorRequest {
-    upstream: MirrorRequestUpstream,
+struct MirrorRequest<'a> {
+    upstream: &'a MirrorUpstream,
     remaining_path: String,
-    digest_hash: String,
+    digest_hash: Sha256Value,
 }
-impl MirrorRequest {
+impl<'a> MirrorRequest<'a> {
     pub(crate) fn uri(&self) -> String {
-        format!("{}/{}", self.upstream.to_uri_root(), self.remaining_path)
+        format!(
+            "{}/{}",
+            self.upstream.base_url.trim_end_matches('/'),
+            self.remaining_path
+        )
     }
 }
 
-fn parse_mirror_request(uri: &Uri) -> Result<MirrorRequest, HttpEndpointError> {
+fn parse_mirror_request<'a>(
+    uri: &Uri,
+    mirror_upstreams: &'a [MirrorUpstream],
+) -> Result<MirrorRequest<'a>, HttpEndpointError> {
     let segments: Vec<String> = uri
         .path()
         .split('/')
@@ -205,39 +205,61 @@ fn parse_mirror_request(uri: &Uri) -> Result<MirrorRequest, HttpEndpointError> {
             segments,
         ));
     }
-    let digest_hash = segments[1].clone();
+    let digest_hash = match segments[1].parse::<Sha256Value>() {
+        Ok(d) => d,
+        Err(_) => {
+            return Err(HttpEndpointError::MissingArgForMethod(
+                format!("{} is not a valid sha256", segments[1]),
+                segments,
+            ))
+        }
+    };
 
-    let m = match segments[2].as_str() {
-        "github.com" => MirrorRequestUpstream::Github,
-        _ => {
+    let upstream = match mirror_upstreams.iter().find(|u| u.name == segments[2]) {
+        Some(u) => u,
+        None => {
             return Err(HttpEndpointError::MissingArgForMethod(
                 format!(
-                    "upstream {} not in supported upstreams: github.com",
-                    segments[2]
+                    "upstream {} not in supported upstreams: {}",
+                    segments[2],
+                    mirror_upstreams
+                        .iter()
+                        .map(|u| u.name.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", ")
                 ),
                 segments,
             ))
         }
     };
 
+    let remaining_path = segments
+        .iter()
+        .skip(3)
+        .map(|e| e.as_str())
+        .collect::<Vec<&str>>()
+        .join("/");
+    if !upstream.is_path_allowed(&remaining_path) {
+        return Err(HttpEndpointError::MirrorNotAllowed(format!(
+            "{} is outside of the allowed paths for upstream {}",
+            remaining_path, upstream.name
+        )));
+    }
+
     let key = MirrorRequest {
-        upstream: m,
+        upstream,
         digest_hash,
-        remaining_path: segments
-            .iter()
-            .skip(3)
-            .map(|e| e.as_str())
-            .collect::<Vec<&str>>()
-            .join("/"),
+        remaining_path,
     };
     Ok(key)
 }
 
 impl<T: StorageBackend + 'static> HttpEndpoint<T> {
-    pub fn new(storage_backend: T) -> HttpEndpoint<T> {
+    pub fn new(storage_backend: T, mirror_upstreams: Arc<Vec<MirrorUpstream>>) -> HttpEndpoint<T> {
         HttpEndpoint {
             storage_backend,
             health_status: Default::default(),
+            mirror_upstreams,
         }
     }
     pub async fn dispatch(&self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
@@ -251,6 +273,10 @@ impl<T: StorageBackend + 'static> HttpEndpoint<T> {
                         .status(StatusCode::NOT_ACCEPTABLE)
                         .body(format!("{:?}", err).into())
                         .unwrap()),
+                    HttpEndpointError::MirrorNotAllowed(_) => Ok(Response::builder()
+                        .status(StatusCode::FORBIDDEN)
+                        .body(format!("{:?}", err).into())
+                        .unwrap()),
                     _ => Ok(internal_server_error(format!("{:?}", err))),
                 }
             }
@@ -277,6 +303,11 @@ impl<T: StorageBackend + 'static> HttpEndpoint<T> {
                 .await
                 .map_err(|e| HttpEndpointError::Unknown(format!("Failure to join : {:#?}", e)))
             }
+            (&Method::GET, "metrics") => Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header(http::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(METRICS.render().into())
+                .unwrap()),
             (&Method::GET, "upstream_mirror") => self.process_upstream_mirror(req).await,
             (&Method::GET, "bazelfe_index") => {
                 // Test what happens when file cannot be be found
@@ -381,25 +412,27 @@ impl<T: StorageBackend + 'static> HttpEndpoint<T> {
         &self,
         req: Request<Body>,
     ) -> Result<Response<Body>, HttpEndpointError> {
-        let mirror_request = parse_mirror_request(req.uri())?;
+        let mirror_request = parse_mirror_request(req.uri(), &self.mirror_upstreams)?;
         tracing::debug!("Got mirror request for: {:?}", mirror_request);
 
         let digest = self
             .storage_backend
-            .build_digest_from_hash_if_present(&mirror_request.digest_hash)
+            .build_digest_from_hash_if_present(&mirror_request.digest_hash.to_string())
             .await?;
 
         let digest = match digest {
             None => match self
                 .storage_backend
-                .download_file_to_cas(mirror_request.uri().as_str())
+                .download_verified_file_to_cas(
+                    mirror_request.uri().as_str(),
+                    mirror_request.upstream.auth_header.as_deref(),
+                    &mirror_request.digest_hash,
+                )
                 .await
             {
-                Ok(d) => {
-                    if d.hash != mirror_request.digest_hash {
-                        return Err(HttpEndpointError::BadData(format!("Tried to fetch/download the hash {}, but ended up with a digest of {:?} which is incorrect", mirror_request.digest_hash, d), ));
-                    }
-                    d
+                Ok(d) => d,
+                Err(StorageBackendError::InvalidDigestForDataInbound(expected, actual)) => {
+                    return Err(HttpEndpointError::BadData(format!("Tried to fetch/download the hash {}, but ended up with a digest of {} which is incorrect", expected, actual), ));
                 }
                 Err(ex) => {
                     return Err(HttpEndpointError::ExternalIOFailure(
@@ -513,34 +546,82 @@ mod tests {
 
     #[test]
     fn test_parse_upstream_url() -> Result<(), Box<dyn std::error::Error>> {
-        fn expect_bad_parse(url: &str) {
+        let upstreams = crate::config::mirror_config::default_mirror_upstreams();
+        let expect_bad_parse = |url: &str| {
             let uri: Uri = url.parse().unwrap();
             assert!(
-                parse_mirror_request(&uri).is_err(),
+                parse_mirror_request(&uri, &upstreams).is_err(),
                 "url {} should have failed to parse",
                 url
             )
-        }
+        };
 
         expect_bad_parse("http://foo.com/upstream_url/myproj/myrepo/mysha");
         expect_bad_parse("http://localhost:10000/upstream_mirreor/a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79/github.com/foo.tar.gz");
         expect_bad_parse("http://localhost:10000/upstream_mirror/github.com/foo.tar.gz");
         expect_bad_parse("http://localhost:10000/upstream_mirror/a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79/gdithub.com/foo.tar.gz");
         expect_bad_parse("http://localhost:10000/upstream_mirror/a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79/github.com");
+        expect_bad_parse("http://localhost:10000/upstream_mirror/notasha/github.com/foo.tar.gz");
 
         let uri = http::Uri::from_static("http://localhost:10000/upstream_mirror/a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79/github.com/foo.tar.gz");
-        let parsed = parse_mirror_request(&uri)?;
+        let parsed = parse_mirror_request(&uri, &upstreams)?;
 
         let expected = MirrorRequest {
-            upstream: MirrorRequestUpstream::Github,
+            upstream: &upstreams[0],
             remaining_path: String::from("foo.tar.gz"),
-            digest_hash: String::from(
-                "a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79",
-            ),
+            digest_hash: "a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79"
+                .parse()?,
         };
 
         assert_eq!(parsed, expected);
+        assert_eq!(parsed.uri(), "https://github.com/foo.tar.gz");
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_parse_upstream_url_allowed_prefixes() -> Result<(), Box<dyn std::error::Error>> {
+        let upstreams = vec![MirrorUpstream {
+            name: String::from("artifactory"),
+            base_url: String::from("https://artifactory.internal/artifactory/"),
+            auth_header: Some(String::from("Bearer abc")),
+            allowed_path_prefixes: vec![String::from("/libs-release")],
+        }];
+        assert!(!format!("{:?}", upstreams).contains("abc"));
+        let sha = "a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79";
+
+        let uri: Uri = format!(
+            "http://localhost:10000/upstream_mirror/{}/artifactory/libs-release/com/foo/foo-1.0.jar",
+            sha
+        )
+        .parse()?;
+        assert_eq!(
+            parse_mirror_request(&uri, &upstreams)?.uri(),
+            "https://artifactory.internal/artifactory/libs-release/com/foo/foo-1.0.jar"
+        );
+
+        for path in [
+            "libs-snapshot/com/foo/foo-1.0.jar",
+            "libs-release/../libs-snapshot/foo.jar",
+            "libs-release/%2E%2E/libs-snapshot/foo.jar",
+            "libs-release%2F..%2Flibs-snapshot/foo.jar",
+            "libs-release/%2e%2e%2flibs-snapshot/foo.jar",
+            "libs-release-evil/foo.jar",
+        ] {
+            let uri: Uri = format!(
+                "http://localhost:10000/upstream_mirror/{}/artifactory/{}",
+                sha, path
+            )
+            .parse()?;
+            assert!(
+                matches!(
+                    parse_mirror_request(&uri, &upstreams),
+                    Err(HttpEndpointError::MirrorNotAllowed(_))
+                ),
+                "{} should not be allowed",
+                path
+            );
+        }
         Ok(())
     }
 }
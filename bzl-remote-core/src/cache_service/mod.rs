@@ -19,40 +19,150 @@ where
 }
 
 pub mod action_cache_service;
+mod archive;
 pub mod bytestream_service;
 pub mod capabilities_service;
 pub mod content_addressable_storage_service;
 pub mod fetch_service;
 pub mod http_endpoint;
 pub mod metadata_service;
+pub mod push_service;
 
 use std::sync::Arc;
+use std::time::Duration;
 
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use tracing::info;
 
+use crate::config::cache_service_config::CacheServiceStorage;
 use crate::config::Config;
 pub async fn storage_backend_from_config(
     config: &Config,
 ) -> Result<Arc<dyn crate::storage_backend::StorageBackend>, Box<dyn std::error::Error>> {
-    match &config.cache_config.cache_backend {
-        crate::config::cache_service_config::CacheServiceStorage::OnLocalDisk { path } => {
+    let storage_config = &config.cache_config.cache_backend;
+    storage_backend_from_storage_config(storage_config, backend_kind(storage_config).to_string())
+        .await
+}
+
+fn backend_kind(storage_config: &CacheServiceStorage) -> &'static str {
+    match storage_config {
+        CacheServiceStorage::OnLocalDisk { .. } => "OnLocalDisk",
+        CacheServiceStorage::BazelDiskCache { .. } => "BazelDiskCache",
+        CacheServiceStorage::InMemory {} => "InMemory",
+        CacheServiceStorage::RemoteGrpc { .. } => "RemoteGrpc",
+        CacheServiceStorage::CloudBackend(_) => "CloudBackend",
+        CacheServiceStorage::Tiered { .. } => "Tiered",
+    }
+}
+
+// Boxed since tiered backends recurse into their own tiers.
+// Every backend is instrumented under `name` so metrics can be split per tier.
+fn storage_backend_from_storage_config(
+    storage_config: &CacheServiceStorage,
+    name: String,
+) -> BoxFuture<
+    '_,
+    Result<Arc<dyn crate::storage_backend::StorageBackend>, Box<dyn std::error::Error>>,
+> {
+    async move {
+        let backend = build_storage_backend(storage_config, &name).await?;
+        Ok(Arc::new(crate::storage_backend::InstrumentedBackend::new(
+            name, backend,
+        ))
+            as Arc<dyn crate::storage_backend::StorageBackend>)
+    }
+    .boxed()
+}
+
+async fn build_storage_backend(
+    storage_config: &CacheServiceStorage,
+    name: &str,
+) -> Result<Arc<dyn crate::storage_backend::StorageBackend>, Box<dyn std::error::Error>> {
+    match storage_config {
+        CacheServiceStorage::OnLocalDisk {
+            path,
+            max_size_bytes,
+            max_age_seconds,
+            gc_interval_seconds,
+        } => {
             info!("Setup OnLocalDisk backend for launch in path {:#?}", path);
+            let eviction_policy = crate::storage_backend::EvictionPolicy {
+                max_size_bytes: *max_size_bytes,
+                max_age: max_age_seconds.map(Duration::from_secs),
+            };
+            let backend = Arc::new(
+                crate::storage_backend::LocalDiskStorageBackend::open_with_eviction_policy(
+                    path,
+                    eviction_policy,
+                )?,
+            );
+            if eviction_policy.is_enabled() {
+                let interval = Duration::from_secs(gc_interval_seconds.unwrap_or(5 * 60));
+                info!(
+                    "Enabling local disk garbage collection every {:?} with {:?}",
+                    interval, eviction_policy
+                );
+                backend.spawn_garbage_collector(interval);
+            }
+            Ok(backend)
+        }
+        CacheServiceStorage::BazelDiskCache { path } => {
+            info!(
+                "Setup BazelDiskCache backend for launch in path {:#?}",
+                path
+            );
             Ok(Arc::new(
-                crate::storage_backend::LocalDiskStorageBackend::open(path)?,
+                crate::storage_backend::BazelDiskCacheBackend::open(path)?,
             ))
         }
-        crate::config::cache_service_config::CacheServiceStorage::InMemory {} => {
+        CacheServiceStorage::InMemory {} => {
             info!("Setup InMemory backend for launch");
             Ok(Arc::new(
                 crate::storage_backend::InMemoryStorageBackend::default(),
             ))
         }
-        crate::config::cache_service_config::CacheServiceStorage::CloudBackend(cfg) => {
+        CacheServiceStorage::CloudBackend(cfg) => {
             info!("Setup CloudBackend for launch: {:#?}", cfg);
             Ok(Arc::new(
                 crate::storage_backend::CloudBackend::new(cfg).await?,
             ))
         }
+        CacheServiceStorage::RemoteGrpc { url } => {
+            info!("Setup RemoteGrpc backend for launch against {}", url);
+            Ok(Arc::new(
+                crate::storage_backend::RemoteGrpcBackend::connect(url)
+                    .await
+                    .map_err(|e| e as Box<dyn std::error::Error>)?,
+            ))
+        }
+        CacheServiceStorage::Tiered {
+            tiers,
+            write_policy,
+        } => {
+            info!(
+                "Setup Tiered backend for launch with {} tiers, write policy {:?}",
+                tiers.len(),
+                write_policy
+            );
+            let mut backends = Vec::with_capacity(tiers.len());
+            for (idx, tier) in tiers.iter().enumerate() {
+                let tier_name = format!("{}/tier{}_{}", name, idx, backend_kind(tier));
+                backends.push(storage_backend_from_storage_config(tier, tier_name).await?);
+            }
+            let write_policy = match write_policy {
+                crate::config::cache_service_config::TieredWritePolicy::Sync => {
+                    crate::storage_backend::WritePolicy::Sync
+                }
+                crate::config::cache_service_config::TieredWritePolicy::Async => {
+                    crate::storage_backend::WritePolicy::Async
+                }
+            };
+            Ok(Arc::new(crate::storage_backend::TieredBackend::new(
+                backends,
+                write_policy,
+            )?))
+        }
     }
 }
 
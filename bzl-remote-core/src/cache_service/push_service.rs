@@ -0,0 +1,287 @@
+use super::fetch_service::sri_sha256;
+use crate::storage_backend::StorageBackend;
+use bazelfe_protos::build::bazel::remote::{
+    asset::v1::{
+        push_server, PushBlobRequest, PushBlobResponse, PushDirectoryRequest,
+        PushDirectoryResponse, Qualifier,
+    },
+    execution::v2::Digest,
+};
+use bazelfe_protos::bzl_remote::bazelfe_kv::{
+    key_val_key, remote_asset_key::AssetKind, KeyValKey, RemoteAssetKey, RemoteAssetQualifier,
+};
+use prost::Message;
+use tonic::Request;
+
+fn asset_kv_key(kind: AssetKind, uri: &str, qualifiers: &[Qualifier]) -> Vec<u8> {
+    let mut qualifiers: Vec<RemoteAssetQualifier> = qualifiers
+        .iter()
+        .map(|q| RemoteAssetQualifier {
+            name: q.name.clone(),
+            value: q.value.clone(),
+        })
+        .collect();
+    qualifiers.sort_by(|a, b| (&a.name, &a.value).cmp(&(&b.name, &b.value)));
+
+    KeyValKey {
+        keys: Some(key_val_key::Keys::RemoteAsset(RemoteAssetKey {
+            kind: kind as i32,
+            uri: uri.to_string(),
+            qualifiers,
+        })),
+    }
+    .encode_to_vec()
+}
+
+/// Looks up a digest pushed for any of `uris` with exactly these qualifiers.
+/// Mappings whose content has since been evicted from the CAS are ignored.
+pub(crate) async fn lookup_pushed_asset<T: StorageBackend>(
+    storage_backend: &T,
+    kind: AssetKind,
+    uris: &[String],
+    qualifiers: &[Qualifier],
+) -> Result<Option<(Digest, String)>, tonic::Status> {
+    for uri in uris {
+        let data = match storage_backend
+            .get_kv(&asset_kv_key(kind, uri, qualifiers))
+            .await?
+        {
+            Some(data) => data,
+            None => continue,
+        };
+        let digest = Digest::decode(&data[..]).map_err(|e| {
+            tonic::Status::internal(format!(
+                "Failed to decode pushed digest for {}: {:#?}",
+                uri, e
+            ))
+        })?;
+        if storage_backend.cas_exists(&digest).await? {
+            return Ok(Some((digest, uri.clone())));
+        }
+        tracing::info!(
+            "Pushed asset {} points at {}, which is no longer in the CAS",
+            uri,
+            digest.hash
+        );
+    }
+    Ok(None)
+}
+
+#[derive(Debug)]
+pub struct PushServiceStruct<T> {
+    storage_backend: T,
+}
+
+impl<T> PushServiceStruct<T>
+where
+    T: StorageBackend,
+{
+    pub fn new(storage_backend: T) -> PushServiceStruct<T> {
+        PushServiceStruct { storage_backend }
+    }
+
+    async fn push(
+        &self,
+        kind: AssetKind,
+        uris: &[String],
+        qualifiers: &[Qualifier],
+        digest: Option<Digest>,
+    ) -> Result<(), tonic::Status> {
+        if uris.is_empty() {
+            return Err(tonic::Status::invalid_argument(
+                "Push request had no uris included.",
+            ));
+        }
+        let digest = digest.ok_or_else(|| {
+            tonic::Status::invalid_argument("Push request had no digest included.")
+        })?;
+        // A directory's checksum is the one of the archive it came from, which we never see.
+        if kind == AssetKind::Blob {
+            if let Some(expected_hash) = sri_sha256(qualifiers)? {
+                if expected_hash != digest.hash {
+                    return Err(tonic::Status::invalid_argument(format!(
+                        "Pushed digest {} does not match the checksum.sri qualifier, which expects {}",
+                        digest.hash, expected_hash
+                    )));
+                }
+            }
+        }
+        // Only accept mappings we can serve, otherwise fetches would be told about content we don't have.
+        if !self.storage_backend.cas_exists(&digest).await? {
+            return Err(tonic::Status::failed_precondition(format!(
+                "Pushed digest {} is not present in the CAS, upload it first",
+                digest.hash
+            )));
+        }
+
+        let value = digest.encode_to_vec();
+        for uri in uris {
+            tracing::info!("Recording pushed {:?} {} -> {}", kind, uri, digest.hash);
+            self.storage_backend
+                .put_kv(&asset_kv_key(kind, uri, qualifiers), &value)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl<T> push_server::Push for PushServiceStruct<T>
+where
+    T: StorageBackend + 'static,
+{
+    async fn push_blob(
+        &self,
+        req: Request<PushBlobRequest>,
+    ) -> Result<tonic::Response<PushBlobResponse>, tonic::Status> {
+        let push_blob_request = req.into_inner();
+        self.push(
+            AssetKind::Blob,
+            &push_blob_request.uris,
+            &push_blob_request.qualifiers,
+            push_blob_request.blob_digest,
+        )
+        .await?;
+        Ok(tonic::Response::new(PushBlobResponse {}))
+    }
+
+    async fn push_directory(
+        &self,
+        req: Request<PushDirectoryRequest>,
+    ) -> Result<tonic::Response<PushDirectoryResponse>, tonic::Status> {
+        let push_directory_request = req.into_inner();
+        self.push(
+            AssetKind::Directory,
+            &push_directory_request.uris,
+            &push_directory_request.qualifiers,
+            push_directory_request.root_directory_digest,
+        )
+        .await?;
+        Ok(tonic::Response::new(PushDirectoryResponse {}))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use base64::{engine::general_purpose::STANDARD as B64, Engine as _};
+    use bazelfe_protos::build::bazel::remote::asset::v1::{fetch_server::Fetch, FetchBlobRequest};
+    use push_server::Push;
+    use sha2::Digest as _;
+
+    use super::*;
+    use crate::cache_service::fetch_service::FetchServiceStruct;
+    use crate::hash::sha256_value::Sha256Value;
+    use crate::storage_backend::{InMemoryStorageBackend, UploadType};
+
+    fn qualifier(name: &str, value: &str) -> Qualifier {
+        Qualifier {
+            name: name.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pushed_blob_is_fetched() -> Result<(), Box<dyn std::error::Error>> {
+        let backend = Arc::new(InMemoryStorageBackend::default());
+        let data = b"third party jar".to_vec();
+        let sha_v: Sha256Value = data.as_slice().try_into()?;
+        let digest = Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        };
+
+        let push_service = PushServiceStruct::new(Arc::clone(&backend));
+        let uri = String::from("https://repo.example.com/foo-1.0.jar");
+        let push_req = || PushBlobRequest {
+            uris: vec![uri.clone()],
+            qualifiers: vec![qualifier("resource_type", "application/java-archive")],
+            blob_digest: Some(digest.clone()),
+            ..Default::default()
+        };
+
+        let not_uploaded = push_service.push_blob(Request::new(push_req())).await;
+        assert_eq!(
+            not_uploaded.unwrap_err().code(),
+            tonic::Code::FailedPrecondition
+        );
+
+        backend
+            .cas_insert(&digest, UploadType::InMemory(data))
+            .await?;
+        push_service.push_blob(Request::new(push_req())).await?;
+
+        // No checksum qualifier and an unreachable uri, so only the pushed mapping can satisfy this.
+        let fetch_service = FetchServiceStruct::new(Arc::clone(&backend));
+        let response = fetch_service
+            .fetch_blob(Request::new(FetchBlobRequest {
+                uris: vec![String::from("http://localhost:1/missing"), uri.clone()],
+                qualifiers: vec![qualifier("resource_type", "application/java-archive")],
+                ..Default::default()
+            }))
+            .await?
+            .into_inner();
+        assert_eq!(response.blob_digest, Some(digest));
+        assert_eq!(response.uri, uri);
+
+        // Qualifiers are part of the key.
+        let other_qualifiers = fetch_service
+            .fetch_blob(Request::new(FetchBlobRequest {
+                uris: vec![uri.clone()],
+                qualifiers: vec![qualifier("resource_type", "application/zip")],
+                ..Default::default()
+            }))
+            .await;
+        assert!(other_qualifiers.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_mismatched_checksum() -> Result<(), Box<dyn std::error::Error>> {
+        let backend = Arc::new(InMemoryStorageBackend::default());
+        let data = b"third party jar".to_vec();
+        let sha_v: Sha256Value = data.as_slice().try_into()?;
+        let digest = Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        };
+        backend
+            .cas_insert(&digest, UploadType::InMemory(data.clone()))
+            .await?;
+
+        let push_service = PushServiceStruct::new(Arc::clone(&backend));
+        let push_req = |checksum_of: &[u8]| PushBlobRequest {
+            uris: vec![String::from("https://repo.example.com/foo-1.0.jar")],
+            qualifiers: vec![qualifier(
+                "checksum.sri",
+                &format!("sha256-{}", B64.encode(sha2::Sha256::digest(checksum_of))),
+            )],
+            blob_digest: Some(digest.clone()),
+            ..Default::default()
+        };
+
+        let mismatch = push_service
+            .push_blob(Request::new(push_req(b"something else")))
+            .await;
+        assert_eq!(mismatch.unwrap_err().code(), tonic::Code::InvalidArgument);
+        push_service
+            .push_blob(Request::new(push_req(&data)))
+            .await?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_qualifier_order_is_ignored() {
+        let a = qualifier("a", "1");
+        let b = qualifier("b", "2");
+        assert_eq!(
+            asset_kv_key(AssetKind::Directory, "uri", &[a.clone(), b.clone()]),
+            asset_kv_key(AssetKind::Directory, "uri", &[b, a.clone()])
+        );
+        assert_ne!(
+            asset_kv_key(AssetKind::Directory, "uri", std::slice::from_ref(&a)),
+            asset_kv_key(AssetKind::Blob, "uri", &[a])
+        );
+    }
+}
@@ -1,4 +1,6 @@
 use super::cache_service_config::CacheServiceConfig;
+use super::mirror_config::{default_mirror_upstreams, MirrorUpstream};
+use super::security_config::{AuthConfig, TlsConfig};
 use serde::{Deserialize, Deserializer};
 
 #[derive(Deserialize, Debug, PartialEq, Eq)]
@@ -12,12 +14,51 @@ pub struct Config {
 
     #[serde(default = "default_send_buffer_size")]
     pub send_buffer_size: usize,
+
+    /// Serve over TLS instead of plaintext when set.
+    #[serde(rename = "TlsConfig", default)]
+    pub tls_config: Option<TlsConfig>,
+
+    #[serde(rename = "AuthConfig", default = "AuthConfig::default")]
+    pub auth_config: AuthConfig,
+
+    /// Upstreams the `/upstream_mirror` http endpoint may fetch from.
+    #[serde(rename = "MirrorUpstreams", default = "default_mirror_upstreams")]
+    pub mirror_upstreams: Vec<MirrorUpstream>,
+
+    /// Remote asset directory fetches fail when the archive expands to more than this.
+    #[serde(default = "default_max_archive_decompressed_bytes")]
+    pub max_archive_decompressed_bytes: u64,
 }
 
+pub const DEFAULT_MAX_ARCHIVE_DECOMPRESSED_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
 fn default_send_buffer_size() -> usize {
     4194304 - 1024
 }
 
+fn default_max_archive_decompressed_bytes() -> u64 {
+    DEFAULT_MAX_ARCHIVE_DECOMPRESSED_BYTES
+}
+
+impl Config {
+    /// Checks across sections, each section is checked on its own as it's parsed.
+    pub(super) fn validate(&self) -> Result<(), String> {
+        let verifies_client_certs = self
+            .tls_config
+            .as_ref()
+            .map(|tls_config| tls_config.client_ca_path.is_some())
+            .unwrap_or(false);
+        if !self.auth_config.client_certs.is_empty() && !verifies_client_certs {
+            // Clients are never asked for a certificate, so these grants could never apply.
+            return Err(String::from(
+                "AuthConfig.client_certs needs TlsConfig.client_ca_path to be set",
+            ));
+        }
+        Ok(())
+    }
+}
+
 // We want to use the serde configured defaults for our default implemenation to not be
 // building up two separate paths.
 impl Default for Config {
@@ -43,6 +84,7 @@ where
 mod tests {
 
     use crate::config::cache_service_config::CloudBackendConfig;
+    use crate::config::security_config::{ClientCertGrant, Permission, TokenGrant};
 
     use super::*;
     #[test]
@@ -76,7 +118,105 @@ mod tests {
         assert_eq!(
             config.cache_config.cache_backend,
             super::super::cache_service_config::CacheServiceStorage::OnLocalDisk {
-                path: std::path::PathBuf::from("/foo/bar/storage")
+                path: std::path::PathBuf::from("/foo/bar/storage"),
+                max_size_bytes: None,
+                max_age_seconds: None,
+                gc_interval_seconds: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_on_disk_with_limits() {
+        let config: Config = toml::from_str(
+            r#"
+        [[CacheServiceConfig]]
+          path = '/foo/bar/storage'
+          max_size_bytes = 1073741824
+          max_age_seconds = 604800
+          type = 'OnLocalDisk'
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.cache_config.cache_backend,
+            super::super::cache_service_config::CacheServiceStorage::OnLocalDisk {
+                path: std::path::PathBuf::from("/foo/bar/storage"),
+                max_size_bytes: Some(1073741824),
+                max_age_seconds: Some(604800),
+                gc_interval_seconds: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_bazel_disk_cache() {
+        let config: Config = toml::from_str(
+            r#"
+        [[CacheServiceConfig]]
+          path = '/home/me/.cache/bazel-disk'
+          type = 'BazelDiskCache'
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.cache_config.cache_backend,
+            super::super::cache_service_config::CacheServiceStorage::BazelDiskCache {
+                path: std::path::PathBuf::from("/home/me/.cache/bazel-disk"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_remote_grpc() {
+        let config: Config = toml::from_str(
+            r#"
+        [[CacheServiceConfig]]
+          url = 'http://central-cache:10000'
+          type = 'RemoteGrpc'
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.cache_config.cache_backend,
+            super::super::cache_service_config::CacheServiceStorage::RemoteGrpc {
+                url: String::from("http://central-cache:10000"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_tiered() {
+        let config: Config = toml::from_str(
+            r#"
+        [[CacheServiceConfig]]
+          type = 'Tiered'
+          write_policy = 'Async'
+          [[CacheServiceConfig.tiers]]
+            type = 'InMemory'
+          [[CacheServiceConfig.tiers]]
+            path = '/foo/bar/storage'
+            type = 'OnLocalDisk'
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.cache_config.cache_backend,
+            super::super::cache_service_config::CacheServiceStorage::Tiered {
+                tiers: vec![
+                    super::super::cache_service_config::CacheServiceStorage::InMemory {},
+                    super::super::cache_service_config::CacheServiceStorage::OnLocalDisk {
+                        path: std::path::PathBuf::from("/foo/bar/storage"),
+                        max_size_bytes: None,
+                        max_age_seconds: None,
+                        gc_interval_seconds: None,
+                    },
+                ],
+                write_policy: super::super::cache_service_config::TieredWritePolicy::Async,
             }
         );
     }
@@ -110,6 +250,111 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_tls_and_auth() {
+        let config: Config = toml::from_str(
+            r#"
+        [TlsConfig]
+          cert_path = '/etc/cache/server.pem'
+          key_path = '/etc/cache/server.key'
+          client_ca_path = '/etc/cache/clients_ca.pem'
+
+        [AuthConfig]
+          anonymous_permission = 'None'
+          [[AuthConfig.tokens]]
+            token = 'ci-secret'
+            permission = 'ReadWrite'
+          [[AuthConfig.client_certs]]
+            sha256 = 'abcd'
+            permission = 'ReadOnly'
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.tls_config,
+            Some(TlsConfig {
+                cert_path: std::path::PathBuf::from("/etc/cache/server.pem"),
+                key_path: std::path::PathBuf::from("/etc/cache/server.key"),
+                client_ca_path: Some(std::path::PathBuf::from("/etc/cache/clients_ca.pem")),
+                require_client_cert: false,
+            })
+        );
+        assert_eq!(
+            config.auth_config,
+            AuthConfig {
+                anonymous_permission: Permission::None,
+                tokens: vec![TokenGrant {
+                    token: String::from("ci-secret"),
+                    permission: Permission::ReadWrite,
+                }],
+                client_certs: vec![ClientCertGrant {
+                    sha256: String::from("abcd"),
+                    permission: Permission::ReadOnly,
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn test_client_cert_grants_need_a_client_ca() {
+        let err = super::super::parse_config(
+            r#"
+        [TlsConfig]
+          cert_path = '/etc/cache/server.pem'
+          key_path = '/etc/cache/server.key'
+
+        [AuthConfig]
+          anonymous_permission = 'None'
+          [[AuthConfig.client_certs]]
+            sha256 = 'abcd'
+            permission = 'ReadOnly'
+        "#,
+        )
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("AuthConfig.client_certs needs TlsConfig.client_ca_path"));
+    }
+
+    #[test]
+    fn test_mirror_upstreams() {
+        let config: Config = toml::from_str(
+            r#"
+        [[MirrorUpstreams]]
+          name = 'maven'
+          base_url = 'https://repo1.maven.org/maven2'
+        [[MirrorUpstreams]]
+          name = 'artifactory'
+          base_url = 'https://artifactory.internal/artifactory'
+          auth_header = 'Bearer abc'
+          allowed_path_prefixes = ['libs-release/', 'pypi-remote/']
+        "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.mirror_upstreams,
+            vec![
+                MirrorUpstream {
+                    name: String::from("maven"),
+                    base_url: String::from("https://repo1.maven.org/maven2"),
+                    auth_header: None,
+                    allowed_path_prefixes: vec![],
+                },
+                MirrorUpstream {
+                    name: String::from("artifactory"),
+                    base_url: String::from("https://artifactory.internal/artifactory"),
+                    auth_header: Some(String::from("Bearer abc")),
+                    allowed_path_prefixes: vec![
+                        String::from("libs-release/"),
+                        String::from("pypi-remote/")
+                    ],
+                },
+            ]
+        );
+    }
+
     #[test]
     fn test_empty_parse() {
         let config: Config = toml::from_str("").unwrap();
@@ -118,5 +363,11 @@ mod tests {
             config.cache_config.cache_backend,
             super::super::cache_service_config::CacheServiceStorage::InMemory {}
         );
+        assert_eq!(config.tls_config, None);
+        assert_eq!(
+            config.auth_config.anonymous_permission,
+            Permission::ReadWrite
+        );
+        assert_eq!(config.mirror_upstreams, default_mirror_upstreams());
     }
 }
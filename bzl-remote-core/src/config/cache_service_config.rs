@@ -1,13 +1,45 @@
 use std::path::PathBuf;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
 #[serde(tag = "type")]
 pub enum CacheServiceStorage {
-    OnLocalDisk { path: PathBuf },
+    OnLocalDisk {
+        path: PathBuf,
+        /// Once the stored data exceeds this we evict the least recently used entries.
+        max_size_bytes: Option<u64>,
+        /// Entries not accessed for this long are evicted.
+        max_age_seconds: Option<u64>,
+        /// How often the garbage collector runs, defaults to every 5 minutes.
+        #[serde(default, deserialize_with = "parse_gc_interval_seconds")]
+        gc_interval_seconds: Option<u64>,
+    },
+    /// A directory shared with bazel's own `--disk_cache`, using the same layout.
+    BazelDiskCache {
+        path: PathBuf,
+    },
     InMemory {},
+    /// Forwards to another REAPI compatible cache, e.g. `http://central-cache:10000`.
+    RemoteGrpc {
+        url: String,
+    },
     CloudBackend(CloudBackendConfig),
+    /// Chains the listed backends, fastest first, as a read-through/write-through cache.
+    Tiered {
+        tiers: Vec<CacheServiceStorage>,
+        #[serde(default)]
+        write_policy: TieredWritePolicy,
+    },
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum TieredWritePolicy {
+    /// Writes reach every tier before we reply.
+    #[default]
+    Sync,
+    /// Only the first tier is written before we reply, the rest are filled in the background.
+    Async,
 }
 
 #[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
@@ -35,5 +67,40 @@ fn cache_backend() -> CacheServiceStorage {
     CacheServiceStorage::InMemory {}
 }
 
+fn parse_gc_interval_seconds<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<u64>::deserialize(deserializer)? {
+        Some(0) => Err(serde::de::Error::custom(
+            "gc_interval_seconds must be at least 1",
+        )),
+        other => Ok(other),
+    }
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rejects_zero_gc_interval() {
+        let parse = |gc_interval_seconds: u64| {
+            toml::from_str::<CacheServiceStorage>(&format!(
+                "type = 'OnLocalDisk'\npath = '/foo'\ngc_interval_seconds = {}",
+                gc_interval_seconds
+            ))
+        };
+        let err = parse(0).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("gc_interval_seconds must be at least 1"));
+        assert!(matches!(
+            parse(60).unwrap(),
+            CacheServiceStorage::OnLocalDisk {
+                gc_interval_seconds: Some(60),
+                ..
+            }
+        ));
+    }
+}
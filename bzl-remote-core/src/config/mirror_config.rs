@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+/// An upstream the http endpoint will mirror through `/upstream_mirror/<sha256>/<name>/<path>`.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct MirrorUpstream {
+    /// Path segment clients use to select this upstream, e.g. `github.com`.
+    pub name: String,
+    /// What the remaining path is appended to, e.g. `https://repo1.maven.org/maven2`.
+    pub base_url: String,
+    /// Sent as the `Authorization` header, only to hosts on `base_url`.
+    #[serde(default)]
+    pub auth_header: Option<String>,
+    /// When non-empty, only paths under one of these may be mirrored, compared a path segment at a time.
+    #[serde(default)]
+    pub allowed_path_prefixes: Vec<String>,
+}
+
+// The auth header is a credential, keep it out of the logs.
+impl std::fmt::Debug for MirrorUpstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MirrorUpstream")
+            .field("name", &self.name)
+            .field("base_url", &self.base_url)
+            .field(
+                "auth_header",
+                &self.auth_header.as_ref().map(|_| "<redacted>"),
+            )
+            .field("allowed_path_prefixes", &self.allowed_path_prefixes)
+            .finish()
+    }
+}
+
+fn path_segments(path: &str) -> Vec<&str> {
+    path.split('/').filter(|e| !e.is_empty()).collect()
+}
+
+impl MirrorUpstream {
+    /// `path` is still percent-encoded. It is decoded before checking, as the upstream would see
+    /// `%2F..%2F` as a step up the tree too.
+    pub fn is_path_allowed(&self, path: &str) -> bool {
+        let decoded = match urlencoding::decode(path) {
+            Ok(decoded) => decoded,
+            Err(_) => return false,
+        };
+        let segments = path_segments(&decoded);
+        if segments.iter().any(|e| *e == "." || *e == "..") {
+            return false;
+        }
+        self.allowed_path_prefixes.is_empty()
+            || self
+                .allowed_path_prefixes
+                .iter()
+                .any(|p| segments.starts_with(&path_segments(p)))
+    }
+}
+
+// Keep mirroring github working for configs written before upstreams were configurable.
+pub(crate) fn default_mirror_upstreams() -> Vec<MirrorUpstream> {
+    vec![MirrorUpstream {
+        name: String::from("github.com"),
+        base_url: String::from("https://github.com"),
+        auth_header: None,
+        allowed_path_prefixes: Vec::default(),
+    }]
+}
@@ -1,12 +1,18 @@
 mod base_config;
 use anyhow::anyhow;
-pub use base_config::Config;
+pub use base_config::{Config, DEFAULT_MAX_ARCHIVE_DECOMPRESSED_BYTES};
 use std::path::{Path, PathBuf};
 
 pub mod cache_service_config;
+pub mod mirror_config;
+pub mod security_config;
 
 pub fn parse_config(input: &str) -> Result<Config, toml::de::Error> {
-    toml::from_str(input)
+    let config: Config = toml::from_str(input)?;
+    config
+        .validate()
+        .map_err(<toml::de::Error as serde::de::Error>::custom)?;
+    Ok(config)
 }
 
 pub fn load_config_file(
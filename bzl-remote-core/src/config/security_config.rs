@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Permission {
+    None,
+    ReadOnly,
+    ReadWrite,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct TlsConfig {
+    /// PEM encoded certificate chain served to clients.
+    pub cert_path: PathBuf,
+    /// PEM encoded private key for `cert_path`.
+    pub key_path: PathBuf,
+    /// PEM encoded CA(s) client certificates are verified against, enables mTLS.
+    pub client_ca_path: Option<PathBuf>,
+    /// Refuse connections that don't present a client certificate, only meaningful with `client_ca_path`.
+    #[serde(default)]
+    pub require_client_cert: bool,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct TokenGrant {
+    /// Sent by clients as `Authorization: Bearer <token>`.
+    pub token: String,
+    pub permission: Permission,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct ClientCertGrant {
+    /// Hex encoded sha256 of the DER encoded client certificate.
+    pub sha256: String,
+    pub permission: Permission,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct AuthConfig {
+    /// What requests carrying neither a token nor a known client cert may do.
+    /// Defaults to read-write so existing deployments keep working.
+    #[serde(default = "default_anonymous_permission")]
+    pub anonymous_permission: Permission,
+
+    #[serde(default)]
+    pub tokens: Vec<TokenGrant>,
+
+    /// Only presented when `TlsConfig.client_ca_path` is set, so these need it.
+    #[serde(default)]
+    pub client_certs: Vec<ClientCertGrant>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        toml::from_str("").unwrap()
+    }
+}
+
+fn default_anonymous_permission() -> Permission {
+    Permission::ReadWrite
+}
@@ -2,5 +2,6 @@ pub mod cache_client;
 pub mod cache_service;
 pub mod config;
 pub mod hash;
+pub mod metrics;
 pub mod server;
 pub mod storage_backend;
@@ -0,0 +1,318 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    pub static ref METRICS: CacheMetrics = CacheMetrics::default();
+}
+
+const RATIO_BUCKETS: &[f64] = &[0.0, 0.1, 0.25, 0.5, 0.75, 0.9, 1.0];
+const REQUEST_SIZE_BUCKETS: &[f64] = &[1.0, 10.0, 100.0, 1000.0, 10000.0, 100000.0];
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.inc_by(1)
+    }
+
+    pub fn inc_by(&self, v: u64) {
+        self.0.fetch_add(v, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: &'static [f64],
+    bucket_counts: Vec<AtomicU64>,
+    sum_bits: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Histogram {
+    fn new(buckets: &'static [f64]) -> Self {
+        Self {
+            buckets,
+            bucket_counts: buckets.iter().map(|_| AtomicU64::default()).collect(),
+            sum_bits: AtomicU64::new(0_f64.to_bits()),
+            count: AtomicU64::default(),
+        }
+    }
+
+    pub fn observe(&self, v: f64) {
+        for (bound, bucket_count) in self.buckets.iter().zip(self.bucket_counts.iter()) {
+            if v <= *bound {
+                bucket_count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let _ = self
+            .sum_bits
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+                Some((f64::from_bits(bits) + v).to_bits())
+            });
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+type Labels = Vec<(&'static str, String)>;
+
+/// A set of metrics sharing a name, one per distinct set of label values.
+#[derive(Debug)]
+pub struct Family<M> {
+    new_metric: fn() -> M,
+    metrics: RwLock<BTreeMap<Labels, Arc<M>>>,
+}
+
+impl<M> Family<M> {
+    fn new(new_metric: fn() -> M) -> Self {
+        Self {
+            new_metric,
+            metrics: RwLock::new(BTreeMap::default()),
+        }
+    }
+
+    pub fn with_labels(&self, labels: &[(&'static str, &str)]) -> Arc<M> {
+        let labels: Labels = labels.iter().map(|(k, v)| (*k, v.to_string())).collect();
+        if let Some(m) = self.metrics.read().unwrap().get(&labels) {
+            return Arc::clone(m);
+        }
+        let mut metrics = self.metrics.write().unwrap();
+        Arc::clone(
+            metrics
+                .entry(labels)
+                .or_insert_with(|| Arc::new((self.new_metric)())),
+        )
+    }
+
+    fn snapshot(&self) -> Vec<(Labels, Arc<M>)> {
+        self.metrics
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.clone(), Arc::clone(v)))
+            .collect()
+    }
+}
+
+/// Everything the cache server exports on `/metrics`.
+#[derive(Debug)]
+pub struct CacheMetrics {
+    pub action_cache_requests: Family<Counter>,
+    pub cas_bytes_read: Family<Counter>,
+    pub cas_bytes_written: Family<Counter>,
+    pub find_missing_blobs_digests: Histogram,
+    pub find_missing_blobs_missing_ratio: Histogram,
+    pub bytestream_duration_seconds: Family<Histogram>,
+    pub storage_backend_errors: Family<Counter>,
+}
+
+impl Default for CacheMetrics {
+    fn default() -> Self {
+        Self {
+            action_cache_requests: Family::new(Counter::default),
+            cas_bytes_read: Family::new(Counter::default),
+            cas_bytes_written: Family::new(Counter::default),
+            find_missing_blobs_digests: Histogram::new(REQUEST_SIZE_BUCKETS),
+            find_missing_blobs_missing_ratio: Histogram::new(RATIO_BUCKETS),
+            bytestream_duration_seconds: Family::new(|| Histogram::new(LATENCY_BUCKETS)),
+            storage_backend_errors: Family::new(Counter::default),
+        }
+    }
+}
+
+fn escape_label_value(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &[(&'static str, String)], extra: Option<(&str, String)>) -> String {
+    let mut parts: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(v)))
+        .collect();
+    if let Some((k, v)) = extra {
+        parts.push(format!("{}=\"{}\"", k, v));
+    }
+    if parts.is_empty() {
+        String::default()
+    } else {
+        format!("{{{}}}", parts.join(","))
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, tpe: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, tpe);
+}
+
+fn write_counter_family(out: &mut String, name: &str, help: &str, family: &Family<Counter>) {
+    write_header(out, name, help, "counter");
+    for (labels, counter) in family.snapshot() {
+        let _ = writeln!(
+            out,
+            "{}{} {}",
+            name,
+            format_labels(&labels, None),
+            counter.get()
+        );
+    }
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &[(&'static str, String)], h: &Histogram) {
+    for (bound, bucket_count) in h.buckets.iter().zip(h.bucket_counts.iter()) {
+        let _ = writeln!(
+            out,
+            "{}_bucket{} {}",
+            name,
+            format_labels(labels, Some(("le", bound.to_string()))),
+            bucket_count.load(Ordering::Relaxed)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{}_bucket{} {}",
+        name,
+        format_labels(labels, Some(("le", String::from("+Inf")))),
+        h.count()
+    );
+    let _ = writeln!(
+        out,
+        "{}_sum{} {}",
+        name,
+        format_labels(labels, None),
+        f64::from_bits(h.sum_bits.load(Ordering::Relaxed))
+    );
+    let _ = writeln!(
+        out,
+        "{}_count{} {}",
+        name,
+        format_labels(labels, None),
+        h.count()
+    );
+}
+
+impl CacheMetrics {
+    /// Renders all metrics in the prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::default();
+        write_counter_family(
+            &mut out,
+            "bzl_action_cache_requests_total",
+            "Action cache lookups by result (hit or miss).",
+            &self.action_cache_requests,
+        );
+        write_counter_family(
+            &mut out,
+            "bzl_cas_read_bytes_total",
+            "Bytes read from the CAS, by storage backend.",
+            &self.cas_bytes_read,
+        );
+        write_counter_family(
+            &mut out,
+            "bzl_cas_written_bytes_total",
+            "Bytes written to the CAS, by storage backend.",
+            &self.cas_bytes_written,
+        );
+
+        write_header(
+            &mut out,
+            "bzl_find_missing_blobs_digests",
+            "Number of digests per FindMissingBlobs request.",
+            "histogram",
+        );
+        write_histogram(
+            &mut out,
+            "bzl_find_missing_blobs_digests",
+            &[],
+            &self.find_missing_blobs_digests,
+        );
+        write_header(
+            &mut out,
+            "bzl_find_missing_blobs_missing_ratio",
+            "Fraction of digests reported missing per FindMissingBlobs request.",
+            "histogram",
+        );
+        write_histogram(
+            &mut out,
+            "bzl_find_missing_blobs_missing_ratio",
+            &[],
+            &self.find_missing_blobs_missing_ratio,
+        );
+
+        write_header(
+            &mut out,
+            "bzl_bytestream_duration_seconds",
+            "Time taken to serve ByteStream reads and writes.",
+            "histogram",
+        );
+        for (labels, h) in self.bytestream_duration_seconds.snapshot() {
+            write_histogram(&mut out, "bzl_bytestream_duration_seconds", &labels, &h);
+        }
+
+        write_counter_family(
+            &mut out,
+            "bzl_storage_backend_errors_total",
+            "Errors returned by storage backends, by backend and error kind.",
+            &self.storage_backend_errors,
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = CacheMetrics::default();
+        metrics
+            .action_cache_requests
+            .with_labels(&[("result", "hit")])
+            .inc();
+        metrics
+            .cas_bytes_read
+            .with_labels(&[("backend", "InMemory")])
+            .inc_by(1024);
+        metrics.find_missing_blobs_missing_ratio.observe(0.25);
+        metrics.find_missing_blobs_missing_ratio.observe(1.0);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("# TYPE bzl_action_cache_requests_total counter\n"));
+        assert!(rendered.contains("bzl_action_cache_requests_total{result=\"hit\"} 1\n"));
+        assert!(rendered.contains("bzl_cas_read_bytes_total{backend=\"InMemory\"} 1024\n"));
+        assert!(rendered.contains("bzl_find_missing_blobs_missing_ratio_bucket{le=\"0.1\"} 0\n"));
+        assert!(rendered.contains("bzl_find_missing_blobs_missing_ratio_bucket{le=\"0.25\"} 1\n"));
+        assert!(rendered.contains("bzl_find_missing_blobs_missing_ratio_bucket{le=\"+Inf\"} 2\n"));
+        assert!(rendered.contains("bzl_find_missing_blobs_missing_ratio_sum 1.25\n"));
+        assert!(rendered.contains("bzl_find_missing_blobs_missing_ratio_count 2\n"));
+    }
+
+    #[test]
+    fn test_label_escaping() {
+        let metrics = CacheMetrics::default();
+        metrics
+            .storage_backend_errors
+            .with_labels(&[("backend", "weird\"name"), ("error", "IOError")])
+            .inc();
+        assert!(metrics.render().contains(
+            "bzl_storage_backend_errors_total{backend=\"weird\\\"name\",error=\"IOError\"} 1\n"
+        ));
+    }
+}
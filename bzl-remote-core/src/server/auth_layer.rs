@@ -0,0 +1,461 @@
+use hyper::{Body, StatusCode};
+use sha2::Digest;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tonic::body::BoxBody;
+use tower::{Layer, Service};
+
+use crate::config::security_config::{AuthConfig, Permission};
+
+use super::{BoxError, EitherBody};
+
+// gRPC methods that mutate the cache, everything else only reads.
+const GRPC_WRITE_METHODS: &[&str] = &[
+    "/build.bazel.remote.execution.v2.ActionCache/UpdateActionResult",
+    "/build.bazel.remote.execution.v2.ContentAddressableStorage/BatchUpdateBlobs",
+    "/google.bytestream.ByteStream/Write",
+];
+// Fetch downloads whatever it is pointed at into the CAS, so it writes too.
+const GRPC_WRITE_SERVICES: &[&str] = &[
+    "/build.bazel.remote.asset.v1.Push/",
+    "/build.bazel.remote.asset.v1.Fetch/",
+];
+
+// Http GETs that store what they fetch from upstream into the CAS.
+const HTTP_WRITE_PATH_PREFIXES: &[&str] = &["/upstream_mirror/"];
+
+// Load balancers need to probe us without credentials.
+const UNAUTHENTICATED_HTTP_PATHS: &[&str] = &["/healthcheck"];
+
+#[derive(Debug, PartialEq, Eq)]
+enum AuthDecision {
+    Allow,
+    Unauthenticated(&'static str),
+    PermissionDenied(&'static str),
+}
+
+/// Resolves the credentials on a request to the permission they grant.
+#[derive(Debug)]
+pub struct Authorizer {
+    anonymous_permission: Permission,
+    tokens: Vec<(Vec<u8>, Permission)>,
+    client_certs: HashMap<String, Permission>,
+}
+
+impl Authorizer {
+    pub fn new(auth_config: &AuthConfig) -> Self {
+        Self {
+            anonymous_permission: auth_config.anonymous_permission,
+            tokens: auth_config
+                .tokens
+                .iter()
+                .map(|t| (t.token.as_bytes().to_vec(), t.permission))
+                .collect(),
+            client_certs: auth_config
+                .client_certs
+                .iter()
+                .map(|c| (c.sha256.to_lowercase(), c.permission))
+                .collect(),
+        }
+    }
+
+    fn token_permission(&self, token: &[u8]) -> Option<Permission> {
+        // Check every token so the time taken doesn't reveal which one nearly matched.
+        let mut found = None;
+        for (candidate, permission) in self.tokens.iter() {
+            if constant_time_eq(candidate, token) {
+                found = Some(*permission);
+            }
+        }
+        found
+    }
+
+    fn permission_for(
+        &self,
+        bearer_token: Option<&[u8]>,
+        client_cert_sha256: Option<&str>,
+    ) -> Result<Permission, AuthDecision> {
+        let mut permission = self.anonymous_permission;
+        if let Some(token) = bearer_token {
+            match self.token_permission(token) {
+                Some(p) => permission = permission.max(p),
+                None => return Err(AuthDecision::Unauthenticated("Unknown bearer token")),
+            }
+        }
+        if let Some(p) = client_cert_sha256.and_then(|sha| self.client_certs.get(sha)) {
+            permission = permission.max(*p);
+        }
+        Ok(permission)
+    }
+
+    fn decide<B>(&self, req: &hyper::Request<B>, client_cert_sha256: Option<&str>) -> AuthDecision {
+        let required = required_permission(req);
+        if required == Permission::None {
+            return AuthDecision::Allow;
+        }
+        let bearer_token = req
+            .headers()
+            .get(http::header::AUTHORIZATION)
+            .and_then(|v| v.as_bytes().strip_prefix(b"Bearer "));
+
+        match self.permission_for(bearer_token, client_cert_sha256) {
+            Err(decision) => decision,
+            Ok(granted) if granted >= required => AuthDecision::Allow,
+            Ok(Permission::None) => {
+                AuthDecision::Unauthenticated("Credentials are required to access this cache")
+            }
+            Ok(_) => AuthDecision::PermissionDenied("Credentials only grant read access"),
+        }
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// The cache server routes on the http version, not the content type a client
+// claims, so classify requests the same way or a grpc content type on an http/1
+// PUT would be checked against the read only grpc rules.
+fn is_grpc<B>(req: &hyper::Request<B>) -> bool {
+    req.version() == http::Version::HTTP_2
+}
+
+fn required_permission<B>(req: &hyper::Request<B>) -> Permission {
+    let path = req.uri().path();
+    if is_grpc(req) {
+        if GRPC_WRITE_METHODS.contains(&path)
+            || GRPC_WRITE_SERVICES.iter().any(|s| path.starts_with(s))
+        {
+            Permission::ReadWrite
+        } else {
+            Permission::ReadOnly
+        }
+    } else if UNAUTHENTICATED_HTTP_PATHS.contains(&path) {
+        Permission::None
+    } else if HTTP_WRITE_PATH_PREFIXES
+        .iter()
+        .any(|p| path.starts_with(p) || path == p.trim_end_matches('/'))
+    {
+        Permission::ReadWrite
+    } else if req.method() == http::Method::GET || req.method() == http::Method::HEAD {
+        Permission::ReadOnly
+    } else {
+        Permission::ReadWrite
+    }
+}
+
+fn rejection<B>(
+    req: &hyper::Request<B>,
+    decision: AuthDecision,
+) -> hyper::Response<EitherBody<Body, BoxBody>> {
+    let (status, grpc_status, message) = match decision {
+        AuthDecision::Allow => unreachable!("Allowed requests are never rejected"),
+        AuthDecision::Unauthenticated(msg) => (
+            StatusCode::UNAUTHORIZED,
+            tonic::Status::unauthenticated(msg),
+            msg,
+        ),
+        AuthDecision::PermissionDenied(msg) => (
+            StatusCode::FORBIDDEN,
+            tonic::Status::permission_denied(msg),
+            msg,
+        ),
+    };
+    if is_grpc(req) {
+        grpc_status.to_http().map(EitherBody::Right)
+    } else {
+        hyper::Response::builder()
+            .status(status)
+            .body(EitherBody::Left(Body::from(message)))
+            .unwrap()
+    }
+}
+
+/// Rejects requests whose bearer token or client certificate doesn't grant
+/// the permission the called endpoint needs. One layer is built per connection,
+/// carrying the certificate the client presented during the TLS handshake.
+#[derive(Debug, Clone)]
+pub struct AuthLayer {
+    authorizer: Arc<Authorizer>,
+    client_cert_sha256: Option<Arc<str>>,
+}
+
+impl AuthLayer {
+    pub fn new(authorizer: Arc<Authorizer>, client_certificate_der: Option<&[u8]>) -> Self {
+        let client_cert_sha256 =
+            client_certificate_der.map(|der| hex_sha256(der).into_boxed_str().into());
+        Self {
+            authorizer,
+            client_cert_sha256,
+        }
+    }
+}
+
+fn hex_sha256(data: &[u8]) -> String {
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(data);
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl<S> Layer<S> for AuthLayer {
+    type Service = AuthService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        AuthService {
+            inner: service,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthService<S> {
+    inner: S,
+    layer: AuthLayer,
+}
+
+impl<S> Service<hyper::Request<Body>> for AuthService<S>
+where
+    S: Service<
+            hyper::Request<Body>,
+            Response = hyper::Response<EitherBody<Body, BoxBody>>,
+            Error = BoxError,
+        > + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: hyper::Request<Body>) -> Self::Future {
+        let decision = self
+            .layer
+            .authorizer
+            .decide(&req, self.layer.client_cert_sha256.as_deref());
+        if decision != AuthDecision::Allow {
+            tracing::info!(
+                "Rejected request to {} with {:?}",
+                req.uri().path(),
+                decision
+            );
+            let response = rejection(&req, decision);
+            return Box::pin(async move { Ok(response) });
+        }
+
+        // See GrpcErrorTraceService, the service we were polled ready on must be the one we call.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move { inner.call(req).await })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::security_config::{ClientCertGrant, TokenGrant};
+
+    use super::*;
+
+    fn authorizer() -> Authorizer {
+        Authorizer::new(&AuthConfig {
+            anonymous_permission: Permission::None,
+            tokens: vec![
+                TokenGrant {
+                    token: String::from("reader"),
+                    permission: Permission::ReadOnly,
+                },
+                TokenGrant {
+                    token: String::from("writer"),
+                    permission: Permission::ReadWrite,
+                },
+            ],
+            client_certs: vec![ClientCertGrant {
+                sha256: hex_sha256(b"fake cert"),
+                permission: Permission::ReadWrite,
+            }],
+        })
+    }
+
+    fn grpc_request(path: &str, token: Option<&str>) -> hyper::Request<Body> {
+        let mut builder = hyper::Request::builder()
+            .method(http::Method::POST)
+            .version(http::Version::HTTP_2)
+            .uri(path)
+            .header(http::header::CONTENT_TYPE, "application/grpc");
+        if let Some(token) = token {
+            builder = builder.header(http::header::AUTHORIZATION, format!("Bearer {}", token));
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn test_grpc_permissions() {
+        let authorizer = authorizer();
+        let read = "/build.bazel.remote.execution.v2.ActionCache/GetActionResult";
+        let write = "/build.bazel.remote.execution.v2.ActionCache/UpdateActionResult";
+
+        assert!(matches!(
+            authorizer.decide(&grpc_request(read, None), None),
+            AuthDecision::Unauthenticated(_)
+        ));
+        assert!(matches!(
+            authorizer.decide(&grpc_request(read, Some("bogus")), None),
+            AuthDecision::Unauthenticated(_)
+        ));
+        assert_eq!(
+            authorizer.decide(&grpc_request(read, Some("reader")), None),
+            AuthDecision::Allow
+        );
+        assert!(matches!(
+            authorizer.decide(&grpc_request(write, Some("reader")), None),
+            AuthDecision::PermissionDenied(_)
+        ));
+        assert_eq!(
+            authorizer.decide(&grpc_request(write, Some("writer")), None),
+            AuthDecision::Allow
+        );
+
+        let cert_sha = hex_sha256(b"fake cert");
+        assert_eq!(
+            authorizer.decide(&grpc_request(write, None), Some(&cert_sha)),
+            AuthDecision::Allow
+        );
+    }
+
+    #[test]
+    fn test_http_permissions() {
+        let authorizer = authorizer();
+        let health = hyper::Request::builder()
+            .uri("/healthcheck")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(authorizer.decide(&health, None), AuthDecision::Allow);
+
+        let put = hyper::Request::builder()
+            .method(http::Method::PUT)
+            .uri("/cas/abc")
+            .header(http::header::AUTHORIZATION, "Bearer reader")
+            .body(Body::empty())
+            .unwrap();
+        assert!(matches!(
+            authorizer.decide(&put, None),
+            AuthDecision::PermissionDenied(_)
+        ));
+
+        let get = hyper::Request::builder()
+            .uri("/cas/abc")
+            .header(http::header::AUTHORIZATION, "Bearer reader")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(authorizer.decide(&get, None), AuthDecision::Allow);
+    }
+
+    #[test]
+    fn test_http1_with_grpc_content_type_is_still_http() {
+        let authorizer = authorizer();
+        for path in [
+            "/cas/abc",
+            "/upstream_mirror/a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79/github.com/foo.tar.gz",
+        ] {
+            let put = |token: &str| {
+                hyper::Request::builder()
+                    .method(http::Method::PUT)
+                    .version(http::Version::HTTP_11)
+                    .uri(path)
+                    .header(http::header::CONTENT_TYPE, "application/grpc")
+                    .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                    .body(Body::empty())
+                    .unwrap()
+            };
+            assert_eq!(required_permission(&put("reader")), Permission::ReadWrite);
+            assert!(matches!(
+                authorizer.decide(&put("reader"), None),
+                AuthDecision::PermissionDenied(_)
+            ));
+            assert_eq!(
+                authorizer.decide(&put("writer"), None),
+                AuthDecision::Allow
+            );
+        }
+    }
+
+    #[test]
+    fn test_fetching_into_the_cas_needs_write() {
+        let authorizer = authorizer();
+        for fetch in [
+            "/build.bazel.remote.asset.v1.Fetch/FetchBlob",
+            "/build.bazel.remote.asset.v1.Fetch/FetchDirectory",
+        ] {
+            assert!(matches!(
+                authorizer.decide(&grpc_request(fetch, Some("reader")), None),
+                AuthDecision::PermissionDenied(_)
+            ));
+            assert_eq!(
+                authorizer.decide(&grpc_request(fetch, Some("writer")), None),
+                AuthDecision::Allow
+            );
+        }
+
+        let mirror = |token: &str| {
+            hyper::Request::builder()
+                .uri("/upstream_mirror/a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79/github.com/foo.tar.gz")
+                .header(http::header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        assert!(matches!(
+            authorizer.decide(&mirror("reader"), None),
+            AuthDecision::PermissionDenied(_)
+        ));
+        assert_eq!(
+            authorizer.decide(&mirror("writer"), None),
+            AuthDecision::Allow
+        );
+    }
+
+    #[tokio::test]
+    async fn test_layer_rejects_with_grpc_status() -> Result<(), BoxError> {
+        let inner = tower::service_fn(|_req: hyper::Request<Body>| async {
+            Ok::<_, BoxError>(hyper::Response::new(EitherBody::Left(Body::from("ok"))))
+        });
+        let mut service = AuthLayer::new(Arc::new(authorizer()), None).layer(inner);
+
+        let response = service
+            .call(grpc_request(
+                "/google.bytestream.ByteStream/Write",
+                Some("reader"),
+            ))
+            .await?;
+        assert_eq!(
+            response
+                .headers()
+                .get("grpc-status")
+                .map(|v| v.to_str().unwrap().to_string()),
+            Some((tonic::Code::PermissionDenied as i32).to_string())
+        );
+
+        let response = service
+            .call(grpc_request(
+                "/google.bytestream.ByteStream/Read",
+                Some("reader"),
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get("grpc-status").is_none());
+        Ok(())
+    }
+}
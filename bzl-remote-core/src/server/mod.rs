@@ -1,7 +1,11 @@
+mod auth_layer;
+pub use auth_layer::{AuthLayer, Authorizer};
 mod either_body;
 pub use either_body::EitherBody;
 mod grpc_error_trace_layer;
 pub use grpc_error_trace_layer::GrpcErrorTraceLayer;
+mod tls;
+pub use tls::tls_acceptor_from_config;
 type BoxError = Box<dyn std::error::Error + Send + Sync + 'static>;
 
 fn map_option_err<T, U: Into<BoxError>>(err: Option<Result<T, U>>) -> Option<Result<T, BoxError>> {
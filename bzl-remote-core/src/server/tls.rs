@@ -0,0 +1,65 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use tokio_rustls::rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient,
+};
+use tokio_rustls::rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+use crate::config::security_config::TlsConfig;
+
+fn load_certs(path: &Path) -> Result<Vec<Certificate>, Box<dyn std::error::Error>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No certificates found in {}", path.to_string_lossy()).into());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKey, Box<dyn std::error::Error>> {
+    for item in rustls_pemfile::read_all(&mut BufReader::new(File::open(path)?))? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => (),
+        }
+    }
+    Err(anyhow!("No private key found in {}", path.to_string_lossy()).into())
+}
+
+/// Builds the acceptor used to terminate TLS on incoming connections, verifying
+/// client certificates against `client_ca_path` when configured.
+pub fn tls_acceptor_from_config(
+    tls_config: &TlsConfig,
+) -> Result<TlsAcceptor, Box<dyn std::error::Error>> {
+    let certs = load_certs(&tls_config.cert_path)?;
+    let key = load_private_key(&tls_config.key_path)?;
+
+    let builder = ServerConfig::builder().with_safe_defaults();
+    let mut server_config = match &tls_config.client_ca_path {
+        Some(client_ca_path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(client_ca_path)? {
+                roots.add(&cert)?;
+            }
+            let verifier = if tls_config.require_client_cert {
+                AllowAnyAuthenticatedClient::new(roots).boxed()
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots).boxed()
+            };
+            builder
+                .with_client_cert_verifier(verifier)
+                .with_single_cert(certs, key)?
+        }
+        None => builder.with_no_client_auth().with_single_cert(certs, key)?,
+    };
+    // We serve both gRPC and the http endpoint off the same port.
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
@@ -13,7 +13,7 @@ pub enum UploadType {
     OnDisk(PathBuf),
 }
 
-pub type DataReturnTpe = Box<dyn AsRef<[u8]> + Send>;
+pub type DataReturnTpe = Box<dyn AsRef<[u8]> + Send + Sync>;
 
 pub async fn insert_action_result_to_cas<T: StorageBackend>(
     cas: T,
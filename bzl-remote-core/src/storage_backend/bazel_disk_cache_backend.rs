@@ -0,0 +1,374 @@
+use bazelfe_protos::build::bazel::remote::execution::v2::{self as execution};
+use prost::Message;
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::hash::sha256_value::Sha256Value;
+
+use super::api::insert_action_result_to_cas;
+use super::api::DataReturnTpe;
+use super::StorageBackend;
+use super::StorageBackendError;
+use super::UploadType;
+
+// Bazel doesn't have a notion of a kv store in its disk cache, so we keep ours alongside
+// in a directory bazel will never look at.
+const KV_DIRECTORY: &str = "bzl_remote_kv";
+
+#[derive(Debug)]
+struct MemoryMappedFile {
+    _f: std::fs::File,
+    mmap: memmap2::Mmap,
+}
+
+impl AsRef<[u8]> for MemoryMappedFile {
+    fn as_ref(&self) -> &[u8] {
+        &self.mmap
+    }
+}
+
+/// Serves a directory laid out the way bazel writes `--disk_cache`,
+/// `ac/<2 char prefix>/<hash>` for action results and `cas/<2 char prefix>/<hash>` for blobs.
+/// Bazel can keep using the same directory concurrently, all of our writes go through
+/// a temp file and an atomic rename as bazel's own do.
+#[derive(Debug)]
+pub struct BazelDiskCacheBackend {
+    root: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl BazelDiskCacheBackend {
+    pub fn open<P: AsRef<Path>>(p: P) -> Result<Self, Box<dyn std::error::Error>> {
+        let root = p.as_ref().to_path_buf();
+        std::fs::create_dir_all(root.join("ac"))?;
+        std::fs::create_dir_all(root.join("cas"))?;
+        std::fs::create_dir_all(root.join(KV_DIRECTORY))?;
+
+        let tmp_path = root.join("tmp");
+        std::fs::create_dir_all(&tmp_path)?;
+
+        Ok(Self { root, tmp_path })
+    }
+
+    // The hash comes straight from the client, parsing it ensures it can't be used to escape the cache directory.
+    fn entry_path(&self, kind: &str, hash: &str) -> Result<PathBuf, StorageBackendError> {
+        let hash = Sha256Value::from_str(hash)?.to_string();
+        Ok(self.root.join(kind).join(&hash[0..2]).join(&hash))
+    }
+
+    fn cas_path(&self, hash: &str) -> Result<PathBuf, StorageBackendError> {
+        self.entry_path("cas", hash)
+    }
+
+    fn ac_path(&self, hash: &str) -> Result<PathBuf, StorageBackendError> {
+        self.entry_path("ac", hash)
+    }
+
+    fn kv_path(&self, key: &[u8]) -> Result<PathBuf, StorageBackendError> {
+        let key_sha: Sha256Value = key.try_into()?;
+        self.entry_path(KV_DIRECTORY, &key_sha.to_string())
+    }
+
+    fn new_tmp_path(&self) -> PathBuf {
+        self.tmp_path
+            .join(format!("{}.tmp", rand::random::<usize>()))
+    }
+
+    async fn write_bytes(&self, target: &Path, data: &[u8]) -> Result<(), StorageBackendError> {
+        let tmp_path = self.new_tmp_path();
+        if let Err(e) = tokio::fs::write(&tmp_path, data).await {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(StorageBackendError::ErrorAndMessage(
+                "Error attempting to write to file".to_string(),
+                Box::new(e),
+            ));
+        }
+        self.move_into_place(&tmp_path, target)
+    }
+
+    fn move_into_place(&self, source: &Path, target: &Path) -> Result<(), StorageBackendError> {
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // Try rename first, since that is atomic and fast if on the same file system
+        if std::fs::rename(source, target).is_ok() {
+            return Ok(());
+        }
+        let tmp_path = self.new_tmp_path();
+        if let Err(e) = std::fs::copy(source, &tmp_path) {
+            let _ = std::fs::remove_file(&tmp_path);
+            let _ = std::fs::remove_file(source);
+            return Err(StorageBackendError::InternalError(Box::new(e)));
+        }
+        let _ = std::fs::remove_file(source);
+        std::fs::rename(&tmp_path, target)
+            .map_err(|e| StorageBackendError::InternalError(Box::new(e)))
+    }
+
+    async fn read_file(path: &Path) -> Result<Option<Vec<u8>>, StorageBackendError> {
+        match tokio::fs::read(path).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StorageBackendError::ErrorAndMessage(
+                format!("Error attempting to read {:?}", path),
+                Box::new(e),
+            )),
+        }
+    }
+
+    async fn assert_inbound_digest_match(
+        digest: &execution::Digest,
+        data: &UploadType,
+    ) -> Result<(), StorageBackendError> {
+        let expected_digest = Sha256Value::try_from(digest)?;
+        let (actual_value, actual_len) = match data {
+            UploadType::InMemory(d) => (Sha256Value::try_from(&d[..])?, d.len()),
+            UploadType::OnDisk(f) => (
+                Sha256Value::from_path(f).await?,
+                std::fs::metadata(f)?.len() as usize,
+            ),
+        };
+        if actual_len as i64 != digest.size_bytes {
+            return Err(StorageBackendError::InvalidSizeForDataInbound(
+                expected_digest,
+                digest.size_bytes,
+                actual_len,
+            ));
+        }
+        if actual_value != expected_digest {
+            return Err(StorageBackendError::InvalidDigestForDataInbound(
+                expected_digest,
+                actual_value,
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for BazelDiskCacheBackend {
+    async fn get_kv(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageBackendError> {
+        Self::read_file(&self.kv_path(key)?).await
+    }
+
+    async fn put_kv(&self, key: &[u8], value: &[u8]) -> Result<(), StorageBackendError> {
+        self.write_bytes(&self.kv_path(key)?, value).await
+    }
+
+    async fn get_action_result(
+        &self,
+        digest: &execution::Digest,
+    ) -> Result<Option<Arc<execution::ActionResult>>, StorageBackendError> {
+        match Self::read_file(&self.ac_path(&digest.hash)?).await? {
+            Some(data) => Ok(Some(Arc::new(execution::ActionResult::decode(&data[..])?))),
+            None => Ok(None),
+        }
+    }
+
+    async fn put_action_result(
+        &self,
+        digest: &execution::Digest,
+        action_result: &execution::ActionResult,
+    ) -> Result<execution::Digest, StorageBackendError> {
+        let action_result_digest = insert_action_result_to_cas(self, action_result).await?;
+        self.write_bytes(&self.ac_path(&digest.hash)?, &action_result.encode_to_vec())
+            .await?;
+        Ok(action_result_digest)
+    }
+
+    async fn build_digest_from_hash_if_present(
+        &self,
+        hash: &String,
+    ) -> Result<Option<execution::Digest>, StorageBackendError> {
+        match tokio::fs::metadata(self.cas_path(hash)?).await {
+            Ok(metadata) => Ok(Some(execution::Digest {
+                hash: hash.clone(),
+                size_bytes: metadata.len() as i64,
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(StorageBackendError::ErrorAndMessage(
+                "Error attempting to get hash entry metadata".to_string(),
+                Box::new(e),
+            )),
+        }
+    }
+
+    async fn cas_filter_for_missing(
+        &self,
+        digests: &mut Vec<execution::Digest>,
+    ) -> Result<(), StorageBackendError> {
+        let mut res: Vec<execution::Digest> = Vec::with_capacity(digests.len());
+        for ele in digests.drain(..) {
+            if !self.cas_exists(&ele).await? {
+                res.push(ele);
+            }
+        }
+        std::mem::swap(digests, &mut res);
+
+        Ok(())
+    }
+
+    async fn cas_exists(&self, digest: &execution::Digest) -> Result<bool, StorageBackendError> {
+        Ok(self.cas_path(&digest.hash)?.exists())
+    }
+
+    async fn cas_insert(
+        &self,
+        digest: &execution::Digest,
+        data: UploadType,
+    ) -> Result<(), StorageBackendError> {
+        let target = self.cas_path(&digest.hash)?;
+        if target.exists() {
+            return Ok(());
+        }
+
+        // Bazel trusts whatever it finds in its disk cache, so never let bad data in.
+        Self::assert_inbound_digest_match(digest, &data).await?;
+
+        match data {
+            UploadType::InMemory(data) => self.write_bytes(&target, &data).await,
+            UploadType::OnDisk(path) => self.move_into_place(&path, &target),
+        }
+    }
+
+    async fn cas_get_data(
+        &self,
+        digest: &execution::Digest,
+    ) -> Result<Option<DataReturnTpe>, StorageBackendError> {
+        let path = self.cas_path(&digest.hash)?;
+        let f = match std::fs::File::open(&path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(StorageBackendError::InternalError(e.into())),
+        };
+
+        // Mapping an empty file fails, so just hand back an empty buffer.
+        if f.metadata()?.len() == 0 {
+            return Ok(Some(Box::new(Vec::<u8>::default())));
+        }
+
+        let mmap = unsafe {
+            memmap2::Mmap::map(&f).map_err(|e| StorageBackendError::InternalError(e.into()))?
+        };
+
+        Ok(Some(Box::new(MemoryMappedFile { _f: f, mmap })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn digest_for(data: &[u8]) -> execution::Digest {
+        let sha_v: Sha256Value = data.try_into().expect("Should be able to hash");
+        execution::Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_uses_bazel_layout() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+        let backend = BazelDiskCacheBackend::open(tmp_dir.path())?;
+
+        let data = b"Hello world".to_vec();
+        let digest = digest_for(&data);
+        backend
+            .cas_insert(&digest, UploadType::InMemory(data.clone()))
+            .await?;
+
+        let expected_cas_path = tmp_dir
+            .path()
+            .join("cas")
+            .join(&digest.hash[0..2])
+            .join(&digest.hash);
+        assert_eq!(std::fs::read(&expected_cas_path)?, data);
+        assert_eq!(
+            backend
+                .cas_get_data(&digest)
+                .await?
+                .map(|d| d.as_ref().as_ref().to_vec()),
+            Some(data)
+        );
+
+        let action_digest = digest_for(b"my action");
+        let action_result = execution::ActionResult {
+            exit_code: 3,
+            ..Default::default()
+        };
+        backend
+            .put_action_result(&action_digest, &action_result)
+            .await?;
+        let expected_ac_path = tmp_dir
+            .path()
+            .join("ac")
+            .join(&action_digest.hash[0..2])
+            .join(&action_digest.hash);
+        assert_eq!(
+            execution::ActionResult::decode(&std::fs::read(&expected_ac_path)?[..])?,
+            action_result
+        );
+        assert_eq!(
+            backend
+                .get_action_result(&action_digest)
+                .await?
+                .map(|e| e.as_ref().clone()),
+            Some(action_result)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reads_existing_bazel_entries() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+        let data = b"written by bazel".to_vec();
+        let digest = digest_for(&data);
+        let prefix_dir = tmp_dir.path().join("cas").join(&digest.hash[0..2]);
+        std::fs::create_dir_all(&prefix_dir)?;
+        std::fs::write(prefix_dir.join(&digest.hash), &data)?;
+
+        let backend = BazelDiskCacheBackend::open(tmp_dir.path())?;
+
+        let missing = digest_for(b"not present");
+        let mut digests = vec![digest.clone(), missing.clone()];
+        backend.cas_filter_for_missing(&mut digests).await?;
+        assert_eq!(digests, vec![missing]);
+
+        assert_eq!(
+            backend
+                .build_digest_from_hash_if_present(&digest.hash)
+                .await?,
+            Some(digest)
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rejects_bad_input() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+        let backend = BazelDiskCacheBackend::open(tmp_dir.path())?;
+
+        let escaping_digest = execution::Digest {
+            hash: String::from("../../etc/passwd"),
+            size_bytes: 4,
+        };
+        assert!(backend.cas_exists(&escaping_digest).await.is_err());
+
+        let mut wrong_data_digest = digest_for(b"some data");
+        wrong_data_digest.size_bytes = 10;
+        assert!(backend
+            .cas_insert(
+                &wrong_data_digest,
+                UploadType::InMemory(b"other data".to_vec())
+            )
+            .await
+            .is_err());
+        assert!(!backend.cas_exists(&wrong_data_digest).await?);
+        Ok(())
+    }
+}
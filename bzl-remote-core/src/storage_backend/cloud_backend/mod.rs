@@ -151,7 +151,7 @@ impl CloudBackend {
                 .await?;
         }
 
-        connection.set(key, value).await?;
+        connection.set::<_, _, ()>(key, value).await?;
 
         Ok(())
     }
@@ -195,7 +195,7 @@ impl CloudBackend {
         let mut connection = self.ac_redis.clone();
 
         connection
-            .set(hash_bytes, action_result.encode_to_vec())
+            .set::<_, _, ()>(hash_bytes, action_result.encode_to_vec())
             .await?;
 
         Ok(())
@@ -218,7 +218,7 @@ impl CloudBackend {
                 .atomic()
                 .hset(mini_key, hash_bytes, digest.size_bytes as u64)
                 .expire(mini_key, 60 * 60 * 24 * 30)
-                .query_async(&mut connection)
+                .query_async::<_, ()>(&mut connection)
                 .await
         };
 
@@ -382,7 +382,7 @@ impl CloudBackend {
             .atomic()
             .set(hash_bytes, data)
             .expire(hash_bytes, 60 * 60 * 24 * 3)
-            .query_async(&mut connection)
+            .query_async::<_, ()>(&mut connection)
             .await?;
 
         Ok(())
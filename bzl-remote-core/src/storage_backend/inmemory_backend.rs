@@ -5,6 +5,7 @@ use dashmap::DashMap;
 use std::sync::Arc;
 
 use super::api::insert_action_result_to_cas;
+use super::api::DataReturnTpe;
 use super::api::UploadType;
 use super::StorageBackend;
 use super::StorageBackendError;
@@ -130,12 +131,12 @@ impl StorageBackend for InMemoryStorageBackend {
     async fn cas_get_data(
         &self,
         digest: &execution::Digest,
-    ) -> Result<Option<Box<dyn AsRef<[u8]> + Send>>, StorageBackendError> {
+    ) -> Result<Option<DataReturnTpe>, StorageBackendError> {
         let lut = digest.hash.as_bytes();
 
         Ok(self.cas_store.get(lut).map(|e| {
             let v = Arc::clone(&e);
-            let b: Box<dyn AsRef<[u8]> + Send> = Box::new(ArcRefBox(v));
+            let b: DataReturnTpe = Box::new(ArcRefBox(v));
             b
         }))
     }
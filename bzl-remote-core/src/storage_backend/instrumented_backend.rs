@@ -0,0 +1,142 @@
+use bazelfe_protos::build::bazel::remote::execution::v2::{self as execution};
+
+use std::sync::Arc;
+
+use crate::metrics::METRICS;
+
+use super::api::DataReturnTpe;
+use super::StorageBackend;
+use super::StorageBackendError;
+use super::UploadType;
+
+/// Wraps a backend, recording CAS throughput and errors under its name in the global metrics.
+#[derive(Debug)]
+pub struct InstrumentedBackend {
+    name: String,
+    inner: Arc<dyn StorageBackend>,
+}
+
+impl InstrumentedBackend {
+    pub fn new<S: Into<String>>(name: S, inner: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            name: name.into(),
+            inner,
+        }
+    }
+
+    fn record<T>(&self, res: Result<T, StorageBackendError>) -> Result<T, StorageBackendError> {
+        if let Err(e) = &res {
+            METRICS
+                .storage_backend_errors
+                .with_labels(&[("backend", &self.name), ("error", e.variant_name())])
+                .inc();
+        }
+        res
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for InstrumentedBackend {
+    async fn get_kv(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageBackendError> {
+        self.record(self.inner.get_kv(key).await)
+    }
+
+    async fn put_kv(&self, key: &[u8], value: &[u8]) -> Result<(), StorageBackendError> {
+        self.record(self.inner.put_kv(key, value).await)
+    }
+
+    async fn get_action_result(
+        &self,
+        digest: &execution::Digest,
+    ) -> Result<Option<Arc<execution::ActionResult>>, StorageBackendError> {
+        self.record(self.inner.get_action_result(digest).await)
+    }
+
+    async fn put_action_result(
+        &self,
+        digest: &execution::Digest,
+        action_result: &execution::ActionResult,
+    ) -> Result<execution::Digest, StorageBackendError> {
+        self.record(self.inner.put_action_result(digest, action_result).await)
+    }
+
+    async fn build_digest_from_hash_if_present(
+        &self,
+        hash: &String,
+    ) -> Result<Option<execution::Digest>, StorageBackendError> {
+        self.record(self.inner.build_digest_from_hash_if_present(hash).await)
+    }
+
+    async fn cas_filter_for_missing(
+        &self,
+        digests: &mut Vec<execution::Digest>,
+    ) -> Result<(), StorageBackendError> {
+        self.record(self.inner.cas_filter_for_missing(digests).await)
+    }
+
+    async fn cas_exists(&self, digest: &execution::Digest) -> Result<bool, StorageBackendError> {
+        self.record(self.inner.cas_exists(digest).await)
+    }
+
+    async fn cas_insert(
+        &self,
+        digest: &execution::Digest,
+        data: UploadType,
+    ) -> Result<(), StorageBackendError> {
+        let res = self.record(self.inner.cas_insert(digest, data).await);
+        if res.is_ok() {
+            METRICS
+                .cas_bytes_written
+                .with_labels(&[("backend", &self.name)])
+                .inc_by(digest.size_bytes.max(0) as u64);
+        }
+        res
+    }
+
+    async fn cas_get_data(
+        &self,
+        digest: &execution::Digest,
+    ) -> Result<Option<DataReturnTpe>, StorageBackendError> {
+        let res = self.record(self.inner.cas_get_data(digest).await);
+        if let Ok(Some(data)) = &res {
+            METRICS
+                .cas_bytes_read
+                .with_labels(&[("backend", &self.name)])
+                .inc_by(data.as_ref().as_ref().len() as u64);
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::sha256_value::Sha256Value;
+    use crate::storage_backend::InMemoryStorageBackend;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_records_cas_bytes() -> Result<(), Box<dyn std::error::Error>> {
+        let backend = InstrumentedBackend::new(
+            "test_records_cas_bytes",
+            Arc::new(InMemoryStorageBackend::default()),
+        );
+        let data = b"some bytes".to_vec();
+        let sha_v: Sha256Value = data.as_slice().try_into()?;
+        let digest = execution::Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        };
+
+        backend
+            .cas_insert(&digest, UploadType::InMemory(data))
+            .await?;
+        backend.cas_get_data(&digest).await?;
+        backend.cas_get_data(&digest).await?;
+
+        let labels = [("backend", "test_records_cas_bytes")];
+        assert_eq!(METRICS.cas_bytes_written.with_labels(&labels).get(), 10);
+        assert_eq!(METRICS.cas_bytes_read.with_labels(&labels).get(), 20);
+        Ok(())
+    }
+}
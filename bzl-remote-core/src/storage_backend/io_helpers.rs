@@ -9,9 +9,12 @@ use crate::hash::sha256_value::Sha256Value;
 
 use super::{StorageBackend, StorageBackendError, UploadType};
 
+/// Streams `body` to a temp file and inserts it into the CAS. When `expected_sha256` is given
+/// content hashing to anything else is rejected before it reaches the CAS.
 pub async fn store_body_in_cas<T: StorageBackend>(
     storage_backend: &T,
     body: &mut hyper::Body,
+    expected_sha256: Option<&Sha256Value>,
 ) -> Result<Digest, StorageBackendError> {
     let tmp_file = NamedTempFile::new()?;
     let mut tokio_output = tokio::fs::File::create(tmp_file.path()).await?;
@@ -32,6 +35,13 @@ pub async fn store_body_in_cas<T: StorageBackend>(
     drop(tokio_output);
 
     let sha = Sha256Value::new_from_slice(&hasher.finalize())?;
+    if let Some(expected) = expected_sha256 {
+        if expected != &sha {
+            return Err(StorageBackendError::InvalidDigestForDataInbound(
+                *expected, sha,
+            ));
+        }
+    }
 
     let digest = Digest {
         hash: sha.to_string(),
@@ -48,6 +58,15 @@ pub async fn store_body_in_cas<T: StorageBackend>(
 #[async_trait::async_trait]
 pub trait BackendIOHelpers: StorageBackend + Sized {
     async fn download_file_to_cas(&self, url: &str) -> Result<Digest, StorageBackendError>;
+
+    /// Like `download_file_to_cas`, but only stores the file if it hashes to `expected_sha256`.
+    /// `auth_header` is sent as the `Authorization` header, but not across redirects to other hosts.
+    async fn download_verified_file_to_cas(
+        &self,
+        url: &str,
+        auth_header: Option<&str>,
+        expected_sha256: &Sha256Value,
+    ) -> Result<Digest, StorageBackendError>;
 }
 
 fn compute_redirect(old_url: &str, location_uri: &str) -> Result<hyper::Uri, StorageBackendError> {
@@ -85,11 +104,22 @@ enum RedirectOrValue {
 async fn inner_download_file_to_cas<T: StorageBackend>(
     storage: &T,
     url: &str,
+    auth_header: Option<&str>,
+    expected_sha256: Option<&Sha256Value>,
 ) -> Result<RedirectOrValue, StorageBackendError> {
-    let uri = url.parse::<hyper::Uri>().unwrap();
+    let uri = url.parse::<hyper::Uri>().map_err(|e| {
+        StorageBackendError::Unknown(format!("Unable to parse url with error {:#?}", e))
+    })?;
     let https = hyper_tls::HttpsConnector::new();
     let client = hyper::Client::builder().build::<_, hyper::Body>(https);
-    match client.get(uri).await {
+    let mut request = hyper::Request::get(uri);
+    if let Some(auth_header) = auth_header {
+        request = request.header(header::AUTHORIZATION, auth_header);
+    }
+    let request = request.body(hyper::Body::empty()).map_err(|e| {
+        StorageBackendError::Unknown(format!("Unable to build request with error {:#?}", e))
+    })?;
+    match client.request(request).await {
         Ok(mut res) => {
             if res.status().is_redirection() {
                 let new_url = res.headers().get(header::LOCATION);
@@ -118,7 +148,7 @@ async fn inner_download_file_to_cas<T: StorageBackend>(
                     res.status()
                 )))
             } else {
-                let r = store_body_in_cas(storage, res.body_mut()).await?;
+                let r = store_body_in_cas(storage, res.body_mut(), expected_sha256).await?;
                 Ok(RedirectOrValue::Value(r))
             }
         }
@@ -128,23 +158,92 @@ async fn inner_download_file_to_cas<T: StorageBackend>(
         )),
     }
 }
+fn same_authority(a: &str, b: &str) -> bool {
+    match (a.parse::<hyper::Uri>(), b.parse::<hyper::Uri>()) {
+        (Ok(a), Ok(b)) => a.scheme() == b.scheme() && a.authority() == b.authority(),
+        _ => false,
+    }
+}
+
+async fn follow_redirects_to_cas<T: StorageBackend>(
+    storage: &T,
+    url: &str,
+    auth_header: Option<&str>,
+    expected_sha256: Option<&Sha256Value>,
+) -> Result<Digest, StorageBackendError> {
+    let mut next_url: String = url.to_string();
+
+    for _ in 0..MAX_REDIRECT_DEPTH {
+        // Upstreams like artifactory redirect to pre-signed blob store urls, which reject our credentials.
+        let auth_header = auth_header.filter(|_| same_authority(url, &next_url));
+        match inner_download_file_to_cas(storage, next_url.as_str(), auth_header, expected_sha256)
+            .await?
+        {
+            RedirectOrValue::Value(v) => return Ok(v),
+            RedirectOrValue::Redirect(url) => {
+                next_url = url;
+            }
+        }
+    }
+
+    Err(StorageBackendError::Unknown(format!(
+        "Have followed too many redirects, Original url: {:#?}, final url: {:#?}",
+        url, next_url
+    )))
+}
+
 #[async_trait::async_trait]
 impl<T: StorageBackend> BackendIOHelpers for T {
     async fn download_file_to_cas(&self, url: &str) -> Result<Digest, StorageBackendError> {
-        let mut next_url: String = url.to_string();
+        follow_redirects_to_cas(self, url, None, None).await
+    }
 
-        for _ in 0..MAX_REDIRECT_DEPTH {
-            match inner_download_file_to_cas(self, next_url.as_str()).await? {
-                RedirectOrValue::Value(v) => return Ok(v),
-                RedirectOrValue::Redirect(url) => {
-                    next_url = url;
-                }
-            }
-        }
+    async fn download_verified_file_to_cas(
+        &self,
+        url: &str,
+        auth_header: Option<&str>,
+        expected_sha256: &Sha256Value,
+    ) -> Result<Digest, StorageBackendError> {
+        follow_redirects_to_cas(self, url, auth_header, Some(expected_sha256)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::storage_backend::InMemoryStorageBackend;
 
-        return Err(StorageBackendError::Unknown(format!(
-            "Have followed too many redirects, Original url: {:#?}, final url: {:#?}",
-            url, next_url
-        )));
+    use super::*;
+
+    #[tokio::test]
+    async fn test_store_body_rejects_unexpected_content() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let backend = InMemoryStorageBackend::default();
+        let expected = Sha256Value::from_str(
+            "a3f37db1bf47603b45daebdd5012d1845adb2c21e6250e94c79014e5bc873b79",
+        )?;
+
+        let mut body = hyper::Body::from("not what we asked for");
+        let res = store_body_in_cas(&backend, &mut body, Some(&expected)).await;
+        let actual = match res {
+            Err(StorageBackendError::InvalidDigestForDataInbound(e, actual)) => {
+                assert_eq!(e, expected);
+                actual
+            }
+            other => panic!("Expected a digest mismatch, got {:?}", other),
+        };
+        assert!(
+            backend
+                .build_digest_from_hash_if_present(&actual.to_string())
+                .await?
+                .is_none(),
+            "Mismatched content should never reach the CAS"
+        );
+
+        let mut body = hyper::Body::from("not what we asked for");
+        let digest = store_body_in_cas(&backend, &mut body, Some(&actual)).await?;
+        assert_eq!(digest.hash, actual.to_string());
+        Ok(())
     }
 }
@@ -0,0 +1,145 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::super::StorageBackendError;
+
+// Re-writing the access time on every single read would double the write load on sled
+// for hot entries, so we only bump it once it is at least this stale.
+const ATIME_RESOLUTION_SECS: u64 = 60;
+
+pub fn now_epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessEntry {
+    pub last_access_secs: u64,
+    pub size_bytes: u64,
+}
+
+impl AccessEntry {
+    fn encode(&self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[0..8].copy_from_slice(&self.last_access_secs.to_be_bytes());
+        bytes[8..16].copy_from_slice(&self.size_bytes.to_be_bytes());
+        bytes
+    }
+
+    fn decode(data: &[u8]) -> Option<AccessEntry> {
+        if data.len() != 16 {
+            return None;
+        }
+        let mut atime = [0; 8];
+        atime.copy_from_slice(&data[0..8]);
+        let mut size = [0; 8];
+        size.copy_from_slice(&data[8..16]);
+        Some(AccessEntry {
+            last_access_secs: u64::from_be_bytes(atime),
+            size_bytes: u64::from_be_bytes(size),
+        })
+    }
+}
+
+/// Tracks the last access time and stored size of entries in one of our sled trees
+/// so the garbage collector can evict the least recently used ones.
+#[derive(Debug)]
+pub struct AccessTracker {
+    tree: sled::Tree,
+}
+
+impl AccessTracker {
+    pub fn new(
+        sled_connection: &sled::Db,
+        tree_name: &'static str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let tree = sled_connection.open_tree(tree_name)?;
+        Ok(Self { tree })
+    }
+
+    pub fn record(&self, key: &[u8], size_bytes: u64) -> Result<(), StorageBackendError> {
+        self.record_at(key, size_bytes, now_epoch_secs())
+    }
+
+    pub fn record_at(
+        &self,
+        key: &[u8],
+        size_bytes: u64,
+        last_access_secs: u64,
+    ) -> Result<(), StorageBackendError> {
+        let entry = AccessEntry {
+            last_access_secs,
+            size_bytes,
+        };
+        self.tree.insert(key, &entry.encode())?;
+        Ok(())
+    }
+
+    /// Bump the access time of an already tracked key, keeping its recorded size.
+    /// Untracked keys are left alone, the garbage collector will backfill them.
+    pub fn touch(&self, key: &[u8]) -> Result<(), StorageBackendError> {
+        let now = now_epoch_secs();
+        if let Some(existing) = self.get(key)? {
+            if existing.last_access_secs + ATIME_RESOLUTION_SECS <= now {
+                self.record_at(key, existing.size_bytes, now)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<AccessEntry>, StorageBackendError> {
+        Ok(self
+            .tree
+            .get(key)?
+            .and_then(|v| AccessEntry::decode(v.as_ref())))
+    }
+
+    pub fn contains(&self, key: &[u8]) -> Result<bool, StorageBackendError> {
+        Ok(self.tree.contains_key(key)?)
+    }
+
+    pub fn remove(&self, key: &[u8]) -> Result<(), StorageBackendError> {
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<(Vec<u8>, AccessEntry)>, StorageBackendError> {
+        let mut res = Vec::default();
+        for kv in self.tree.iter() {
+            let (k, v) = kv?;
+            if let Some(entry) = AccessEntry::decode(v.as_ref()) {
+                res.push((k.to_vec(), entry));
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[test]
+    fn test_touch_keeps_size() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+        let sled_connection = sled::open(tmp_dir.path().join("sled"))?;
+        let tracker = AccessTracker::new(&sled_connection, "test_atime")?;
+
+        tracker.touch(b"missing")?;
+        assert_eq!(tracker.get(b"missing")?, None);
+
+        tracker.record_at(b"foo", 1234, 10)?;
+        tracker.touch(b"foo")?;
+        let entry = tracker.get(b"foo")?.expect("Should be present");
+        assert_eq!(entry.size_bytes, 1234);
+        assert!(entry.last_access_secs > 10);
+
+        assert_eq!(tracker.entries()?.len(), 1);
+        tracker.remove(b"foo")?;
+        assert!(!tracker.contains(b"foo")?);
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use bazelfe_protos::build::bazel::remote::execution::v2::{self as execution};
 use prost::{DecodeError, Message};
 
+use super::access_tracker::AccessTracker;
 use super::kv_store::KvStore;
 
 #[derive(PartialEq, Hash, Eq, Debug)]
@@ -25,14 +26,37 @@ impl std::borrow::Borrow<[u8]> for ByteWrapper {
 }
 
 #[derive(Debug)]
-pub struct ActionCache(KvStore);
+pub struct ActionCache(KvStore, AccessTracker);
 
 impl ActionCache {
     pub fn new(sled_connection: &sled::Db) -> Result<Self, Box<dyn std::error::Error>> {
-        Ok(ActionCache(super::kv_store::KvStore::new(
-            sled_connection,
-            "ac",
-        )?))
+        Ok(ActionCache(
+            super::kv_store::KvStore::new(sled_connection, "ac")?,
+            AccessTracker::new(sled_connection, "ac_atime")?,
+        ))
+    }
+
+    pub fn access_tracker(&self) -> &AccessTracker {
+        &self.1
+    }
+
+    /// Start tracking any entries which were stored before we recorded access times.
+    pub fn backfill_access_entries(&self) -> Result<usize, super::StorageBackendError> {
+        let mut backfilled = 0;
+        for kv in self.0.iter() {
+            let (k, v) = kv?;
+            if !self.1.contains(&k)? {
+                self.1.record(&k, v.len() as u64)?;
+                backfilled += 1;
+            }
+        }
+        Ok(backfilled)
+    }
+
+    pub fn remove(&self, hash: &[u8]) -> Result<(), super::StorageBackendError> {
+        self.0.remove(hash)?;
+        self.1.remove(hash)?;
+        Ok(())
     }
 
     pub fn get_action(
@@ -41,7 +65,10 @@ impl ActionCache {
     ) -> Result<Option<execution::ActionResult>, super::StorageBackendError> {
         let key = digest.hash.as_bytes();
         match self.0.get(key)? {
-            Some(tree_tpe) => Ok(Some(execution::ActionResult::decode(tree_tpe.as_ref())?)),
+            Some(tree_tpe) => {
+                self.1.touch(key)?;
+                Ok(Some(execution::ActionResult::decode(tree_tpe.as_ref())?))
+            }
             None => Ok(None),
         }
     }
@@ -54,6 +81,7 @@ impl ActionCache {
         let key = digest.hash.as_bytes();
         let value = action_result.encode_to_vec();
         self.0.insert(key, &value)?;
+        self.1.record(key, value.len() as u64)?;
 
         Ok(())
     }
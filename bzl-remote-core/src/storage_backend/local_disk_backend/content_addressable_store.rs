@@ -6,6 +6,7 @@ use std::path::PathBuf;
 use std::sync::Arc;
 
 use super::super::StorageBackendError;
+use super::access_tracker::AccessTracker;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum InMemoryLutTreeType {
@@ -41,6 +42,17 @@ impl AsRef<[u8]> for ArcDynBox {
     }
 }
 
+fn file_len(path: &std::path::Path) -> Result<u64, StorageBackendError> {
+    Ok(std::fs::metadata(path)
+        .map_err(|e| {
+            StorageBackendError::ErrorAndMessage(
+                "Error attempting to get stored file metadata".to_string(),
+                Box::new(e),
+            )
+        })?
+        .len())
+}
+
 // from the sled docs for incrementing
 fn sled_u64_increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
     let number = match old {
@@ -60,6 +72,7 @@ pub struct ContentAddressableStore {
     lut_tree: sled::Tree,
     small_file_tree: sled::Tree,
     large_blob_path: PathBuf,
+    access_tracker: AccessTracker,
 }
 
 impl ContentAddressableStore {
@@ -69,17 +82,24 @@ impl ContentAddressableStore {
     ) -> Result<Self, Box<dyn std::error::Error>> {
         let lut_tree = sled_connection.open_tree("cas_lut")?;
         let small_file_tree = sled_connection.open_tree("cas_small")?;
+        let access_tracker = AccessTracker::new(sled_connection, "cas_atime")?;
         Ok(Self {
             lut_tree,
             small_file_tree,
             large_blob_path,
+            access_tracker,
         })
     }
 
+    pub fn access_tracker(&self) -> &AccessTracker {
+        &self.access_tracker
+    }
+
     fn store_metadata(
         &self,
         digest: &execution::Digest,
         metadata: InMemoryLutTreeType,
+        size_bytes: u64,
     ) -> Result<(), super::StorageBackendError> {
         let key = digest.hash.as_bytes();
 
@@ -89,9 +109,76 @@ impl ContentAddressableStore {
         };
 
         self.lut_tree.insert(key, &v.to_be_bytes())?;
+        self.access_tracker.record(key, size_bytes)?;
         Ok(())
     }
 
+    fn stored_size(&self, hash: &String) -> Result<Option<u64>, StorageBackendError> {
+        match self.get_metadata(hash)? {
+            None => Ok(None),
+            Some(InMemoryLutTreeType::OnDisk) => {
+                Ok(Some(file_len(&self.expected_path_hash(hash))?))
+            }
+            Some(InMemoryLutTreeType::InDB(idx)) => {
+                Ok(Some(self.get_from_small_db(idx)?.len() as u64))
+            }
+        }
+    }
+
+    /// Mark this hash as recently used, so it is among the last to be evicted.
+    pub fn touch(&self, hash: &str) -> Result<(), StorageBackendError> {
+        self.access_tracker.touch(hash.as_bytes())
+    }
+
+    /// Start tracking any entries which were stored before we recorded access times.
+    /// They are treated as having been accessed now.
+    pub fn backfill_access_entries(&self) -> Result<usize, StorageBackendError> {
+        let mut backfilled = 0;
+        for k in self.lut_tree.iter().keys() {
+            let k = k?;
+            if self.access_tracker.contains(&k)? {
+                continue;
+            }
+            let hash = String::from_utf8_lossy(&k).to_string();
+            if let Some(size_bytes) = self.stored_size(&hash)? {
+                self.access_tracker.record(&k, size_bytes)?;
+                backfilled += 1;
+            }
+        }
+        Ok(backfilled)
+    }
+
+    /// Remove the data stored for this hash, returning the number of bytes freed if it was present.
+    pub fn remove(&self, hash: &String) -> Result<Option<u64>, StorageBackendError> {
+        let key = hash.as_bytes();
+        let removed = match self.get_metadata(hash)? {
+            None => None,
+            Some(metadata) => {
+                let size_bytes = self.stored_size(hash).unwrap_or(None).unwrap_or_default();
+                self.lut_tree.remove(key)?;
+                match metadata {
+                    InMemoryLutTreeType::OnDisk => {
+                        let p = self.expected_path_hash(hash);
+                        if let Err(e) = std::fs::remove_file(&p) {
+                            if e.kind() != std::io::ErrorKind::NotFound {
+                                return Err(StorageBackendError::ErrorAndMessage(
+                                    format!("Unable to remove evicted blob at {:?}", p),
+                                    Box::new(e),
+                                ));
+                            }
+                        }
+                    }
+                    InMemoryLutTreeType::InDB(idx) => {
+                        self.small_file_tree.remove(idx.to_be_bytes())?;
+                    }
+                }
+                Some(size_bytes)
+            }
+        };
+        self.access_tracker.remove(key)?;
+        Ok(removed)
+    }
+
     pub async fn build_digest_from_hash_if_present(
         &self,
         hash: &String,
@@ -297,7 +384,7 @@ impl ContentAddressableStore {
                 let idx = self.get_next_small_idx()?;
 
                 self.small_file_tree.insert(idx.to_be_bytes(), &data[..])?;
-                self.store_metadata(digest, InMemoryLutTreeType::InDB(idx))?;
+                self.store_metadata(digest, InMemoryLutTreeType::InDB(idx), data.len() as u64)?;
                 Ok(DataLocation::InMemory)
             }
             crate::storage_backend::UploadType::OnDisk(f) => {
@@ -309,7 +396,11 @@ impl ContentAddressableStore {
                 }
                 // Try rename first, since that is atomic and fast if on the same file system
                 if std::fs::rename(&f, &expected_path).is_ok() {
-                    self.store_metadata(digest, InMemoryLutTreeType::OnDisk)?;
+                    self.store_metadata(
+                        digest,
+                        InMemoryLutTreeType::OnDisk,
+                        file_len(&expected_path)?,
+                    )?;
                     return Ok(DataLocation::OnDisk(expected_path));
                 }
                 tracing::info!("Rename failed, doing copy");
@@ -332,7 +423,11 @@ impl ContentAddressableStore {
                 std::fs::rename(&tmp_path, &expected_path)
                     .map_err(|e| StorageBackendError::InternalError(Box::new(e)))?;
 
-                self.store_metadata(digest, InMemoryLutTreeType::OnDisk)?;
+                self.store_metadata(
+                    digest,
+                    InMemoryLutTreeType::OnDisk,
+                    file_len(&expected_path)?,
+                )?;
                 Ok(DataLocation::OnDisk(expected_path))
             }
         }
@@ -357,6 +452,7 @@ impl ContentAddressableStore {
         digest: &execution::Digest,
     ) -> Result<core::option::Option<ArcDynBox>, StorageBackendError> {
         if let Some(metadata) = self.get_metadata(&digest.hash)? {
+            self.touch(&digest.hash)?;
             match metadata {
                 InMemoryLutTreeType::OnDisk => {
                     Ok(Some(ArcDynBox(Arc::new(self.get_from_disk(digest)?))))
@@ -29,6 +29,15 @@ impl KvStore {
         self.tree.insert(key, value)?;
         Ok(())
     }
+
+    pub fn remove(&self, key: &[u8]) -> Result<(), super::StorageBackendError> {
+        self.tree.remove(key)?;
+        Ok(())
+    }
+
+    pub fn iter(&self) -> sled::Iter {
+        self.tree.iter()
+    }
 }
 
 #[cfg(test)]
@@ -3,13 +3,17 @@ use bazelfe_protos::build::bazel::remote::execution::v2::{self as execution};
 mod content_addressable_store;
 use content_addressable_store::ContentAddressableStore;
 
+mod access_tracker;
 mod action_cache;
 mod kv_store;
+use access_tracker::{now_epoch_secs, AccessEntry};
 use action_cache::ActionCache;
+use prost::Message;
 
 use std::path::Path;
 
 use std::sync::Arc;
+use std::time::Duration;
 
 pub use self::content_addressable_store::DataLocation;
 use self::kv_store::KvStore;
@@ -20,18 +24,57 @@ use super::StorageBackend;
 use super::StorageBackendError;
 use super::UploadType;
 
+// Anything accessed this recently is never evicted, this covers blobs referenced by an action result
+// we have just served, which the client is likely about to download.
+const RECENTLY_USED_GRACE_SECS: u64 = 5 * 60;
+
+/// Limits applied by the garbage collector, with nothing set we never evict.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EvictionPolicy {
+    pub max_size_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+impl EvictionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_size_bytes.is_some() || self.max_age.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GarbageCollectionStats {
+    pub evicted_cas_entries: usize,
+    pub evicted_action_results: usize,
+    pub freed_bytes: u64,
+    pub remaining_bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TrackedEntryType {
+    Cas,
+    ActionResult,
+}
+
 #[derive(Debug)]
 pub struct LocalDiskStorageBackend {
     content_addressable_store: ContentAddressableStore,
     action_cache: ActionCache,
     kv_store: KvStore,
+    eviction_policy: EvictionPolicy,
 }
 
 impl LocalDiskStorageBackend {
+    pub fn open<P: AsRef<Path>>(p: P) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::open_with_eviction_policy(p, EvictionPolicy::default())
+    }
+
     // This should probably change to being a setup error return type
     // but given this is done during the open flow only, going to short cut and use a dyn Error for now.
     // Lossy in the type.
-    pub fn open<P: AsRef<Path>>(p: P) -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn open_with_eviction_policy<P: AsRef<Path>>(
+        p: P,
+        eviction_policy: EvictionPolicy,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let root_path = p.as_ref();
         std::fs::create_dir_all(root_path)?;
 
@@ -51,9 +94,155 @@ impl LocalDiskStorageBackend {
             action_cache,
             content_addressable_store,
             kv_store,
+            eviction_policy,
         })
     }
 
+    pub fn eviction_policy(&self) -> &EvictionPolicy {
+        &self.eviction_policy
+    }
+
+    /// Periodically run the garbage collector on a blocking thread, until the backend is dropped.
+    pub fn spawn_garbage_collector(self: &Arc<Self>, interval: Duration) {
+        let weak_self = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let backend = match weak_self.upgrade() {
+                    Some(b) => b,
+                    None => break,
+                };
+                match tokio::task::spawn_blocking(move || backend.garbage_collect()).await {
+                    Ok(Ok(stats)) => tracing::info!("Local disk garbage collection: {:?}", stats),
+                    Ok(Err(e)) => tracing::error!("Local disk garbage collection failed: {}", e),
+                    Err(e) => tracing::error!("Local disk garbage collection panicked: {:?}", e),
+                }
+            }
+        });
+    }
+
+    /// Evict least recently used CAS blobs and action cache entries until we are within
+    /// the configured size, along with anything older than the max age.
+    pub fn garbage_collect(&self) -> Result<GarbageCollectionStats, StorageBackendError> {
+        let mut stats = GarbageCollectionStats::default();
+        if !self.eviction_policy.is_enabled() {
+            return Ok(stats);
+        }
+
+        self.content_addressable_store.backfill_access_entries()?;
+        self.action_cache.backfill_access_entries()?;
+
+        let mut entries: Vec<(AccessEntry, TrackedEntryType, Vec<u8>)> = Vec::default();
+        for (k, entry) in self.content_addressable_store.access_tracker().entries()? {
+            entries.push((entry, TrackedEntryType::Cas, k));
+        }
+        for (k, entry) in self.action_cache.access_tracker().entries()? {
+            entries.push((entry, TrackedEntryType::ActionResult, k));
+        }
+        entries.sort_by_key(|(entry, _, _)| entry.last_access_secs);
+
+        let now = now_epoch_secs();
+        let protected_after = now.saturating_sub(RECENTLY_USED_GRACE_SECS);
+        let expire_before = self
+            .eviction_policy
+            .max_age
+            .map(|max_age| now.saturating_sub(max_age.as_secs()));
+
+        let mut remaining_bytes: u64 = entries.iter().map(|(e, _, _)| e.size_bytes).sum();
+
+        for (entry, entry_type, key) in entries.into_iter() {
+            if entry.last_access_secs >= protected_after {
+                break;
+            }
+            let expired = expire_before
+                .map(|t| entry.last_access_secs < t)
+                .unwrap_or(false);
+            let over_size = self
+                .eviction_policy
+                .max_size_bytes
+                .map(|max_size| remaining_bytes > max_size)
+                .unwrap_or(false);
+            // Entries are sorted oldest first, once neither condition holds it won't for any later entry.
+            if !(expired || over_size) {
+                break;
+            }
+
+            let tracker = match entry_type {
+                TrackedEntryType::Cas => self.content_addressable_store.access_tracker(),
+                TrackedEntryType::ActionResult => self.action_cache.access_tracker(),
+            };
+            // It may have been used since we took our snapshot, if so leave it alone.
+            if tracker.get(&key)? != Some(entry) {
+                continue;
+            }
+
+            match entry_type {
+                TrackedEntryType::Cas => {
+                    let hash = String::from_utf8_lossy(&key).to_string();
+                    self.content_addressable_store.remove(&hash)?;
+                    stats.evicted_cas_entries += 1;
+                }
+                TrackedEntryType::ActionResult => {
+                    self.action_cache.remove(&key)?;
+                    stats.evicted_action_results += 1;
+                }
+            }
+            stats.freed_bytes += entry.size_bytes;
+            remaining_bytes = remaining_bytes.saturating_sub(entry.size_bytes);
+        }
+        stats.remaining_bytes = remaining_bytes;
+        Ok(stats)
+    }
+
+    // Marks everything an action result points at as recently used, so the client
+    // can go on to fetch the outputs. Returns false if any of them has already been evicted.
+    async fn touch_action_result_references(
+        &self,
+        action_result: &execution::ActionResult,
+    ) -> Result<bool, StorageBackendError> {
+        let mut digests: Vec<&execution::Digest> = Vec::default();
+        digests.extend(
+            action_result
+                .output_files
+                .iter()
+                .filter_map(|f| f.digest.as_ref()),
+        );
+        digests.extend(
+            action_result
+                .output_directories
+                .iter()
+                .filter_map(|d| d.tree_digest.as_ref()),
+        );
+        digests.extend(action_result.stdout_digest.iter());
+        digests.extend(action_result.stderr_digest.iter());
+
+        let mut tree_files: Vec<execution::Digest> = Vec::default();
+        for output_directory in action_result.output_directories.iter() {
+            if let Some(tree_digest) = output_directory.tree_digest.as_ref() {
+                if let Some(data) = self
+                    .content_addressable_store
+                    .get_to_vec(tree_digest)
+                    .await?
+                {
+                    let tree = execution::Tree::decode(&data[..])?;
+                    for directory in tree.root.iter().chain(tree.children.iter()) {
+                        tree_files.extend(directory.files.iter().filter_map(|f| f.digest.clone()));
+                    }
+                }
+            }
+        }
+        digests.extend(tree_files.iter());
+
+        for digest in digests {
+            if !self.content_addressable_store.exists(digest)? {
+                return Ok(false);
+            }
+            self.content_addressable_store.touch(&digest.hash)?;
+        }
+        Ok(true)
+    }
+
     pub async fn insert(
         &self,
         digest: &execution::Digest,
@@ -85,9 +274,21 @@ impl StorageBackend for LocalDiskStorageBackend {
         &self,
         digest: &execution::Digest,
     ) -> Result<Option<Arc<execution::ActionResult>>, StorageBackendError> {
-        self.action_cache
-            .get_action(digest)
-            .map(|e| e.map(Arc::new))
+        let action_result = match self.action_cache.get_action(digest)? {
+            Some(r) => r,
+            None => return Ok(None),
+        };
+
+        if self.eviction_policy.is_enabled()
+            && !self.touch_action_result_references(&action_result).await?
+        {
+            tracing::info!(
+                "Action result for {} references evicted blobs, treating as a cache miss",
+                digest.hash
+            );
+            return Ok(None);
+        }
+        Ok(Some(Arc::new(action_result)))
     }
     async fn put_action_result(
         &self,
@@ -116,6 +317,9 @@ impl StorageBackend for LocalDiskStorageBackend {
         for ele in digests.drain(..) {
             if !self.content_addressable_store.exists(&ele)? {
                 res.push(ele);
+            } else {
+                // The client is going to rely on this being present, so count it as a use.
+                self.content_addressable_store.touch(&ele.hash)?;
             }
         }
         std::mem::swap(digests, &mut res);
@@ -147,3 +351,165 @@ impl StorageBackend for LocalDiskStorageBackend {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::hash::sha256_value::Sha256Value;
+
+    fn digest_for(data: &[u8]) -> execution::Digest {
+        let sha_v: Sha256Value = data.try_into().expect("Should be able to hash");
+        execution::Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        }
+    }
+
+    async fn insert_aged(
+        backend: &LocalDiskStorageBackend,
+        data: Vec<u8>,
+        age_secs: u64,
+    ) -> execution::Digest {
+        let digest = digest_for(&data);
+        backend
+            .cas_insert(&digest, UploadType::InMemory(data))
+            .await
+            .expect("Should be able to insert");
+        backend
+            .content_addressable_store
+            .access_tracker()
+            .record_at(
+                digest.hash.as_bytes(),
+                digest.size_bytes as u64,
+                now_epoch_secs() - age_secs,
+            )
+            .expect("Should be able to set access time");
+        digest
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+        let backend = LocalDiskStorageBackend::open_with_eviction_policy(
+            tmp_dir.path(),
+            EvictionPolicy {
+                max_size_bytes: Some(300),
+                max_age: None,
+            },
+        )?;
+
+        let oldest = insert_aged(&backend, vec![1; 200], 3600).await;
+        let older = insert_aged(&backend, vec![2; 200], 1800).await;
+        let newest = insert_aged(&backend, vec![3; 200], 900).await;
+
+        // Reading it makes it the most recently used.
+        assert!(backend.cas_get_data(&oldest).await?.is_some());
+
+        let stats = backend.garbage_collect()?;
+        assert_eq!(stats.evicted_cas_entries, 2);
+        assert_eq!(stats.freed_bytes, 400);
+        assert_eq!(stats.remaining_bytes, 200);
+
+        assert!(backend.cas_exists(&oldest).await?);
+        assert!(!backend.cas_exists(&older).await?);
+        assert!(!backend.cas_exists(&newest).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recently_used_never_evicted() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+        let backend = LocalDiskStorageBackend::open_with_eviction_policy(
+            tmp_dir.path(),
+            EvictionPolicy {
+                max_size_bytes: Some(10),
+                max_age: None,
+            },
+        )?;
+
+        let fresh = insert_aged(&backend, vec![1; 200], 0).await;
+        let stats = backend.garbage_collect()?;
+        assert_eq!(stats.evicted_cas_entries, 0);
+        assert!(backend.cas_exists(&fresh).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_max_age_evicts_action_results() -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+        let backend = LocalDiskStorageBackend::open_with_eviction_policy(
+            tmp_dir.path(),
+            EvictionPolicy {
+                max_size_bytes: None,
+                max_age: Some(Duration::from_secs(24 * 3600)),
+            },
+        )?;
+
+        let output = insert_aged(&backend, vec![5; 100], 48 * 3600).await;
+        let action_digest = digest_for(b"my action");
+        let action_result = execution::ActionResult {
+            output_files: vec![execution::OutputFile {
+                path: String::from("foo.jar"),
+                digest: Some(output.clone()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let action_result_digest = backend
+            .put_action_result(&action_digest, &action_result)
+            .await?;
+        backend.action_cache.access_tracker().record_at(
+            action_digest.hash.as_bytes(),
+            10,
+            now_epoch_secs() - 48 * 3600,
+        )?;
+        backend
+            .content_addressable_store
+            .access_tracker()
+            .record_at(
+                action_result_digest.hash.as_bytes(),
+                action_result_digest.size_bytes as u64,
+                now_epoch_secs() - 48 * 3600,
+            )?;
+
+        let stats = backend.garbage_collect()?;
+        assert_eq!(stats.evicted_action_results, 1);
+        assert_eq!(stats.evicted_cas_entries, 2);
+        assert_eq!(backend.get_action_result(&action_digest).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_action_result_with_evicted_outputs_is_a_miss(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let tmp_dir = tempdir()?;
+        let backend = LocalDiskStorageBackend::open_with_eviction_policy(
+            tmp_dir.path(),
+            EvictionPolicy {
+                max_size_bytes: Some(1024 * 1024),
+                max_age: None,
+            },
+        )?;
+
+        let output = insert_aged(&backend, vec![5; 100], 0).await;
+        let action_digest = digest_for(b"my action");
+        let action_result = execution::ActionResult {
+            output_files: vec![execution::OutputFile {
+                path: String::from("foo.jar"),
+                digest: Some(output.clone()),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        backend
+            .put_action_result(&action_digest, &action_result)
+            .await?;
+        assert!(backend.get_action_result(&action_digest).await?.is_some());
+
+        backend.content_addressable_store.remove(&output.hash)?;
+        assert_eq!(backend.get_action_result(&action_digest).await?, None);
+        Ok(())
+    }
+}
@@ -46,6 +46,23 @@ pub enum StorageBackendError {
     #[error("Outbound data size mismatch, expected sha {0} with len {1}, but got len {2}")]
     InvalidSizeForDataOutbound(crate::hash::sha256_value::Sha256Value, i64, u64),
 }
+impl StorageBackendError {
+    /// Stable name of the variant, used to label error metrics.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Self::Unknown(_) => "Unknown",
+            Self::IOError(_) => "IOError",
+            Self::InternalError(_) => "InternalError",
+            Self::ErrorAndMessage(_, _) => "ErrorAndMessage",
+            Self::InvalidSha256Value(_) => "InvalidSha256Value",
+            Self::InvalidDigestForDataInbound(_, _) => "InvalidDigestForDataInbound",
+            Self::InvalidDigestForDataOutbound(_, _) => "InvalidDigestForDataOutbound",
+            Self::InvalidSizeForDataInbound(_, _, _) => "InvalidSizeForDataInbound",
+            Self::InvalidSizeForDataOutbound(_, _, _) => "InvalidSizeForDataOutbound",
+        }
+    }
+}
+
 // directly build these when we are making internal errors
 impl From<bazelfe_protos::digest_utils::DigestExtractError> for StorageBackendError {
     fn from(e: bazelfe_protos::digest_utils::DigestExtractError) -> Self {
@@ -57,14 +74,25 @@ impl From<bazelfe_protos::digest_utils::DigestExtractError> for StorageBackendEr
 }
 
 mod api;
+mod bazel_disk_cache_backend;
 mod cloud_backend;
 mod inmemory_backend;
+mod instrumented_backend;
 mod io_helpers;
 mod local_disk_backend;
+mod remote_grpc_backend;
+mod tiered_backend;
 
 pub use api::StorageBackend;
 pub use api::UploadType;
+pub use bazel_disk_cache_backend::BazelDiskCacheBackend;
 pub use cloud_backend::CloudBackend;
 pub use inmemory_backend::InMemoryStorageBackend;
+pub use instrumented_backend::InstrumentedBackend;
 pub use io_helpers::BackendIOHelpers;
+pub use local_disk_backend::EvictionPolicy;
+pub use local_disk_backend::GarbageCollectionStats;
 pub use local_disk_backend::LocalDiskStorageBackend;
+pub use remote_grpc_backend::RemoteGrpcBackend;
+pub use tiered_backend::TieredBackend;
+pub use tiered_backend::WritePolicy;
@@ -0,0 +1,392 @@
+use bazelfe_protos::build::bazel::remote::execution::v2::{self as execution};
+
+use std::sync::Arc;
+
+use tempfile::NamedTempFile;
+
+use crate::cache_client::CacheClient;
+use crate::hash::sha256_value::Sha256Value;
+
+use super::api::DataReturnTpe;
+use super::StorageBackend;
+use super::StorageBackendError;
+use super::UploadType;
+
+// Keep each bytestream chunk well under the default 4MB grpc message limit.
+const UPLOAD_CHUNK_SIZE: u64 = 1024 * 1024;
+
+// REAPI has no notion of a kv store, so kv values are stored as blobs of their own instance,
+// each referenced from an action result keyed on a digest derived from the kv key.
+const KV_INSTANCE_NAME: &str = "bzl_remote_kv";
+// Upstreams that ignore instance names share one action cache, the prefix keeps kv keys from ever
+// colliding with real actions there.
+const KV_ACTION_PREFIX: &[u8] = b"bzl_remote_kv:";
+const KV_VALUE_PATH: &str = "value";
+
+// A blob streamed down from the upstream into a temp file, removed once dropped.
+struct DownloadedBlob {
+    _file: NamedTempFile,
+    mmap: memmap2::Mmap,
+}
+
+impl AsRef<[u8]> for DownloadedBlob {
+    fn as_ref(&self) -> &[u8] {
+        self.mmap.as_ref()
+    }
+}
+
+fn remote_error(message: &str, e: Box<dyn std::error::Error + Send + Sync>) -> StorageBackendError {
+    StorageBackendError::ErrorAndMessage(format!("Upstream cache error: {}", message), e)
+}
+
+/// Forwards everything to another REAPI compatible server, e.g. to run the cache-server
+/// as an edge proxy in front of a central cache.
+#[derive(Debug)]
+pub struct RemoteGrpcBackend {
+    cache_client: CacheClient,
+    kv_client: CacheClient,
+}
+
+impl RemoteGrpcBackend {
+    pub async fn connect<S: AsRef<str>>(
+        connect_url: S,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self::new(CacheClient::connect(connect_url).await?))
+    }
+
+    pub fn new(cache_client: CacheClient) -> Self {
+        let kv_client = cache_client.with_instance_name(KV_INSTANCE_NAME);
+        Self {
+            cache_client,
+            kv_client,
+        }
+    }
+
+    fn digest_of(data: &[u8]) -> Result<execution::Digest, StorageBackendError> {
+        let sha_v: Sha256Value = data.try_into()?;
+        Ok(execution::Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        })
+    }
+
+    fn kv_action_digest(key: &[u8]) -> Result<execution::Digest, StorageBackendError> {
+        let mut action_key = KV_ACTION_PREFIX.to_vec();
+        action_key.extend_from_slice(key);
+        Self::digest_of(&action_key)
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for RemoteGrpcBackend {
+    async fn get_kv(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageBackendError> {
+        let action_digest = Self::kv_action_digest(key)?;
+        let action_result = self
+            .kv_client
+            .action_cache()
+            .get_action_result(&action_digest)
+            .await
+            .map_err(|e| remote_error("fetching kv entry", e))?;
+        let value_digest = match action_result.and_then(|r| {
+            r.output_files
+                .into_iter()
+                .find(|f| f.path == KV_VALUE_PATH)
+                .and_then(|f| f.digest)
+        }) {
+            Some(value_digest) => value_digest,
+            None => return Ok(None),
+        };
+        self.kv_client
+            .cas()
+            .fetch_to_bytes(&value_digest)
+            .await
+            .map_err(|e| remote_error("fetching kv value", e))
+    }
+
+    async fn put_kv(&self, key: &[u8], value: &[u8]) -> Result<(), StorageBackendError> {
+        let action_digest = Self::kv_action_digest(key)?;
+        let value_digest = Self::digest_of(value)?;
+        self.kv_client
+            .cas()
+            .upload_bytes(&value_digest, value.to_vec(), UPLOAD_CHUNK_SIZE)
+            .await
+            .map_err(|e| remote_error("storing kv value", e))?;
+        let action_result = execution::ActionResult {
+            output_files: vec![execution::OutputFile {
+                path: KV_VALUE_PATH.to_string(),
+                digest: Some(value_digest),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        self.kv_client
+            .action_cache()
+            .update_action_result(&action_digest, &action_result)
+            .await
+            .map_err(|e| remote_error("storing kv entry", e))?;
+        Ok(())
+    }
+
+    async fn get_action_result(
+        &self,
+        digest: &execution::Digest,
+    ) -> Result<Option<Arc<execution::ActionResult>>, StorageBackendError> {
+        let action_result = self
+            .cache_client
+            .action_cache()
+            .get_action_result(digest)
+            .await
+            .map_err(|e| remote_error("fetching action result", e))?;
+        Ok(action_result.map(Arc::new))
+    }
+
+    async fn put_action_result(
+        &self,
+        digest: &execution::Digest,
+        action_result: &execution::ActionResult,
+    ) -> Result<execution::Digest, StorageBackendError> {
+        self.cache_client
+            .action_cache()
+            .update_action_result(digest, action_result)
+            .await
+            .map_err(|e| remote_error("storing action result", e))?;
+
+        // The upstream stores the action result in its own CAS, we only need to report its digest.
+        Self::digest_of(&prost::Message::encode_to_vec(action_result))
+    }
+
+    async fn build_digest_from_hash_if_present(
+        &self,
+        hash: &String,
+    ) -> Result<Option<execution::Digest>, StorageBackendError> {
+        match self.cache_client.cas().find_digest(hash).await {
+            Ok(digest) => Ok(digest),
+            Err(e) => {
+                // Only our own cache-server implements the metadata service.
+                if let Some(status) = e.downcast_ref::<tonic::Status>() {
+                    if status.code() == tonic::Code::Unimplemented {
+                        return Ok(None);
+                    }
+                }
+                Err(remote_error("looking up digest for hash", e))
+            }
+        }
+    }
+
+    async fn cas_filter_for_missing(
+        &self,
+        digests: &mut Vec<execution::Digest>,
+    ) -> Result<(), StorageBackendError> {
+        if digests.is_empty() {
+            return Ok(());
+        }
+        let mut missing = self
+            .cache_client
+            .cas()
+            .find_missing_blobs(std::mem::take(digests))
+            .await
+            .map_err(|e| remote_error("finding missing blobs", e))?;
+        std::mem::swap(digests, &mut missing);
+        Ok(())
+    }
+
+    async fn cas_exists(&self, digest: &execution::Digest) -> Result<bool, StorageBackendError> {
+        let mut digests = vec![digest.clone()];
+        self.cas_filter_for_missing(&mut digests).await?;
+        Ok(digests.is_empty())
+    }
+
+    async fn cas_insert(
+        &self,
+        digest: &execution::Digest,
+        data: UploadType,
+    ) -> Result<(), StorageBackendError> {
+        match data {
+            UploadType::InMemory(data) => self
+                .cache_client
+                .cas()
+                .upload_bytes(digest, data, UPLOAD_CHUNK_SIZE)
+                .await
+                .map_err(|e| remote_error("uploading blob", e)),
+            UploadType::OnDisk(path) => {
+                let res = self
+                    .cache_client
+                    .cas()
+                    .upload_file(digest, &path, UPLOAD_CHUNK_SIZE)
+                    .await
+                    .map_err(|e| remote_error("uploading blob", e));
+                let _ = std::fs::remove_file(&path);
+                res
+            }
+        }
+    }
+
+    async fn cas_get_data(
+        &self,
+        digest: &execution::Digest,
+    ) -> Result<Option<DataReturnTpe>, StorageBackendError> {
+        if digest.size_bytes == 0 {
+            // Mapping an empty file fails, and REAPI servers always hold the empty blob.
+            return Ok(Some(Box::new(Vec::<u8>::default())));
+        }
+        let file = NamedTempFile::new()?;
+        let found = self
+            .cache_client
+            .cas()
+            .fetch_to_path(digest, file.path())
+            .await
+            .map_err(|e| remote_error("fetching blob", e))?;
+        if found.is_none() {
+            return Ok(None);
+        }
+        let mmap = unsafe {
+            memmap2::Mmap::map(file.as_file())
+                .map_err(|e| StorageBackendError::InternalError(e.into()))?
+        };
+        Ok(Some(Box::new(DownloadedBlob { _file: file, mmap })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bazelfe_protos::build::bazel::remote::execution::v2::action_cache_server::ActionCacheServer;
+    use bazelfe_protos::build::bazel::remote::execution::v2::content_addressable_storage_server::ContentAddressableStorageServer;
+    use bazelfe_protos::bzl_remote::metadata_service::metadata_service_server::MetadataServiceServer;
+    use bazelfe_protos::google::bytestream::byte_stream_server::ByteStreamServer;
+
+    use crate::cache_service::action_cache_service::ActionCacheService;
+    use crate::cache_service::bytestream_service::ByteStreamService;
+    use crate::cache_service::content_addressable_storage_service::ContentAddressableStorageService;
+    use crate::cache_service::metadata_service::MetadataService;
+    use crate::storage_backend::InMemoryStorageBackend;
+
+    use super::*;
+
+    async fn start_upstream(
+    ) -> Result<(String, Arc<InMemoryStorageBackend>), Box<dyn std::error::Error>> {
+        let storage_backend = Arc::new(InMemoryStorageBackend::default());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+
+        let server = tonic::transport::Server::builder()
+            .add_service(ActionCacheServer::new(ActionCacheService::new(Arc::clone(
+                &storage_backend,
+            ))))
+            .add_service(ContentAddressableStorageServer::new(
+                ContentAddressableStorageService::new(Arc::clone(&storage_backend)),
+            ))
+            .add_service(ByteStreamServer::new(ByteStreamService::new(
+                Arc::clone(&storage_backend),
+                64 * 1024,
+            )))
+            .add_service(MetadataServiceServer::new(MetadataService::new(
+                Arc::clone(&storage_backend),
+            )))
+            .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener));
+        tokio::spawn(server);
+
+        Ok((format!("http://{}", addr), storage_backend))
+    }
+
+    fn digest_for(data: &[u8]) -> execution::Digest {
+        let sha_v: Sha256Value = data.try_into().expect("Should be able to hash");
+        execution::Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trips_through_upstream() -> Result<(), Box<dyn std::error::Error>> {
+        let (url, upstream) = start_upstream().await?;
+        let backend = RemoteGrpcBackend::connect(url)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let data = b"Some data to push through the proxy".to_vec();
+        let digest = digest_for(&data);
+        let missing = digest_for(b"never uploaded");
+
+        backend
+            .cas_insert(&digest, UploadType::InMemory(data.clone()))
+            .await?;
+        assert!(upstream.cas_exists(&digest).await?);
+
+        let mut digests = vec![digest.clone(), missing.clone()];
+        backend.cas_filter_for_missing(&mut digests).await?;
+        assert_eq!(digests, vec![missing.clone()]);
+
+        assert_eq!(
+            backend
+                .cas_get_data(&digest)
+                .await?
+                .map(|d| d.as_ref().as_ref().to_vec()),
+            Some(data)
+        );
+        assert!(backend.cas_get_data(&missing).await?.is_none());
+        assert_eq!(
+            backend
+                .build_digest_from_hash_if_present(&digest.hash)
+                .await?,
+            Some(digest)
+        );
+
+        let action_digest = digest_for(b"my action");
+        let action_result = execution::ActionResult {
+            exit_code: 2,
+            ..Default::default()
+        };
+        assert!(backend.get_action_result(&action_digest).await?.is_none());
+        backend
+            .put_action_result(&action_digest, &action_result)
+            .await?;
+        assert_eq!(
+            backend
+                .get_action_result(&action_digest)
+                .await?
+                .map(|r| r.as_ref().clone()),
+            Some(action_result)
+        );
+
+        assert_eq!(backend.get_kv(b"key").await?, None);
+        backend.put_kv(b"key", b"value").await?;
+        assert_eq!(backend.get_kv(b"key").await?, Some(b"value".to_vec()));
+        // The value is a blob of its own rather than smuggled through the action result.
+        assert!(upstream.cas_exists(&digest_for(b"value")).await?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_uploads_files_in_chunks() -> Result<(), Box<dyn std::error::Error>> {
+        let (url, upstream) = start_upstream().await?;
+        let backend = RemoteGrpcBackend::connect(url)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let data: Vec<u8> = (0..(UPLOAD_CHUNK_SIZE * 5 / 2))
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let digest = digest_for(&data);
+        let tmp_path = NamedTempFile::new()?.into_temp_path();
+        std::fs::write(&tmp_path, &data)?;
+
+        backend
+            .cas_insert(&digest, UploadType::OnDisk(tmp_path.to_path_buf()))
+            .await?;
+        assert_eq!(
+            upstream
+                .cas_get_data(&digest)
+                .await?
+                .map(|d| d.as_ref().as_ref().to_vec()),
+            Some(data.clone())
+        );
+        assert_eq!(
+            backend
+                .cas_get_data(&digest)
+                .await?
+                .map(|d| d.as_ref().as_ref().to_vec()),
+            Some(data)
+        );
+        Ok(())
+    }
+}
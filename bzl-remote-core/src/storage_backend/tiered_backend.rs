@@ -0,0 +1,537 @@
+use bazelfe_protos::build::bazel::remote::execution::v2::{self as execution};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use tempfile::{NamedTempFile, TempPath};
+use tokio::sync::Semaphore;
+
+use std::io::Write;
+use std::sync::Arc;
+
+use super::api::DataReturnTpe;
+use super::StorageBackend;
+use super::StorageBackendError;
+use super::UploadType;
+
+type PendingWrite = BoxFuture<'static, Result<(), StorageBackendError>>;
+
+/// Upper bound on background writes in flight. Async writes wait for a slot, copies into the
+/// faster tiers made on reads are dropped when none is free and left to a later read.
+const MAX_BACKGROUND_WRITES: usize = 64;
+
+// Lets the caller and a background fill share a blob fetched from a slower tier without copying it.
+struct SharedData(Arc<dyn AsRef<[u8]> + Send + Sync>);
+
+impl AsRef<[u8]> for SharedData {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref().as_ref()
+    }
+}
+
+/// How writes reach the tiers behind the fastest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WritePolicy {
+    /// Every tier has the data before the call returns.
+    #[default]
+    Sync,
+    /// Only the fastest tier is written inline, the rest are filled in the background.
+    Async,
+}
+
+/// Composes an ordered list of backends, fastest first, as a read-through/write-through cache.
+/// Reads walk down the tiers and copy whatever they find into the tiers in front of it in the
+/// background.
+#[derive(Debug)]
+pub struct TieredBackend {
+    tiers: Vec<Arc<dyn StorageBackend>>,
+    write_policy: WritePolicy,
+    background_writes: Arc<Semaphore>,
+}
+
+impl TieredBackend {
+    pub fn new(
+        tiers: Vec<Arc<dyn StorageBackend>>,
+        write_policy: WritePolicy,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if tiers.is_empty() {
+            return Err("A tiered backend needs at least one tier".into());
+        }
+        Ok(Self {
+            tiers,
+            write_policy,
+            background_writes: Arc::new(Semaphore::new(MAX_BACKGROUND_WRITES)),
+        })
+    }
+
+    pub fn write_policy(&self) -> WritePolicy {
+        self.write_policy
+    }
+
+    fn fastest_tier(&self) -> &Arc<dyn StorageBackend> {
+        &self.tiers[0]
+    }
+
+    fn lower_tiers(&self) -> &[Arc<dyn StorageBackend>] {
+        &self.tiers[1..]
+    }
+
+    async fn apply_writes(&self, writes: Vec<PendingWrite>) -> Result<(), StorageBackendError> {
+        match self.write_policy {
+            WritePolicy::Sync => {
+                for write in writes {
+                    write.await?;
+                }
+            }
+            WritePolicy::Async => {
+                if !writes.is_empty() {
+                    let permit = Arc::clone(&self.background_writes)
+                        .acquire_owned()
+                        .await
+                        .map_err(|e| StorageBackendError::InternalError(Box::new(e)))?;
+                    Self::spawn_writes(permit, writes);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Copies found on a read never hold up the read itself, whatever the write policy.
+    fn backfill(&self, writes: Vec<PendingWrite>) {
+        if writes.is_empty() {
+            return;
+        }
+        match Arc::clone(&self.background_writes).try_acquire_owned() {
+            Ok(permit) => Self::spawn_writes(permit, writes),
+            Err(_) => tracing::debug!("Too many background writes in flight, skipping a back-fill"),
+        }
+    }
+
+    fn spawn_writes(permit: tokio::sync::OwnedSemaphorePermit, writes: Vec<PendingWrite>) {
+        tokio::spawn(async move {
+            for write in writes {
+                if let Err(e) = write.await {
+                    tracing::warn!("Failed to write to a cache tier: {}", e);
+                }
+            }
+            drop(permit);
+        });
+    }
+
+    // Backends are free to move a file handed to them on disk, so each tier gets its own copy.
+    async fn duplicate_upload(
+        data: &UploadType,
+    ) -> Result<(UploadType, Option<TempPath>), StorageBackendError> {
+        match data {
+            UploadType::InMemory(d) => Ok((UploadType::InMemory(d.clone()), None)),
+            UploadType::OnDisk(path) => {
+                let path = path.clone();
+                let tmp_path = tokio::task::spawn_blocking(move || {
+                    let tmp_path = NamedTempFile::new()?.into_temp_path();
+                    std::fs::copy(path, &tmp_path)?;
+                    Ok::<_, StorageBackendError>(tmp_path)
+                })
+                .await
+                .map_err(|e| StorageBackendError::InternalError(Box::new(e)))??;
+                Ok((UploadType::OnDisk(tmp_path.to_path_buf()), Some(tmp_path)))
+            }
+        }
+    }
+
+    async fn filter_for_missing(
+        tiers: &[Arc<dyn StorageBackend>],
+        digests: &mut Vec<execution::Digest>,
+    ) -> Result<(), StorageBackendError> {
+        for tier in tiers.iter() {
+            if digests.is_empty() {
+                break;
+            }
+            tier.cas_filter_for_missing(digests).await?;
+        }
+        Ok(())
+    }
+
+    fn output_digests(action_result: &execution::ActionResult) -> Vec<execution::Digest> {
+        action_result
+            .output_files
+            .iter()
+            .filter_map(|f| f.digest.clone())
+            .chain(
+                action_result
+                    .output_directories
+                    .iter()
+                    .filter_map(|d| d.tree_digest.clone()),
+            )
+            .chain(action_result.stdout_digest.clone())
+            .chain(action_result.stderr_digest.clone())
+            .collect()
+    }
+
+    // Streams a blob held by a slower tier to disk and hands each faster tier its own copy.
+    fn cas_backfill(
+        tiers: Vec<Arc<dyn StorageBackend>>,
+        digest: execution::Digest,
+        data: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    ) -> PendingWrite {
+        async move {
+            let tmp_path = tokio::task::spawn_blocking(move || {
+                let mut tmp = NamedTempFile::new()?;
+                tmp.write_all(data.as_ref().as_ref())?;
+                tmp.flush()?;
+                Ok::<_, StorageBackendError>(tmp.into_temp_path())
+            })
+            .await
+            .map_err(|e| StorageBackendError::InternalError(Box::new(e)))??;
+            let mut uploads = Vec::with_capacity(tiers.len());
+            for _ in tiers.iter() {
+                uploads.push(
+                    Self::duplicate_upload(&UploadType::OnDisk(tmp_path.to_path_buf())).await?,
+                );
+            }
+            for write in Self::cas_writes(&tiers, &digest, uploads) {
+                write.await?;
+            }
+            drop(tmp_path);
+            Ok(())
+        }
+        .boxed()
+    }
+
+    fn cas_writes(
+        tiers: &[Arc<dyn StorageBackend>],
+        digest: &execution::Digest,
+        mut uploads: Vec<(UploadType, Option<TempPath>)>,
+    ) -> Vec<PendingWrite> {
+        tiers
+            .iter()
+            .zip(uploads.drain(..))
+            .map(|(tier, (data, tmp_path))| {
+                let tier = Arc::clone(tier);
+                let digest = digest.clone();
+                async move {
+                    let res = tier.cas_insert(&digest, data).await;
+                    drop(tmp_path);
+                    res
+                }
+                .boxed()
+            })
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl StorageBackend for TieredBackend {
+    async fn get_kv(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StorageBackendError> {
+        for (idx, tier) in self.tiers.iter().enumerate() {
+            if let Some(value) = tier.get_kv(key).await? {
+                let writes = self.tiers[..idx]
+                    .iter()
+                    .map(|faster| {
+                        let faster = Arc::clone(faster);
+                        let key = key.to_vec();
+                        let value = value.clone();
+                        async move { faster.put_kv(&key, &value).await }.boxed()
+                    })
+                    .collect();
+                self.backfill(writes);
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn put_kv(&self, key: &[u8], value: &[u8]) -> Result<(), StorageBackendError> {
+        self.fastest_tier().put_kv(key, value).await?;
+        let writes = self
+            .lower_tiers()
+            .iter()
+            .map(|tier| {
+                let tier = Arc::clone(tier);
+                let key = key.to_vec();
+                let value = value.to_vec();
+                async move { tier.put_kv(&key, &value).await }.boxed()
+            })
+            .collect();
+        self.apply_writes(writes).await
+    }
+
+    async fn get_action_result(
+        &self,
+        digest: &execution::Digest,
+    ) -> Result<Option<Arc<execution::ActionResult>>, StorageBackendError> {
+        for (idx, tier) in self.tiers.iter().enumerate() {
+            if let Some(action_result) = tier.get_action_result(digest).await? {
+                if idx > 0 {
+                    // An entry whose outputs have been evicted would only spread a cache hit
+                    // that can't be served, so it stays where it is.
+                    let tiers = self.tiers.clone();
+                    let digest = digest.clone();
+                    let action_result = Arc::clone(&action_result);
+                    let promote = async move {
+                        let mut missing = Self::output_digests(&action_result);
+                        Self::filter_for_missing(&tiers, &mut missing).await?;
+                        if !missing.is_empty() {
+                            tracing::debug!(
+                                "Not promoting action result {}, {} outputs are missing",
+                                digest.hash,
+                                missing.len()
+                            );
+                            return Ok(());
+                        }
+                        for faster in tiers[..idx].iter() {
+                            faster.put_action_result(&digest, &action_result).await?;
+                        }
+                        Ok(())
+                    }
+                    .boxed();
+                    self.backfill(vec![promote]);
+                }
+                return Ok(Some(action_result));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn put_action_result(
+        &self,
+        digest: &execution::Digest,
+        action_result: &execution::ActionResult,
+    ) -> Result<execution::Digest, StorageBackendError> {
+        let action_result_digest = self
+            .fastest_tier()
+            .put_action_result(digest, action_result)
+            .await?;
+        let writes = self
+            .lower_tiers()
+            .iter()
+            .map(|tier| {
+                let tier = Arc::clone(tier);
+                let digest = digest.clone();
+                let action_result = action_result.clone();
+                async move {
+                    tier.put_action_result(&digest, &action_result)
+                        .await
+                        .map(|_| ())
+                }
+                .boxed()
+            })
+            .collect();
+        self.apply_writes(writes).await?;
+        Ok(action_result_digest)
+    }
+
+    async fn build_digest_from_hash_if_present(
+        &self,
+        hash: &String,
+    ) -> Result<Option<execution::Digest>, StorageBackendError> {
+        for tier in self.tiers.iter() {
+            if let Some(digest) = tier.build_digest_from_hash_if_present(hash).await? {
+                return Ok(Some(digest));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn cas_filter_for_missing(
+        &self,
+        digests: &mut Vec<execution::Digest>,
+    ) -> Result<(), StorageBackendError> {
+        Self::filter_for_missing(&self.tiers, digests).await
+    }
+
+    async fn cas_exists(&self, digest: &execution::Digest) -> Result<bool, StorageBackendError> {
+        for tier in self.tiers.iter() {
+            if tier.cas_exists(digest).await? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    async fn cas_insert(
+        &self,
+        digest: &execution::Digest,
+        data: UploadType,
+    ) -> Result<(), StorageBackendError> {
+        let mut uploads = Vec::with_capacity(self.lower_tiers().len());
+        for _ in self.lower_tiers() {
+            uploads.push(Self::duplicate_upload(&data).await?);
+        }
+        self.fastest_tier().cas_insert(digest, data).await?;
+        let writes = Self::cas_writes(self.lower_tiers(), digest, uploads);
+        self.apply_writes(writes).await
+    }
+
+    async fn cas_get_data(
+        &self,
+        digest: &execution::Digest,
+    ) -> Result<Option<DataReturnTpe>, StorageBackendError> {
+        if let Some(data) = self.fastest_tier().cas_get_data(digest).await? {
+            return Ok(Some(data));
+        }
+        for (idx, tier) in self.tiers.iter().enumerate().skip(1) {
+            if let Some(data) = tier.cas_get_data(digest).await? {
+                let data: Arc<dyn AsRef<[u8]> + Send + Sync> = Arc::from(data);
+                self.backfill(vec![Self::cas_backfill(
+                    self.tiers[..idx].to_vec(),
+                    digest.clone(),
+                    Arc::clone(&data),
+                )]);
+                return Ok(Some(Box::new(SharedData(data))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::sha256_value::Sha256Value;
+    use crate::storage_backend::InMemoryStorageBackend;
+
+    use super::*;
+
+    fn digest_for(data: &[u8]) -> execution::Digest {
+        let sha_v: Sha256Value = data.try_into().expect("Should be able to hash");
+        execution::Digest {
+            hash: sha_v.to_string(),
+            size_bytes: data.len() as i64,
+        }
+    }
+
+    fn setup(
+        write_policy: WritePolicy,
+    ) -> (
+        TieredBackend,
+        Arc<InMemoryStorageBackend>,
+        Arc<InMemoryStorageBackend>,
+    ) {
+        let fast = Arc::new(InMemoryStorageBackend::default());
+        let slow = Arc::new(InMemoryStorageBackend::default());
+        let tiered = TieredBackend::new(
+            vec![
+                Arc::clone(&fast) as Arc<dyn StorageBackend>,
+                Arc::clone(&slow) as Arc<dyn StorageBackend>,
+            ],
+            write_policy,
+        )
+        .expect("Should be able to build");
+        (tiered, fast, slow)
+    }
+
+    async fn wait_for_background_writes(tiered: &TieredBackend) {
+        let _all = tiered
+            .background_writes
+            .acquire_many(MAX_BACKGROUND_WRITES as u32)
+            .await
+            .expect("Semaphore is never closed");
+    }
+
+    #[tokio::test]
+    async fn test_reads_populate_faster_tiers() -> Result<(), Box<dyn std::error::Error>> {
+        let (tiered, fast, slow) = setup(WritePolicy::Sync);
+
+        let data = b"only in the slow tier".to_vec();
+        let digest = digest_for(&data);
+        slow.cas_insert(&digest, UploadType::InMemory(data.clone()))
+            .await?;
+        slow.put_kv(b"key", b"value").await?;
+
+        assert!(!fast.cas_exists(&digest).await?);
+        assert_eq!(
+            tiered
+                .cas_get_data(&digest)
+                .await?
+                .map(|d| d.as_ref().as_ref().to_vec()),
+            Some(data)
+        );
+        wait_for_background_writes(&tiered).await;
+        assert!(fast.cas_exists(&digest).await?);
+
+        assert_eq!(tiered.get_kv(b"key").await?, Some(b"value".to_vec()));
+        wait_for_background_writes(&tiered).await;
+        assert_eq!(fast.get_kv(b"key").await?, Some(b"value".to_vec()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_only_promotes_action_results_with_outputs(
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (tiered, fast, slow) = setup(WritePolicy::Sync);
+
+        let output = b"output".to_vec();
+        let output_digest = digest_for(&output);
+        slow.cas_insert(&output_digest, UploadType::InMemory(output))
+            .await?;
+        let action_result_with_outputs = |digest: execution::Digest| execution::ActionResult {
+            output_files: vec![execution::OutputFile {
+                path: "out".to_string(),
+                digest: Some(digest),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let present = digest_for(b"present");
+        slow.put_action_result(&present, &action_result_with_outputs(output_digest))
+            .await?;
+        let evicted = digest_for(b"evicted");
+        slow.put_action_result(&evicted, &action_result_with_outputs(digest_for(b"gone")))
+            .await?;
+
+        assert!(tiered.get_action_result(&present).await?.is_some());
+        assert!(tiered.get_action_result(&evicted).await?.is_some());
+        wait_for_background_writes(&tiered).await;
+        assert!(fast.get_action_result(&present).await?.is_some());
+        assert!(fast.get_action_result(&evicted).await?.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_for_missing_consults_all_tiers() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let (tiered, fast, slow) = setup(WritePolicy::Sync);
+
+        let in_fast = digest_for(b"fast");
+        let in_slow = digest_for(b"slow");
+        let missing = digest_for(b"missing");
+        fast.cas_insert(&in_fast, UploadType::InMemory(b"fast".to_vec()))
+            .await?;
+        slow.cas_insert(&in_slow, UploadType::InMemory(b"slow".to_vec()))
+            .await?;
+
+        let mut digests = vec![in_fast, in_slow, missing.clone()];
+        tiered.cas_filter_for_missing(&mut digests).await?;
+        assert_eq!(digests, vec![missing]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_policies() -> Result<(), Box<dyn std::error::Error>> {
+        let (tiered, fast, slow) = setup(WritePolicy::Sync);
+        let action_digest = digest_for(b"action");
+        let action_result = execution::ActionResult {
+            exit_code: 1,
+            ..Default::default()
+        };
+        tiered
+            .put_action_result(&action_digest, &action_result)
+            .await?;
+        assert!(fast.get_action_result(&action_digest).await?.is_some());
+        assert!(slow.get_action_result(&action_digest).await?.is_some());
+
+        let (tiered, fast, slow) = setup(WritePolicy::Async);
+        let data = b"written in the background".to_vec();
+        let digest = digest_for(&data);
+        tiered
+            .cas_insert(&digest, UploadType::InMemory(data))
+            .await?;
+        assert!(fast.cas_exists(&digest).await?);
+        for _ in 0..100 {
+            if slow.cas_exists(&digest).await? {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert!(slow.cas_exists(&digest).await?);
+        Ok(())
+    }
+}